@@ -0,0 +1,59 @@
+//! Times filtering a synthetic tree: cold, repeated, and after changing one file.
+//!
+//! Run with `cargo bench -p git-filter-tree`. Criterion is not used so that the benchmark
+//! needs no extra dependencies.
+
+use git_filter_tree::{FilterOptions, FilterTree};
+use git2::{Oid, Repository};
+use std::time::{Duration, Instant};
+
+const DIRS: usize = 200;
+const FILES: usize = 100;
+const RUNS: u32 = 10;
+
+/// Builds a tree of `DIRS` directories holding `FILES` files each, with `changed` written into
+/// the first file.
+fn build_tree(repo: &Repository, changed: &[u8]) -> Result<Oid, git2::Error> {
+    let mut root = repo.treebuilder(None)?;
+    for dir in 0..DIRS {
+        let mut builder = repo.treebuilder(None)?;
+        for file in 0..FILES {
+            let content = if dir == 0 && file == 0 {
+                changed.to_vec()
+            } else {
+                format!("{dir}/{file}").into_bytes()
+            };
+            let ext = if file % 2 == 0 { "rs" } else { "txt" };
+            builder.insert(format!("f{file}.{ext}"), repo.blob(&content)?, 0o100644)?;
+        }
+        root.insert(format!("d{dir}"), builder.write()?, 0o040000)?;
+    }
+    root.write()
+}
+
+fn time(repo: &Repository, tree: Oid, options: &FilterOptions) -> Result<Duration, git2::Error> {
+    let tree = repo.find_tree(tree)?;
+    let started = Instant::now();
+    repo.filter_by_patterns_with_stats(&tree, &["**/*.rs"], options)?;
+    Ok(started.elapsed())
+}
+
+fn main() -> Result<(), git2::Error> {
+    let dir = std::env::temp_dir().join(format!("git-filter-tree-bench-{}", std::process::id()));
+    let repo = Repository::init_bare(&dir)?;
+    let options = FilterOptions::default();
+    let tree = build_tree(&repo, b"original")?;
+
+    println!("cold:      {:?}", time(&repo, tree, &options)?);
+    let mut warm = Duration::ZERO;
+    for _ in 0..RUNS {
+        warm += time(&repo, tree, &options)?;
+    }
+    println!("repeated:  {:?}", warm / RUNS);
+    let changed = build_tree(&repo, b"changed")?;
+    println!("one edit:  {:?}", time(&repo, changed, &options)?);
+
+    drop(repo);
+    let _ = std::fs::remove_dir_all(dir);
+    Ok(())
+}
@@ -0,0 +1,51 @@
+//! Memoization of filtered trees.
+//!
+//! Filtering is deterministic: the same tree at the same path, filtered with the same patterns
+//! and options, always yields the same tree. Results are remembered for the life of the process,
+//! so filtering trees that share unchanged subtrees (successive upstream commits, or the same
+//! `HEAD` for several operations) only walks the subtrees that changed.
+
+use crate::FilterStats;
+use git2::Oid;
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+
+/// Entries kept before the cache is cleared, bounding its memory use.
+const CAPACITY: usize = 1 << 16;
+
+/// Identifies one filtered tree.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct Key {
+    /// The unfiltered tree.
+    pub tree: Oid,
+    /// The path of the tree from the root of the walk.
+    pub prefix: Vec<u8>,
+    /// Whether the tree lies in a matched directory.
+    pub include_all: bool,
+    /// A hash of the patterns and options of the walk.
+    pub filter: u64,
+}
+
+/// A filtered tree, the number of tree entries visited to filter it, and, when collected,
+/// the statistics of filtering it.
+#[derive(Debug, Clone)]
+pub(crate) struct Entry {
+    pub tree: Oid,
+    pub entries: usize,
+    pub stats: Option<(FilterStats, Vec<bool>)>,
+}
+
+static CACHE: LazyLock<Mutex<HashMap<Key, Entry>>> = LazyLock::new(Default::default);
+
+pub(crate) fn get(key: &Key) -> Option<Entry> {
+    CACHE.lock().ok()?.get(key).cloned()
+}
+
+pub(crate) fn insert(key: Key, entry: Entry) {
+    if let Ok(mut cache) = CACHE.lock() {
+        if cache.len() >= CAPACITY {
+            cache.clear();
+        }
+        cache.insert(key, entry);
+    }
+}
@@ -1,16 +1,88 @@
+use crate::{ErrorPolicy, PatternSyntax, SubmodulePolicy, SymlinkPolicy};
 use clap::Parser;
 
 #[derive(Parser)]
 #[command(name = "git-filter-tree")]
 #[command(author, version, about = "Filter Git tree entries by gitattributes-style patterns", long_about = None)]
 pub struct Cli {
-    /// Tree-ish reference (commit, branch, tag, or tree SHA)
+    /// Tree-ish reference (commit, branch, tag, or tree SHA), or a revision range with --history
     pub treeish: String,
 
     /// Gitattributes-style patterns to filter tree entries
     #[arg(required = true)]
     pub patterns: Vec<String>,
 
+    /// How patterns are interpreted
+    #[arg(short, long, value_enum, default_value = "glob")]
+    pub syntax: PatternSyntax,
+
+    /// Match patterns case-insensitively
+    #[arg(short, long)]
+    pub ignore_case: bool,
+
+    /// Treat patterns as literal paths rather than globs
+    #[arg(long)]
+    pub literal: bool,
+
+    /// Keep everything inside directories matched by a pattern
+    #[arg(long)]
+    pub match_directories: bool,
+
+    /// How to handle matched submodules
+    #[arg(long, value_enum, default_value = "skip")]
+    pub submodules: SubmodulePolicy,
+
+    /// How to handle matched symlinks
+    #[arg(long, value_enum, default_value = "preserve")]
+    pub symlinks: SymlinkPolicy,
+
+    /// Store executable files as regular files
+    #[arg(long)]
+    pub normalize_executable: bool,
+
+    /// Drop entries matching this pattern even if they match (repeatable)
+    #[arg(short = 'x', long = "exclude", value_name = "PATTERN")]
+    pub excludes: Vec<String>,
+
+    /// Keep this directory, adding an empty .gitkeep file if filtering empties it (repeatable)
+    #[arg(long = "keep-dir", value_name = "DIR")]
+    pub keep_dirs: Vec<String>,
+
+    /// Fail on trees nested deeper than this many directories [default: 256]
+    #[arg(long, value_name = "N")]
+    pub max_depth: Option<usize>,
+
+    /// Fail after visiting this many tree entries
+    #[arg(long, value_name = "N")]
+    pub max_entries: Option<usize>,
+
+    /// How to handle subtrees that cannot be read
+    #[arg(long, value_enum, default_value = "fail")]
+    pub on_error: ErrorPolicy,
+
+    /// Keep only entries under this directory, removing it from their paths
+    #[arg(long, value_name = "DIR")]
+    pub strip_prefix: Option<String>,
+
+    /// Place the filtered entries under this directory
+    #[arg(long, value_name = "DIR")]
+    pub add_prefix: Option<String>,
+
+    /// Write the filtered tree as a commit reusing the author, committer and message of the
+    /// source commit, and output the commit SHA
+    #[arg(short, long)]
+    pub commit: bool,
+
+    /// Point this ref at the new commit (implies --commit); its previous target becomes the
+    /// commit's parent
+    #[arg(long, value_name = "REF")]
+    pub write_ref: Option<String>,
+
+    /// Rewrite every commit reachable from the tree-ish (or in the given range, such as
+    /// `v1.0..main`) and output the rewritten tip
+    #[arg(long, conflicts_with_all = ["strip_prefix", "add_prefix", "format"])]
+    pub history: bool,
+
     /// Output format
     #[arg(short, long, value_enum, default_value = "tree-sha")]
     pub format: OutputFormat,
@@ -24,4 +96,6 @@ pub enum OutputFormat {
     Entries,
     /// Output detailed tree information
     Detailed,
+    /// Output the paths of the files the filter removed
+    Removed,
 }
@@ -0,0 +1,102 @@
+//! Rewriting a range of commits with filtered trees.
+
+use crate::{FilterOptions, FilterTree};
+use git2::{Commit, Error, Oid, Repository, Sort};
+use std::collections::HashMap;
+
+/// The result of [`FilterTree::filter_history`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct FilteredHistory {
+    /// The rewritten tip of the range, or `None` if no commit in the range touched the
+    /// filtered paths.
+    pub head: Option<Oid>,
+    /// Maps each commit in the range to its rewrite. Commits that do not change the filtered
+    /// tree map to their rewritten parent (or `None` when they have none).
+    pub commits: HashMap<Oid, Option<Oid>>,
+}
+
+pub(crate) fn filter_history(
+    repo: &Repository,
+    range: &str,
+    patterns: &[&str],
+    options: &FilterOptions,
+) -> Result<FilteredHistory, Error> {
+    let spec = repo.revparse(range)?;
+    let mut walk = repo.revwalk()?;
+    walk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)?;
+
+    let tip = if spec.mode().contains(git2::RevparseMode::RANGE) {
+        let from = spec
+            .from()
+            .ok_or_else(|| Error::from_str("Invalid range"))?;
+        let to = spec.to().ok_or_else(|| Error::from_str("Invalid range"))?;
+        walk.hide(from.peel_to_commit()?.id())?;
+        to.peel_to_commit()?.id()
+    } else {
+        let from = spec
+            .from()
+            .ok_or_else(|| Error::from_str("Invalid revision"))?;
+        from.peel_to_commit()?.id()
+    };
+    walk.push(tip)?;
+
+    let mut history = FilteredHistory::default();
+    for oid in walk {
+        let commit = repo.find_commit(oid?)?;
+        let rewritten = rewrite_commit(repo, &commit, patterns, options, &history.commits)?;
+        history.commits.insert(commit.id(), rewritten);
+    }
+
+    history.head = history.commits.get(&tip).copied().flatten();
+    Ok(history)
+}
+
+/// Rewrites `commit` with its filtered tree on top of its rewritten parents. Parents outside
+/// the range are kept as they are. A commit that leaves the filtered tree unchanged is dropped
+/// in favor of its parent.
+fn rewrite_commit(
+    repo: &Repository,
+    commit: &Commit<'_>,
+    patterns: &[&str],
+    options: &FilterOptions,
+    rewritten: &HashMap<Oid, Option<Oid>>,
+) -> Result<Option<Oid>, Error> {
+    let tree = commit.tree()?;
+    let filtered = repo.filter_with_options(&tree, patterns, options)?;
+
+    let mut parent_ids: Vec<Oid> = Vec::new();
+    for parent in commit.parent_ids() {
+        let mapped = match rewritten.get(&parent) {
+            Some(mapped) => *mapped,
+            None => Some(parent),
+        };
+        if let Some(id) = mapped
+            && !parent_ids.contains(&id)
+        {
+            parent_ids.push(id);
+        }
+    }
+
+    let parents = parent_ids
+        .iter()
+        .map(|id| repo.find_commit(*id))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    match parents.as_slice() {
+        [] if filtered.is_empty() => return Ok(None),
+        [parent] if parent.tree_id() == filtered.id() => return Ok(Some(parent.id())),
+        _ => {}
+    }
+
+    let parents: Vec<&Commit> = parents.iter().collect();
+    let message = String::from_utf8_lossy(commit.message_bytes());
+    repo.commit(
+        None,
+        &commit.author(),
+        &commit.committer(),
+        &message,
+        &filtered,
+        &parents,
+    )
+    .map(Some)
+}
@@ -1,10 +1,117 @@
 //! Provides a `FilterTree` trait, and an implementation for `git2::Repository`, which allows for pruning trees by Git pathspec patterns.
 
 pub use git2::{Error, Repository};
-use globset::{GlobSet, GlobSetBuilder};
+use git2::{ErrorClass, ErrorCode};
+use globset::{Candidate, GlobSet, GlobSetBuilder};
+use std::borrow::Cow;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::Path;
 
+mod cache;
 #[cfg(feature = "cli")]
 pub mod cli;
+mod history;
+mod wildmatch;
+
+pub use history::FilteredHistory;
+
+use wildmatch::AttrPattern;
+
+/// How filter patterns are interpreted.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum PatternSyntax {
+    /// Shell-style globs as implemented by `globset`, matched against full paths.
+    #[default]
+    Glob,
+    /// Git's gitattributes pattern rules, so that patterns behave exactly as they do in a
+    /// `.gitattributes` file.
+    GitAttributes,
+}
+
+/// What to do with submodule (gitlink) entries matched by a pattern.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum SubmodulePolicy {
+    /// Leave gitlinks out of the filtered tree.
+    #[default]
+    Skip,
+    /// Keep gitlinks in the filtered tree as-is.
+    Keep,
+    /// Fail if a gitlink is matched.
+    Error,
+}
+
+/// What to do with symbolic links matched by a pattern.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum SymlinkPolicy {
+    /// Keep symlinks as they are.
+    #[default]
+    Preserve,
+    /// Fail if a symlink is matched.
+    Reject,
+    /// Replace each symlink with a regular file holding the content of the file it points
+    /// to. Fails for links that leave the tree, dangle, or point to a directory.
+    Materialize,
+}
+
+/// What to do when a subtree cannot be read, for example because its objects are missing.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum ErrorPolicy {
+    /// Fail the whole walk.
+    #[default]
+    Fail,
+    /// Leave the subtree out of the filtered tree and record its path in
+    /// [`FilterStats::skipped_subtrees`].
+    Skip,
+}
+
+/// Options controlling how [`FilterTree`] matches patterns.
+#[derive(Debug, Default, Clone)]
+pub struct FilterOptions {
+    /// The pattern syntax to use.
+    pub syntax: PatternSyntax,
+    /// Match paths case-insensitively (ASCII only), as on case-insensitive file systems.
+    pub case_insensitive: bool,
+    /// Treat each pattern as a literal path, without interpreting wildcards.
+    pub literal: bool,
+    /// Let a pattern that matches a directory also match everything inside it, as if it
+    /// were followed by `/**`.
+    pub match_directories: bool,
+    /// How matched submodule (gitlink) entries are handled.
+    pub submodules: SubmodulePolicy,
+    /// How matched symbolic links are handled.
+    pub symlinks: SymlinkPolicy,
+    /// Store executable files as regular (non-executable) files.
+    pub normalize_executable: bool,
+    /// Drop entries matching any of these patterns even when they match the filter patterns.
+    /// An excluded directory is dropped with everything inside it.
+    pub excludes: Vec<String>,
+    /// Directories, as slash-separated paths from the root, kept even when filtering leaves
+    /// them empty, by adding an empty `.gitkeep` file to them. Directories that do not exist
+    /// or are excluded are not created.
+    pub keep_dirs: Vec<String>,
+    /// Fail on trees nested more than this many directories deep, rather than recursing
+    /// without bound. Defaults to [`DEFAULT_MAX_DEPTH`].
+    pub max_depth: Option<usize>,
+    /// Fail after visiting this many tree entries. Unlimited by default.
+    pub max_entries: Option<usize>,
+    /// How subtrees that cannot be read are handled. Policy violations and exceeded limits
+    /// always fail. Collect statistics to learn which subtrees were skipped.
+    pub on_error: ErrorPolicy,
+}
+
+/// The default [`FilterOptions::max_depth`]. Git's `core.maxTreeDepth` allows 2048 (512 on
+/// Windows), but each level of this walk takes several kilobytes of stack in unoptimized
+/// builds; 256 levels fit in a 2 MiB thread stack, far deeper than real repositories nest.
+pub const DEFAULT_MAX_DEPTH: usize = 256;
+
+/// A callback applied to each matched blob, given its full path and content.
+/// Returning `Some(content)` replaces the blob; `None` keeps it unchanged. Non-UTF-8 bytes
+/// in the path are replaced with `U+FFFD`.
+pub type BlobTransform<'t> = dyn FnMut(&str, &[u8]) -> Option<Vec<u8>> + 't;
 
 pub trait FilterTree {
     /// Filters tree entries by gitattributes-style patterns and returns a new tree with contents filtered through the provided patterns.
@@ -14,6 +121,93 @@ pub trait FilterTree {
         tree: &'a git2::Tree<'a>,
         patterns: &[&str],
     ) -> Result<git2::Tree<'a>, Error>;
+
+    /// Filters tree entries like [`filter_by_patterns`](Self::filter_by_patterns), interpreting
+    /// the patterns according to `options`.
+    fn filter_with_options<'a>(
+        &'a self,
+        tree: &'a git2::Tree<'a>,
+        patterns: &[&str],
+        options: &FilterOptions,
+    ) -> Result<git2::Tree<'a>, Error>;
+
+    /// Filters tree entries like [`filter_with_options`](Self::filter_with_options), also
+    /// returning counts of matched and skipped files and the patterns that matched nothing.
+    fn filter_by_patterns_with_stats<'a>(
+        &'a self,
+        tree: &'a git2::Tree<'a>,
+        patterns: &[&str],
+        options: &FilterOptions,
+    ) -> Result<(git2::Tree<'a>, FilterStats), Error>;
+
+    /// Filters tree entries like [`filter_with_options`](Self::filter_with_options), then re-roots
+    /// the result: only entries under the `strip` directory are kept, with that prefix removed,
+    /// and the remaining tree is nested under the `add_prefix` directory.
+    /// Patterns are matched against the original paths. Either prefix may be empty.
+    fn filter_and_rebase<'a>(
+        &'a self,
+        tree: &'a git2::Tree<'a>,
+        patterns: &[&str],
+        options: &FilterOptions,
+        strip: &str,
+        add_prefix: &str,
+    ) -> Result<git2::Tree<'a>, Error>;
+
+    /// Re-roots an already filtered tree the way
+    /// [`filter_and_rebase`](Self::filter_and_rebase) does, so that a result of
+    /// [`filter_by_patterns_with_stats`](Self::filter_by_patterns_with_stats) can be re-rooted
+    /// without filtering again.
+    fn rebase_tree<'a>(
+        &'a self,
+        tree: git2::Tree<'a>,
+        strip: &str,
+        add_prefix: &str,
+    ) -> Result<git2::Tree<'a>, Error>;
+
+    /// Filters tree entries like [`filter_with_options`](Self::filter_with_options), passing each
+    /// matched blob through `transform` before insertion. New blob objects are written for
+    /// rewritten content.
+    fn filter_and_transform<'a>(
+        &'a self,
+        tree: &'a git2::Tree<'a>,
+        patterns: &[&str],
+        options: &FilterOptions,
+        transform: &mut BlobTransform<'_>,
+    ) -> Result<git2::Tree<'a>, Error>;
+
+    /// Filters tree entries like [`filter_with_options`](Self::filter_with_options) and returns
+    /// the difference from `tree` to the filtered tree: removed files as deletions, and, since
+    /// unmodified files are included, kept files as unmodified ones. Files changed on the way,
+    /// like materialized symlinks, are modifications or type changes.
+    fn filter_diff<'a>(
+        &'a self,
+        tree: &'a git2::Tree<'a>,
+        patterns: &[&str],
+        options: &FilterOptions,
+    ) -> Result<git2::Diff<'a>, Error>;
+
+    /// Filters a tree of this repository like
+    /// [`filter_with_options`](Self::filter_with_options), writing the result and every object
+    /// it refers to into `dest` instead. Objects are only read from this repository, so it can
+    /// be a shared cache of upstream trees, or hold objects in memory, while `dest` is the
+    /// repository consuming them. Objects `dest` already has are not copied again.
+    fn filter_into<'d>(
+        &self,
+        dest: &'d Repository,
+        tree: &git2::Tree<'_>,
+        patterns: &[&str],
+        options: &FilterOptions,
+    ) -> Result<git2::Tree<'d>, Error>;
+
+    /// Rewrites every commit in `range` (a revision such as `main`, or a range such as
+    /// `v1.0..main`) with its filtered tree, rewriting parents consistently. Commits that do not
+    /// change the filtered tree are dropped. No refs are updated.
+    fn filter_history(
+        &self,
+        range: &str,
+        patterns: &[&str],
+        options: &FilterOptions,
+    ) -> Result<FilteredHistory, Error>;
 }
 
 impl FilterTree for git2::Repository {
@@ -22,96 +216,841 @@ impl FilterTree for git2::Repository {
         tree: &'a git2::Tree<'a>,
         patterns: &[&str],
     ) -> Result<git2::Tree<'a>, Error> {
+        self.filter_with_options(tree, patterns, &FilterOptions::default())
+    }
+
+    fn filter_with_options<'a>(
+        &'a self,
+        tree: &'a git2::Tree<'a>,
+        patterns: &[&str],
+        options: &FilterOptions,
+    ) -> Result<git2::Tree<'a>, Error> {
+        let matcher = Matcher::new(patterns, options)?;
+
+        // Recursively filter the tree
+        Walk::new(&matcher, options, None).run(self, tree)
+    }
+
+    fn filter_by_patterns_with_stats<'a>(
+        &'a self,
+        tree: &'a git2::Tree<'a>,
+        patterns: &[&str],
+        options: &FilterOptions,
+    ) -> Result<(git2::Tree<'a>, FilterStats), Error> {
+        let matcher = Matcher::new(patterns, options)?;
+        let mut walk = Walk::new(&matcher, options, None).with_stats();
+        let filtered = walk.run(self, tree)?;
+        Ok((filtered, walk.finish().unwrap_or_default()))
+    }
+
+    fn filter_and_rebase<'a>(
+        &'a self,
+        tree: &'a git2::Tree<'a>,
+        patterns: &[&str],
+        options: &FilterOptions,
+        strip: &str,
+        add_prefix: &str,
+    ) -> Result<git2::Tree<'a>, Error> {
+        let filtered = self.filter_with_options(tree, patterns, options)?;
+        self.rebase_tree(filtered, strip, add_prefix)
+    }
+
+    fn rebase_tree<'a>(
+        &'a self,
+        tree: git2::Tree<'a>,
+        strip: &str,
+        add_prefix: &str,
+    ) -> Result<git2::Tree<'a>, Error> {
+        let stripped = subtree_at(self, tree, strip)?;
+        nest_under(self, stripped, add_prefix)
+    }
+
+    fn filter_and_transform<'a>(
+        &'a self,
+        tree: &'a git2::Tree<'a>,
+        patterns: &[&str],
+        options: &FilterOptions,
+        transform: &mut BlobTransform<'_>,
+    ) -> Result<git2::Tree<'a>, Error> {
+        let matcher = Matcher::new(patterns, options)?;
+        Walk::new(&matcher, options, Some(transform)).run(self, tree)
+    }
+
+    fn filter_diff<'a>(
+        &'a self,
+        tree: &'a git2::Tree<'a>,
+        patterns: &[&str],
+        options: &FilterOptions,
+    ) -> Result<git2::Diff<'a>, Error> {
+        let filtered = self.filter_with_options(tree, patterns, options)?;
+        let mut diff_opts = git2::DiffOptions::new();
+        diff_opts
+            .include_unmodified(true)
+            .include_typechange(true)
+            .ignore_submodules(false);
+        self.diff_tree_to_tree(Some(tree), Some(&filtered), Some(&mut diff_opts))
+    }
+
+    fn filter_into<'d>(
+        &self,
+        dest: &'d Repository,
+        tree: &git2::Tree<'_>,
+        patterns: &[&str],
+        options: &FilterOptions,
+    ) -> Result<git2::Tree<'d>, Error> {
+        let matcher = Matcher::new(patterns, options)?;
+        Walk::new(&matcher, options, None)
+            .reading_from(self)
+            .run(dest, tree)
+    }
+
+    fn filter_history(
+        &self,
+        range: &str,
+        patterns: &[&str],
+        options: &FilterOptions,
+    ) -> Result<FilteredHistory, Error> {
+        Matcher::new(patterns, options)?;
+        history::filter_history(self, range, patterns, options)
+    }
+}
+
+/// A compiled set of patterns.
+struct Matcher {
+    patterns: Patterns,
+    sources: Vec<String>,
+    match_directories: bool,
+    excludes: Option<Box<Matcher>>,
+}
+
+enum Patterns {
+    Glob(GlobSet),
+    GitAttributes(Vec<AttrPattern>),
+}
+
+impl Matcher {
+    fn new(patterns: &[&str], options: &FilterOptions) -> Result<Self, Error> {
         if patterns.is_empty() {
             return Err(Error::from_str("At least one pattern is required"));
         }
 
-        // Build GlobSet matcher
-        let mut glob_builder = GlobSetBuilder::new();
-        for pattern in patterns {
-            let glob = globset::Glob::new(pattern)
-                .map_err(|e| Error::from_str(&format!("Invalid pattern '{}': {}", pattern, e)))?;
-            glob_builder.add(glob);
+        let sources = patterns;
+        let patterns = match options.syntax {
+            PatternSyntax::Glob => {
+                let mut glob_builder = GlobSetBuilder::new();
+                for pattern in patterns {
+                    let source = if options.literal {
+                        globset::escape(pattern)
+                    } else {
+                        pattern.to_string()
+                    };
+                    let glob = globset::GlobBuilder::new(&source)
+                        .case_insensitive(options.case_insensitive)
+                        .build()
+                        .map_err(|e| {
+                            Error::from_str(&format!("Invalid pattern '{}': {}", pattern, e))
+                        })?;
+                    glob_builder.add(glob);
+                }
+
+                let set = glob_builder
+                    .build()
+                    .map_err(|e| Error::from_str(&e.to_string()))?;
+                Patterns::Glob(set)
+            }
+            PatternSyntax::GitAttributes => patterns
+                .iter()
+                .map(|pattern| {
+                    if options.literal {
+                        AttrPattern::new(&wildmatch::escape(pattern), options.case_insensitive)
+                    } else {
+                        AttrPattern::new(pattern, options.case_insensitive)
+                    }
+                })
+                .collect::<Result<_, _>>()
+                .map(Patterns::GitAttributes)?,
+        };
+
+        let excludes = if options.excludes.is_empty() {
+            None
+        } else {
+            let excludes: Vec<&str> = options.excludes.iter().map(String::as_str).collect();
+            let options = FilterOptions {
+                match_directories: true,
+                excludes: Vec::new(),
+                ..options.clone()
+            };
+            Some(Box::new(Matcher::new(&excludes, &options)?))
+        };
+
+        Ok(Matcher {
+            patterns,
+            sources: sources.iter().map(|s| s.to_string()).collect(),
+            match_directories: options.match_directories,
+            excludes,
+        })
+    }
+
+    /// Returns `true` if the entry at `path` is dropped by an exclude pattern.
+    fn excludes(&self, path: &[u8], is_dir: bool) -> bool {
+        self.excludes
+            .as_ref()
+            .is_some_and(|excludes| excludes.matches(path, is_dir, None))
+    }
+
+    /// Returns `true` if the file at `path` matches any pattern, recording every matching
+    /// pattern in `hits` when given.
+    fn matches_file(&self, path: &[u8], hits: Option<&mut [bool]>) -> bool {
+        self.matches(path, false, hits)
+    }
+
+    /// Returns `true` if everything inside the directory at `path` should be kept.
+    fn includes_dir(&self, path: &[u8], hits: Option<&mut [bool]>) -> bool {
+        self.match_directories && self.matches(path, true, hits)
+    }
+
+    fn matches(&self, path: &[u8], is_dir: bool, mut hits: Option<&mut [bool]>) -> bool {
+        match &self.patterns {
+            Patterns::Glob(set) => {
+                let path = glob_path(path);
+                let candidate = Candidate::new(path.as_ref());
+                match hits {
+                    Some(hits) => {
+                        let matches = set.matches_candidate(&candidate);
+                        for &i in &matches {
+                            hits[i] = true;
+                        }
+                        !matches.is_empty()
+                    }
+                    None => set.is_match_candidate(&candidate),
+                }
+            }
+            Patterns::GitAttributes(patterns) => {
+                let mut matched = false;
+                for (i, pattern) in patterns.iter().enumerate() {
+                    let is_match = if is_dir {
+                        pattern.matches_dir(path)
+                    } else {
+                        pattern.matches_file(path)
+                    };
+                    if is_match {
+                        matched = true;
+                        match hits.as_deref_mut() {
+                            Some(hits) => hits[i] = true,
+                            None => break,
+                        }
+                    }
+                }
+                matched
+            }
         }
+    }
+}
 
-        let matcher = glob_builder
-            .build()
-            .map_err(|e| Error::from_str(&e.to_string()))?;
+/// Returns the tree path `path` as a [`Path`] for glob matching. Tree paths are bytes, which
+/// are used unchanged on Unix; elsewhere non-UTF-8 bytes are replaced.
+#[cfg(unix)]
+fn glob_path(path: &[u8]) -> Cow<'_, Path> {
+    use std::os::unix::ffi::OsStrExt;
+    Cow::Borrowed(Path::new(std::ffi::OsStr::from_bytes(path)))
+}
 
-        // Recursively filter the tree
-        filter_tree_recursive(self, tree, "", &matcher)
+#[cfg(not(unix))]
+fn glob_path(path: &[u8]) -> Cow<'_, Path> {
+    match String::from_utf8_lossy(path) {
+        Cow::Borrowed(path) => Cow::Borrowed(Path::new(path)),
+        Cow::Owned(path) => Cow::Owned(path.into()),
+    }
+}
+
+/// Joins `name` to the directory path `prefix`.
+fn join_path(prefix: &[u8], name: &[u8]) -> Vec<u8> {
+    if prefix.is_empty() {
+        return name.to_vec();
     }
+    let mut path = Vec::with_capacity(prefix.len() + 1 + name.len());
+    path.extend_from_slice(prefix);
+    path.push(b'/');
+    path.extend_from_slice(name);
+    path
 }
 
-/// Recursively filters a tree, matching patterns against full paths.
-/// Returns a new tree containing only entries that match or have matching descendants.
-fn filter_tree_recursive<'a>(
+/// Splits a slash-separated directory path into its non-empty components.
+fn path_components(path: &str) -> impl DoubleEndedIterator<Item = &str> {
+    path.split('/').filter(|c| !c.is_empty() && *c != ".")
+}
+
+/// Returns the subtree of `tree` at `path`, or an empty tree if there is no directory there.
+fn subtree_at<'a>(
     repo: &'a Repository,
-    tree: &'a git2::Tree<'a>,
-    prefix: &str,
-    matcher: &GlobSet,
+    tree: git2::Tree<'a>,
+    path: &str,
 ) -> Result<git2::Tree<'a>, Error> {
-    let mut builder = repo.treebuilder(None)?;
+    let mut current = tree;
+    for component in path_components(path) {
+        let next = match current.get_name(component) {
+            Some(entry) if entry.kind() == Some(git2::ObjectType::Tree) => entry.id(),
+            _ => {
+                let empty = repo.treebuilder(None)?.write()?;
+                return repo.find_tree(empty);
+            }
+        };
+        current = repo.find_tree(next)?;
+    }
+    Ok(current)
+}
 
-    for entry in tree.iter() {
-        let name = entry.name().unwrap_or("");
-        let full_path = if prefix.is_empty() {
-            name.to_string()
+/// Wraps `tree` in one directory level per component of `path`.
+fn nest_under<'a>(
+    repo: &'a Repository,
+    tree: git2::Tree<'a>,
+    path: &str,
+) -> Result<git2::Tree<'a>, Error> {
+    if tree.is_empty() {
+        return Ok(tree);
+    }
+
+    let mut oid = tree.id();
+    for component in path_components(path).rev() {
+        let mut builder = repo.treebuilder(None)?;
+        builder.insert(component, oid, 0o040000)?;
+        oid = builder.write()?;
+    }
+    repo.find_tree(oid)
+}
+
+/// Counts gathered while filtering a tree, as returned by
+/// [`FilterTree::filter_by_patterns_with_stats`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct FilterStats {
+    /// Number of files kept in the filtered tree.
+    pub matched_files: usize,
+    /// Number of files left out of the filtered tree.
+    pub skipped_files: usize,
+    /// Total size in bytes of the files kept (before any transformation).
+    pub total_bytes: u64,
+    /// Patterns that matched no file (or, with `match_directories`, no directory).
+    pub unmatched_patterns: Vec<String>,
+    /// Paths of the submodule (gitlink) entries matched, whether kept or skipped.
+    pub gitlinks: Vec<String>,
+    /// Paths of the subtrees left out because they could not be read, with
+    /// [`ErrorPolicy::Skip`].
+    pub skipped_subtrees: Vec<String>,
+}
+
+/// State carried through a recursive tree walk.
+struct Walk<'w, 't> {
+    matcher: &'w Matcher,
+    options: &'w FilterOptions,
+    /// The repository objects are read from, when it is not the one written to.
+    source: Option<&'w Repository>,
+    root: Option<git2::Oid>,
+    transform: Option<&'w mut BlobTransform<'t>>,
+    stats: Option<FilterStats>,
+    hits: Vec<bool>,
+    /// The [`cache::Key::filter`] of this walk, or `None` if results cannot be cached.
+    cache_key: Option<u64>,
+    /// How many directories below the root the tree being filtered is.
+    depth: usize,
+    /// Number of tree entries visited so far.
+    entries: usize,
+}
+
+impl<'w, 't> Walk<'w, 't> {
+    fn new(
+        matcher: &'w Matcher,
+        options: &'w FilterOptions,
+        transform: Option<&'w mut BlobTransform<'t>>,
+    ) -> Self {
+        Self {
+            matcher,
+            options,
+            source: None,
+            root: None,
+            transform,
+            stats: None,
+            hits: Vec::new(),
+            cache_key: None,
+            depth: 0,
+            entries: 0,
+        }
+    }
+
+    /// Reads the filtered tree and its objects from `source`, copying what the result needs
+    /// into the repository written to.
+    fn reading_from(mut self, source: &'w Repository) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    /// Enables statistics collection for this walk.
+    fn with_stats(mut self) -> Self {
+        self.stats = Some(FilterStats::default());
+        self.hits = vec![false; self.matcher.sources.len()];
+        self
+    }
+
+    /// Returns the collected statistics, if enabled.
+    fn finish(self) -> Option<FilterStats> {
+        let mut stats = self.stats?;
+        stats.unmatched_patterns = self
+            .matcher
+            .sources
+            .iter()
+            .zip(&self.hits)
+            .filter(|(_, hit)| !**hit)
+            .map(|(source, _)| source.clone())
+            .collect();
+        Some(stats)
+    }
+
+    fn hits(&mut self) -> Option<&mut [bool]> {
+        if self.stats.is_some() {
+            Some(&mut self.hits)
         } else {
-            format!("{}/{}", prefix, name)
+            None
+        }
+    }
+
+    /// Filters `tree` from its root.
+    fn run<'r>(
+        &mut self,
+        repo: &'r Repository,
+        tree: &git2::Tree<'_>,
+    ) -> Result<git2::Tree<'r>, Error> {
+        self.root = Some(tree.id());
+        // Transformed content depends on the callback, which cannot be compared, and skipped
+        // subtrees on which objects the repository has.
+        self.cache_key = (self.transform.is_none() && self.options.on_error == ErrorPolicy::Fail)
+            .then(|| self.filter_key(tree.id()));
+        self.filter_cached(repo, tree, b"", false)
+    }
+
+    /// Hashes everything besides the filtered tree that determines the result of the walk.
+    ///
+    /// The repository is not part of the key: results are content-addressed, and a cached
+    /// result is only reused when the repository has it, so repositories without a path (such
+    /// as in-memory ones) share the cache too.
+    fn filter_key(&self, root: git2::Oid) -> u64 {
+        let options = self.options;
+        let mut hasher = DefaultHasher::new();
+        self.matcher.sources.hash(&mut hasher);
+        options.syntax.hash(&mut hasher);
+        options.case_insensitive.hash(&mut hasher);
+        options.literal.hash(&mut hasher);
+        options.match_directories.hash(&mut hasher);
+        options.submodules.hash(&mut hasher);
+        options.symlinks.hash(&mut hasher);
+        options.normalize_executable.hash(&mut hasher);
+        options.excludes.hash(&mut hasher);
+        options.keep_dirs.hash(&mut hasher);
+        options.max_depth.hash(&mut hasher);
+        options.max_entries.hash(&mut hasher);
+        options.on_error.hash(&mut hasher);
+        self.stats.is_some().hash(&mut hasher);
+        // Materialized symlinks are resolved from the root of the walk.
+        if options.symlinks == SymlinkPolicy::Materialize {
+            root.hash(&mut hasher);
+        }
+        hasher.finish()
+    }
+
+    /// Filters `tree` like [`filter`](Self::filter), reusing the result of an earlier walk
+    /// of the same tree at the same path with the same patterns and options.
+    fn filter_cached<'r>(
+        &mut self,
+        repo: &'r Repository,
+        tree: &git2::Tree<'_>,
+        prefix: &[u8],
+        include_all: bool,
+    ) -> Result<git2::Tree<'r>, Error> {
+        let Some(filter) = self.cache_key else {
+            return self.filter(repo, tree, prefix, include_all);
+        };
+        let key = cache::Key {
+            tree: tree.id(),
+            prefix: prefix.to_vec(),
+            include_all,
+            filter,
+        };
+        if let Some(entry) = cache::get(&key)
+            && let Ok(filtered) = repo.find_tree(entry.tree)
+        {
+            // The skipped entries still count towards the limit of this walk.
+            self.count_entries(entry.entries)?;
+            self.absorb(entry.stats);
+            return Ok(filtered);
+        }
+
+        // Collect the statistics of this tree alone, so they can be replayed on a cache hit.
+        let outer = self.stats.as_mut().map(|stats| {
+            let hits = vec![false; self.hits.len()];
+            (
+                std::mem::take(stats),
+                std::mem::replace(&mut self.hits, hits),
+            )
+        });
+        let entries = self.entries;
+        let result = self.filter(repo, tree, prefix, include_all);
+        let entries = self.entries - entries;
+        let own = outer.map(|(stats, hits)| {
+            let own = self.stats.replace(stats).unwrap_or_default();
+            (own, std::mem::replace(&mut self.hits, hits))
+        });
+
+        let filtered = result?;
+        self.absorb(own.clone());
+        cache::insert(
+            key,
+            cache::Entry {
+                tree: filtered.id(),
+                entries,
+                stats: own,
+            },
+        );
+        Ok(filtered)
+    }
+
+    /// Counts `n` more visited tree entries, failing past [`FilterOptions::max_entries`].
+    fn count_entries(&mut self, n: usize) -> Result<(), Error> {
+        self.entries += n;
+        match self.options.max_entries {
+            Some(max) if self.entries > max => Err(policy_error(format!(
+                "Tree has more than {} entries, the configured limit",
+                max
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Adds the statistics of a filtered subtree to those of the walk.
+    fn absorb(&mut self, subtree: Option<(FilterStats, Vec<bool>)>) {
+        let (Some(stats), Some((subtree, hits))) = (self.stats.as_mut(), subtree) else {
+            return;
+        };
+        stats.matched_files += subtree.matched_files;
+        stats.skipped_files += subtree.skipped_files;
+        stats.total_bytes += subtree.total_bytes;
+        stats.gitlinks.extend(subtree.gitlinks);
+        stats.skipped_subtrees.extend(subtree.skipped_subtrees);
+        for (hit, subtree_hit) in self.hits.iter_mut().zip(hits) {
+            *hit |= subtree_hit;
+        }
+    }
+
+    /// Returns `true` if matched subtrees can be copied without visiting their entries.
+    fn copies_subtrees(&self) -> bool {
+        self.transform.is_none()
+            && self.stats.is_none()
+            && self.options.submodules == SubmodulePolicy::Keep
+            && self.options.symlinks == SymlinkPolicy::Preserve
+            && !self.options.normalize_executable
+            && self.options.excludes.is_empty()
+    }
+
+    /// Returns the blob and mode to store for the matched blob `entry` at `path`.
+    fn blob_entry(
+        &self,
+        repo: &Repository,
+        entry: &git2::TreeEntry<'_>,
+        path: &[u8],
+    ) -> Result<(git2::Oid, i32), Error> {
+        let mut id = entry.id();
+        let mut mode = entry.filemode();
+
+        if mode == 0o120000 {
+            match self.options.symlinks {
+                SymlinkPolicy::Preserve => {}
+                SymlinkPolicy::Reject => {
+                    return Err(policy_error(format!(
+                        "Symlink at '{}' matches the filter patterns",
+                        String::from_utf8_lossy(path)
+                    )));
+                }
+                SymlinkPolicy::Materialize => {
+                    (id, mode) = self.resolve_symlink(repo, id, path)?;
+                }
+            }
+        }
+
+        if self.options.normalize_executable && mode == 0o100755 {
+            mode = 0o100644;
+        }
+
+        Ok((id, mode))
+    }
+
+    /// Follows the symlink blob `id` at `path` to the regular file it points to.
+    fn resolve_symlink(
+        &self,
+        repo: &Repository,
+        mut id: git2::Oid,
+        path: &[u8],
+    ) -> Result<(git2::Oid, i32), Error> {
+        let repo = self.source.unwrap_or(repo);
+        let root = repo.find_tree(self.root.expect("walk started with run()"))?;
+        let mut link_path = path.to_vec();
+        let cannot = |why: &str| {
+            policy_error(format!(
+                "Cannot materialize symlink at '{}': {}",
+                String::from_utf8_lossy(path),
+                why
+            ))
         };
 
-        match entry.kind() {
-            Some(git2::ObjectType::Blob) => {
-                // Check if this file matches the pattern
-                if matcher.is_match(&full_path) {
-                    builder.insert(name, entry.id(), entry.filemode())?;
+        // Follow chains of links, giving up on cycles like Git's MAXSYMLINKS.
+        for _ in 0..40 {
+            let blob = repo.find_blob(id)?;
+            let target = blob.content();
+
+            if target.starts_with(b"/") {
+                return Err(cannot("target is an absolute path"));
+            }
+
+            let mut components: Vec<&[u8]> = link_path.split(|&b| b == b'/').collect();
+            components.pop();
+            for component in target.split(|&b| b == b'/') {
+                match component {
+                    b"" | b"." => {}
+                    b".." => {
+                        if components.pop().is_none() {
+                            return Err(cannot("target is outside the tree"));
+                        }
+                    }
+                    c => components.push(c),
                 }
             }
-            Some(git2::ObjectType::Tree) => {
-                // Recursively filter the subtree
-                let subtree = entry.to_object(repo)?.peel_to_tree()?;
-                match filter_tree_recursive(repo, &subtree, &full_path, matcher) {
-                    Ok(filtered_subtree) => {
-                        // Only include the subtree if it has matching entries
-                        if !filtered_subtree.is_empty() {
-                            builder.insert(name, filtered_subtree.id(), entry.filemode())?;
+            let resolved = components.join(&b'/');
+
+            let entry = root
+                .get_path(&glob_path(&resolved))
+                .map_err(|_| cannot("target does not exist"))?;
+            match entry.filemode() {
+                0o120000 => {
+                    id = entry.id();
+                    link_path = resolved;
+                }
+                0o100644 | 0o100755 => return Ok((entry.id(), entry.filemode())),
+                _ => return Err(cannot("target is not a regular file")),
+            }
+        }
+
+        Err(cannot("too many levels of symbolic links"))
+    }
+
+    /// Recursively filters a tree, matching patterns against full paths.
+    /// Returns a new tree containing only entries that match or have matching descendants.
+    /// When `include_all` is set, every entry is kept (the tree lies in a matched directory).
+    fn filter<'r>(
+        &mut self,
+        repo: &'r Repository,
+        tree: &git2::Tree<'_>,
+        prefix: &[u8],
+        include_all: bool,
+    ) -> Result<git2::Tree<'r>, Error> {
+        let mut builder = repo.treebuilder(None)?;
+        let source = self.source.unwrap_or(repo);
+
+        for entry in tree.iter() {
+            self.count_entries(1)?;
+
+            // Names are bytes and need not be UTF-8.
+            let name = entry.name_bytes();
+            let full_path = join_path(prefix, name);
+
+            match entry.kind() {
+                Some(git2::ObjectType::Blob) => {
+                    // Check if this file matches the pattern
+                    let matcher = self.matcher;
+                    let matched = (matcher.matches_file(&full_path, self.hits()) || include_all)
+                        && !matcher.excludes(&full_path, false);
+                    if let Some(stats) = self.stats.as_mut() {
+                        if matched {
+                            stats.matched_files += 1;
+                            stats.total_bytes += source.odb()?.read_header(entry.id())?.0 as u64;
+                        } else {
+                            stats.skipped_files += 1;
                         }
                     }
-                    Err(_) => {
-                        // Skip subtrees that cause errors
+
+                    if matched {
+                        let (mut id, mode) = self.blob_entry(repo, &entry, &full_path)?;
+                        if let Some(transform) = self.transform.as_deref_mut() {
+                            let blob = source.find_blob(id)?;
+                            let path = String::from_utf8_lossy(&full_path);
+                            if let Some(content) = transform(&path, blob.content()) {
+                                id = repo.blob(&content)?;
+                            }
+                        }
+                        if let Some(source) = self.source {
+                            copy_objects(source, repo, id)?;
+                        }
+                        builder.insert(name, id, mode)?;
+                    }
+                }
+                Some(git2::ObjectType::Tree) => {
+                    let matcher = self.matcher;
+                    if matcher.excludes(&full_path, true) {
                         continue;
                     }
+                    let include_all = matcher.includes_dir(&full_path, self.hits()) || include_all;
+                    if include_all && self.copies_subtrees() {
+                        if let Some(source) = self.source {
+                            copy_objects(source, repo, entry.id())?;
+                        }
+                        builder.insert(name, entry.id(), entry.filemode())?;
+                        continue;
+                    }
+
+                    // Recursively filter the subtree
+                    let max_depth = self.options.max_depth.unwrap_or(DEFAULT_MAX_DEPTH);
+                    if self.depth >= max_depth {
+                        return Err(policy_error(format!(
+                            "Tree at '{}' is nested more than {} directories deep",
+                            String::from_utf8_lossy(&full_path),
+                            max_depth
+                        )));
+                    }
+                    self.depth += 1;
+                    let result = source
+                        .find_tree(entry.id())
+                        .map_err(|e| {
+                            Error::new(
+                                e.code(),
+                                e.class(),
+                                format!(
+                                    "Failed to read tree at '{}': {}",
+                                    String::from_utf8_lossy(&full_path),
+                                    e.message()
+                                ),
+                            )
+                        })
+                        .and_then(|subtree| {
+                            self.filter_cached(repo, &subtree, &full_path, include_all)
+                        });
+                    self.depth -= 1;
+                    match result {
+                        Ok(filtered_subtree) => {
+                            // Only include the subtree if it has matching entries
+                            if !filtered_subtree.is_empty() {
+                                builder.insert(name, filtered_subtree.id(), entry.filemode())?;
+                            }
+                        }
+                        // Policy violations are reported, not skipped
+                        Err(e) if e.class() == ErrorClass::Filter => return Err(e),
+                        Err(e) => match self.options.on_error {
+                            ErrorPolicy::Fail => return Err(e),
+                            ErrorPolicy::Skip => {
+                                if let Some(stats) = self.stats.as_mut() {
+                                    stats
+                                        .skipped_subtrees
+                                        .push(String::from_utf8_lossy(&full_path).into_owned());
+                                }
+                            }
+                        },
+                    }
+                }
+                Some(git2::ObjectType::Commit) => {
+                    // A submodule gitlink
+                    let matcher = self.matcher;
+                    if !(matcher.matches_file(&full_path, self.hits()) || include_all)
+                        || matcher.excludes(&full_path, false)
+                    {
+                        continue;
+                    }
+                    if let Some(stats) = self.stats.as_mut() {
+                        stats
+                            .gitlinks
+                            .push(String::from_utf8_lossy(&full_path).into_owned());
+                    }
+
+                    match self.options.submodules {
+                        SubmodulePolicy::Skip => continue,
+                        SubmodulePolicy::Keep => {
+                            builder.insert(name, entry.id(), entry.filemode())?;
+                        }
+                        SubmodulePolicy::Error => {
+                            return Err(policy_error(format!(
+                                "Submodule at '{}' matches the filter patterns",
+                                String::from_utf8_lossy(&full_path)
+                            )));
+                        }
+                    }
+                }
+                _ => {
+                    // Skip other object types
+                    continue;
                 }
-            }
-            _ => {
-                // Skip other object types (commits, tags, etc.)
-                continue;
             }
         }
+
+        if builder.is_empty() && !prefix.is_empty() && self.keeps_dir(prefix) {
+            builder.insert(KEEP_FILE, repo.blob(b"")?, 0o100644)?;
+        }
+
+        let tree_oid = builder.write()?;
+        repo.find_tree(tree_oid)
     }
 
-    let tree_oid = builder.write()?;
-    repo.find_tree(tree_oid)
+    /// Returns `true` if the directory at `path` is one of [`FilterOptions::keep_dirs`].
+    fn keeps_dir(&self, path: &[u8]) -> bool {
+        self.options
+            .keep_dirs
+            .iter()
+            .any(|dir| dir.trim_matches('/').as_bytes() == path)
+    }
+}
+
+/// The file added to otherwise empty [kept directories](FilterOptions::keep_dirs).
+const KEEP_FILE: &str = ".gitkeep";
+
+/// Copies the object `id` from `source` into `dest`, with everything it contains if it is a
+/// tree, skipping objects `dest` already has. Submodule commits are not copied.
+///
+/// Trees are written after everything they contain, so a tree in `dest` is always complete,
+/// even if an earlier copy failed partway.
+fn copy_objects(source: &Repository, dest: &Repository, id: git2::Oid) -> Result<(), Error> {
+    let source_odb = source.odb()?;
+    let dest_odb = dest.odb()?;
+    // Each tree is visited twice: first to queue its entries, then to write it.
+    let mut pending = vec![(id, false)];
+    while let Some((id, expanded)) = pending.pop() {
+        if dest_odb.exists(id) {
+            continue;
+        }
+        let (_, kind) = source_odb.read_header(id)?;
+        if kind == git2::ObjectType::Tree && !expanded {
+            pending.push((id, true));
+            let tree = source.find_tree(id)?;
+            pending.extend(
+                tree.iter()
+                    .filter(|entry| entry.kind() != Some(git2::ObjectType::Commit))
+                    .map(|entry| (entry.id(), false)),
+            );
+            continue;
+        }
+        let object = source_odb.read(id)?;
+        dest_odb.write(object.kind(), object.data())?;
+    }
+    Ok(())
+}
+
+/// Builds an error for a rejected entry or an exceeded limit. These are propagated out of
+/// subtrees rather than skipped.
+fn policy_error(message: String) -> Error {
+    Error::new(ErrorCode::GenericError, ErrorClass::Filter, message)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::fs;
-    use std::path::PathBuf;
+    use tempfile::TempDir;
 
-    fn setup_test_repo() -> (Repository, PathBuf) {
-        let thread_id = std::thread::current().id();
-        let temp_path = std::env::temp_dir().join(format!("git-filter-tree-test-{:?}", thread_id));
-        let _ = fs::remove_dir_all(&temp_path);
-        fs::create_dir_all(&temp_path).unwrap();
-        let repo = Repository::init_bare(&temp_path).unwrap();
-        (repo, temp_path)
-    }
-
-    fn cleanup_test_repo(path: PathBuf) {
-        let _ = fs::remove_dir_all(path);
+    fn setup_test_repo() -> (Repository, TempDir) {
+        let dir = TempDir::new().unwrap();
+        let repo = Repository::init_bare(dir.path()).unwrap();
+        (repo, dir)
     }
 
     fn create_test_tree<'a>(repo: &'a Repository) -> Result<git2::Tree<'a>, Error> {
@@ -130,9 +1069,56 @@ mod tests {
         repo.find_tree(tree_oid)
     }
 
+    #[test]
+    fn test_copy_objects_never_leaves_incomplete_trees() -> Result<(), Error> {
+        let (repo, _dir) = setup_test_repo();
+        let blob = repo.blob(b"copied")?;
+        let mut sub = repo.treebuilder(None)?;
+        sub.insert("file.txt", blob, 0o100644)?;
+        let sub = sub.write()?;
+        let mut root = repo.treebuilder(None)?;
+        root.insert("sub", sub, 0o040000)?;
+        let root = root.write()?;
+
+        // The source has both trees but not the blob, so copying fails partway.
+        let (source, _source_dir) = setup_test_repo();
+        let odb = repo.odb()?;
+        for id in [root, sub] {
+            let object = odb.read(id)?;
+            source.odb()?.write(object.kind(), object.data())?;
+        }
+        let (dest, _dest_dir) = setup_test_repo();
+        assert!(copy_objects(&source, &dest, root).is_err());
+        assert!(!dest.odb()?.exists(root));
+        assert!(!dest.odb()?.exists(sub));
+
+        source.blob(b"copied")?;
+        copy_objects(&source, &dest, root)?;
+        for id in [root, sub, blob] {
+            assert!(dest.odb()?.exists(id));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter_in_memory_repositories() -> Result<(), Error> {
+        // Each filter writes into its own object database; the second repository must not
+        // be handed a cached tree that only the first one has.
+        for _ in 0..2 {
+            let odb = git2::Odb::new()?;
+            odb.add_new_mempack_backend(1)?;
+            let repo = Repository::from_odb(odb)?;
+            let tree = create_test_tree(&repo)?;
+            let filtered = repo.filter_by_patterns(&tree, &["*.rs"])?;
+            assert_eq!(filtered.len(), 1);
+            assert!(filtered.get_name("file2.rs").is_some());
+        }
+        Ok(())
+    }
+
     #[test]
     fn test_filter_single_pattern() -> Result<(), Error> {
-        let (repo, temp_path) = setup_test_repo();
+        let (repo, _dir) = setup_test_repo();
 
         let tree = create_test_tree(&repo)?;
         assert_eq!(tree.len(), 3);
@@ -143,14 +1129,170 @@ mod tests {
         assert!(filtered.get_name("file1.txt").is_some());
         assert!(filtered.get_name("file2.rs").is_none());
         assert!(filtered.get_name("test.md").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter_keeps_non_utf8_names() -> Result<(), Error> {
+        let (repo, _dir) = setup_test_repo();
+
+        let blob = repo.blob(b"content")?;
+        let mut sub = repo.treebuilder(None)?;
+        sub.insert(&b"caf\xe9.txt"[..], blob, 0o100644)?;
+        sub.insert(&b"caf\xe9.rs"[..], blob, 0o100644)?;
+        let sub = sub.write()?;
+        let mut root = repo.treebuilder(None)?;
+        root.insert(&b"d\xfcr"[..], sub, 0o040000)?;
+        let tree = repo.find_tree(root.write()?)?;
+
+        for syntax in [PatternSyntax::Glob, PatternSyntax::GitAttributes] {
+            let options = FilterOptions {
+                syntax,
+                ..Default::default()
+            };
+            let filtered = repo.filter_with_options(&tree, &["*.txt"], &options)?;
+            let dir = filtered.get_name_bytes(b"d\xfcr").expect("directory kept");
+            let dir = repo.find_tree(dir.id())?;
+            assert_eq!(dir.len(), 1);
+            assert!(dir.get_name_bytes(b"caf\xe9.txt").is_some());
+        }
+        Ok(())
+    }
+
+    /// Builds a tree holding `file.txt` inside `depth` nested `d` directories.
+    fn create_deep_tree<'a>(repo: &'a Repository, depth: usize) -> Result<git2::Tree<'a>, Error> {
+        let mut builder = repo.treebuilder(None)?;
+        builder.insert("file.txt", repo.blob(b"deep")?, 0o100644)?;
+        let mut oid = builder.write()?;
+        for _ in 0..depth {
+            let mut builder = repo.treebuilder(None)?;
+            builder.insert("d", oid, 0o040000)?;
+            oid = builder.write()?;
+        }
+        repo.find_tree(oid)
+    }
 
-        cleanup_test_repo(temp_path);
+    #[test]
+    fn test_filter_limits_depth() -> Result<(), Error> {
+        let (repo, _dir) = setup_test_repo();
+
+        let tree = create_deep_tree(&repo, 3)?;
+        let limited = |max_depth| FilterOptions {
+            max_depth: Some(max_depth),
+            ..Default::default()
+        };
+        let filtered = repo.filter_with_options(&tree, &["*.txt"], &limited(3))?;
+        assert!(filtered.get_path(Path::new("d/d/d/file.txt")).is_ok());
+        let err = repo
+            .filter_with_options(&tree, &["*.txt"], &limited(2))
+            .unwrap_err();
+        assert_eq!(err.class(), ErrorClass::Filter);
+        assert!(err.message().contains("'d/d/d'"), "{}", err.message());
+
+        // The default limit fails cleanly rather than exhausting the stack.
+        let tree = create_deep_tree(&repo, DEFAULT_MAX_DEPTH + 1)?;
+        assert!(repo.filter_by_patterns(&tree, &["*.txt"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter_limits_entries() -> Result<(), Error> {
+        let (repo, _dir) = setup_test_repo();
+
+        let tree = create_nested_tree(&repo)?;
+        let limited = |max_entries| FilterOptions {
+            max_entries: Some(max_entries),
+            ..Default::default()
+        };
+        assert!(
+            repo.filter_with_options(&tree, &["*.rs"], &limited(100))
+                .is_ok()
+        );
+        let err = repo
+            .filter_with_options(&tree, &["*.rs"], &limited(2))
+            .unwrap_err();
+        assert!(
+            err.message().contains("more than 2 entries"),
+            "{}",
+            err.message()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter_limits_entries_of_cached_subtrees() -> Result<(), Error> {
+        let (repo, _dir) = setup_test_repo();
+
+        let mut lib = repo.treebuilder(None)?;
+        for name in ["a.rs", "b.rs", "c.rs", "d.rs"] {
+            lib.insert(
+                name,
+                repo.blob(format!("cached {name}").as_bytes())?,
+                0o100644,
+            )?;
+        }
+        let lib = lib.write()?;
+        let mut small = repo.treebuilder(None)?;
+        small.insert("lib", lib, 0o040000)?;
+        let small = repo.find_tree(small.write()?)?;
+        let mut large = repo.treebuilder(None)?;
+        large.insert("lib", lib, 0o040000)?;
+        large.insert("x.txt", repo.blob(b"x")?, 0o100644)?;
+        large.insert("y.txt", repo.blob(b"y")?, 0o100644)?;
+        let large = repo.find_tree(large.write()?)?;
+
+        let options = FilterOptions {
+            max_entries: Some(6),
+            ..Default::default()
+        };
+        // Filtering the small tree caches `lib`, whose entries must still be counted when
+        // the large tree reuses it.
+        repo.filter_with_options(&small, &["*.rs"], &options)?;
+        let err = repo
+            .filter_with_options(&large, &["*.rs"], &options)
+            .unwrap_err();
+        assert!(
+            err.message().contains("more than 6 entries"),
+            "{}",
+            err.message()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter_unreadable_subtree() -> Result<(), Error> {
+        let (repo, dir) = setup_test_repo();
+
+        let blob = repo.blob(b"content")?;
+        let mut sub = repo.treebuilder(None)?;
+        sub.insert("lost.txt", blob, 0o100644)?;
+        let sub = sub.write()?;
+        let mut root = repo.treebuilder(None)?;
+        root.insert("file.txt", blob, 0o100644)?;
+        root.insert("lost", sub, 0o040000)?;
+        let tree = repo.find_tree(root.write()?)?;
+
+        // Remove the subtree object so that reading it fails.
+        let hex = sub.to_string();
+        fs::remove_file(dir.path().join("objects").join(&hex[..2]).join(&hex[2..])).unwrap();
+
+        let err = repo.filter_by_patterns(&tree, &["*.txt"]).unwrap_err();
+        assert!(err.message().contains("'lost'"), "{}", err.message());
+
+        let options = FilterOptions {
+            on_error: ErrorPolicy::Skip,
+            ..Default::default()
+        };
+        let (filtered, stats) = repo.filter_by_patterns_with_stats(&tree, &["*.txt"], &options)?;
+        assert!(filtered.get_name("file.txt").is_some());
+        assert!(filtered.get_name("lost").is_none());
+        assert_eq!(stats.skipped_subtrees, ["lost"]);
         Ok(())
     }
 
     #[test]
     fn test_filter_multiple_patterns() -> Result<(), Error> {
-        let (repo, temp_path) = setup_test_repo();
+        let (repo, _dir) = setup_test_repo();
 
         let tree = create_test_tree(&repo)?;
 
@@ -160,14 +1302,12 @@ mod tests {
         assert!(filtered.get_name("file1.txt").is_some());
         assert!(filtered.get_name("file2.rs").is_some());
         assert!(filtered.get_name("test.md").is_none());
-
-        cleanup_test_repo(temp_path);
         Ok(())
     }
 
     #[test]
     fn test_filter_exact_match() -> Result<(), Error> {
-        let (repo, temp_path) = setup_test_repo();
+        let (repo, _dir) = setup_test_repo();
 
         let tree = create_test_tree(&repo)?;
 
@@ -175,14 +1315,12 @@ mod tests {
         let filtered = repo.filter_by_patterns(&tree, &["file1.txt"])?;
         assert_eq!(filtered.len(), 1);
         assert!(filtered.get_name("file1.txt").is_some());
-
-        cleanup_test_repo(temp_path);
         Ok(())
     }
 
     #[test]
     fn test_filter_wildcard_patterns() -> Result<(), Error> {
-        let (repo, temp_path) = setup_test_repo();
+        let (repo, _dir) = setup_test_repo();
 
         let tree = create_test_tree(&repo)?;
 
@@ -192,42 +1330,36 @@ mod tests {
         assert!(filtered.get_name("file1.txt").is_some());
         assert!(filtered.get_name("file2.rs").is_some());
         assert!(filtered.get_name("test.md").is_none());
-
-        cleanup_test_repo(temp_path);
         Ok(())
     }
 
     #[test]
     fn test_filter_no_matches() -> Result<(), Error> {
-        let (repo, temp_path) = setup_test_repo();
+        let (repo, _dir) = setup_test_repo();
 
         let tree = create_test_tree(&repo)?;
 
         // Filter with pattern that matches nothing
         let filtered = repo.filter_by_patterns(&tree, &["*.nonexistent"])?;
         assert_eq!(filtered.len(), 0);
-
-        cleanup_test_repo(temp_path);
         Ok(())
     }
 
     #[test]
     fn test_filter_all_matches() -> Result<(), Error> {
-        let (repo, temp_path) = setup_test_repo();
+        let (repo, _dir) = setup_test_repo();
 
         let tree = create_test_tree(&repo)?;
 
         // Filter with pattern that matches everything
         let filtered = repo.filter_by_patterns(&tree, &["*"])?;
         assert_eq!(filtered.len(), 3);
-
-        cleanup_test_repo(temp_path);
         Ok(())
     }
 
     #[test]
     fn test_filter_empty_patterns_error() {
-        let (repo, temp_path) = setup_test_repo();
+        let (repo, _dir) = setup_test_repo();
 
         let tree = create_test_tree(&repo).unwrap();
 
@@ -238,26 +1370,22 @@ mod tests {
             result.unwrap_err().message(),
             "At least one pattern is required"
         );
-
-        cleanup_test_repo(temp_path);
     }
 
     #[test]
     fn test_filter_invalid_pattern_error() {
-        let (repo, temp_path) = setup_test_repo();
+        let (repo, _dir) = setup_test_repo();
 
         let tree = create_test_tree(&repo).unwrap();
 
         // Invalid glob pattern should return an error
         let result = repo.filter_by_patterns(&tree, &["[invalid"]);
         assert!(result.is_err());
-
-        cleanup_test_repo(temp_path);
     }
 
     #[test]
     fn test_filter_with_nested_tree() -> Result<(), Error> {
-        let (repo, temp_path) = setup_test_repo();
+        let (repo, _dir) = setup_test_repo();
 
         let mut tree_builder = repo.treebuilder(None)?;
 
@@ -278,14 +1406,12 @@ mod tests {
         // Filter - should keep both file and directory
         let filtered = repo.filter_by_patterns(&tree, &["*"])?;
         assert_eq!(filtered.len(), 2);
-
-        cleanup_test_repo(temp_path);
         Ok(())
     }
 
     #[test]
     fn test_filter_preserves_empty_tree() -> Result<(), Error> {
-        let (repo, temp_path) = setup_test_repo();
+        let (repo, _dir) = setup_test_repo();
 
         // Create an empty tree
         let tree_builder = repo.treebuilder(None)?;
@@ -297,14 +1423,12 @@ mod tests {
         // Filter empty tree
         let filtered = repo.filter_by_patterns(&tree, &["*"])?;
         assert_eq!(filtered.len(), 0);
-
-        cleanup_test_repo(temp_path);
         Ok(())
     }
 
     #[test]
     fn test_filter_case_sensitive() -> Result<(), Error> {
-        let (repo, temp_path) = setup_test_repo();
+        let (repo, _dir) = setup_test_repo();
 
         let mut tree_builder = repo.treebuilder(None)?;
         let blob1 = repo.blob(b"content1")?;
@@ -320,14 +1444,12 @@ mod tests {
         let filtered = repo.filter_by_patterns(&tree, &["file.txt"])?;
         assert_eq!(filtered.len(), 1);
         assert!(filtered.get_name("file.txt").is_some());
-
-        cleanup_test_repo(temp_path);
         Ok(())
     }
 
     #[test]
     fn test_filter_complex_patterns() -> Result<(), Error> {
-        let (repo, temp_path) = setup_test_repo();
+        let (repo, _dir) = setup_test_repo();
 
         let mut tree_builder = repo.treebuilder(None)?;
         let blob = repo.blob(b"content")?;
@@ -347,8 +1469,659 @@ mod tests {
         assert!(filtered.get_name("test2.rs").is_some());
         assert!(filtered.get_name("README.md").is_some());
         assert!(filtered.get_name("data.json").is_none());
+        Ok(())
+    }
+
+    fn create_nested_tree<'a>(repo: &'a Repository) -> Result<git2::Tree<'a>, Error> {
+        // lib/src/{a.rs, util/b.rs}, lib/README.md, top.txt
+        let blob = repo.blob(b"content")?;
+
+        let mut util = repo.treebuilder(None)?;
+        util.insert("b.rs", blob, 0o100644)?;
+        let util_oid = util.write()?;
+
+        let mut src = repo.treebuilder(None)?;
+        src.insert("a.rs", blob, 0o100644)?;
+        src.insert("util", util_oid, 0o040000)?;
+        let src_oid = src.write()?;
+
+        let mut lib = repo.treebuilder(None)?;
+        lib.insert("src", src_oid, 0o040000)?;
+        lib.insert("README.md", blob, 0o100644)?;
+        let lib_oid = lib.write()?;
+
+        let mut root = repo.treebuilder(None)?;
+        root.insert("lib", lib_oid, 0o040000)?;
+        root.insert("top.txt", blob, 0o100644)?;
+        let root_oid = root.write()?;
+
+        repo.find_tree(root_oid)
+    }
+
+    #[test]
+    fn test_filter_gitattributes_syntax() -> Result<(), Error> {
+        let (repo, _dir) = setup_test_repo();
+
+        let tree = create_nested_tree(&repo)?;
+        let options = FilterOptions {
+            syntax: PatternSyntax::GitAttributes,
+            ..Default::default()
+        };
+
+        // "*" does not cross directory boundaries in anchored patterns.
+        let filtered = repo.filter_with_options(&tree, &["lib/src/*"], &options)?;
+        assert!(
+            filtered
+                .get_path(std::path::Path::new("lib/src/a.rs"))
+                .is_ok()
+        );
+        assert!(
+            filtered
+                .get_path(std::path::Path::new("lib/src/util/b.rs"))
+                .is_err()
+        );
+
+        // Patterns without a slash match basenames at any depth.
+        let filtered = repo.filter_with_options(&tree, &["b.rs"], &options)?;
+        assert!(
+            filtered
+                .get_path(std::path::Path::new("lib/src/util/b.rs"))
+                .is_ok()
+        );
+
+        // A leading slash anchors to the root.
+        let filtered = repo.filter_with_options(&tree, &["/README.md"], &options)?;
+        assert_eq!(filtered.len(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter_case_insensitive_option() -> Result<(), Error> {
+        let (repo, _dir) = setup_test_repo();
+
+        let tree = create_test_tree(&repo)?;
+        let options = FilterOptions {
+            case_insensitive: true,
+            ..Default::default()
+        };
+
+        let filtered = repo.filter_with_options(&tree, &["FILE*.TXT"], &options)?;
+        assert_eq!(filtered.len(), 1);
+        assert!(filtered.get_name("file1.txt").is_some());
+
+        let options = FilterOptions {
+            syntax: PatternSyntax::GitAttributes,
+            ..options
+        };
+        let filtered = repo.filter_with_options(&tree, &["*.MD"], &options)?;
+        assert!(filtered.get_name("test.md").is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter_literal_option() -> Result<(), Error> {
+        let (repo, _dir) = setup_test_repo();
+
+        let mut tree_builder = repo.treebuilder(None)?;
+        let blob = repo.blob(b"content")?;
+        tree_builder.insert("a*.txt", blob, 0o100644)?;
+        tree_builder.insert("ab.txt", blob, 0o100644)?;
+        let tree = repo.find_tree(tree_builder.write()?)?;
+
+        for syntax in [PatternSyntax::Glob, PatternSyntax::GitAttributes] {
+            let options = FilterOptions {
+                syntax,
+                literal: true,
+                ..Default::default()
+            };
+            let filtered = repo.filter_with_options(&tree, &["a*.txt"], &options)?;
+            assert_eq!(filtered.len(), 1);
+            assert!(filtered.get_name("a*.txt").is_some());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter_match_directories_option() -> Result<(), Error> {
+        let (repo, _dir) = setup_test_repo();
+
+        let tree = create_nested_tree(&repo)?;
+
+        // By default a directory pattern matches no files.
+        let filtered = repo.filter_by_patterns(&tree, &["lib/src"])?;
+        assert_eq!(filtered.len(), 0);
+
+        for syntax in [PatternSyntax::Glob, PatternSyntax::GitAttributes] {
+            let options = FilterOptions {
+                syntax,
+                match_directories: true,
+                ..Default::default()
+            };
+            let filtered = repo.filter_with_options(&tree, &["lib/src"], &options)?;
+            assert!(
+                filtered
+                    .get_path(std::path::Path::new("lib/src/util/b.rs"))
+                    .is_ok()
+            );
+            assert!(
+                filtered
+                    .get_path(std::path::Path::new("lib/README.md"))
+                    .is_err()
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter_excludes() -> Result<(), Error> {
+        let (repo, _dir) = setup_test_repo();
+
+        let tree = create_nested_tree(&repo)?;
+
+        for syntax in [PatternSyntax::Glob, PatternSyntax::GitAttributes] {
+            let options = FilterOptions {
+                syntax,
+                match_directories: true,
+                excludes: vec!["lib/src/util".to_string(), "*.md".to_string()],
+                ..Default::default()
+            };
+            let filtered = repo.filter_with_options(&tree, &["lib"], &options)?;
+            assert!(
+                filtered
+                    .get_path(std::path::Path::new("lib/src/a.rs"))
+                    .is_ok()
+            );
+            assert!(
+                filtered
+                    .get_path(std::path::Path::new("lib/src/util"))
+                    .is_err()
+            );
+            assert!(
+                filtered
+                    .get_path(std::path::Path::new("lib/README.md"))
+                    .is_err()
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter_keeps_configured_dirs() -> Result<(), Error> {
+        let (repo, _dir) = setup_test_repo();
+
+        let tree = create_nested_tree(&repo)?;
+        let options = FilterOptions {
+            syntax: PatternSyntax::GitAttributes,
+            keep_dirs: vec!["lib/src/util/".to_string(), "missing".to_string()],
+            ..Default::default()
+        };
+        let filtered = repo.filter_with_options(&tree, &["top.txt"], &options)?;
+
+        let keep = filtered.get_path(Path::new("lib/src/util/.gitkeep"))?;
+        assert_eq!(repo.find_blob(keep.id())?.content(), b"");
+        assert!(filtered.get_path(Path::new("lib/src/a.rs")).is_err());
+        assert!(filtered.get_path(Path::new("missing")).is_err());
+
+        // Directories with vendored content are left as they are.
+        let filtered = repo.filter_with_options(&tree, &["b.rs"], &options)?;
+        assert_eq!(
+            filtered
+                .get_path(Path::new("lib/src/util"))?
+                .to_object(&repo)?
+                .as_tree()
+                .unwrap()
+                .len(),
+            1
+        );
+        Ok(())
+    }
 
-        cleanup_test_repo(temp_path);
+    #[test]
+    fn test_filter_diff_reports_removed_and_kept() -> Result<(), Error> {
+        let (repo, _dir) = setup_test_repo();
+
+        let tree = create_nested_tree(&repo)?;
+        let options = FilterOptions {
+            syntax: PatternSyntax::GitAttributes,
+            excludes: vec!["b.rs".to_string()],
+            ..Default::default()
+        };
+        let diff = repo.filter_diff(&tree, &["lib/**"], &options)?;
+        let mut removed = Vec::new();
+        let mut kept = Vec::new();
+        for delta in diff.deltas() {
+            let path = delta
+                .old_file()
+                .path()
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_string();
+            match delta.status() {
+                git2::Delta::Deleted => removed.push(path),
+                git2::Delta::Unmodified => kept.push(path),
+                status => panic!("unexpected {status:?} for {path}"),
+            }
+        }
+        assert_eq!(removed, ["lib/src/util/b.rs", "top.txt"]);
+        assert_eq!(kept, ["lib/README.md", "lib/src/a.rs"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter_into_other_repository() -> Result<(), Error> {
+        let (source, _dir) = setup_test_repo();
+        let (dest, _dest_dir) = setup_test_repo();
+
+        let tree = create_nested_tree(&source)?;
+        let options = FilterOptions {
+            syntax: PatternSyntax::GitAttributes,
+            ..Default::default()
+        };
+        let filtered = source.filter_into(&dest, &tree, &["lib/src/**", "top.txt"], &options)?;
+
+        // The matched directory is copied whole, with its blobs.
+        let b = filtered.get_path(Path::new("lib/src/util/b.rs"))?;
+        assert_eq!(dest.find_blob(b.id())?.content(), b"content");
+        assert!(filtered.get_path(Path::new("lib/README.md")).is_err());
+        assert!(source.find_tree(filtered.id()).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter_with_stats() -> Result<(), Error> {
+        let (repo, _dir) = setup_test_repo();
+
+        let tree = create_test_tree(&repo)?;
+
+        let (filtered, stats) = repo.filter_by_patterns_with_stats(
+            &tree,
+            &["*.txt", "*.rs", "*.nonexistent"],
+            &FilterOptions::default(),
+        )?;
+        assert_eq!(filtered.len(), 2);
+        assert_eq!(stats.matched_files, 2);
+        assert_eq!(stats.skipped_files, 1);
+        assert_eq!(stats.total_bytes, 16);
+        assert_eq!(stats.unmatched_patterns, ["*.nonexistent"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter_reuses_shared_subtrees_with_their_stats() -> Result<(), Error> {
+        let (repo, _dir) = setup_test_repo();
+
+        let lib = create_test_tree(&repo)?;
+        let root = |name: &str| -> Result<git2::Tree<'_>, Error> {
+            let mut builder = repo.treebuilder(None)?;
+            builder.insert("lib", lib.id(), 0o040000)?;
+            builder.insert(name, repo.blob(name.as_bytes())?, 0o100644)?;
+            repo.find_tree(builder.write()?)
+        };
+        let patterns = ["lib/*.rs", "*.md", "lib/*.nonexistent"];
+
+        // The second walk finds `lib` in the cache and must report the same stats.
+        for name in ["a.md", "b.md"] {
+            let tree = root(name)?;
+            let (filtered, stats) =
+                repo.filter_by_patterns_with_stats(&tree, &patterns, &FilterOptions::default())?;
+            assert_eq!(filtered.len(), 2);
+            // Glob `*` crosses directories, so `*.md` also matches `lib/test.md`.
+            assert_eq!(stats.matched_files, 3);
+            assert_eq!(stats.skipped_files, 1);
+            assert_eq!(stats.unmatched_patterns, ["lib/*.nonexistent"]);
+
+            let plain = repo.filter_by_patterns(&tree, &patterns)?;
+            assert_eq!(plain.id(), filtered.id());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter_with_stats_counts_matched_directories() -> Result<(), Error> {
+        let (repo, _dir) = setup_test_repo();
+
+        let tree = create_nested_tree(&repo)?;
+        let options = FilterOptions {
+            syntax: PatternSyntax::GitAttributes,
+            match_directories: true,
+            ..Default::default()
+        };
+
+        let (filtered, stats) =
+            repo.filter_by_patterns_with_stats(&tree, &["src", "missing/"], &options)?;
+        assert!(
+            filtered
+                .get_path(std::path::Path::new("lib/src/util/b.rs"))
+                .is_ok()
+        );
+        assert_eq!(stats.matched_files, 2);
+        assert_eq!(stats.skipped_files, 2);
+        assert_eq!(stats.unmatched_patterns, ["missing/"]);
+        Ok(())
+    }
+
+    fn create_tree_with_gitlink<'a>(repo: &'a Repository) -> Result<git2::Tree<'a>, Error> {
+        let blob = repo.blob(b"content")?;
+        let commit = git2::Oid::from_str("0123456789abcdef0123456789abcdef01234567")?;
+
+        let mut deps = repo.treebuilder(None)?;
+        deps.insert("sub", commit, 0o160000)?;
+        deps.insert("file.txt", blob, 0o100644)?;
+        let deps_oid = deps.write()?;
+
+        let mut root = repo.treebuilder(None)?;
+        root.insert("deps", deps_oid, 0o040000)?;
+        let root_oid = root.write()?;
+
+        repo.find_tree(root_oid)
+    }
+
+    #[test]
+    fn test_filter_submodule_policies() -> Result<(), Error> {
+        let (repo, _dir) = setup_test_repo();
+
+        let tree = create_tree_with_gitlink(&repo)?;
+        let sub = std::path::Path::new("deps/sub");
+
+        // Skipped by default, but reported.
+        let (filtered, stats) =
+            repo.filter_by_patterns_with_stats(&tree, &["deps/*"], &FilterOptions::default())?;
+        assert!(filtered.get_path(sub).is_err());
+        assert_eq!(stats.gitlinks, ["deps/sub"]);
+
+        let keep = FilterOptions {
+            submodules: SubmodulePolicy::Keep,
+            ..Default::default()
+        };
+        let filtered = repo.filter_with_options(&tree, &["deps/*"], &keep)?;
+        assert_eq!(filtered.get_path(sub)?.filemode(), 0o160000);
+
+        let error = FilterOptions {
+            submodules: SubmodulePolicy::Error,
+            ..Default::default()
+        };
+        let result = repo.filter_with_options(&tree, &["deps/*"], &error);
+        assert!(result.unwrap_err().message().contains("deps/sub"));
+
+        // Unmatched gitlinks are not an error.
+        assert!(repo.filter_with_options(&tree, &["*.txt"], &error).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter_skips_gitlinks_in_matched_directories() -> Result<(), Error> {
+        let (repo, _dir) = setup_test_repo();
+
+        let tree = create_tree_with_gitlink(&repo)?;
+        let options = FilterOptions {
+            match_directories: true,
+            ..Default::default()
+        };
+
+        let filtered = repo.filter_with_options(&tree, &["deps"], &options)?;
+        assert!(
+            filtered
+                .get_path(std::path::Path::new("deps/file.txt"))
+                .is_ok()
+        );
+        assert!(filtered.get_path(std::path::Path::new("deps/sub")).is_err());
+        Ok(())
+    }
+
+    fn create_tree_with_links<'a>(repo: &'a Repository) -> Result<git2::Tree<'a>, Error> {
+        let script = repo.blob(b"#!/bin/sh\n")?;
+        let readme = repo.blob(b"readme")?;
+
+        let mut docs = repo.treebuilder(None)?;
+        docs.insert("README", readme, 0o100644)?;
+        docs.insert("link", repo.blob(b"README")?, 0o120000)?;
+        let docs_oid = docs.write()?;
+
+        let mut root = repo.treebuilder(None)?;
+        root.insert("docs", docs_oid, 0o040000)?;
+        root.insert("run.sh", script, 0o100755)?;
+        root.insert("readme", repo.blob(b"docs/link")?, 0o120000)?;
+        root.insert("escape", repo.blob(b"../outside")?, 0o120000)?;
+        root.insert("dir", repo.blob(b"docs")?, 0o120000)?;
+        let root_oid = root.write()?;
+
+        repo.find_tree(root_oid)
+    }
+
+    #[test]
+    fn test_filter_symlink_policies() -> Result<(), Error> {
+        let (repo, _dir) = setup_test_repo();
+
+        let tree = create_tree_with_links(&repo)?;
+
+        let filtered = repo.filter_by_patterns(&tree, &["readme"])?;
+        assert_eq!(filtered.get_name("readme").unwrap().filemode(), 0o120000);
+
+        let reject = FilterOptions {
+            symlinks: SymlinkPolicy::Reject,
+            ..Default::default()
+        };
+        assert!(
+            repo.filter_with_options(&tree, &["readme"], &reject)
+                .is_err()
+        );
+        assert!(repo.filter_with_options(&tree, &["*.sh"], &reject).is_ok());
+
+        // Links are followed through chains to the regular file.
+        let materialize = FilterOptions {
+            symlinks: SymlinkPolicy::Materialize,
+            ..Default::default()
+        };
+        let filtered = repo.filter_with_options(&tree, &["readme"], &materialize)?;
+        let entry = filtered.get_name("readme").unwrap();
+        assert_eq!(entry.filemode(), 0o100644);
+        assert_eq!(repo.find_blob(entry.id())?.content(), b"readme");
+
+        assert!(
+            repo.filter_with_options(&tree, &["escape"], &materialize)
+                .is_err()
+        );
+        assert!(
+            repo.filter_with_options(&tree, &["dir"], &materialize)
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter_normalize_executable() -> Result<(), Error> {
+        let (repo, _dir) = setup_test_repo();
+
+        let tree = create_tree_with_links(&repo)?;
+
+        let filtered = repo.filter_by_patterns(&tree, &["*.sh"])?;
+        assert_eq!(filtered.get_name("run.sh").unwrap().filemode(), 0o100755);
+
+        let options = FilterOptions {
+            normalize_executable: true,
+            ..Default::default()
+        };
+        let filtered = repo.filter_with_options(&tree, &["*.sh"], &options)?;
+        assert_eq!(filtered.get_name("run.sh").unwrap().filemode(), 0o100644);
+        Ok(())
+    }
+
+    fn commit_tree(
+        repo: &Repository,
+        files: &[(&str, &[u8])],
+        message: &str,
+        parents: &[git2::Oid],
+    ) -> Result<git2::Oid, Error> {
+        let mut index = git2::Index::new()?;
+        for (path, content) in files {
+            let entry = git2::IndexEntry {
+                ctime: git2::IndexTime::new(0, 0),
+                mtime: git2::IndexTime::new(0, 0),
+                dev: 0,
+                ino: 0,
+                mode: 0o100644,
+                uid: 0,
+                gid: 0,
+                file_size: content.len() as u32,
+                id: repo.blob(content)?,
+                flags: 0,
+                flags_extended: 0,
+                path: path.as_bytes().to_vec(),
+            };
+            index.add(&entry)?;
+        }
+        let tree = repo.find_tree(index.write_tree_to(repo)?)?;
+        let sig = git2::Signature::new("Test", "test@test", &git2::Time::new(0, 0))?;
+        let parents = parents
+            .iter()
+            .map(|id| repo.find_commit(*id))
+            .collect::<Result<Vec<_>, _>>()?;
+        let parents: Vec<&git2::Commit> = parents.iter().collect();
+        repo.commit(None, &sig, &sig, message, &tree, &parents)
+    }
+
+    #[test]
+    fn test_filter_history_rewrites_and_prunes() -> Result<(), Error> {
+        let (repo, _dir) = setup_test_repo();
+
+        let c1 = commit_tree(&repo, &[("other.txt", b"1")], "unrelated root", &[])?;
+        let c2 = commit_tree(
+            &repo,
+            &[("other.txt", b"1"), ("lib/a.rs", b"a")],
+            "add lib",
+            &[c1],
+        )?;
+        let c3 = commit_tree(
+            &repo,
+            &[("other.txt", b"2"), ("lib/a.rs", b"a")],
+            "touch other",
+            &[c2],
+        )?;
+        let c4 = commit_tree(
+            &repo,
+            &[("other.txt", b"2"), ("lib/a.rs", b"b")],
+            "change lib",
+            &[c3],
+        )?;
+        repo.reference("refs/heads/main", c4, true, "test")?;
+
+        let history = repo.filter_history("main", &["lib/**"], &FilterOptions::default())?;
+        assert_eq!(history.commits[&c1], None);
+        assert_eq!(history.commits[&c3], history.commits[&c2]);
+
+        let head = repo.find_commit(history.head.unwrap())?;
+        assert_eq!(head.summary(), Some("change lib"));
+        assert!(head.tree()?.get_name("other.txt").is_none());
+        let parent = head.parent(0)?;
+        assert_eq!(parent.summary(), Some("add lib"));
+        assert_eq!(parent.parent_count(), 0);
+
+        // A range keeps the parent outside it.
+        let history = repo.filter_history(
+            &format!("{}..main", c3),
+            &["lib/**"],
+            &FilterOptions::default(),
+        )?;
+        assert_eq!(history.commits.len(), 1);
+        let head = repo.find_commit(history.head.unwrap())?;
+        assert_eq!(head.parent_id(0)?, c3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter_and_transform_rewrites_matched_blobs() -> Result<(), Error> {
+        let (repo, _dir) = setup_test_repo();
+
+        let tree = create_nested_tree(&repo)?;
+
+        let mut seen = Vec::new();
+        let transformed = repo.filter_and_transform(
+            &tree,
+            &["lib/src/**"],
+            &FilterOptions::default(),
+            &mut |path, data| {
+                seen.push(path.to_string());
+                if path.ends_with("a.rs") {
+                    Some([b"// vendored\n", data].concat())
+                } else {
+                    None
+                }
+            },
+        )?;
+
+        seen.sort();
+        assert_eq!(seen, ["lib/src/a.rs", "lib/src/util/b.rs"]);
+
+        let a = transformed.get_path(std::path::Path::new("lib/src/a.rs"))?;
+        assert_eq!(repo.find_blob(a.id())?.content(), b"// vendored\ncontent");
+
+        let b = transformed.get_path(std::path::Path::new("lib/src/util/b.rs"))?;
+        assert_eq!(repo.find_blob(b.id())?.content(), b"content");
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter_and_rebase_strips_and_adds_prefix() -> Result<(), Error> {
+        let (repo, _dir) = setup_test_repo();
+
+        let tree = create_nested_tree(&repo)?;
+
+        let rebased = repo.filter_and_rebase(
+            &tree,
+            &["lib/**"],
+            &FilterOptions::default(),
+            "lib/src",
+            "third_party/foo",
+        )?;
+        assert_eq!(rebased.len(), 1);
+        assert!(
+            rebased
+                .get_path(std::path::Path::new("third_party/foo/a.rs"))
+                .is_ok()
+        );
+        assert!(
+            rebased
+                .get_path(std::path::Path::new("third_party/foo/util/b.rs"))
+                .is_ok()
+        );
+        // Entries outside the stripped prefix are dropped.
+        assert!(
+            rebased
+                .get_path(std::path::Path::new("third_party/foo/README.md"))
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter_and_rebase_empty_prefixes_is_plain_filter() -> Result<(), Error> {
+        let (repo, _dir) = setup_test_repo();
+
+        let tree = create_nested_tree(&repo)?;
+
+        let rebased =
+            repo.filter_and_rebase(&tree, &["*.txt"], &FilterOptions::default(), "", "")?;
+        let filtered = repo.filter_by_patterns(&tree, &["*.txt"])?;
+        assert_eq!(rebased.id(), filtered.id());
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter_and_rebase_missing_strip_dir_is_empty() -> Result<(), Error> {
+        let (repo, _dir) = setup_test_repo();
+
+        let tree = create_nested_tree(&repo)?;
+
+        let rebased = repo.filter_and_rebase(
+            &tree,
+            &["**"],
+            &FilterOptions::default(),
+            "does/not/exist",
+            "vendor",
+        )?;
+        assert_eq!(rebased.len(), 0);
         Ok(())
     }
 }
@@ -1,8 +1,6 @@
-mod cli;
-
 use clap::Parser;
-use cli::{Cli, OutputFormat};
-use git_filter_tree::FilterTree;
+use git_filter_tree::cli::{Cli, OutputFormat};
+use git_filter_tree::{FilterOptions, FilterTree};
 use git2 as git;
 use std::process;
 
@@ -19,15 +17,61 @@ fn run() -> Result<(), Box<dyn std::error::Error>> {
     // Open the repository in current directory
     let repo = git::Repository::open(".")?;
 
+    // Convert patterns to string slices
+    let patterns: Vec<&str> = cli.patterns.iter().map(|s| s.as_str()).collect();
+
+    let options = FilterOptions {
+        syntax: cli.syntax,
+        case_insensitive: cli.ignore_case,
+        literal: cli.literal,
+        match_directories: cli.match_directories,
+        submodules: cli.submodules,
+        symlinks: cli.symlinks,
+        normalize_executable: cli.normalize_executable,
+        excludes: cli.excludes,
+        keep_dirs: cli.keep_dirs,
+        max_depth: cli.max_depth,
+        max_entries: cli.max_entries,
+        on_error: cli.on_error,
+    };
+
+    if cli.history {
+        let history = repo.filter_history(&cli.treeish, &patterns, &options)?;
+        let head = history
+            .head
+            .ok_or("No commit in the range touches the filtered paths")?;
+        if let Some(name) = &cli.write_ref {
+            let message = format!("filter-tree: {}", cli.treeish);
+            repo.reference(name, head, true, &message)?;
+        }
+        println!("{}", head);
+        return Ok(());
+    }
+
     // Resolve the tree-ish to a tree
     let obj = repo.revparse_single(&cli.treeish)?;
     let tree = obj.peel_to_tree()?;
 
-    // Convert patterns to string slices
-    let patterns: Vec<&str> = cli.patterns.iter().map(|s| s.as_str()).collect();
+    // Filter the tree by patterns, re-rooting it if requested. Skipped subtrees are only
+    // reported through the statistics.
+    let (filtered, stats) = repo.filter_by_patterns_with_stats(&tree, &patterns, &options)?;
+    for path in &stats.skipped_subtrees {
+        eprintln!("warning: skipped unreadable tree '{}'", path);
+    }
+    let filtered_tree = repo.rebase_tree(
+        filtered,
+        cli.strip_prefix.as_deref().unwrap_or(""),
+        cli.add_prefix.as_deref().unwrap_or(""),
+    )?;
 
-    // Filter the tree by patterns
-    let filtered_tree = repo.filter_by_patterns(&tree, &patterns)?;
+    if cli.commit || cli.write_ref.is_some() {
+        let source = obj
+            .peel_to_commit()
+            .map_err(|_| format!("'{}' is not a commit", cli.treeish))?;
+        let commit = write_commit(&repo, &source, &filtered_tree, cli.write_ref.as_deref())?;
+        println!("{}", commit);
+        return Ok(());
+    }
 
     // Output based on format
     match cli.format {
@@ -36,7 +80,7 @@ fn run() -> Result<(), Box<dyn std::error::Error>> {
         }
         OutputFormat::Entries => {
             for entry in filtered_tree.iter() {
-                let name = entry.name().unwrap_or("<invalid-utf8>");
+                let name = String::from_utf8_lossy(entry.name_bytes());
                 let kind = match entry.kind() {
                     Some(git::ObjectType::Blob) => "blob",
                     Some(git::ObjectType::Tree) => "tree",
@@ -51,7 +95,7 @@ fn run() -> Result<(), Box<dyn std::error::Error>> {
             println!("Entries: {}", filtered_tree.len());
             println!();
             for entry in filtered_tree.iter() {
-                let name = entry.name().unwrap_or("<invalid-utf8>");
+                let name = String::from_utf8_lossy(entry.name_bytes());
                 let kind = match entry.kind() {
                     Some(git::ObjectType::Blob) => "blob",
                     Some(git::ObjectType::Tree) => "tree",
@@ -63,7 +107,51 @@ fn run() -> Result<(), Box<dyn std::error::Error>> {
                 println!("{:06o} {} {}\t{}", mode, kind, id, name);
             }
         }
+        OutputFormat::Removed => {
+            // Paths are those of the source tree, before any re-rooting.
+            let diff = repo.filter_diff(&tree, &patterns, &options)?;
+            for delta in diff.deltas() {
+                if delta.status() == git::Delta::Deleted {
+                    let path = delta.old_file().path_bytes().unwrap_or_default();
+                    println!("{}", String::from_utf8_lossy(path));
+                }
+            }
+        }
     }
 
     Ok(())
 }
+
+/// Commits `tree` with the metadata of `source`, advancing `target_ref` when given.
+fn write_commit(
+    repo: &git::Repository,
+    source: &git::Commit,
+    tree: &git::Tree,
+    target_ref: Option<&str>,
+) -> Result<git::Oid, git::Error> {
+    let parent = match target_ref {
+        Some(name) => match repo.find_reference(name) {
+            Ok(reference) => Some(reference.peel_to_commit()?),
+            Err(e) if e.code() == git::ErrorCode::NotFound => None,
+            Err(e) => return Err(e),
+        },
+        None => None,
+    };
+    let parents: Vec<&git::Commit> = parent.iter().collect();
+
+    let commit = repo.commit(
+        None,
+        &source.author(),
+        &source.committer(),
+        &String::from_utf8_lossy(source.message_bytes()),
+        tree,
+        &parents,
+    )?;
+
+    if let Some(name) = target_ref {
+        let message = format!("filter-tree: {}", source.id());
+        repo.reference(name, commit, true, &message)?;
+    }
+
+    Ok(commit)
+}
@@ -0,0 +1,451 @@
+//! Pattern matching with Git's gitattributes rules.
+//!
+//! This follows `wildmatch.c` and the pattern handling in `attr.c` / `dir.c`:
+//!
+//! - A pattern without a `/` matches the file's basename at any depth.
+//! - A pattern containing a `/` is anchored to the tree root (a leading `/` is
+//!   dropped) and matched against the full path; `*`, `?` and `[...]` never
+//!   match a `/`.
+//! - `**/` matches in all directories, a trailing `/**` matches everything
+//!   inside, `/**/` matches zero or more directories, and any other `**` acts
+//!   like `*`.
+//! - A trailing `/` restricts the pattern to directories, which never match
+//!   files (gitattributes do not apply recursively through directories).
+//! - `\` escapes the next character; `[!...]`, `[^...]`, ranges and POSIX
+//!   classes such as `[:alpha:]` are supported in brackets.
+//!
+//! Case-insensitive matching mirrors `core.ignoreCase` (`WM_CASEFOLD`).
+
+use git2::Error;
+
+/// A single gitattributes pattern.
+#[derive(Debug, Clone)]
+pub(crate) struct AttrPattern {
+    pattern: Vec<u8>,
+    basename_only: bool,
+    dir_only: bool,
+    casefold: bool,
+}
+
+impl AttrPattern {
+    pub(crate) fn new(pattern: &str, casefold: bool) -> Result<Self, Error> {
+        if pattern.starts_with('!') {
+            return Err(Error::from_str(&format!(
+                "Invalid pattern '{}': negative patterns are not allowed in gitattributes",
+                pattern
+            )));
+        }
+
+        let (pattern, dir_only) = match pattern.strip_suffix('/') {
+            Some(p) => (p, true),
+            None => (pattern, false),
+        };
+        let basename_only = !pattern.contains('/');
+        let pattern = pattern.strip_prefix('/').unwrap_or(pattern);
+
+        if pattern.is_empty() {
+            return Err(Error::from_str("Invalid pattern '': pattern is empty"));
+        }
+
+        let mut pattern = pattern.as_bytes().to_vec();
+        if casefold {
+            pattern.make_ascii_lowercase();
+        }
+
+        Ok(Self {
+            pattern,
+            basename_only,
+            dir_only,
+            casefold,
+        })
+    }
+
+    /// Returns `true` if this pattern applies to the file at `path`, a
+    /// slash-separated path relative to the tree root. Paths are bytes, as
+    /// Git stores them, and need not be UTF-8.
+    pub(crate) fn matches_file(&self, path: &[u8]) -> bool {
+        !self.dir_only && self.matches(path)
+    }
+
+    /// Returns `true` if this pattern applies to the directory at `path`.
+    pub(crate) fn matches_dir(&self, path: &[u8]) -> bool {
+        self.matches(path)
+    }
+
+    fn matches(&self, path: &[u8]) -> bool {
+        let text = if self.basename_only {
+            path.rsplit(|&b| b == b'/').next().unwrap_or(path)
+        } else {
+            path
+        };
+
+        if self.casefold {
+            let text = text.to_ascii_lowercase();
+            wildmatch(&self.pattern, &text, true)
+        } else {
+            wildmatch(&self.pattern, text, false)
+        }
+    }
+}
+
+/// Escapes all wildcard characters in `pattern` so it matches literally.
+pub(crate) fn escape(pattern: &str) -> String {
+    let mut escaped = String::with_capacity(pattern.len());
+    for c in pattern.chars() {
+        if matches!(c, '\\' | '*' | '?' | '[' | '!') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Match,
+    NoMatch,
+    AbortAll,
+    AbortToStarStar,
+}
+
+/// Matches `text` against `pattern` with `WM_PATHNAME` semantics. With
+/// `casefold`, both are expected to be lowercased already.
+fn wildmatch(pattern: &[u8], text: &[u8], casefold: bool) -> bool {
+    dowild(pattern, text, casefold) == Outcome::Match
+}
+
+fn dowild(p: &[u8], text: &[u8], casefold: bool) -> Outcome {
+    let mut pi = 0;
+    let mut ti = 0;
+
+    while pi < p.len() {
+        let mut p_ch = p[pi];
+        let t_ch = text.get(ti).copied();
+
+        if t_ch.is_none() && p_ch != b'*' {
+            return Outcome::AbortAll;
+        }
+        let t_ch = t_ch.unwrap_or(0);
+
+        match p_ch {
+            b'?' => {
+                if t_ch == b'/' {
+                    return Outcome::NoMatch;
+                }
+            }
+            b'*' => {
+                let match_slash;
+                pi += 1;
+                if p.get(pi) == Some(&b'*') {
+                    let prev_is_boundary = pi < 2 || p[pi - 2] == b'/';
+                    while p.get(pi) == Some(&b'*') {
+                        pi += 1;
+                    }
+                    let next = p.get(pi).copied();
+                    if prev_is_boundary
+                        && (next.is_none()
+                            || next == Some(b'/')
+                            || (next == Some(b'\\') && p.get(pi + 1) == Some(&b'/')))
+                    {
+                        // "**/" may match zero directories.
+                        if next == Some(b'/')
+                            && dowild(&p[pi + 1..], &text[ti..], casefold) == Outcome::Match
+                        {
+                            return Outcome::Match;
+                        }
+                        match_slash = true;
+                    } else {
+                        // Not a standalone "**": behaves like "*".
+                        match_slash = false;
+                    }
+                } else {
+                    match_slash = false;
+                }
+
+                if pi == p.len() {
+                    // Trailing "*" matches the rest unless it would cross a "/".
+                    if !match_slash && text[ti..].contains(&b'/') {
+                        return Outcome::AbortToStarStar;
+                    }
+                    return Outcome::Match;
+                }
+
+                if !match_slash && p[pi] == b'/' {
+                    // "*/" only needs to consume up to the next "/".
+                    match text[ti..].iter().position(|&c| c == b'/') {
+                        Some(slash) => {
+                            ti += slash + 1;
+                            pi += 1;
+                            continue;
+                        }
+                        None => return Outcome::AbortAll,
+                    }
+                }
+
+                while ti < text.len() {
+                    let matched = dowild(&p[pi..], &text[ti..], casefold);
+                    if matched != Outcome::NoMatch {
+                        if !match_slash || matched != Outcome::AbortToStarStar {
+                            return matched;
+                        }
+                    } else if !match_slash && text[ti] == b'/' {
+                        return Outcome::AbortToStarStar;
+                    }
+                    ti += 1;
+                }
+                return Outcome::AbortAll;
+            }
+            b'[' => {
+                pi += 1;
+                let Some(&first) = p.get(pi) else {
+                    return Outcome::AbortAll;
+                };
+                p_ch = if first == b'!' { b'^' } else { first };
+                let negated = p_ch == b'^';
+                if negated {
+                    pi += 1;
+                    match p.get(pi) {
+                        Some(&c) => p_ch = c,
+                        None => return Outcome::AbortAll,
+                    }
+                }
+
+                let mut prev_ch = 0u8;
+                let mut matched = false;
+                loop {
+                    if pi >= p.len() {
+                        return Outcome::AbortAll;
+                    }
+                    if p_ch == b'\\' {
+                        pi += 1;
+                        match p.get(pi) {
+                            Some(&c) => p_ch = c,
+                            None => return Outcome::AbortAll,
+                        }
+                        if t_ch == p_ch {
+                            matched = true;
+                        }
+                    } else if p_ch == b'-'
+                        && prev_ch != 0
+                        && p.get(pi + 1).is_some_and(|&c| c != b']')
+                    {
+                        pi += 1;
+                        p_ch = p[pi];
+                        if p_ch == b'\\' {
+                            pi += 1;
+                            match p.get(pi) {
+                                Some(&c) => p_ch = c,
+                                None => return Outcome::AbortAll,
+                            }
+                        }
+                        if t_ch <= p_ch && t_ch >= prev_ch {
+                            matched = true;
+                        }
+                        p_ch = 0; // This makes "prev_ch" get set to 0.
+                    } else if p_ch == b'[' && p.get(pi + 1) == Some(&b':') {
+                        let start = pi + 2;
+                        let Some(end) = p[start..].iter().position(|&c| c == b']') else {
+                            return Outcome::AbortAll;
+                        };
+                        let end = start + end;
+                        if end == start || p[end - 1] != b':' {
+                            // Didn't find ":]", so treat like a normal set.
+                            p_ch = b'[';
+                            if t_ch == p_ch {
+                                matched = true;
+                            }
+                        } else {
+                            match posix_class(&p[start..end - 1], t_ch, casefold) {
+                                Some(true) => matched = true,
+                                Some(false) => {}
+                                None => return Outcome::AbortAll,
+                            }
+                            pi = end;
+                            p_ch = 0; // This makes "prev_ch" get set to 0.
+                        }
+                    } else if t_ch == p_ch {
+                        matched = true;
+                    }
+
+                    prev_ch = p_ch;
+                    pi += 1;
+                    match p.get(pi) {
+                        Some(&b']') => break,
+                        Some(&c) => p_ch = c,
+                        None => return Outcome::AbortAll,
+                    }
+                }
+
+                if matched == negated || t_ch == b'/' {
+                    return Outcome::NoMatch;
+                }
+            }
+            _ => {
+                if p_ch == b'\\' {
+                    pi += 1;
+                    match p.get(pi) {
+                        Some(&c) => p_ch = c,
+                        None => return Outcome::NoMatch,
+                    }
+                }
+                if t_ch != p_ch {
+                    return Outcome::NoMatch;
+                }
+            }
+        }
+
+        pi += 1;
+        ti += 1;
+    }
+
+    if ti == text.len() {
+        Outcome::Match
+    } else {
+        Outcome::NoMatch
+    }
+}
+
+/// Tests `c` against a POSIX bracket class, or `None` for an unknown class.
+fn posix_class(name: &[u8], c: u8, casefold: bool) -> Option<bool> {
+    Some(match name {
+        b"alnum" => c.is_ascii_alphanumeric(),
+        b"alpha" => c.is_ascii_alphabetic(),
+        b"blank" => c == b' ' || c == b'\t',
+        b"cntrl" => c.is_ascii_control(),
+        b"digit" => c.is_ascii_digit(),
+        b"graph" => c.is_ascii_graphic(),
+        b"lower" | b"upper" if casefold => c.is_ascii_alphabetic(),
+        b"lower" => c.is_ascii_lowercase(),
+        b"print" => c.is_ascii_graphic() || c == b' ',
+        b"punct" => c.is_ascii_punctuation(),
+        b"space" => c.is_ascii_whitespace() || c == b'\x0b',
+        b"upper" => c.is_ascii_uppercase(),
+        b"xdigit" => c.is_ascii_hexdigit(),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, path: &str) -> bool {
+        AttrPattern::new(pattern, false)
+            .unwrap()
+            .matches_file(path.as_bytes())
+    }
+
+    #[test]
+    fn basename_patterns_match_at_any_depth() {
+        assert!(matches("*.txt", "a.txt"));
+        assert!(matches("*.txt", "dir/sub/a.txt"));
+        assert!(matches("README", "docs/README"));
+        assert!(!matches("*.txt", "a.txt/b.rs"));
+    }
+
+    #[test]
+    fn slash_anchors_to_root() {
+        assert!(matches("/a.txt", "a.txt"));
+        assert!(!matches("/a.txt", "dir/a.txt"));
+        assert!(matches("dir/*.rs", "dir/a.rs"));
+        assert!(!matches("dir/*.rs", "dir/sub/a.rs"));
+        assert!(!matches("dir/*.rs", "other/dir/a.rs"));
+    }
+
+    #[test]
+    fn double_star() {
+        assert!(matches("**/foo", "foo"));
+        assert!(matches("**/foo", "a/b/foo"));
+        assert!(matches("lib/**", "lib/a"));
+        assert!(matches("lib/**", "lib/a/b/c"));
+        assert!(!matches("lib/**", "lib"));
+        assert!(matches("a/**/b", "a/b"));
+        assert!(matches("a/**/b", "a/x/y/b"));
+        assert!(!matches("a/**/b", "a/x/c"));
+        // A "**" that is not a whole path component acts like "*".
+        assert!(matches("a/x**/b", "a/xyz/b"));
+        assert!(!matches("a/x**/b", "a/x/y/b"));
+    }
+
+    #[test]
+    fn directory_patterns_do_not_match_files() {
+        assert!(!matches("lib/", "lib"));
+        assert!(!matches("lib/", "lib/a.rs"));
+    }
+
+    #[test]
+    fn wildcards_do_not_cross_slashes() {
+        assert!(!matches("a?b", "a/b"));
+        assert!(!matches("a[/]b", "a/b"));
+        assert!(matches("a/?.rs", "a/b.rs"));
+    }
+
+    #[test]
+    fn bracket_expressions() {
+        assert!(matches("[abc].rs", "b.rs"));
+        assert!(!matches("[abc].rs", "d.rs"));
+        assert!(matches("[!abc].rs", "d.rs"));
+        assert!(matches("[^abc].rs", "d.rs"));
+        assert!(matches("[a-c].rs", "b.rs"));
+        assert!(matches("[]].rs", "].rs"));
+        assert!(matches("[[:digit:]].rs", "7.rs"));
+        assert!(!matches("[[:digit:]].rs", "x.rs"));
+        assert!(matches("[[:upper:][:digit:]]x", "Ax"));
+    }
+
+    #[test]
+    fn non_utf8_paths() {
+        let pattern = AttrPattern::new("*.txt", false).unwrap();
+        assert!(pattern.matches_file(b"dir/\xff\xfe.txt"));
+        assert!(pattern.matches_file(b"\xff/a.txt"));
+        assert!(!pattern.matches_file(b"a.txt/\xff"));
+        let pattern = AttrPattern::new("dir/?.txt", false).unwrap();
+        assert!(pattern.matches_file(b"dir/\xff.txt"));
+    }
+
+    #[test]
+    fn escapes() {
+        assert!(matches("\\*.rs", "*.rs"));
+        assert!(!matches("\\*.rs", "a.rs"));
+    }
+
+    #[test]
+    fn negative_patterns_are_rejected() {
+        assert!(AttrPattern::new("!*.rs", false).is_err());
+    }
+
+    #[test]
+    fn casefold() {
+        let pattern = AttrPattern::new("Lib/*.RS", true).unwrap();
+        assert!(pattern.matches_file(b"lib/a.rs"));
+        assert!(pattern.matches_file(b"LIB/A.Rs"));
+        assert!(!matches("Lib/*.RS", "lib/a.rs"));
+
+        let pattern = AttrPattern::new("[[:upper:]]x", true).unwrap();
+        assert!(pattern.matches_file(b"ax"));
+    }
+
+    #[test]
+    fn escaped_patterns_match_literally() {
+        assert!(matches(&escape("a*[b]?.rs"), "a*[b]?.rs"));
+        assert!(!matches(&escape("*.rs"), "a.rs"));
+        assert!(matches(&escape("!important"), "!important"));
+    }
+
+    #[test]
+    fn directory_patterns_match_directories() {
+        let pattern = AttrPattern::new("lib/", false).unwrap();
+        assert!(pattern.matches_dir(b"lib"));
+        assert!(pattern.matches_dir(b"src/lib"));
+        assert!(
+            !AttrPattern::new("/lib/", false)
+                .unwrap()
+                .matches_dir(b"src/lib")
+        );
+        assert!(
+            AttrPattern::new("lib", false)
+                .unwrap()
+                .matches_dir(b"src/lib")
+        );
+    }
+}
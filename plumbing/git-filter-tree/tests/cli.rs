@@ -0,0 +1,192 @@
+//! Integration tests for the `git-filter-tree` binary.
+
+use git2::{Oid, Repository, Signature};
+use std::{
+    fs,
+    process::{Command, Output},
+};
+use tempfile::TempDir;
+
+/// A repository with two commits: the first adds `src/lib.rs` and `README.md`, the second
+/// changes `README.md` and adds `src/main.rs`.
+fn setup_repo() -> (TempDir, Repository) {
+    let dir = TempDir::new().unwrap();
+    let repo = Repository::init(dir.path()).unwrap();
+    commit(
+        &repo,
+        &[("src/lib.rs", "lib\n"), ("README.md", "one\n")],
+        "first",
+    );
+    commit(
+        &repo,
+        &[("src/main.rs", "main\n"), ("README.md", "two\n")],
+        "second",
+    );
+    (dir, repo)
+}
+
+/// Write `files` into the working tree and commit everything in it on top of `HEAD`.
+fn commit(repo: &Repository, files: &[(&str, &str)], message: &str) -> Oid {
+    let workdir = repo.workdir().unwrap();
+    for (path, content) in files {
+        let full = workdir.join(path);
+        fs::create_dir_all(full.parent().unwrap()).unwrap();
+        fs::write(full, content).unwrap();
+    }
+    let mut index = repo.index().unwrap();
+    index
+        .add_all(["*"], git2::IndexAddOption::DEFAULT, None)
+        .unwrap();
+    index.write().unwrap();
+    let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+    let sig = Signature::now("Test", "test@test").unwrap();
+    let parent = repo.head().ok().map(|h| h.peel_to_commit().unwrap());
+    let parents: Vec<&git2::Commit> = parent.iter().collect();
+    repo.commit(Some("HEAD"), &sig, &sig, message, &tree, &parents)
+        .unwrap()
+}
+
+/// Run `git-filter-tree` with `args` in `repo`.
+fn filter_tree(repo: &Repository, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_git-filter-tree"))
+        .args(args)
+        .current_dir(repo.workdir().unwrap())
+        .output()
+        .unwrap()
+}
+
+/// Run `git-filter-tree` with `args` in `repo`, failing unless it succeeds, and return the
+/// object id it prints.
+fn filter_tree_id(repo: &Repository, args: &[&str]) -> Oid {
+    let output = filter_tree(repo, args);
+    assert!(output.status.success(), "{}", describe(&output));
+    Oid::from_str(String::from_utf8_lossy(&output.stdout).trim()).unwrap()
+}
+
+fn describe(output: &Output) -> String {
+    format!(
+        "status {:?}\nstdout:\n{}\nstderr:\n{}",
+        output.status.code(),
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    )
+}
+
+/// The paths of all blobs in `tree`, in tree order.
+fn paths(tree: &git2::Tree<'_>) -> Vec<String> {
+    let mut paths = Vec::new();
+    tree.walk(git2::TreeWalkMode::PreOrder, |root, entry| {
+        if entry.kind() == Some(git2::ObjectType::Blob) {
+            paths.push(format!("{root}{}", entry.name().unwrap()));
+        }
+        git2::TreeWalkResult::Ok
+    })
+    .unwrap();
+    paths
+}
+
+#[test]
+fn commit_reuses_the_source_metadata() {
+    let (_dir, repo) = setup_repo();
+    let head = repo.head().unwrap().peel_to_commit().unwrap();
+
+    let id = filter_tree_id(&repo, &["HEAD", "src/**", "--commit"]);
+    let commit = repo.find_commit(id).unwrap();
+    assert_eq!(commit.message(), Some("second"));
+    assert_eq!(commit.author().name(), head.author().name());
+    assert_eq!(commit.parent_count(), 0);
+    assert_eq!(
+        paths(&commit.tree().unwrap()),
+        ["src/lib.rs", "src/main.rs"]
+    );
+}
+
+#[test]
+fn write_ref_chains_onto_its_previous_target() {
+    let (_dir, repo) = setup_repo();
+
+    let first = filter_tree_id(&repo, &["HEAD~", "src/**", "--write-ref", "refs/heads/src"]);
+    assert_eq!(repo.refname_to_id("refs/heads/src").unwrap(), first);
+    assert_eq!(repo.find_commit(first).unwrap().parent_count(), 0);
+
+    let second = filter_tree_id(&repo, &["HEAD", "src/**", "--write-ref", "refs/heads/src"]);
+    assert_eq!(repo.refname_to_id("refs/heads/src").unwrap(), second);
+    let commit = repo.find_commit(second).unwrap();
+    assert_eq!(commit.parent_id(0).unwrap(), first);
+    assert_eq!(
+        paths(&commit.tree().unwrap()),
+        ["src/lib.rs", "src/main.rs"]
+    );
+}
+
+#[test]
+fn history_rewrites_every_commit() {
+    let (_dir, repo) = setup_repo();
+
+    let tip = filter_tree_id(
+        &repo,
+        &[
+            "HEAD",
+            "README.md",
+            "--history",
+            "--write-ref",
+            "refs/heads/readme",
+        ],
+    );
+    assert_eq!(repo.refname_to_id("refs/heads/readme").unwrap(), tip);
+
+    let tip = repo.find_commit(tip).unwrap();
+    assert_eq!(tip.message(), Some("second"));
+    assert_eq!(paths(&tip.tree().unwrap()), ["README.md"]);
+    let parent = tip.parent(0).unwrap();
+    assert_eq!(parent.message(), Some("first"));
+    assert_eq!(parent.parent_count(), 0);
+}
+
+#[test]
+fn history_rejects_tree_options() {
+    let (_dir, repo) = setup_repo();
+
+    for option in [
+        &["--strip-prefix", "src"][..],
+        &["--add-prefix", "vendor"],
+        &["--format", "entries"],
+    ] {
+        let mut args = vec!["HEAD", "src/**", "--history"];
+        args.extend(option);
+        let output = filter_tree(&repo, &args);
+        assert_eq!(output.status.code(), Some(2), "{}", describe(&output));
+        assert!(
+            String::from_utf8_lossy(&output.stderr).contains("cannot be used with"),
+            "{}",
+            describe(&output)
+        );
+    }
+}
+
+#[test]
+fn skipped_subtrees_are_reported_once() {
+    let (dir, repo) = setup_repo();
+
+    let blob = repo.blob(b"content").unwrap();
+    let mut sub = repo.treebuilder(None).unwrap();
+    sub.insert("lost.rs", blob, 0o100644).unwrap();
+    let sub = sub.write().unwrap();
+    let mut root = repo.treebuilder(None).unwrap();
+    root.insert("file.rs", blob, 0o100644).unwrap();
+    root.insert("lost", sub, 0o040000).unwrap();
+    let tree = root.write().unwrap();
+
+    // Remove the subtree object so that reading it fails.
+    let hex = sub.to_string();
+    let objects = dir.path().join(".git").join("objects");
+    fs::remove_file(objects.join(&hex[..2]).join(&hex[2..])).unwrap();
+
+    let output = filter_tree(&repo, &[&tree.to_string(), "*.rs", "--on-error", "skip"]);
+    assert!(output.status.success(), "{}", describe(&output));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(stderr, "warning: skipped unreadable tree 'lost'\n");
+
+    let id = Oid::from_str(String::from_utf8_lossy(&output.stdout).trim()).unwrap();
+    assert_eq!(paths(&repo.find_tree(id).unwrap()), ["file.rs"]);
+}
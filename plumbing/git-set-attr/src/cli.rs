@@ -9,10 +9,35 @@ pub struct Cli {
     pub pattern: String,
 
     /// Attributes to set (e.g. "diff", "-text", "filter=lfs")
-    #[arg(required = true)]
+    #[arg(required_unless_present_any = ["unset", "get"])]
     pub attributes: Vec<String>,
 
     /// Path to the .gitattributes file to modify
     #[arg(short, long)]
     pub file: Option<PathBuf>,
+
+    /// Rewrite the pattern's existing values in place instead of appending
+    /// ("!attr" removes an attribute)
+    #[arg(short, long, conflicts_with = "unset")]
+    pub replace: bool,
+
+    /// Remove this attribute from the pattern's lines (repeatable)
+    #[arg(short, long, value_name = "NAME", conflicts_with = "attributes")]
+    pub unset: Vec<String>,
+
+    /// Define PATTERN as a macro for ATTRIBUTES (top-level .gitattributes only)
+    #[arg(short, long = "macro", conflicts_with_all = ["replace", "unset"])]
+    pub define_macro: bool,
+
+    /// Afterwards sort and align the lines of the file (see `canonicalize`)
+    #[arg(long)]
+    pub canonicalize: bool,
+
+    /// Print the attributes in effect for PATTERN, read as a path
+    #[arg(
+        short,
+        long,
+        conflicts_with_all = ["attributes", "unset", "replace", "file", "define_macro", "canonicalize"]
+    )]
+    pub get: bool,
 }
@@ -12,32 +12,123 @@
 //! 5. local (untracked, unreplicable) Git configuration files in `.git/`.
 //!
 //! When *writing* attribute values, users typically write to repository
-//! configuration files.
-
+//! configuration files. Files are updated atomically under a `<file>.lock`
+//! lock (see [`LockFile`]), so concurrent writers do not clobber each other.
+//!
+//! # Macros
+//!
+//! A line of the form `[attr]name attributes...` defines the macro attribute
+//! `name`; setting it on a pattern sets each of its attributes. Git only reads
+//! macro definitions from the top-level `.gitattributes` file and
+//! `$GIT_DIR/info/attributes`. Macros are defined with
+//! [`SetAttr::define_macro`], and set macros (including the built-in
+//! `binary`) are expanded when checking whether an attribute is already set.
+
+use git2::{AttrCheckFlags, AttrValue};
 pub use git2::{Error, Repository};
 use std::{
-    fs::{self, OpenOptions},
-    io::{BufRead, BufReader, Write},
+    borrow::Cow,
+    collections::HashMap,
+    fs,
     path::{Path, PathBuf},
 };
 
 #[cfg(feature = "cli")]
 pub mod cli;
+mod lockfile;
+mod path;
+
+pub use lockfile::LockFile;
+pub use path::normalize_path;
 
 /// A trait which provides methods for settings attributes in a Git repository.
 pub trait SetAttr {
     /// Set attributes in the appropriate `.gitattributes` file.
     ///
-    /// The `.gitattributes` file in the current directory is used if one exists;
-    /// otherwise, the `.gitattributes` file found first while
-    /// walking up the directory tree from the current directory to the
-    /// repository's root directory is used.
+    /// Unless `gitattributes` is given, the `.gitattributes` file in the
+    /// current directory is used if one exists; otherwise, the
+    /// `.gitattributes` file found first while walking up the directory tree
+    /// from the current directory to the repository's root directory is used.
+    /// Library users that must not depend on the process's current directory
+    /// pass the file found by [`find_gitattributes_file`] instead.
     fn set_attr(
         &self,
         pattern: &str,
         attributes: &[&str],
         gitattributes: Option<&Path>,
     ) -> Result<(), Error>;
+
+    /// Set the attributes of many patterns like [`set_attr`](Self::set_attr),
+    /// in order, reading and writing the `.gitattributes` file only once.
+    ///
+    /// Every pattern and attribute is validated before the file is touched,
+    /// so an invalid edit leaves it unchanged.
+    fn set_attrs_batch(
+        &self,
+        edits: &[(&str, &[&str])],
+        gitattributes: Option<&Path>,
+    ) -> Result<(), Error>;
+
+    /// Set attributes like [`set_attr`](Self::set_attr), but rewrite the
+    /// existing line for `pattern` in place.
+    ///
+    /// Every attribute named in `attributes` replaces the values of that name
+    /// already given for `pattern`, keeping the position of the first one; an
+    /// attribute given as `!attr` is removed instead. Further lines for
+    /// `pattern` lose the replaced attributes and are dropped once empty. A
+    /// new line is only appended when no line for `pattern` exists.
+    fn replace_attr(
+        &self,
+        pattern: &str,
+        attributes: &[&str],
+        gitattributes: Option<&Path>,
+    ) -> Result<(), Error>;
+
+    /// Remove the attributes named in `names` from every line for `pattern`,
+    /// dropping lines left without attributes and keeping the other
+    /// attributes in place. Unlike `-attr`, this leaves no trace of the
+    /// attributes in the file. A missing file is left alone.
+    ///
+    /// Names may be given in any attribute syntax (`diff`, `-diff`,
+    /// `filter=lfs`); only the name is used.
+    fn unset_attr(
+        &self,
+        pattern: &str,
+        names: &[&str],
+        gitattributes: Option<&Path>,
+    ) -> Result<(), Error>;
+
+    /// Return the attributes in effect for `path`, relative to the root of
+    /// the working tree, sorted by name and written as in `.gitattributes`
+    /// (`diff`, `-text`, `filter=lfs`).
+    ///
+    /// Attribute names are collected from the `.gitattributes` files of the
+    /// directories containing `path`, `$GIT_DIR/info/attributes` and
+    /// `core.attributesFile`; their values are resolved in Git's precedence
+    /// order, so a deeper file overrides a shallower one and a later line an
+    /// earlier one. Unspecified attributes are omitted.
+    ///
+    /// Named to avoid clashing with git2's `Repository::get_attr`, which looks
+    /// up a single attribute.
+    fn get_attrs(&self, path: &Path) -> Result<Vec<String>, Error>;
+
+    /// Define the macro attribute `name` as `attributes`, replacing any
+    /// earlier definition of it.
+    ///
+    /// The definition is written to the top-level `.gitattributes` file
+    /// unless `gitattributes` is given, which must then be that file or
+    /// `$GIT_DIR/info/attributes`, as Git ignores macros elsewhere.
+    fn define_macro(
+        &self,
+        name: &str,
+        attributes: &[&str],
+        gitattributes: Option<&Path>,
+    ) -> Result<(), Error>;
+
+    /// Rewrite the `.gitattributes` file in its [canonical](canonicalize)
+    /// form, found as for [`set_attr`](Self::set_attr) unless `gitattributes`
+    /// is given.
+    fn canonicalize_attrs(&self, gitattributes: Option<&Path>) -> Result<(), Error>;
 }
 
 impl SetAttr for Repository {
@@ -46,78 +137,476 @@ impl SetAttr for Repository {
         pattern: &str,
         attributes: &[&str],
         gitattributes: Option<&Path>,
+    ) -> Result<(), Error> {
+        self.set_attrs_batch(&[(pattern, attributes)], gitattributes)
+    }
+
+    fn set_attrs_batch(
+        &self,
+        edits: &[(&str, &[&str])],
+        gitattributes: Option<&Path>,
     ) -> Result<(), Error> {
         let gitattributes_path = if let Some(path) = gitattributes {
             path.to_path_buf()
         } else {
-            find_gitattributes_file(self)?
+            find_gitattributes_file(self, None)?
         };
 
+        for (pattern, attributes) in edits {
+            validate_pattern(pattern)?;
+            validate_attributes(attributes)?;
+        }
+
+        let lock = LockFile::acquire(&gitattributes_path)?;
+        let (mut lines, style) = read_lines(&gitattributes_path)?;
+
+        for (pattern, attributes) in edits {
+            let new_attrs = filter_new_attributes(pattern, attributes, &lines);
+            if !new_attrs.is_empty() {
+                let attr_line = format_attribute_line(pattern, &new_attrs);
+                lines.push(attr_line);
+            }
+        }
+
+        write_lines(lock, &lines, style)
+    }
+
+    fn replace_attr(
+        &self,
+        pattern: &str,
+        attributes: &[&str],
+        gitattributes: Option<&Path>,
+    ) -> Result<(), Error> {
+        let gitattributes_path = if let Some(path) = gitattributes {
+            path.to_path_buf()
+        } else {
+            find_gitattributes_file(self, None)?
+        };
+
+        validate_pattern(pattern)?;
         validate_attributes(attributes)?;
 
-        let mut lines = if gitattributes_path.exists() {
-            let file = fs::File::open(&gitattributes_path)
-                .map_err(|e| Error::from_str(&format!("Failed to open .gitattributes: {e}")))?;
-            let reader = BufReader::new(file);
-            reader
-                .lines()
-                .collect::<Result<Vec<_>, _>>()
-                .map_err(|e| Error::from_str(&format!("Failed to read .gitattributes: {e}")))?
+        let lock = LockFile::acquire(&gitattributes_path)?;
+        let (mut lines, style) = read_lines(&gitattributes_path)?;
+        replace_attributes(pattern, attributes, &mut lines);
+        write_lines(lock, &lines, style)
+    }
+
+    fn unset_attr(
+        &self,
+        pattern: &str,
+        names: &[&str],
+        gitattributes: Option<&Path>,
+    ) -> Result<(), Error> {
+        let gitattributes_path = if let Some(path) = gitattributes {
+            path.to_path_buf()
         } else {
-            Vec::new()
+            find_gitattributes_file(self, None)?
         };
 
-        let new_attrs = filter_new_attributes(pattern, attributes, &lines);
+        validate_pattern(pattern)?;
+        validate_attributes(names)?;
+        if !gitattributes_path.exists() {
+            return Ok(());
+        }
+
+        let removals: Vec<String> = names
+            .iter()
+            .filter(|name| !name.trim().is_empty())
+            .map(|name| format!("!{}", parse_attribute_string(name).0))
+            .collect();
+        let removals: Vec<&str> = removals.iter().map(String::as_str).collect();
+
+        let lock = LockFile::acquire(&gitattributes_path)?;
+        let (mut lines, style) = read_lines(&gitattributes_path)?;
+        let before = lines.clone();
+        replace_attributes(pattern, &removals, &mut lines);
+        if lines == before {
+            return Ok(());
+        }
+        write_lines(lock, &lines, style)
+    }
+
+    fn get_attrs(&self, path: &Path) -> Result<Vec<String>, Error> {
+        let workdir = self
+            .workdir()
+            .ok_or_else(|| Error::from_str("Repository has no working directory"))?;
+
+        let mut files = vec![workdir.join(".gitattributes")];
+        let mut dir = workdir.to_path_buf();
+        for component in path.parent().into_iter().flat_map(Path::components) {
+            dir.push(component);
+            files.push(dir.join(".gitattributes"));
+        }
+        files.push(self.commondir().join("info").join("attributes"));
+        if let Ok(global) = self.config()?.get_path("core.attributesFile") {
+            files.push(global);
+        }
+
+        let mut names = Vec::new();
+        for file in &files {
+            for line in read_lines(file)?.0 {
+                names.extend(attribute_names(&line));
+            }
+        }
+        names.sort();
+        names.dedup();
+
+        let mut attributes = Vec::new();
+        for name in names {
+            let value = self.get_attr_bytes(path, &name, AttrCheckFlags::FILE_THEN_INDEX)?;
+            match AttrValue::from_bytes(value) {
+                AttrValue::True => attributes.push(name),
+                AttrValue::False => attributes.push(format!("-{name}")),
+                AttrValue::String(value) => attributes.push(format!("{name}={value}")),
+                AttrValue::Bytes(value) => {
+                    attributes.push(format!("{name}={}", String::from_utf8_lossy(value)))
+                }
+                AttrValue::Unspecified => {}
+            }
+        }
+        Ok(attributes)
+    }
+
+    fn define_macro(
+        &self,
+        name: &str,
+        attributes: &[&str],
+        gitattributes: Option<&Path>,
+    ) -> Result<(), Error> {
+        let workdir = self
+            .workdir()
+            .ok_or_else(|| Error::from_str("Repository has no working directory"))?;
+        let root = workdir.join(".gitattributes");
+        let info = self.commondir().join("info").join("attributes");
+        let gitattributes_path = match gitattributes {
+            None => root,
+            Some(path) if same_file(path, &root) || same_file(path, &info) => path.to_path_buf(),
+            Some(path) => {
+                return Err(Error::from_str(&format!(
+                    "Macros can only be defined in the top-level .gitattributes or \
+                     $GIT_DIR/info/attributes, not {}",
+                    path.display()
+                )));
+            }
+        };
+
+        if name.is_empty()
+            || name.starts_with(['-', '!'])
+            || name.contains(|c: char| c == '=' || c.is_whitespace())
+        {
+            return Err(Error::from_str(&format!("Invalid macro name '{name}'")));
+        }
+        validate_attributes(attributes)?;
+
+        let pattern = format!("{MACRO_PREFIX}{name}");
+        let definition = format_attribute_line(&pattern, attributes);
+        let lock = LockFile::acquire(&gitattributes_path)?;
+        let (mut lines, style) = read_lines(&gitattributes_path)?;
+        let mut defined = false;
+        lines.retain_mut(|line| {
+            if split_line(line).is_none_or(|(p, _)| p != pattern) {
+                return true;
+            }
+            if defined {
+                return false;
+            }
+            defined = true;
+            *line = definition.clone();
+            true
+        });
+        if !defined {
+            lines.push(definition);
+        }
+        write_lines(lock, &lines, style)
+    }
+
+    fn canonicalize_attrs(&self, gitattributes: Option<&Path>) -> Result<(), Error> {
+        let gitattributes_path = if let Some(path) = gitattributes {
+            path.to_path_buf()
+        } else {
+            find_gitattributes_file(self, None)?
+        };
+
+        let lock = LockFile::acquire(&gitattributes_path)?;
+        let (lines, style) = read_lines(&gitattributes_path)?;
+        let canonical = canonicalize(&lines);
+        if canonical == lines {
+            return Ok(());
+        }
+        write_lines(lock, &canonical, style)
+    }
+}
+
+/// Return `lines` in a canonical form, so that repeated edits by tools
+/// produce small diffs.
+///
+/// Within each block of attribute lines, delimited by blank lines and macro
+/// definitions (which keep their place), lines are stably sorted by pattern,
+/// whitespace between attributes is collapsed and attributes start in the
+/// same column. Comments directly above a line move with it.
+///
+/// Sorting changes which line wins when two patterns in a block match the
+/// same path and set the same attribute differently; keep such lines in
+/// separate blocks.
+pub fn canonicalize(lines: &[String]) -> Vec<String> {
+    let mut out = Vec::with_capacity(lines.len());
+    let mut block = Vec::new();
+    let mut comments = Vec::new();
+
+    for line in lines {
+        let line = line.trim_end();
+        if line.trim_start().starts_with('#') {
+            comments.push(line.to_string());
+            continue;
+        }
+        match split_line(line) {
+            Some((pattern, attrs)) if !pattern.starts_with(MACRO_PREFIX) => {
+                let attrs = attrs.split_whitespace().collect::<Vec<_>>().join(" ");
+                block.push(BlockLine {
+                    comments: std::mem::take(&mut comments),
+                    pattern,
+                    attrs,
+                });
+            }
+            _ => {
+                flush_block(&mut block, &mut out);
+                out.append(&mut comments);
+                out.push(line.to_string());
+            }
+        }
+    }
+    flush_block(&mut block, &mut out);
+    out.append(&mut comments);
+    out
+}
+
+/// An attribute line in a block being canonicalized.
+struct BlockLine {
+    comments: Vec<String>,
+    pattern: String,
+    attrs: String,
+}
+
+/// Sort and align a block of attribute lines into `out`.
+fn flush_block(block: &mut Vec<BlockLine>, out: &mut Vec<String>) {
+    block.sort_by(|a, b| a.pattern.cmp(&b.pattern));
+    let width = block
+        .iter()
+        .map(|line| quote_pattern(&line.pattern).chars().count())
+        .max()
+        .unwrap_or(0);
+    for line in block.drain(..) {
+        out.extend(line.comments);
+        let pattern = quote_pattern(&line.pattern);
+        if line.attrs.is_empty() {
+            out.push(pattern.into_owned());
+        } else {
+            out.push(format!("{pattern:width$} {}", line.attrs));
+        }
+    }
+}
+
+/// The prefix of a macro definition line.
+const MACRO_PREFIX: &str = "[attr]";
+
+/// Whether `a` and `b` name the same file, comparing normalized paths.
+fn same_file(a: &Path, b: &Path) -> bool {
+    normalize_path(a) == normalize_path(b)
+}
+
+/// Reject patterns that are macro definitions rather than path patterns.
+fn validate_pattern(pattern: &str) -> Result<(), Error> {
+    if pattern.starts_with(MACRO_PREFIX) {
+        return Err(Error::from_str(&format!(
+            "Invalid pattern '{pattern}': define macros with define_macro"
+        )));
+    }
+    Ok(())
+}
+
+/// Return the attribute names mentioned on a `.gitattributes` line,
+/// including macro names and the attributes of the built-in `binary` macro.
+fn attribute_names(line: &str) -> Vec<String> {
+    let Some((pattern, attrs)) = split_line(line) else {
+        return Vec::new();
+    };
 
-        if !new_attrs.is_empty() {
-            let attr_line = format_attribute_line(pattern, &new_attrs);
-            lines.push(attr_line);
+    let mut names = Vec::new();
+    if let Some(macro_name) = pattern.strip_prefix(MACRO_PREFIX) {
+        names.push(macro_name.to_string());
+    }
+    for part in attrs.split_whitespace() {
+        let (name, _) = parse_attribute_string(part);
+        if name == "binary" {
+            names.extend(["diff", "merge", "text"].map(String::from));
         }
+        names.push(name);
+    }
+    names
+}
+
+/// The line endings of a file, preserved when its lines are rewritten so that
+/// editing a file authored on Windows does not produce unrelated changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineStyle {
+    /// Lines end in `\r\n` rather than `\n`.
+    pub crlf: bool,
+    /// The last line is terminated.
+    pub final_newline: bool,
+}
 
-        let mut file = OpenOptions::new()
-            .write(true)
-            .create(true)
-            .truncate(true)
-            .open(&gitattributes_path)
-            .map_err(|e| {
-                Error::from_str(&format!("Failed to open .gitattributes for writing: {e}"))
-            })?;
+impl Default for LineStyle {
+    fn default() -> Self {
+        Self {
+            crlf: false,
+            final_newline: true,
+        }
+    }
+}
 
-        for line in lines {
-            writeln!(file, "{line}")
-                .map_err(|e| Error::from_str(&format!("Failed to write to .gitattributes: {e}")))?;
+impl LineStyle {
+    /// Detect the style of `content` from its first line ending and its end.
+    /// Content without any line ending gets the default style.
+    pub fn detect(content: &str) -> Self {
+        let Some(end) = content.find('\n') else {
+            return Self::default();
+        };
+        Self {
+            crlf: content[..end].ends_with('\r'),
+            final_newline: content.ends_with('\n'),
         }
+    }
 
-        file.flush()
-            .map_err(|e| Error::from_str(&format!("Failed to flush .gitattributes: {e}")))?;
+    /// Join `lines` into file content in this style. No lines yield an empty
+    /// file.
+    pub fn join(&self, lines: &[impl AsRef<str>]) -> String {
+        let eol = if self.crlf { "\r\n" } else { "\n" };
+        let mut content = lines
+            .iter()
+            .map(AsRef::as_ref)
+            .collect::<Vec<_>>()
+            .join(eol);
+        if self.final_newline && !lines.is_empty() {
+            content.push_str(eol);
+        }
+        content
+    }
+}
 
-        Ok(())
+/// Read the lines of the `.gitattributes` file at `path`, if it exists, along
+/// with its line style.
+fn read_lines(path: &Path) -> Result<(Vec<String>, LineStyle), Error> {
+    if !path.exists() {
+        return Ok((Vec::new(), LineStyle::default()));
+    }
+
+    let content = fs::read_to_string(path)
+        .map_err(|e| Error::from_str(&format!("Failed to read .gitattributes: {e}")))?;
+    let lines = content.lines().map(str::to_string).collect();
+    Ok((lines, LineStyle::detect(&content)))
+}
+
+/// Write `lines` in `style` to the `.gitattributes` file held by `lock`.
+fn write_lines(lock: LockFile, lines: &[String], style: LineStyle) -> Result<(), Error> {
+    lock.commit(&style.join(lines))
+}
+
+/// Rewrite the lines for `pattern` in `lines` so that the attributes named in
+/// `attributes` take exactly the given values. See [`SetAttr::replace_attr`].
+fn replace_attributes(pattern: &str, attributes: &[&str], lines: &mut Vec<String>) {
+    let attributes: Vec<&str> = attributes
+        .iter()
+        .map(|attr| attr.trim())
+        .filter(|attr| !attr.is_empty())
+        .collect();
+    let name_of = |attr: &str| parse_attribute_string(attr).0;
+    let names: Vec<String> = attributes.iter().map(|attr| name_of(attr)).collect();
+
+    let mut emitted = vec![false; attributes.len()];
+    let mut first = None;
+    let mut index = 0;
+    while index < lines.len() {
+        let parts = match split_line(&lines[index]) {
+            Some((line_pattern, attrs)) if line_pattern == pattern => attrs,
+            _ => {
+                index += 1;
+                continue;
+            }
+        };
+
+        let mut kept = Vec::new();
+        for part in parts.split_whitespace() {
+            let name = name_of(part);
+            if !names.contains(&name) {
+                kept.push(part.to_string());
+                continue;
+            }
+            if first.is_some() {
+                continue;
+            }
+            for (i, attr) in attributes.iter().enumerate() {
+                if !emitted[i] && names[i] == name {
+                    emitted[i] = true;
+                    if !attr.starts_with('!') {
+                        kept.push(attr.to_string());
+                    }
+                }
+            }
+        }
+
+        if first.is_none() {
+            first = Some(index);
+        } else if kept.is_empty() {
+            lines.remove(index);
+            continue;
+        }
+        let line = format_attribute_line(pattern, &kept);
+        lines[index] = line;
+        index += 1;
+    }
+
+    let remaining: Vec<&str> = attributes
+        .iter()
+        .zip(&emitted)
+        .filter(|(attr, emitted)| !**emitted && !attr.starts_with('!'))
+        .map(|(attr, _)| *attr)
+        .collect();
+    match first {
+        Some(index) => {
+            let mut line = lines[index].clone();
+            for attr in remaining {
+                line.push(' ');
+                line.push_str(attr);
+            }
+            lines[index] = line;
+            if split_line(&lines[index]).is_some_and(|(_, attrs)| attrs.trim().is_empty()) {
+                lines.remove(index);
+            }
+        }
+        None if !remaining.is_empty() => lines.push(format_attribute_line(pattern, &remaining)),
+        None => {}
     }
 }
 
 /// Filter out attributes that already exist for the given pattern.
 ///
 /// Parses every existing line that matches `pattern` and collects its
-/// attribute name/state pairs, then returns only those entries from
-/// `attributes` whose state differs (or that are completely new).
+/// attribute name/state pairs, expanding set macros defined in `lines` (and
+/// the built-in `binary`), then returns only those entries from `attributes`
+/// whose state differs (or that are completely new).
 fn filter_new_attributes(pattern: &str, attributes: &[&str], lines: &[String]) -> Vec<String> {
-    use std::collections::HashMap;
-
+    let macros = parse_macros(lines);
     let mut existing_attrs: HashMap<String, String> = HashMap::new();
 
     for line in lines {
-        let trimmed = line.trim();
-        if trimmed.is_empty() || trimmed.starts_with('#') {
+        let Some((line_pattern, attrs)) = split_line(line) else {
             continue;
-        }
-
-        let mut parts = trimmed.split_whitespace();
-        let line_pattern = parts.next().unwrap_or("");
+        };
 
         if line_pattern == pattern {
-            for attr_str in parts {
-                let (name, state) = parse_attribute_string(attr_str);
-                existing_attrs.insert(name, state);
+            for attr_str in attrs.split_whitespace() {
+                insert_expanded(&mut existing_attrs, &macros, attr_str, 0);
             }
         }
     }
@@ -139,6 +628,53 @@ fn filter_new_attributes(pattern: &str, attributes: &[&str], lines: &[String]) -
     new_attrs
 }
 
+/// Collect the macro definitions in `lines`, starting from Git's built-in
+/// `binary` macro. Later definitions replace earlier ones.
+fn parse_macros(lines: &[String]) -> HashMap<String, Vec<String>> {
+    let mut macros = HashMap::new();
+    macros.insert(
+        "binary".to_string(),
+        vec![
+            "-diff".to_string(),
+            "-merge".to_string(),
+            "-text".to_string(),
+        ],
+    );
+
+    for line in lines {
+        let Some((pattern, attrs)) = split_line(line) else {
+            continue;
+        };
+        if let Some(name) = pattern.strip_prefix(MACRO_PREFIX) {
+            let attrs = attrs.split_whitespace().map(str::to_string).collect();
+            macros.insert(name.to_string(), attrs);
+        }
+    }
+    macros
+}
+
+/// Record the state of `attr_str` in `attrs`, followed by the attributes of
+/// the macro it sets, if any.
+fn insert_expanded(
+    attrs: &mut HashMap<String, String>,
+    macros: &HashMap<String, Vec<String>>,
+    attr_str: &str,
+    depth: usize,
+) {
+    let (name, state) = parse_attribute_string(attr_str);
+    let expansion = macros.get(&name).filter(|_| state == "set");
+    attrs.insert(name, state);
+
+    // Macros may set other macros; bound the depth against cycles.
+    if let Some(expansion) = expansion
+        && depth < 8
+    {
+        for attr in expansion {
+            insert_expanded(attrs, macros, attr, depth + 1);
+        }
+    }
+}
+
 /// Parse an attribute string to extract name and state.
 ///
 /// Returns `(name, state_string)` where `state_string` uniquely identifies
@@ -200,9 +736,104 @@ fn validate_attributes(attributes: &[&str]) -> Result<(), Error> {
     Ok(())
 }
 
-/// Format a pattern and attributes into a gitattributes line.
+/// Split a `.gitattributes` line into its pattern and the attributes that
+/// follow it, or return `None` for blank and comment lines.
+///
+/// As in Git, a pattern starting with `"` is unquoted C-style (`"my
+/// dir/*.txt"`, with `\"`, `\\`, `\t`, octal escapes and so on); if the
+/// quoting is malformed, the pattern is read up to the first whitespace.
+pub fn split_line(line: &str) -> Option<(String, &str)> {
+    let trimmed = line.trim_start();
+    if trimmed.is_empty() || trimmed.starts_with('#') {
+        return None;
+    }
+
+    if let Some((pattern, rest)) = unquote_c_style(trimmed) {
+        return Some((pattern, rest));
+    }
+    let end = trimmed.find(char::is_whitespace).unwrap_or(trimmed.len());
+    Some((trimmed[..end].to_string(), &trimmed[end..]))
+}
+
+/// Quote `pattern` C-style if it cannot be written to a `.gitattributes`
+/// line as is: when it contains whitespace or control characters, or starts
+/// with `"`.
+pub fn quote_pattern(pattern: &str) -> Cow<'_, str> {
+    let needs_quoting =
+        pattern.starts_with('"') || pattern.chars().any(|c| c.is_whitespace() || c.is_control());
+    if !needs_quoting {
+        return Cow::Borrowed(pattern);
+    }
+
+    let mut quoted = String::from('"');
+    for c in pattern.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\x07' => quoted.push_str("\\a"),
+            '\x08' => quoted.push_str("\\b"),
+            '\x0c' => quoted.push_str("\\f"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            '\x0b' => quoted.push_str("\\v"),
+            c if c.is_control() && c.is_ascii() => quoted.push_str(&format!("\\{:03o}", c as u8)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    Cow::Owned(quoted)
+}
+
+/// Unquote a C-style quoted string at the start of `s`, returning it and the
+/// rest of `s`, or `None` if `s` does not start with a well-formed quoted
+/// string.
+fn unquote_c_style(s: &str) -> Option<(String, &str)> {
+    let body = s.strip_prefix('"')?;
+    let mut bytes = Vec::new();
+    let mut chars = body.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => {
+                let pattern = String::from_utf8(bytes).ok()?;
+                return Some((pattern, &body[i + 1..]));
+            }
+            '\\' => {
+                let (_, escaped) = chars.next()?;
+                let byte = match escaped {
+                    'a' => 0x07,
+                    'b' => 0x08,
+                    'f' => 0x0c,
+                    'n' => b'\n',
+                    'r' => b'\r',
+                    't' => b'\t',
+                    'v' => 0x0b,
+                    '"' | '\\' => escaped as u8,
+                    '0'..='3' => {
+                        let mut value = escaped.to_digit(8)?;
+                        for _ in 0..2 {
+                            let (_, digit) = chars.next()?;
+                            value = value * 8 + digit.to_digit(8)?;
+                        }
+                        value as u8
+                    }
+                    _ => return None,
+                };
+                bytes.push(byte);
+            }
+            c => {
+                let mut buf = [0; 4];
+                bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            }
+        }
+    }
+    None
+}
+
+/// Format a pattern and attributes into a gitattributes line, quoting the
+/// pattern if necessary.
 fn format_attribute_line(pattern: &str, attributes: &[impl AsRef<str>]) -> String {
-    let mut line = pattern.to_string();
+    let mut line = quote_pattern(pattern).into_owned();
 
     for attr in attributes {
         let attr = attr.as_ref().trim();
@@ -217,21 +848,29 @@ fn format_attribute_line(pattern: &str, attributes: &[impl AsRef<str>]) -> Strin
     line
 }
 
-/// Find the appropriate `.gitattributes` file by walking from the current
-/// directory up to the repository root.
+/// Find the appropriate `.gitattributes` file by walking from `at_dir` up to
+/// the repository root. A relative `at_dir` is relative to the root of the
+/// working tree; without one, the current directory is used.
 ///
 /// Returns the path of the first `.gitattributes` file found, or defaults to
-/// `<current_dir>/.gitattributes` (which will be created on first write).
-fn find_gitattributes_file(repo: &Repository) -> Result<PathBuf, Error> {
+/// `<at_dir>/.gitattributes` (which will be created on first write).
+pub fn find_gitattributes_file(repo: &Repository, at_dir: Option<&Path>) -> Result<PathBuf, Error> {
     let workdir = repo
         .workdir()
         .ok_or_else(|| Error::from_str("Repository has no working directory"))?;
 
-    let current_dir = std::env::current_dir()
-        .map_err(|e| Error::from_str(&format!("Failed to get current directory: {e}")))?;
-
-    let mut dir = current_dir.as_path();
-    while dir.starts_with(workdir) {
+    let start = match at_dir {
+        Some(dir) => workdir.join(dir),
+        None => std::env::current_dir()
+            .map_err(|e| Error::from_str(&format!("Failed to get current directory: {e}")))?,
+    };
+
+    // Compare normalized paths: the working directory from libgit2 and the
+    // current directory may spell the same location differently.
+    let workdir = normalize_path(workdir);
+    let start = normalize_path(&start);
+    let mut dir = start.as_path();
+    while dir.starts_with(&workdir) {
         let gitattributes = dir.join(".gitattributes");
         if gitattributes.exists() {
             return Ok(gitattributes);
@@ -243,8 +882,8 @@ fn find_gitattributes_file(repo: &Repository) -> Result<PathBuf, Error> {
         }
     }
 
-    // No .gitattributes found; default to one in the current directory.
-    Ok(current_dir.join(".gitattributes"))
+    // No .gitattributes found; default to one in the starting directory.
+    Ok(start.join(".gitattributes"))
 }
 
 #[cfg(test)]
@@ -444,6 +1083,160 @@ mod tests {
         assert_eq!(result, vec!["-text"]);
     }
 
+    #[test]
+    fn replace_rewrites_values_in_place() {
+        let mut lines = vec!["*.txt diff filter=foo -text".into(), "*.md text".into()];
+        replace_attributes("*.txt", &["filter=bar", "eol=lf"], &mut lines);
+        assert_eq!(
+            lines,
+            vec!["*.txt diff filter=bar -text eol=lf", "*.md text"]
+        );
+    }
+
+    #[test]
+    fn replace_merges_later_lines_for_the_pattern() {
+        let mut lines = vec![
+            "*.txt filter=foo".into(),
+            "# comment".into(),
+            "*.txt filter=bar".into(),
+            "*.txt filter=baz diff".into(),
+        ];
+        replace_attributes("*.txt", &["filter=qux"], &mut lines);
+        assert_eq!(lines, vec!["*.txt filter=qux", "# comment", "*.txt diff"]);
+    }
+
+    #[test]
+    fn replace_removes_unspecified_attributes() {
+        let mut lines = vec!["*.txt diff filter=foo".into()];
+        replace_attributes("*.txt", &["!filter"], &mut lines);
+        assert_eq!(lines, vec!["*.txt diff"]);
+        replace_attributes("*.txt", &["!diff"], &mut lines);
+        assert!(lines.is_empty());
+    }
+
+    #[test]
+    fn replace_keeps_repeated_attributes_together() {
+        let mut lines = vec!["*.txt m=a diff m=b".into()];
+        replace_attributes("*.txt", &["m=c", "m=d"], &mut lines);
+        assert_eq!(lines, vec!["*.txt m=c m=d diff"]);
+    }
+
+    #[test]
+    fn replace_appends_line_for_new_pattern() {
+        let mut lines = vec!["*.md text".into()];
+        replace_attributes("*.txt", &["diff", "!eol"], &mut lines);
+        assert_eq!(lines, vec!["*.md text", "*.txt diff"]);
+    }
+
+    #[test]
+    fn filter_expands_set_macros() {
+        let lines = vec![
+            "[attr]vendored-dep vendored diff=off".into(),
+            "*.txt vendored-dep".into(),
+            "*.bin binary".into(),
+        ];
+        assert!(filter_new_attributes("*.txt", &["vendored", "diff=off"], &lines).is_empty());
+        assert_eq!(
+            filter_new_attributes("*.txt", &["diff=on"], &lines),
+            vec!["diff=on"]
+        );
+        assert!(filter_new_attributes("*.bin", &["-diff", "text=false"], &lines).is_empty());
+    }
+
+    #[test]
+    fn filter_ignores_unset_macros() {
+        let lines = vec!["[attr]m diff".into(), "*.txt -m".into()];
+        assert_eq!(
+            filter_new_attributes("*.txt", &["diff"], &lines),
+            vec!["diff"]
+        );
+    }
+
+    #[test]
+    fn validate_rejects_macro_patterns() {
+        assert!(validate_pattern("[attr]m").is_err());
+        assert!(validate_pattern("*.txt").is_ok());
+    }
+
+    #[test]
+    fn quoted_patterns_round_trip() {
+        for pattern in [
+            "my dir/*.txt",
+            "\"quoted\"",
+            "tab\there",
+            "back\\slash x",
+            "\x01",
+        ] {
+            let line = format_attribute_line(pattern, &["diff"]);
+            assert_eq!(
+                split_line(&line),
+                Some((pattern.to_string(), " diff")),
+                "{line}"
+            );
+        }
+        assert_eq!(quote_pattern("*.txt"), "*.txt");
+        assert_eq!(quote_pattern("a b"), "\"a b\"");
+        assert_eq!(quote_pattern("a\\ b"), "\"a\\\\ b\"");
+    }
+
+    #[test]
+    fn split_line_unquotes_like_git() {
+        assert_eq!(split_line("  *.txt diff"), Some(("*.txt".into(), " diff")));
+        assert_eq!(
+            split_line("\"a\\101\\tb\" diff"),
+            Some(("aA\tb".into(), " diff"))
+        );
+        // Malformed quoting falls back to whitespace splitting.
+        assert_eq!(split_line("\"a b diff"), Some(("\"a".into(), " b diff")));
+        assert_eq!(split_line("# comment"), None);
+        assert_eq!(split_line("   "), None);
+    }
+
+    #[test]
+    fn canonicalize_sorts_and_aligns_blocks() {
+        let lines: Vec<String> = [
+            "# vendored",
+            "",
+            "# note",
+            "*.txt   vendored  vendor-name=b",
+            "\"my dir/*\" vendored vendor-name=a",
+            "*.md vendored",
+            "",
+            "[attr]m   diff",
+            "z diff",
+            "a",
+        ]
+        .map(String::from)
+        .to_vec();
+        assert_eq!(
+            canonicalize(&lines),
+            vec![
+                "# vendored",
+                "",
+                "*.md       vendored",
+                "# note",
+                "*.txt      vendored vendor-name=b",
+                "\"my dir/*\" vendored vendor-name=a",
+                "",
+                "[attr]m   diff",
+                "a",
+                "z diff",
+            ]
+        );
+        let canonical = canonicalize(&lines);
+        assert_eq!(canonicalize(&canonical), canonical);
+    }
+
+    #[test]
+    fn line_style_round_trips() {
+        for content in ["a\r\nb\r\n", "a\r\nb", "a\nb", "a\nb\n"] {
+            let lines: Vec<&str> = content.lines().collect();
+            assert_eq!(LineStyle::detect(content).join(&lines), content);
+        }
+        assert_eq!(LineStyle::detect(""), LineStyle::default());
+        assert_eq!(LineStyle::detect("a\r\nb").join(&[] as &[&str]), "");
+    }
+
     #[test]
     fn filter_distinguishes_different_values() {
         let lines = vec!["*.txt filter=foo".into()];
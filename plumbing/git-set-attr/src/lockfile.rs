@@ -0,0 +1,144 @@
+//! Atomic, exclusive file updates in the style of Git's `<file>.lock` files.
+//!
+//! Acquiring a [`LockFile`] creates `<path>.lock` exclusively, so concurrent
+//! writers wait for each other instead of clobbering each other's changes.
+//! The new content is written to the lock file, synced to disk and renamed
+//! over `path`, so readers and crashes only ever see the old or new content.
+
+use git2::Error;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+/// How long to wait for another process to release a lock.
+const LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to sleep between attempts to take a lock.
+const LOCK_RETRY: Duration = Duration::from_millis(20);
+
+/// An exclusive lock on a file, released when dropped.
+#[derive(Debug)]
+pub struct LockFile {
+    path: PathBuf,
+    lock_path: PathBuf,
+    file: Option<File>,
+}
+
+impl LockFile {
+    /// Lock `path` for updating, waiting for a concurrent writer to finish.
+    ///
+    /// Fails if the lock is still held after a few seconds, which usually
+    /// means a crashed process left `<path>.lock` behind.
+    pub fn acquire(path: &Path) -> Result<Self, Error> {
+        let mut lock_path = path.as_os_str().to_owned();
+        lock_path.push(".lock");
+        let lock_path = PathBuf::from(lock_path);
+
+        let mut waited = Duration::ZERO;
+        loop {
+            match OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&lock_path)
+            {
+                Ok(file) => {
+                    return Ok(Self {
+                        path: path.to_path_buf(),
+                        lock_path,
+                        file: Some(file),
+                    });
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists && waited < LOCK_TIMEOUT => {
+                    thread::sleep(LOCK_RETRY);
+                    waited += LOCK_RETRY;
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                    return Err(Error::from_str(&format!(
+                        "Unable to lock {}: {} exists. If no other git process is \
+                         running, remove it and try again",
+                        path.display(),
+                        lock_path.display()
+                    )));
+                }
+                Err(e) => {
+                    return Err(Error::from_str(&format!(
+                        "Failed to create {}: {e}",
+                        lock_path.display()
+                    )));
+                }
+            }
+        }
+    }
+
+    /// Replace the locked file with `content` and release the lock.
+    pub fn commit(mut self, content: &str) -> Result<(), Error> {
+        let mut file = self.file.take().expect("lock file is open until committed");
+        let write_error = |e: std::io::Error| {
+            Error::from_str(&format!("Failed to write {}: {e}", self.path.display()))
+        };
+
+        let written = file
+            .write_all(content.as_bytes())
+            .and_then(|()| file.sync_all())
+            .and_then(|()| {
+                drop(file);
+                fs::rename(&self.lock_path, &self.path)
+            });
+        if let Err(e) = written {
+            let _ = fs::remove_file(&self.lock_path);
+            return Err(write_error(e));
+        }
+
+        // Persist the rename itself; not all platforms can open directories.
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty())
+            && let Ok(dir) = File::open(dir)
+        {
+            let _ = dir.sync_all();
+        }
+        Ok(())
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            let _ = fs::remove_file(&self.lock_path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn commit_replaces_file_and_releases_lock() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join(".gitattributes");
+        fs::write(&path, "old\n").unwrap();
+
+        let lock = LockFile::acquire(&path).unwrap();
+        assert!(dir.path().join(".gitattributes.lock").exists());
+        lock.commit("new\n").unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new\n");
+        assert!(!dir.path().join(".gitattributes.lock").exists());
+    }
+
+    #[test]
+    fn dropping_releases_lock_without_writing() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join(".gitattributes");
+        fs::write(&path, "old\n").unwrap();
+
+        drop(LockFile::acquire(&path).unwrap());
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old\n");
+        assert!(LockFile::acquire(&path).is_ok());
+    }
+}
@@ -22,8 +22,28 @@ fn run() -> Result<(), Box<dyn std::error::Error>> {
     // Convert attributes to string slices
     let attributes: Vec<&str> = cli.attributes.iter().map(|s| s.as_str()).collect();
 
+    if cli.get {
+        for attr in repo.get_attrs(std::path::Path::new(&cli.pattern))? {
+            println!("{}: {attr}", cli.pattern);
+        }
+        return Ok(());
+    }
+
     // Set attributes in the appropriate .gitattributes file
-    repo.set_attr(&cli.pattern, &attributes, cli.file.as_deref())?;
+    if cli.define_macro {
+        repo.define_macro(&cli.pattern, &attributes, cli.file.as_deref())?;
+    } else if !cli.unset.is_empty() {
+        let names: Vec<&str> = cli.unset.iter().map(|s| s.as_str()).collect();
+        repo.unset_attr(&cli.pattern, &names, cli.file.as_deref())?;
+    } else if cli.replace {
+        repo.replace_attr(&cli.pattern, &attributes, cli.file.as_deref())?;
+    } else {
+        repo.set_attr(&cli.pattern, &attributes, cli.file.as_deref())?;
+    }
+
+    if cli.canonicalize {
+        repo.canonicalize_attrs(cli.file.as_deref())?;
+    }
 
     Ok(())
 }
@@ -0,0 +1,149 @@
+//! Normalized paths, so that working-tree paths reported by libgit2, the
+//! environment and callers compare equal on every platform.
+//!
+//! On Windows, libgit2 reports the working directory with forward slashes,
+//! the current directory may differ from it in drive-letter case or use a UNC
+//! share, and [`fs::canonicalize`] returns verbatim (`\\?\`) paths that no
+//! other path starts with. [`normalize_path`] resolves all of these to one
+//! form, like the `dunce` crate.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Paths at least this long need the verbatim prefix on Windows.
+const MAX_PATH: usize = 260;
+
+/// Device names Windows reserves in every directory.
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Return `path` with symlinks, `.` and `..` resolved and, on Windows, without
+/// a verbatim prefix where the path can be written without one.
+///
+/// Only the longest existing ancestor is canonicalized; the remaining
+/// components, which may not exist yet, are appended unchanged. A path with
+/// no existing ancestor is returned as is.
+pub fn normalize_path(path: &Path) -> PathBuf {
+    let mut existing = path;
+    let mut missing = Vec::new();
+    loop {
+        if let Ok(canonical) = fs::canonicalize(existing) {
+            let mut normalized = if cfg!(windows) {
+                strip_verbatim(&canonical)
+            } else {
+                canonical
+            };
+            normalized.extend(missing.iter().rev());
+            return normalized;
+        }
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                missing.push(name);
+                existing = parent;
+            }
+            _ => return path.to_path_buf(),
+        }
+    }
+}
+
+/// Remove the verbatim prefix from `\\?\C:\...` and `\\?\UNC\server\...`
+/// paths, unless the path is only valid with it.
+fn strip_verbatim(path: &Path) -> PathBuf {
+    let Some(s) = path.to_str() else {
+        return path.to_path_buf();
+    };
+    let stripped = if let Some(rest) = s.strip_prefix(r"\\?\UNC\") {
+        format!(r"\\{rest}")
+    } else if let Some(rest) = s.strip_prefix(r"\\?\")
+        && is_drive_path(rest)
+    {
+        rest.to_string()
+    } else {
+        return path.to_path_buf();
+    };
+
+    let valid = stripped.len() < MAX_PATH
+        && !stripped.contains('/')
+        && stripped
+            .split('\\')
+            .skip(1)
+            .filter(|name| !name.is_empty())
+            .all(is_valid_name);
+    if valid {
+        PathBuf::from(stripped)
+    } else {
+        path.to_path_buf()
+    }
+}
+
+/// Whether `s` starts with a drive letter followed by `:\`.
+fn is_drive_path(s: &str) -> bool {
+    let bytes = s.as_bytes();
+    bytes.len() >= 3 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':' && bytes[2] == b'\\'
+}
+
+/// Whether `name` can be used as a path component without a verbatim prefix:
+/// Windows strips trailing dots and spaces from such names, and reserves
+/// device names even with an extension.
+fn is_valid_name(name: &str) -> bool {
+    if name.ends_with(['.', ' ']) && name != "." && name != ".." {
+        return false;
+    }
+    let stem = name.split('.').next().unwrap_or(name).trim_end();
+    !RESERVED_NAMES
+        .iter()
+        .any(|reserved| stem.eq_ignore_ascii_case(reserved))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn strip(s: &str) -> String {
+        strip_verbatim(Path::new(s)).to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn strip_verbatim_drive_and_unc_paths() {
+        assert_eq!(strip(r"\\?\C:\repo\src"), r"C:\repo\src");
+        assert_eq!(strip(r"\\?\UNC\server\share\repo"), r"\\server\share\repo");
+        assert_eq!(strip(r"C:\repo"), r"C:\repo");
+        assert_eq!(strip(r"\\?\Volume{1234}\repo"), r"\\?\Volume{1234}\repo");
+    }
+
+    #[test]
+    fn strip_verbatim_keeps_paths_that_need_it() {
+        assert_eq!(strip(r"\\?\C:\repo\con.txt"), r"\\?\C:\repo\con.txt");
+        assert_eq!(strip(r"\\?\C:\repo\dir."), r"\\?\C:\repo\dir.");
+        assert_eq!(strip(r"\\?\C:\repo\a/b"), r"\\?\C:\repo\a/b");
+        let long = format!(r"\\?\C:\{}", "a".repeat(MAX_PATH));
+        assert_eq!(strip(&long), long);
+    }
+
+    #[test]
+    fn normalize_path_resolves_existing_ancestor() {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        let canonical = fs::canonicalize(dir.path()).unwrap();
+
+        let path = dir
+            .path()
+            .join("sub")
+            .join("..")
+            .join("missing")
+            .join("file");
+        assert_eq!(
+            normalize_path(&path),
+            canonical.join("missing").join("file")
+        );
+        assert_eq!(
+            normalize_path(Path::new("no/such/relative")),
+            Path::new("no/such/relative")
+        );
+    }
+}
@@ -236,6 +236,42 @@ fn empty_attributes_is_noop() {
     assert_eq!(read(&ga), "*.txt diff\n", "file should be unchanged");
 }
 
+#[test]
+fn batch_applies_edits_in_order() {
+    let tmp = TempDir::new().unwrap();
+    let repo = Repository::init(&tmp).unwrap();
+    let ga = tmp.path().join(".gitattributes");
+
+    fs::write(&ga, "*.md text\n").unwrap();
+    repo.set_attrs_batch(
+        &[
+            ("*.txt", &["diff"]),
+            ("*.md", &["text", "eol=lf"]),
+            ("*.txt", &["diff", "-text"]),
+        ],
+        Some(&ga),
+    )
+    .unwrap();
+
+    assert_eq!(
+        read(&ga),
+        "*.md text\n*.txt diff\n*.md eol=lf\n*.txt -text\n"
+    );
+}
+
+#[test]
+fn batch_with_invalid_edit_leaves_file_unchanged() {
+    let tmp = TempDir::new().unwrap();
+    let repo = Repository::init(&tmp).unwrap();
+    let ga = tmp.path().join(".gitattributes");
+
+    fs::write(&ga, "*.md text\n").unwrap();
+    let result = repo.set_attrs_batch(&[("*.txt", &["diff"]), ("*.rs", &["has space"])], Some(&ga));
+
+    assert!(result.is_err());
+    assert_eq!(read(&ga), "*.md text\n");
+}
+
 #[test]
 fn multiple_calls_accumulate() {
     let tmp = TempDir::new().unwrap();
@@ -268,3 +304,125 @@ fn idempotent_over_repeated_calls() {
 
     assert_eq!(first, second, "repeated call should be idempotent");
 }
+
+#[test]
+fn replace_updates_existing_line_in_place() {
+    let tmp = TempDir::new().unwrap();
+    let repo = Repository::init(&tmp).unwrap();
+    let ga = tmp.path().join(".gitattributes");
+
+    fs::write(&ga, "*.txt diff filter=foo\n*.md text\n").unwrap();
+    repo.replace_attr("*.txt", &["filter=bar"], Some(&ga))
+        .unwrap();
+
+    assert_eq!(read(&ga), "*.txt diff filter=bar\n*.md text\n");
+
+    repo.replace_attr("*.rs", &["diff"], Some(&ga)).unwrap();
+    assert!(read(&ga).ends_with("*.rs diff\n"));
+}
+
+#[test]
+fn unset_removes_only_named_attributes() {
+    let tmp = TempDir::new().unwrap();
+    let repo = Repository::init(&tmp).unwrap();
+    let ga = tmp.path().join(".gitattributes");
+
+    fs::write(
+        &ga,
+        "*.txt diff filter=lfs -text\n*.txt filter=foo\n*.md filter=lfs\n",
+    )
+    .unwrap();
+    repo.unset_attr("*.txt", &["filter"], Some(&ga)).unwrap();
+
+    assert_eq!(read(&ga), "*.txt diff -text\n*.md filter=lfs\n");
+    repo.unset_attr("*.txt", &["-diff"], Some(&ga)).unwrap();
+    assert_eq!(read(&ga), "*.txt -text\n*.md filter=lfs\n");
+    assert!(repo.unset_attr("*.txt", &["my attr"], Some(&ga)).is_err());
+}
+
+#[test]
+fn get_reports_effective_attributes_in_precedence_order() {
+    let tmp = TempDir::new().unwrap();
+    let repo = Repository::init(&tmp).unwrap();
+    fs::create_dir(tmp.path().join("sub")).unwrap();
+
+    fs::write(
+        tmp.path().join(".gitattributes"),
+        "*.txt diff filter=lfs\n*.txt filter=foo\n*.md text\n",
+    )
+    .unwrap();
+    fs::write(
+        tmp.path().join("sub/.gitattributes"),
+        "*.txt -diff eol=lf\n",
+    )
+    .unwrap();
+
+    let get = |path: &str| repo.get_attrs(std::path::Path::new(path)).unwrap();
+    assert_eq!(get("a.txt"), vec!["diff", "filter=foo"]);
+    assert_eq!(get("sub/a.txt"), vec!["-diff", "eol=lf", "filter=foo"]);
+    assert!(get("a.rs").is_empty());
+}
+
+#[test]
+fn macros_are_defined_at_the_top_level_and_expanded() {
+    let tmp = TempDir::new().unwrap();
+    let repo = Repository::init(&tmp).unwrap();
+    let ga = tmp.path().join(".gitattributes");
+
+    repo.define_macro("vendored-dep", &["vendored", "diff=off"], None)
+        .unwrap();
+    repo.define_macro("vendored-dep", &["vendored", "-diff"], None)
+        .unwrap();
+    assert_eq!(read(&ga), "[attr]vendored-dep vendored -diff\n");
+
+    repo.set_attr("*.txt", &["vendored-dep"], Some(&ga))
+        .unwrap();
+    repo.set_attr("*.txt", &["-diff"], Some(&ga)).unwrap();
+    assert_eq!(read(&ga).lines().count(), 2, "{}", read(&ga));
+    assert_eq!(
+        repo.get_attrs(std::path::Path::new("a.txt")).unwrap(),
+        vec!["-diff", "vendored", "vendored-dep"]
+    );
+
+    fs::create_dir(tmp.path().join("sub")).unwrap();
+    let nested = tmp.path().join("sub/.gitattributes");
+    assert!(repo.define_macro("m", &["diff"], Some(&nested)).is_err());
+    assert!(repo.set_attr("[attr]m", &["diff"], Some(&ga)).is_err());
+}
+
+#[test]
+fn patterns_with_spaces_are_quoted() {
+    let tmp = TempDir::new().unwrap();
+    let repo = Repository::init(&tmp).unwrap();
+    let ga = tmp.path().join(".gitattributes");
+
+    repo.set_attr("my dir/*.txt", &["diff"], Some(&ga)).unwrap();
+    repo.set_attr("my dir/*.txt", &["diff", "-text"], Some(&ga))
+        .unwrap();
+    assert_eq!(read(&ga), "\"my dir/*.txt\" diff\n\"my dir/*.txt\" -text\n");
+}
+
+#[test]
+fn concurrent_writers_do_not_clobber_each_other() {
+    let tmp = TempDir::new().unwrap();
+    let ga = tmp.path().join(".gitattributes");
+    Repository::init(&tmp).unwrap();
+
+    let writers: Vec<_> = (0..8)
+        .map(|i| {
+            let (dir, ga) = (tmp.path().to_path_buf(), ga.clone());
+            std::thread::spawn(move || {
+                let repo = Repository::open(&dir).unwrap();
+                repo.set_attr(&format!("*.{i}"), &["diff"], Some(&ga))
+                    .unwrap();
+            })
+        })
+        .collect();
+    for writer in writers {
+        writer.join().unwrap();
+    }
+
+    let content = read(&ga);
+    assert_eq!(content.lines().count(), 8, "{content}");
+    assert!(!tmp.path().join(".gitattributes.lock").exists());
+}
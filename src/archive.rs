@@ -0,0 +1,552 @@
+//! Tar, gzipped tar and zip archives of vendored trees.
+//!
+//! Archives are reproducible: every entry carries the same modification time
+//! and no owner, and entries are written in path order. Compression uses
+//! deflate with the fixed Huffman codes, which trades some size for a small,
+//! dependency-free encoder.
+
+use crate::ArchiveFormat;
+use std::io::{self, Write};
+
+/// A file to archive.
+pub(crate) struct Entry {
+    pub(crate) path: String,
+    /// The Git file mode: `0o100644`, `0o100755` or `0o120000`.
+    pub(crate) mode: u32,
+    /// The file's content, or a symlink's target.
+    pub(crate) data: Vec<u8>,
+}
+
+impl Entry {
+    fn is_symlink(&self) -> bool {
+        self.mode == 0o120000
+    }
+
+    fn permissions(&self) -> u32 {
+        match self.mode {
+            0o100755 => 0o755,
+            0o120000 => 0o777,
+            _ => 0o644,
+        }
+    }
+}
+
+/// Write `entries` to `out` as an archive in `format`, dated `mtime` (seconds
+/// since the Unix epoch).
+pub(crate) fn write(
+    format: ArchiveFormat,
+    entries: &[Entry],
+    mtime: i64,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    match format {
+        ArchiveFormat::Tar => write_tar(entries, mtime, out),
+        ArchiveFormat::TarGz => {
+            let mut gz = GzipWriter::new(out);
+            write_tar(entries, mtime, &mut gz)?;
+            gz.finish()
+        }
+        ArchiveFormat::Zip => write_zip(entries, mtime, out),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tar
+// ---------------------------------------------------------------------------
+
+const BLOCK: usize = 512;
+
+fn write_tar(entries: &[Entry], mtime: i64, out: &mut dyn Write) -> io::Result<()> {
+    for entry in entries {
+        let (data, link): (&[u8], &[u8]) = if entry.is_symlink() {
+            (&[], &entry.data)
+        } else {
+            (&entry.data, &[])
+        };
+
+        // Names that do not fit the ustar fields go in a pax extended header.
+        let split = split_ustar_path(&entry.path);
+        let mut pax = Vec::new();
+        if split.is_none() {
+            pax.extend(pax_record("path", entry.path.as_bytes()));
+        }
+        if link.len() > 100 {
+            pax.extend(pax_record("linkpath", link));
+        }
+        if !pax.is_empty() {
+            let header = tar_header(b"pax_header", b"", 0o644, pax.len(), mtime, b'x', b"")?;
+            out.write_all(&header)?;
+            write_padded(out, &pax)?;
+        }
+
+        let (prefix, name) = split.unwrap_or((b"", truncate(entry.path.as_bytes(), 100)));
+        let typeflag = if entry.is_symlink() { b'2' } else { b'0' };
+        let header = tar_header(
+            name,
+            prefix,
+            entry.permissions(),
+            data.len(),
+            mtime,
+            typeflag,
+            truncate(link, 100),
+        )?;
+        out.write_all(&header)?;
+        write_padded(out, data)?;
+    }
+    out.write_all(&[0; 2 * BLOCK])
+}
+
+/// Split `path` into the ustar prefix and name fields, or return `None` if it
+/// does not fit them.
+fn split_ustar_path(path: &str) -> Option<(&[u8], &[u8])> {
+    let bytes = path.as_bytes();
+    if bytes.len() <= 100 {
+        return Some((b"", bytes));
+    }
+    path.match_indices('/')
+        .map(|(i, _)| (&bytes[..i], &bytes[i + 1..]))
+        .find(|(prefix, name)| prefix.len() <= 155 && name.len() <= 100 && !name.is_empty())
+}
+
+fn truncate(bytes: &[u8], len: usize) -> &[u8] {
+    &bytes[..bytes.len().min(len)]
+}
+
+/// A pax extended header record, `<length> <key>=<value>\n`, where the length
+/// counts the whole record including its own digits.
+fn pax_record(key: &str, value: &[u8]) -> Vec<u8> {
+    let rest = key.len() + value.len() + 3;
+    let mut len = rest + 1;
+    while len != rest + len.to_string().len() {
+        len = rest + len.to_string().len();
+    }
+    let mut record = format!("{len} {key}=").into_bytes();
+    record.extend_from_slice(value);
+    record.push(b'\n');
+    record
+}
+
+fn tar_header(
+    name: &[u8],
+    prefix: &[u8],
+    mode: u32,
+    size: usize,
+    mtime: i64,
+    typeflag: u8,
+    link: &[u8],
+) -> io::Result<[u8; BLOCK]> {
+    if size as u64 >= 1 << 33 {
+        return Err(io::Error::other("file too large for a tar archive"));
+    }
+    let mut header = [0u8; BLOCK];
+    header[..name.len()].copy_from_slice(name);
+    octal(&mut header[100..108], mode as u64);
+    octal(&mut header[108..116], 0);
+    octal(&mut header[116..124], 0);
+    octal(&mut header[124..136], size as u64);
+    octal(&mut header[136..148], mtime.max(0) as u64);
+    header[156] = typeflag;
+    header[157..157 + link.len()].copy_from_slice(link);
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    header[345..345 + prefix.len()].copy_from_slice(prefix);
+
+    // The checksum is computed with its own field filled with spaces.
+    header[148..156].fill(b' ');
+    let sum: u32 = header.iter().map(|&b| b as u32).sum();
+    octal(&mut header[148..155], sum as u64);
+    Ok(header)
+}
+
+/// Fill `field` with `value` as zero-padded octal digits and a trailing NUL.
+fn octal(field: &mut [u8], value: u64) {
+    let digits = format!("{value:0width$o}", width = field.len() - 1);
+    field[..digits.len()].copy_from_slice(digits.as_bytes());
+    field[digits.len()] = 0;
+}
+
+fn write_padded(out: &mut dyn Write, data: &[u8]) -> io::Result<()> {
+    out.write_all(data)?;
+    let padding = (BLOCK - data.len() % BLOCK) % BLOCK;
+    out.write_all(&[0; BLOCK][..padding])
+}
+
+// ---------------------------------------------------------------------------
+// Zip
+// ---------------------------------------------------------------------------
+
+fn write_zip(entries: &[Entry], mtime: i64, out: &mut dyn Write) -> io::Result<()> {
+    let too_large = || io::Error::other("too many or too large files for a zip archive");
+    let (time, date) = dos_datetime(mtime);
+    let mut offset = 0usize;
+    let mut central = Vec::new();
+    for entry in entries {
+        let compressed = deflate(&entry.data);
+        let crc = crc32(0, &entry.data);
+        let name = entry.path.as_bytes();
+        let sizes = [compressed.len(), entry.data.len(), offset];
+        if sizes.iter().any(|&n| n > u32::MAX as usize) || name.len() > u16::MAX as usize {
+            return Err(too_large());
+        }
+
+        // Local file header: version 2.0, UTF-8 names, deflate.
+        let mut header = Vec::with_capacity(30 + name.len());
+        header.extend(0x04034b50u32.to_le_bytes());
+        header.extend(20u16.to_le_bytes());
+        header.extend(0x0800u16.to_le_bytes());
+        header.extend(8u16.to_le_bytes());
+        header.extend(time.to_le_bytes());
+        header.extend(date.to_le_bytes());
+        header.extend(crc.to_le_bytes());
+        header.extend((compressed.len() as u32).to_le_bytes());
+        header.extend((entry.data.len() as u32).to_le_bytes());
+        header.extend((name.len() as u16).to_le_bytes());
+        header.extend(0u16.to_le_bytes());
+        header.extend(name);
+        out.write_all(&header)?;
+        out.write_all(&compressed)?;
+
+        // Central directory entry, made by Unix so that the external
+        // attributes carry the file mode.
+        central.extend(0x02014b50u32.to_le_bytes());
+        central.extend((3u16 << 8 | 20).to_le_bytes());
+        central.extend_from_slice(&header[4..30]);
+        central.extend(0u16.to_le_bytes());
+        central.extend(0u16.to_le_bytes());
+        central.extend(0u16.to_le_bytes());
+        let kind = if entry.is_symlink() {
+            0o120000
+        } else {
+            0o100000
+        };
+        central.extend(((kind | entry.permissions()) << 16).to_le_bytes());
+        central.extend((offset as u32).to_le_bytes());
+        central.extend(name);
+
+        offset += header.len() + compressed.len();
+    }
+    if entries.len() > u16::MAX as usize || offset > u32::MAX as usize {
+        return Err(too_large());
+    }
+    out.write_all(&central)?;
+
+    let mut end = Vec::with_capacity(22);
+    end.extend(0x06054b50u32.to_le_bytes());
+    end.extend(0u16.to_le_bytes());
+    end.extend(0u16.to_le_bytes());
+    end.extend((entries.len() as u16).to_le_bytes());
+    end.extend((entries.len() as u16).to_le_bytes());
+    end.extend((central.len() as u32).to_le_bytes());
+    end.extend((offset as u32).to_le_bytes());
+    end.extend(0u16.to_le_bytes());
+    out.write_all(&end)
+}
+
+/// Convert seconds since the Unix epoch (UTC) to MS-DOS time and date.
+fn dos_datetime(mtime: i64) -> (u16, u16) {
+    let (year, month, day) = crate::civil_from_days(mtime.div_euclid(86400));
+    let secs = mtime.rem_euclid(86400) as u16;
+    let time = ((secs / 3600) << 11) | ((secs % 3600 / 60) << 5) | ((secs % 60) / 2);
+    let date = (((year.clamp(1980, 2107) - 1980) as u16) << 9) | ((month as u16) << 5) | day as u16;
+    (time, date)
+}
+
+// ---------------------------------------------------------------------------
+// Gzip and deflate
+// ---------------------------------------------------------------------------
+
+/// Compresses everything written to it as a gzip member.
+struct GzipWriter<'a> {
+    out: &'a mut dyn Write,
+    buffer: Vec<u8>,
+    bits: BitWriter,
+    crc: u32,
+    len: u32,
+}
+
+/// Input is compressed in chunks of this size, each its own deflate block.
+const CHUNK: usize = 1 << 16;
+
+impl<'a> GzipWriter<'a> {
+    fn new(out: &'a mut dyn Write) -> Self {
+        let mut bits = BitWriter::default();
+        // Magic, deflate, no flags, no modification time, unknown OS.
+        bits.bytes.extend([0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff]);
+        GzipWriter {
+            out,
+            buffer: Vec::new(),
+            bits,
+            crc: 0,
+            len: 0,
+        }
+    }
+
+    fn compress_chunk(&mut self, last: bool) -> io::Result<()> {
+        let size = self.buffer.len().min(CHUNK);
+        let chunk: Vec<u8> = self.buffer.drain(..size).collect();
+        deflate_block(&mut self.bits, &chunk, last);
+        self.out.write_all(&self.bits.take_bytes())
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        while self.buffer.len() > CHUNK {
+            self.compress_chunk(false)?;
+        }
+        self.compress_chunk(true)?;
+        self.bits.flush();
+        self.bits.bytes.extend(self.crc.to_le_bytes());
+        self.bits.bytes.extend(self.len.to_le_bytes());
+        self.out.write_all(&self.bits.take_bytes())?;
+        self.out.flush()
+    }
+}
+
+impl Write for GzipWriter<'_> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.crc = crc32(self.crc, data);
+        self.len = self.len.wrapping_add(data.len() as u32);
+        self.buffer.extend_from_slice(data);
+        // Keep the last chunk buffered so that `finish` can mark it final.
+        while self.buffer.len() > CHUNK {
+            self.compress_chunk(false)?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Packs bits least significant first, as deflate stores them.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    pending: u32,
+    count: u32,
+}
+
+impl BitWriter {
+    fn bits(&mut self, value: u32, count: u32) {
+        self.pending |= value << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.bytes.push(self.pending as u8);
+            self.pending >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Write a Huffman code, which deflate stores most significant bit first.
+    fn code(&mut self, code: u32, len: u32) {
+        self.bits(code.reverse_bits() >> (32 - len), len);
+    }
+
+    fn flush(&mut self) {
+        if self.count > 0 {
+            self.bits(0, 8 - self.count);
+        }
+    }
+
+    /// Take the complete bytes written so far.
+    fn take_bytes(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.bytes)
+    }
+}
+
+/// Compress `data` as a raw deflate stream.
+fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut bits = BitWriter::default();
+    let mut chunks = data.chunks(CHUNK).peekable();
+    if chunks.peek().is_none() {
+        deflate_block(&mut bits, &[], true);
+    }
+    while let Some(chunk) = chunks.next() {
+        deflate_block(&mut bits, chunk, chunks.peek().is_none());
+    }
+    bits.flush();
+    bits.bytes
+}
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const WINDOW: usize = 1 << 15;
+const MAX_CHAIN: usize = 64;
+
+/// Write `data` as one deflate block with the fixed Huffman codes, finding
+/// repeats within the block.
+fn deflate_block(bits: &mut BitWriter, data: &[u8], last: bool) {
+    bits.bits(last as u32, 1);
+    bits.bits(1, 2);
+
+    // `head` holds the last position of each hashed 3-byte sequence and
+    // `prev` the previous position of the sequence at each position.
+    let mut head = vec![usize::MAX; HASH_SIZE];
+    let mut prev = vec![usize::MAX; data.len()];
+    let insert = |i: usize, head: &mut [usize], prev: &mut [usize]| {
+        if i + MIN_MATCH <= data.len() {
+            let h = hash(&data[i..]);
+            prev[i] = head[h];
+            head[h] = i;
+        }
+    };
+
+    let mut i = 0;
+    while i < data.len() {
+        let (mut best_len, mut best_dist) = (0, 0);
+        if i + MIN_MATCH <= data.len() {
+            let max = (data.len() - i).min(MAX_MATCH);
+            let mut candidate = head[hash(&data[i..])];
+            let mut chain = 0;
+            while candidate != usize::MAX && i - candidate <= WINDOW && chain < MAX_CHAIN {
+                let len = data[candidate..]
+                    .iter()
+                    .zip(&data[i..i + max])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if len > best_len {
+                    (best_len, best_dist) = (len, i - candidate);
+                    if len == max {
+                        break;
+                    }
+                }
+                candidate = prev[candidate];
+                chain += 1;
+            }
+        }
+
+        if best_len >= MIN_MATCH {
+            write_match(bits, best_len, best_dist);
+            for j in i..i + best_len {
+                insert(j, &mut head, &mut prev);
+            }
+            i += best_len;
+        } else {
+            write_literal(bits, data[i] as u32);
+            insert(i, &mut head, &mut prev);
+            i += 1;
+        }
+    }
+    write_literal(bits, 256);
+}
+
+const HASH_SIZE: usize = 1 << 15;
+
+/// Hash the first three bytes of `data`.
+fn hash(data: &[u8]) -> usize {
+    let v = (data[0] as usize) << 16 | (data[1] as usize) << 8 | data[2] as usize;
+    (v.wrapping_mul(2654435761) >> 7) & (HASH_SIZE - 1)
+}
+
+/// Write a literal byte or the end-of-block symbol with the fixed codes.
+fn write_literal(bits: &mut BitWriter, symbol: u32) {
+    match symbol {
+        0..=143 => bits.code(0x30 + symbol, 8),
+        144..=255 => bits.code(0x190 + symbol - 144, 9),
+        256..=279 => bits.code(symbol - 256, 7),
+        _ => bits.code(0xc0 + symbol - 280, 8),
+    }
+}
+
+fn write_match(bits: &mut BitWriter, len: usize, dist: usize) {
+    let code = LENGTH_BASE
+        .iter()
+        .rposition(|&base| base as usize <= len)
+        .unwrap();
+    write_literal(bits, 257 + code as u32);
+    bits.bits(
+        (len - LENGTH_BASE[code] as usize) as u32,
+        LENGTH_EXTRA[code] as u32,
+    );
+
+    let code = DIST_BASE
+        .iter()
+        .rposition(|&base| base as usize <= dist)
+        .unwrap();
+    bits.code(code as u32, 5);
+    bits.bits(
+        (dist - DIST_BASE[code] as usize) as u32,
+        DIST_EXTRA[code] as u32,
+    );
+}
+
+/// Update the CRC-32 (as used by gzip and zip) `crc` with `data`.
+fn crc32(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb88320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(0, b"123456789"), 0xcbf43926);
+        assert_eq!(crc32(crc32(0, b"1234"), b"56789"), 0xcbf43926);
+    }
+
+    #[test]
+    fn pax_record_counts_its_own_length() {
+        assert_eq!(pax_record("path", b"a"), b"9 path=a\n");
+        let record = pax_record("path", &[b'a'; 95]);
+        assert_eq!(record.len(), 105);
+        assert!(record.starts_with(b"105 "));
+    }
+
+    #[test]
+    fn split_ustar_path_uses_prefix() {
+        let long = format!("{}/{}", "d".repeat(120), "f".repeat(90));
+        let (prefix, name) = split_ustar_path(&long).unwrap();
+        assert_eq!(prefix.len(), 120);
+        assert_eq!(name.len(), 90);
+        assert!(split_ustar_path(&"f".repeat(101)).is_none());
+    }
+
+    #[test]
+    fn tar_header_checksum() {
+        let header = tar_header(b"a.txt", b"", 0o644, 3, 0, b'0', b"").unwrap();
+        let sum: u32 = header
+            .iter()
+            .enumerate()
+            .map(|(i, &b)| if (148..156).contains(&i) { b' ' } else { b } as u32)
+            .sum();
+        let field = std::str::from_utf8(&header[148..154]).unwrap();
+        assert_eq!(u32::from_str_radix(field, 8).unwrap(), sum);
+        assert_eq!(&header[124..136], b"00000000003\0");
+    }
+
+    #[test]
+    fn dos_datetime_of_known_date() {
+        // 2024-02-29 13:45:30 UTC
+        let (time, date) = dos_datetime(1709214330);
+        assert_eq!(time, 13 << 11 | 45 << 5 | 15);
+        assert_eq!(date, 44 << 9 | 2 << 5 | 29);
+    }
+}
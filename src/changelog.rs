@@ -0,0 +1,219 @@
+//! Markdown changelogs of upstream commit ranges.
+//!
+//! Commits are grouped by author. When the upstream URL points at a known
+//! forge (GitHub, GitLab, Codeberg or Bitbucket), commit ids link to the
+//! forge's commit pages and the range links to its comparison view.
+
+use crate::VendorLogEntry;
+use git2::Oid;
+
+/// Web links for a repository hosted on a known forge.
+#[derive(Debug, PartialEq, Eq)]
+struct Forge {
+    /// The repository's web page, e.g. `https://github.com/owner/repo`.
+    base: String,
+    /// Path segment before a commit id, e.g. `commit`.
+    commit: &'static str,
+    /// Path segment before a `<old>...<new>` range, if the forge has one.
+    compare: Option<&'static str>,
+}
+
+impl Forge {
+    /// Recognize `url` as an HTTPS, SSH or scp-style URL of a repository on
+    /// a known forge.
+    fn from_url(url: &str) -> Option<Forge> {
+        let (rest, scp) = match url.split_once("://") {
+            Some((_, rest)) => (rest, false),
+            None => (url, true),
+        };
+        let rest = match rest.split_once('@') {
+            Some((user, host)) if !user.contains('/') => host,
+            _ => rest,
+        };
+        let (host, path) = rest.split_once(if scp { ':' } else { '/' })?;
+        let host = host.split(':').next()?.to_ascii_lowercase();
+        let path = path.trim_end_matches('/');
+        let path = path.strip_suffix(".git").unwrap_or(path);
+        if path.split('/').filter(|s| !s.is_empty()).count() < 2 {
+            return None;
+        }
+
+        let (commit, compare) = match host.as_str() {
+            "github.com" => ("commit", Some("compare")),
+            "gitlab.com" => ("-/commit", Some("-/compare")),
+            "codeberg.org" => ("commit", Some("compare")),
+            "bitbucket.org" => ("commits", None),
+            _ => return None,
+        };
+        Some(Forge {
+            base: format!("https://{host}/{path}"),
+            commit,
+            compare,
+        })
+    }
+
+    fn commit_url(&self, id: Oid) -> String {
+        format!("{}/{}/{id}", self.base, self.commit)
+    }
+
+    fn compare_url(&self, old: Oid, new: Oid) -> Option<String> {
+        self.compare
+            .map(|compare| format!("{}/{compare}/{old}...{new}", self.base))
+    }
+}
+
+/// Render the upstream commits `entries` (newest first) of dependency `name`,
+/// fetched from `url`, that lead from `old` to `new` as Markdown.
+pub(crate) fn render(
+    name: &str,
+    url: &str,
+    old: Option<Oid>,
+    new: Oid,
+    entries: &[VendorLogEntry],
+) -> String {
+    let forge = Forge::from_url(url);
+    let link = |id: Oid| {
+        let short = crate::short_id(id);
+        match &forge {
+            Some(forge) => format!("[`{short}`]({})", forge.commit_url(id)),
+            None => format!("`{short}`"),
+        }
+    };
+
+    let range = match old {
+        Some(old) => format!("{}..{}", link(old), link(new)),
+        None => link(new),
+    };
+    let mut out = format!("## {name} {range}\n\n");
+    if entries.is_empty() {
+        out.push_str("No upstream changes.\n");
+        return out;
+    }
+
+    let mut authors: Vec<(&str, Vec<&VendorLogEntry>)> = Vec::new();
+    for entry in entries {
+        match authors
+            .iter_mut()
+            .find(|(author, _)| *author == entry.author)
+        {
+            Some((_, commits)) => commits.push(entry),
+            None => authors.push((&entry.author, vec![entry])),
+        }
+    }
+    out.push_str(&format!(
+        "{} by {}",
+        plural(entries.len(), "commit"),
+        plural(authors.len(), "author")
+    ));
+    let compare = old.and_then(|old| forge.as_ref()?.compare_url(old, new));
+    if let Some(compare) = compare {
+        out.push_str(&format!(" ([compare]({compare}))"));
+    }
+    out.push_str(".\n");
+
+    for (author, commits) in authors {
+        out.push_str(&format!("\n### {author}\n\n"));
+        for entry in commits {
+            out.push_str(&format!("- {} {}\n", link(entry.id), entry.summary));
+        }
+    }
+    out
+}
+
+fn plural(count: usize, noun: &str) -> String {
+    if count == 1 {
+        format!("1 {noun}")
+    } else {
+        format!("{count} {noun}s")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn base(url: &str) -> Option<String> {
+        Forge::from_url(url).map(|forge| forge.base)
+    }
+
+    #[test]
+    fn forge_from_url_forms() {
+        let expected = Some("https://github.com/owner/repo".to_string());
+        assert_eq!(base("https://github.com/owner/repo"), expected);
+        assert_eq!(base("https://github.com/owner/repo.git"), expected);
+        assert_eq!(base("https://github.com/owner/repo/"), expected);
+        assert_eq!(base("git@github.com:owner/repo.git"), expected);
+        assert_eq!(base("ssh://git@github.com/owner/repo.git"), expected);
+        assert_eq!(base("ssh://git@github.com:22/owner/repo"), expected);
+    }
+
+    #[test]
+    fn forge_from_url_keeps_gitlab_subgroups() {
+        let forge = Forge::from_url("https://gitlab.com/group/sub/repo.git").unwrap();
+        assert_eq!(forge.base, "https://gitlab.com/group/sub/repo");
+        assert_eq!(
+            forge.commit_url(Oid::zero()),
+            format!("https://gitlab.com/group/sub/repo/-/commit/{}", Oid::zero())
+        );
+    }
+
+    #[test]
+    fn forge_from_url_unknown() {
+        assert_eq!(base("https://git.example.com/owner/repo"), None);
+        assert_eq!(base("/srv/git/repo"), None);
+        assert_eq!(base("https://github.com/owner"), None);
+    }
+
+    fn entry(id: &str, author: &str, summary: &str) -> VendorLogEntry {
+        VendorLogEntry {
+            id: Oid::from_str(id).unwrap(),
+            author: author.to_string(),
+            email: String::new(),
+            time: git2::Time::new(0, 0),
+            summary: summary.to_string(),
+        }
+    }
+
+    #[test]
+    fn render_groups_by_author() {
+        let entries = [
+            entry("3333333333333333333333333333333333333333", "Bob", "Third"),
+            entry(
+                "2222222222222222222222222222222222222222",
+                "Alice",
+                "Second",
+            ),
+            entry("1111111111111111111111111111111111111111", "Bob", "First"),
+        ];
+        let old = Oid::from_str("0000000000000000000000000000000000000001").unwrap();
+        let out = render("lib", "/srv/lib", Some(old), entries[0].id, &entries);
+        assert_eq!(
+            out,
+            "## lib `0000000`..`3333333`\n\n\
+             3 commits by 2 authors.\n\n\
+             ### Bob\n\n\
+             - `3333333` Third\n\
+             - `1111111` First\n\n\
+             ### Alice\n\n\
+             - `2222222` Second\n"
+        );
+    }
+
+    #[test]
+    fn render_links_forge_commits() {
+        let entries = [entry(
+            "2222222222222222222222222222222222222222",
+            "Alice",
+            "Fix",
+        )];
+        let old = Oid::from_str("1111111111111111111111111111111111111111").unwrap();
+        let new = entries[0].id;
+        let out = render("lib", "git@github.com:o/lib.git", Some(old), new, &entries);
+        assert!(out.contains(&format!(
+            "([compare](https://github.com/o/lib/compare/{old}...{new}))"
+        )));
+        assert!(out.contains(&format!(
+            "- [`2222222`](https://github.com/o/lib/commit/{new}) Fix\n"
+        )));
+    }
+}
@@ -0,0 +1,406 @@
+use crate::{ArchiveFormat, DepsFormat, MergeStrategy, SubmodulePolicy, SymlinkPolicy};
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Parser)]
+#[command(name = "git-vendor")]
+#[command(author, version, about = "In-source vendoring for Git repositories", long_about = None)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+
+    /// Without a pattern, operate on every dependency rather than only those
+    /// declared in the current directory or below
+    #[arg(long, global = true)]
+    pub all: bool,
+
+    /// Also show timings; repeat for more detail
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Only show warnings and errors; status and outdated print nothing, so
+    /// that only their exit status reports updates
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Without a pattern, operate on the dependencies in this group
+    /// (vendor-group=) instead of those in the current directory
+    #[arg(long, global = true, value_name = "GROUP")]
+    pub group: Option<String>,
+
+    /// Read the patterns or names of the dependencies to operate on from
+    /// standard input, one per line, instead of the pattern argument
+    #[arg(long, global = true)]
+    pub stdin: bool,
+
+    /// Separate --stdin input and list/status output with NUL characters;
+    /// status then prints one machine-readable record per dependency
+    #[arg(short = 'z', global = true)]
+    pub nul: bool,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Prepare the repository for vendoring
+    Init,
+
+    /// Track a pattern as vendored content from a remote repository
+    Track {
+        /// Gitattributes-style pattern selecting the vendored paths
+        pattern: String,
+
+        /// URL (or local path) of the upstream repository
+        url: String,
+
+        /// Upstream branch to track (defaults to the remote's HEAD)
+        #[arg(short, long)]
+        branch: Option<String>,
+
+        /// Dependency name (derived from the URL when omitted)
+        #[arg(short, long)]
+        name: Option<String>,
+
+        /// Replace dependencies that conflict with the name or overlap the pattern
+        #[arg(long)]
+        force: bool,
+
+        /// Follow the highest upstream tag matching this Cargo-style version
+        /// requirement, e.g. "^1.2", instead of a branch
+        #[arg(long = "semver", value_name = "REQ")]
+        version: Option<String>,
+
+        /// Vendor only this upstream directory, placing its contents in the
+        /// declaring directory
+        #[arg(long, value_name = "PATH")]
+        src: Option<String>,
+
+        /// Record why the dependency is tracked, e.g. "pinned until CVE fix"
+        #[arg(long)]
+        note: Option<String>,
+
+        /// Sort and align the lines of the edited .gitattributes file
+        #[arg(long)]
+        canonicalize: bool,
+    },
+
+    /// Stop tracking a vendored pattern
+    Untrack {
+        /// Pattern to remove from .gitattributes
+        pattern: String,
+    },
+
+    /// List the patterns of vendored dependencies
+    List {
+        /// Only list the dependency with this pattern
+        pattern: Option<String>,
+    },
+
+    /// Show the status of vendored dependencies; exit with 1 if any has
+    /// fetched changes not merged or was never fetched, or 2 on errors
+    Status {
+        /// Only show the dependency with this pattern
+        pattern: Option<String>,
+    },
+
+    /// Fetch the latest upstream content into refs/vendor/
+    Fetch {
+        /// Only fetch the dependency with this pattern
+        pattern: Option<String>,
+
+        /// Try fetching refs/vendor/ from this remote (name or URL) before the upstream URL
+        #[arg(long, value_name = "REMOTE")]
+        from_mirror: Option<String>,
+
+        /// Send this HTTP header, e.g. `Authorization: Bearer <token>`, with
+        /// every fetch (repeatable)
+        #[arg(short = 'H', long = "header", value_name = "HEADER")]
+        headers: Vec<String>,
+
+        /// Try each upstream up to this many times when fetching fails with
+        /// a transient network error (timeout, reset, HTTP 5xx or 429)
+        #[arg(long, value_name = "N", default_value_t = 1)]
+        attempts: u32,
+
+        /// Seconds to wait before the first retry, doubling for each further
+        /// one
+        #[arg(long, value_name = "SECONDS", value_parser = parse_seconds)]
+        retry_delay: Option<Duration>,
+
+        /// Seconds to leave between connections to the same host
+        #[arg(long, value_name = "SECONDS", value_parser = parse_seconds)]
+        host_interval: Option<Duration>,
+    },
+
+    /// Push refs/vendor/ to a shared remote for others to fetch with --from-mirror
+    Push {
+        /// Remote name or URL to push to
+        remote: String,
+
+        /// Only push the dependency with this pattern
+        pattern: Option<String>,
+    },
+
+    /// Merge fetched upstream content into the current branch
+    Merge {
+        /// Only merge the dependency with this pattern
+        pattern: Option<String>,
+
+        /// Stage the merge without committing
+        #[arg(long)]
+        no_commit: bool,
+
+        /// Stage the merge as an ordinary (non-merge) change
+        #[arg(long)]
+        squash: bool,
+
+        /// Merge commit message
+        #[arg(short, long)]
+        message: Option<String>,
+
+        /// Update the index and commit without touching the working tree
+        #[arg(long)]
+        index_only: bool,
+
+        /// Require the fetched upstream tip to be signed by a key in this file
+        #[arg(long, value_name = "FILE")]
+        allowed_signers: Option<PathBuf>,
+
+        /// How to handle submodules in the upstream tree
+        #[arg(long, value_enum, default_value = "skip")]
+        submodules: SubmodulePolicy,
+
+        /// How to handle symlinks in the upstream tree
+        #[arg(long, value_enum, default_value = "preserve")]
+        symlinks: SymlinkPolicy,
+
+        /// Vendor executable files as regular files
+        #[arg(long)]
+        normalize_executable: bool,
+
+        /// Merge all dependencies into one combined commit
+        #[arg(long, conflicts_with = "interactive")]
+        single_commit: bool,
+
+        /// How to resolve conflicting changes to vendored files
+        #[arg(short = 'X', long, value_enum, default_value = "normal")]
+        strategy: MergeStrategy,
+
+        /// Overwrite uncommitted changes to the vendored paths being merged
+        #[arg(short, long)]
+        force: bool,
+
+        /// Review each outdated dependency and choose which to merge
+        #[arg(short, long)]
+        interactive: bool,
+
+        /// Record merge commits with this author, as `Name <email>`
+        #[arg(long)]
+        author: Option<String>,
+
+        /// Sign merge commits with user.signingkey
+        #[arg(short = 'S', long)]
+        gpg_sign: bool,
+
+        /// Do not sign merge commits, overriding commit.gpgsign
+        #[arg(long, conflicts_with = "gpg_sign")]
+        no_gpg_sign: bool,
+    },
+
+    /// Extract local changes to a dependency as a commit on top of its upstream
+    Extract {
+        /// Pattern of the dependency to extract
+        pattern: String,
+
+        /// Commit message
+        #[arg(short, long)]
+        message: Option<String>,
+
+        /// Create a branch pointing at the extracted commit
+        #[arg(short, long)]
+        branch: Option<String>,
+    },
+
+    /// Write an archive of a dependency's vendored files at the upstream
+    /// commit last merged
+    Archive {
+        /// Name of the dependency
+        name: String,
+
+        /// Write the archive to this file instead of standard output
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+
+        /// Archive format (defaults to the output file's extension, or tar)
+        #[arg(long, value_enum)]
+        format: Option<ArchiveFormat>,
+
+        /// Prepend this to every path in the archive, e.g. "lib-1.2/"
+        #[arg(long)]
+        prefix: Option<String>,
+    },
+
+    /// Fetch and merge upstream changes
+    Update {
+        /// Only update the dependency with this pattern
+        pattern: Option<String>,
+
+        /// Send this HTTP header with every fetch (repeatable)
+        #[arg(short = 'H', long = "header", value_name = "HEADER")]
+        headers: Vec<String>,
+
+        /// Try each upstream up to this many times when fetching fails with
+        /// a transient network error (timeout, reset, HTTP 5xx or 429)
+        #[arg(long, value_name = "N", default_value_t = 1)]
+        attempts: u32,
+
+        /// Seconds to wait before the first retry, doubling for each further
+        /// one
+        #[arg(long, value_name = "SECONDS", value_parser = parse_seconds)]
+        retry_delay: Option<Duration>,
+
+        /// Seconds to leave between connections to the same host
+        #[arg(long, value_name = "SECONDS", value_parser = parse_seconds)]
+        host_interval: Option<Duration>,
+
+        /// Merge each outdated dependency into its own `vendor/update-<name>`
+        /// branch instead of the current branch
+        #[arg(long)]
+        branch_per_dep: bool,
+
+        /// How to resolve conflicting changes to vendored files
+        #[arg(short = 'X', long, value_enum, default_value = "normal")]
+        strategy: MergeStrategy,
+
+        /// Record merge commits with this author, as `Name <email>`
+        #[arg(long)]
+        author: Option<String>,
+
+        /// Sign merge commits with user.signingkey
+        #[arg(short = 'S', long)]
+        gpg_sign: bool,
+
+        /// Do not sign merge commits, overriding commit.gpgsign
+        #[arg(long, conflicts_with = "gpg_sign")]
+        no_gpg_sign: bool,
+    },
+
+    /// Check upstreams for updates without fetching; exit with 1 if any are
+    /// available, or 2 on errors
+    Outdated {
+        /// Only check the dependency with this pattern
+        pattern: Option<String>,
+
+        /// Send this HTTP header when contacting upstreams (repeatable)
+        #[arg(short = 'H', long = "header", value_name = "HEADER")]
+        headers: Vec<String>,
+
+        /// Try each upstream up to this many times when contacting it fails
+        /// with a transient network error (timeout, reset, HTTP 5xx or 429)
+        #[arg(long, value_name = "N", default_value_t = 1)]
+        attempts: u32,
+
+        /// Seconds to wait before the first retry, doubling for each further
+        /// one
+        #[arg(long, value_name = "SECONDS", value_parser = parse_seconds)]
+        retry_delay: Option<Duration>,
+
+        /// Seconds to leave between connections to the same host
+        #[arg(long, value_name = "SECONDS", value_parser = parse_seconds)]
+        host_interval: Option<Duration>,
+    },
+
+    /// Record the vendored upstream state of HEAD in a signed note
+    Attest {
+        /// Only attest the dependency with this pattern
+        pattern: Option<String>,
+    },
+
+    /// Check the attestation recorded for a commit
+    VerifyAttestation {
+        /// The commit to check
+        #[arg(default_value = "HEAD")]
+        commit: String,
+
+        /// Allowed-signers file for the note signature (defaults to
+        /// gpg.ssh.allowedSignersFile)
+        #[arg(long, value_name = "FILE")]
+        allowed_signers: Option<PathBuf>,
+    },
+
+    /// Check the vendoring state for inconsistencies
+    Doctor {
+        /// Fix the problems that can be fixed safely
+        #[arg(long)]
+        fix: bool,
+    },
+
+    /// Move dependency declarations between .gitattributes and .gitvendor.toml
+    Migrate {
+        /// Where to declare the dependencies
+        #[arg(long, value_enum)]
+        to: DepsFormat,
+    },
+
+    /// Show upstream commits not yet merged
+    Log {
+        /// Only show the dependency with this pattern
+        pattern: Option<String>,
+
+        /// Show one commit per line
+        #[arg(long)]
+        oneline: bool,
+
+        /// Limit the number of commits shown per dependency
+        #[arg(short = 'n', long)]
+        max_count: Option<usize>,
+
+        /// Show the commits brought in by the last merge instead
+        #[arg(long)]
+        last: bool,
+    },
+
+    /// Print the upstream commits not yet merged as Markdown, grouped by
+    /// author, for a pull request description
+    Changelog {
+        /// Only describe the dependency with this pattern
+        pattern: Option<String>,
+
+        /// Describe the commits brought in by the last merge instead
+        #[arg(long)]
+        last: bool,
+    },
+
+    /// Show the fetched upstream files that no pattern of a dependency vendors
+    Excluded {
+        /// Only show the dependency with this pattern
+        pattern: Option<String>,
+    },
+
+    /// Show which dependency vendors each file at HEAD
+    Owners {
+        /// Print CODEOWNERS entries for the vendored files instead
+        #[arg(long)]
+        codeowners: bool,
+
+        /// Owner to list in each CODEOWNERS entry, e.g. "@org/team"
+        /// (repeatable)
+        #[arg(long = "owner", value_name = "OWNER", requires = "codeowners")]
+        owners: Vec<String>,
+    },
+
+    /// List staged changes to vendored files that are not part of a vendor
+    /// update, and exit with 2 if there are any; for a pre-commit hook
+    CheckStaged,
+
+    /// Show the fetches and merges recorded in a dependency's vendor ref
+    Reflog {
+        /// Name of the dependency
+        name: String,
+    },
+}
+
+fn parse_seconds(s: &str) -> Result<Duration, String> {
+    let secs: f64 = s.parse().map_err(|e| format!("{e}"))?;
+    Duration::try_from_secs_f64(secs).map_err(|e| e.to_string())
+}
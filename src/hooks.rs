@@ -0,0 +1,116 @@
+//! Hooks run around vendor operations.
+//!
+//! Executables in the repository's hooks directory (`core.hooksPath`, or
+//! `hooks` in the Git directory shared by all worktrees) are run like Git's
+//! own hooks, from the root of the current working tree (the Git directory
+//! when bare):
+//!
+//! ```text
+//! vendor-pre-fetch <name>             before fetching a dependency
+//! vendor-pre-merge <name> <tree>      before merging its filtered upstream tree
+//! vendor-post-commit <name> <commit>  after committing the merge
+//! ```
+//!
+//! A pre hook exiting with a non-zero status aborts the operation. The exit
+//! status of `vendor-post-commit` is reported but otherwise ignored. Hooks
+//! given as a [`VendorHooks`] implementation run after the executables.
+
+use crate::{VendorDep, VendorHooks, resolve_repo_path};
+use git2::{Error, Oid, Repository};
+use std::{path::PathBuf, process::Command};
+
+/// Run the hooks for fetching `dep`.
+pub(crate) fn pre_fetch(
+    repo: &Repository,
+    hooks: Option<&dyn VendorHooks>,
+    dep: &VendorDep,
+) -> Result<(), Error> {
+    if !run_script(repo, "vendor-pre-fetch", &[&dep.name])? {
+        return Err(Error::from_str(&format!(
+            "vendor-pre-fetch hook rejected fetching {}",
+            dep.name
+        )));
+    }
+    match hooks {
+        Some(hooks) => hooks.pre_fetch(repo, dep),
+        None => Ok(()),
+    }
+}
+
+/// Run the hooks for merging the filtered upstream tree `tree` of `dep`.
+pub(crate) fn pre_merge(
+    repo: &Repository,
+    hooks: Option<&dyn VendorHooks>,
+    dep: &VendorDep,
+    tree: Oid,
+) -> Result<(), Error> {
+    if !run_script(repo, "vendor-pre-merge", &[&dep.name, &tree.to_string()])? {
+        return Err(Error::from_str(&format!(
+            "vendor-pre-merge hook rejected merging {}",
+            dep.name
+        )));
+    }
+    match hooks {
+        Some(hooks) => hooks.pre_merge(repo, dep, tree),
+        None => Ok(()),
+    }
+}
+
+/// Run the hooks for `commit`, which merged `dep` and the other patterns of
+/// its name.
+pub(crate) fn post_commit(
+    repo: &Repository,
+    hooks: Option<&dyn VendorHooks>,
+    dep: &VendorDep,
+    commit: Oid,
+) -> Result<(), Error> {
+    if !run_script(
+        repo,
+        "vendor-post-commit",
+        &[&dep.name, &commit.to_string()],
+    )? {
+        warn!("  Warning: vendor-post-commit hook failed");
+    }
+    match hooks {
+        Some(hooks) => hooks.post_commit(repo, dep, commit),
+        None => Ok(()),
+    }
+}
+
+/// Run the hook executable `name` with `args`, returning whether it succeeded.
+/// A missing or non-executable hook counts as success.
+fn run_script(repo: &Repository, name: &str, args: &[&str]) -> Result<bool, Error> {
+    let path = hooks_dir(repo)?.join(name);
+    if !is_executable(&path) {
+        return Ok(true);
+    }
+
+    let status = Command::new(&path)
+        .args(args)
+        .current_dir(repo.workdir().unwrap_or_else(|| repo.path()))
+        .env("GIT_DIR", repo.path())
+        .status()
+        .map_err(|e| Error::from_str(&format!("Failed to run {name} hook: {e}")))?;
+    Ok(status.success())
+}
+
+/// Return the directory hooks are read from.
+fn hooks_dir(repo: &Repository) -> Result<PathBuf, Error> {
+    match repo.config()?.get_string("core.hooksPath") {
+        Ok(path) => Ok(resolve_repo_path(repo, &path)),
+        Err(e) if e.code() == git2::ErrorCode::NotFound => Ok(repo.commondir().join("hooks")),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(unix)]
+fn is_executable(path: &std::path::Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &std::path::Path) -> bool {
+    path.is_file()
+}
@@ -7,17 +7,103 @@
 //! ```
 //!
 //! Fetched content is stored under `refs/vendor/<name>`.
+//!
+//! Alternatively, dependencies can be declared in a `.gitvendor.toml` manifest
+//! at the repository root, which also supports excludes, a placement prefix
+//! and patches. When the manifest exists, `.gitattributes` is not consulted.
+//!
+//! # Attributes
+//!
+//! Besides `vendor-name`, `vendor-url` and `vendor-branch`, a dependency can
+//! set:
+//!
+//! - `vendor-mirror=<url>` (repeatable): a fallback URL, tried in order when
+//!   fetching from `vendor-url` fails.
+//! - `vendor-src=<dir>`: vendors only that upstream directory, whose contents
+//!   land in the declaring directory; the pattern then matches paths inside it.
+//! - `vendor-version=<requirement>`, such as `^1.2`: follows the highest
+//!   upstream tag satisfying the Cargo-style requirement instead of a branch.
+//!   The chosen tag is shown by status and recorded as a `Vendor-Version:`
+//!   trailer when merged.
+//! - `vendor-header=<name>:<value>` (repeatable): an HTTP header sent with the
+//!   dependency's fetches.
+//! - `vendor-upstream=<url>`: the repository that `vendor-url` is a fork of,
+//!   whose default branch status and outdated compare the fork against.
+//! - `vendor-keep-dir=<dir>` (repeatable): keeps that directory, with an empty
+//!   `.gitkeep` file, when none of its files are vendored.
+//! - `vendor-group=<group>` (repeatable): adds the dependency to a group,
+//!   which commands select as `@<group>`.
+//! - `-vendor-default-excludes`: vendors the paths matching
+//!   `vendor.defaultExcludes` too. The patterns in that setting, separated by
+//!   commas or whitespace, such as `**/.github/** **/*.md`, are left out of
+//!   every other dependency.
+//!
+//! A `# vendor-note: <text>` comment directly above a line records why the
+//! dependency is tracked that way.
+//!
+//! # Signing
+//!
+//! `vendor-verify=<allowed-signers-file>` requires the fetched upstream tip,
+//! the commit being merged, to be signed by a key listed in that file.
+//!
+//! `vendor_attest` records the merged upstream commit and filtered tree of
+//! each dependency in a signed note under `refs/notes/vendor`, which
+//! `vendor_verify_attestation` checks.
+//!
+//! # Licenses
+//!
+//! Merges detect the licenses of the vendored content and record them as
+//! `Vendor-License:` trailers. When `vendor.allowedLicenses` lists SPDX
+//! license identifiers, a merge whose content carries any other license, or
+//! none, fails.
+//!
+//! # Blob policy
+//!
+//! Merges also fail when the vendored content has a file larger than
+//! `vendor.maxFileSize` (with an optional `k`, `m` or `g` suffix) or a binary
+//! file matching one of the patterns of `vendor.denyBinary`, such as
+//! `*.so tests/**`. With `vendor.blobPolicy=warn`, such files are only warned
+//! about.
+//!
+//! # Hooks
+//!
+//! Fetches and merges run the `vendor-pre-fetch`, `vendor-pre-merge` and
+//! `vendor-post-commit` hooks, either as executables in the hooks directory
+//! or through a [`VendorHooks`] implementation.
 
-use git_filter_tree::FilterTree;
-use git_set_attr::SetAttr;
+use git_filter_tree::{FilterOptions, FilterStats, FilterTree, PatternSyntax};
+pub use git_filter_tree::{SubmodulePolicy, SymlinkPolicy};
+use git_set_attr::{LineStyle, LockFile, SetAttr};
 use git2::build::CheckoutBuilder;
-use git2::{Error, FetchOptions, MergeOptions, Oid, Repository};
+use git2::{
+    Error, ErrorCode, FetchOptions, Index, MergeOptions, Oid, PushOptions, Reference,
+    RemoteCallbacks, Repository, Signature, Tree,
+};
+use semver::{Version, VersionReq};
 use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, hash_map::Entry},
     fs,
-    io::{BufRead, BufReader, Write},
+    io::{BufRead, BufReader},
     path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
 };
 
+#[macro_use]
+mod report;
+
+mod archive;
+mod changelog;
+#[cfg(feature = "cli")]
+pub mod cli;
+mod hooks;
+mod license;
+mod manifest;
+mod semver;
+#[cfg(feature = "testing")]
+pub mod testing;
+mod verify;
+
 /// High-level options for [`Vendor::vendor_merge`], mirroring `git merge` flags.
 ///
 /// These control the commit/staging behavior of the merge. The low-level
@@ -32,315 +118,3876 @@ pub struct VendorMergeOpts {
     /// is an ordinary (non-merge) commit (`--squash`).
     pub squash: bool,
     /// Override the default merge commit message (`-m`).
+    ///
+    /// When `None`, the message is taken from the `vendor.<name>.message` or
+    /// `vendor.message` Git config template, if set. Templates may contain
+    /// the placeholders `{name}`, `{url}`, `{commit}`, `{branch}` and
+    /// `{date}` (the upstream commit date as `YYYY-MM-DD`).
     pub message: Option<String>,
+    /// Compute the merge purely from objects: update the index (when the
+    /// repository has one) and commit, but never touch the working tree.
+    ///
+    /// This mode also works in bare repositories, where dependencies are read
+    /// from the `.gitattributes` file committed at `HEAD`.
+    pub index_only: bool,
+    /// Require the fetched upstream tip being merged to be signed by a key
+    /// listed in this allowed-signers file, overriding any `vendor-verify=`
+    /// attribute. Earlier upstream commits are not checked.
+    pub allowed_signers: Option<PathBuf>,
+    /// How submodules (gitlinks) in the upstream tree are handled. Skipped
+    /// submodules are reported so that missing directories aren't a surprise.
+    pub submodules: SubmodulePolicy,
+    /// How symlinks in the upstream tree are handled.
+    pub symlinks: SymlinkPolicy,
+    /// Vendor executable upstream files as regular files.
+    pub normalize_executable: bool,
+    /// Merge all selected dependencies into one combined tree and record a
+    /// single commit, with `HEAD` and every merged upstream commit as parents,
+    /// whose message lists each updated dependency. Combined with `no_commit`
+    /// or `squash`, the combined result is staged instead. A conflict in any
+    /// dependency aborts the whole merge.
+    pub single_commit: bool,
+    /// Overwrite uncommitted changes to the paths the merge writes. Without
+    /// it, the merge fails if any of them are staged or, unless `index_only`,
+    /// modified or untracked in the working tree. Other paths are never
+    /// touched.
+    pub force: bool,
+    /// How conflicting changes in vendored files are resolved (`-X`). Explicit
+    /// [`MergeOptions`] take precedence over the file favor chosen here.
+    pub strategy: MergeStrategy,
+    /// Called before each dependency's filtered upstream tree is merged and
+    /// after each merge commit, following any hook executables.
+    pub hooks: Option<Arc<dyn VendorHooks>>,
+    /// Author of the merge commits, as `Name <email>`. Defaults, like Git,
+    /// to `GIT_AUTHOR_NAME` and `GIT_AUTHOR_EMAIL`, then `user.name` and
+    /// `user.email`.
+    pub author: Option<String>,
+    /// Committer of the merge commits, as `Name <email>`. Defaults to
+    /// `GIT_COMMITTER_NAME` and `GIT_COMMITTER_EMAIL`, then `user.name` and
+    /// `user.email`.
+    pub committer: Option<String>,
+    /// Whether to sign the merge commits (`-S` / `--no-gpg-sign`). Defaults
+    /// to signing when a `signer` is given or `commit.gpgsign` is set.
+    pub sign: Option<bool>,
+    /// Signs the merge commits. Defaults to `gpg`, or `ssh-keygen` when
+    /// `gpg.format` is `ssh`, with `user.signingkey`.
+    pub signer: Option<Arc<dyn VendorSigner>>,
 }
 
-/// A vendored dependency parsed from `.gitattributes`.
+/// Signs the commits recorded by [`Vendor::vendor_merge`], for example with
+/// a hardware token or a signing service.
+pub trait VendorSigner: std::fmt::Debug {
+    /// Return the armored signature of the commit object `buffer`, as
+    /// `gpg --detach-sign --armor` or `ssh-keygen -Y sign -n git` print it.
+    fn sign(&self, buffer: &str) -> Result<String, Error>;
+}
+
+/// How [`Vendor::vendor_merge`] resolves local changes to vendored files.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum MergeStrategy {
+    /// Stop on conflicts for the user to resolve.
+    #[default]
+    Normal,
+    /// Resolve conflicting hunks in favor of the local version.
+    Ours,
+    /// Resolve conflicting hunks in favor of the upstream version.
+    Theirs,
+    /// Keep both sides of conflicting hunks.
+    Union,
+    /// Always take upstream for vendored paths, discarding local changes to
+    /// them. Never conflicts.
+    Upstream,
+}
+
+impl MergeStrategy {
+    fn file_favor(self) -> Option<git2::FileFavor> {
+        match self {
+            MergeStrategy::Normal | MergeStrategy::Upstream => None,
+            MergeStrategy::Ours => Some(git2::FileFavor::Ours),
+            MergeStrategy::Theirs => Some(git2::FileFavor::Theirs),
+            MergeStrategy::Union => Some(git2::FileFavor::Union),
+        }
+    }
+}
+
+/// High-level options for [`Vendor::track_pattern`].
+#[derive(Debug, Clone, Default)]
+pub struct VendorTrackOpts {
+    /// Replace conflicting dependencies instead of failing: other patterns of
+    /// the same name with a different URL or branch, and patterns of other
+    /// dependencies overlapping the tracked one.
+    pub force: bool,
+    /// Record why the dependency is tracked (see [`VendorDep::note`]). An
+    /// existing note is kept when `None`.
+    pub note: Option<String>,
+    /// Sort and align the lines of the edited `.gitattributes` file (see
+    /// [`git_set_attr::canonicalize`]) so repeated edits produce small diffs.
+    pub canonicalize: bool,
+    /// Vendor only this upstream directory (see [`VendorDep::src`]).
+    pub src: Option<String>,
+    /// Follow upstream tags matching this requirement (see
+    /// [`VendorDep::version`]).
+    pub version: Option<String>,
+    /// The directory, relative to the root of the working tree, from which to
+    /// look for the `.gitattributes` file to edit, and against which a
+    /// relative local URL is resolved. When `None`, the process's current
+    /// directory ([`std::env::current_dir`]) is used, as the CLI does; library
+    /// callers should set it rather than depend on that ambient state.
+    pub at_dir: Option<PathBuf>,
+}
+
+/// High-level options for [`Vendor::vendor_fetch`].
+///
+/// Partial-clone filters such as `--filter=blob:none` are not supported:
+/// fetches go through libgit2, which can neither request object filters nor
+/// lazily fetch the blobs that survive pattern filtering. Upstream history is
+/// always fetched in full; pushing vendor refs to a [`mirror`](Self::mirror)
+/// lets a team pay that cost once.
+#[derive(Debug, Default)]
+pub struct VendorFetchOpts {
+    /// Try fetching `refs/vendor/<name>` from this remote (a configured remote
+    /// name or a URL) before falling back to the upstream URL. Pair with
+    /// [`Vendor::vendor_push`] to share fetched vendor refs within a team.
+    pub mirror: Option<String>,
+    /// Called before each dependency is fetched, following any
+    /// `vendor-pre-fetch` hook executable.
+    pub hooks: Option<Arc<dyn VendorHooks>>,
+    /// HTTP headers, such as `Authorization: Bearer <token>`, sent with every
+    /// fetch in addition to those of `http.extraHeader` (or
+    /// `http.<url>.extraHeader`) and each dependency's
+    /// [`headers`](VendorDep::headers). They replace any custom headers set on
+    /// the [`FetchOptions`] passed to [`Vendor::vendor_fetch`].
+    pub headers: Vec<String>,
+    /// Try each network source up to this many times when fetching fails
+    /// with a transient error, such as a timeout, a reset connection or an
+    /// HTTP 5xx or 429 response; `0` and `1` both mean a single attempt.
+    pub attempts: u32,
+    /// Wait this long before the first retry, doubling the wait before each
+    /// further one. Defaults to one second.
+    pub retry_delay: Option<Duration>,
+    /// Leave at least this long between connections to the same host, so
+    /// that fetching many dependencies stays below a forge's rate limits.
+    pub host_interval: Option<Duration>,
+}
+
+/// The result of fetching one dependency, as returned by
+/// [`Vendor::vendor_fetch`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VendorFetchStats {
+    pub name: String,
+    /// The commit `refs/vendor/<name>` pointed to before the fetch, if any.
+    pub old: Option<Oid>,
+    /// The commit `refs/vendor/<name>` points to after the fetch.
+    pub new: Oid,
+    /// Whether `refs/vendor/<name>` existed before the fetch, so that only
+    /// upstream objects it does not reach had to be transferred.
+    pub incremental: bool,
+    /// Objects received from the remote.
+    pub received_objects: usize,
+    /// Bytes received from the remote.
+    pub received_bytes: usize,
+    /// The upstream tag fetched to satisfy [`VendorDep::version`].
+    pub tag: Option<String>,
+}
+
+impl VendorFetchStats {
+    fn new(dep: &VendorDep, remote: &git2::Remote<'_>, old: Option<Oid>, new: Oid) -> Self {
+        let progress = remote.stats();
+        VendorFetchStats {
+            name: dep.name.clone(),
+            old,
+            new,
+            incremental: old.is_some(),
+            received_objects: progress.received_objects(),
+            received_bytes: progress.received_bytes(),
+            tag: None,
+        }
+    }
+
+    /// Whether the fetch moved `refs/vendor/<name>`, or created it.
+    pub fn changed(&self) -> bool {
+        self.old != Some(self.new)
+    }
+
+    fn summary(&self) -> String {
+        let update = match self.old {
+            _ if !self.changed() => "already up to date".to_string(),
+            Some(old) => format!("{}..{}", short_id(old), short_id(self.new)),
+            None => format!("new at {}", short_id(self.new)),
+        };
+        format!(
+            "{update}, {} objects, {} bytes received{}",
+            self.received_objects,
+            self.received_bytes,
+            if self.incremental {
+                ", incremental"
+            } else {
+                ""
+            }
+        )
+    }
+}
+
+/// An inconsistency in the vendoring state, as reported by
+/// [`Vendor::vendor_doctor`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VendorFinding {
+    pub kind: VendorFindingKind,
+    /// A description of the problem, naming the file, ref or dependency.
+    pub message: String,
+    /// Whether the problem was fixed.
+    pub fixed: bool,
+}
+
+/// The kinds of problems [`Vendor::vendor_doctor`] detects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VendorFindingKind {
+    /// A `.gitattributes` line has vendor attributes but not `vendored`, so
+    /// it is ignored. Fixed by adding `vendored`.
+    MissingVendoredFlag,
+    /// A `refs/vendor/` or `refs/vendor-upstream/` ref belongs to no
+    /// declared dependency. Fixed by deleting the ref.
+    OrphanedRef,
+    /// A vendor ref does not point to a commit in the repository. Fixed by
+    /// deleting the ref, so that the next fetch recreates it.
+    MissingCommit,
+    /// A pattern matches no files at `HEAD`.
+    UnmatchedPattern,
+    /// Dependencies sharing a name disagree on their URL or branch.
+    ConflictingName,
+}
+
+/// Callbacks run around vendor operations, for example to scan the licenses
+/// of vendored content or regenerate code from it.
+///
+/// Returning an error from a `pre_` method aborts the operation. An error from
+/// [`post_commit`](VendorHooks::post_commit) is returned after the commit has
+/// been recorded.
+pub trait VendorHooks: std::fmt::Debug {
+    /// Called before `dep` is fetched.
+    fn pre_fetch(&self, repo: &Repository, dep: &VendorDep) -> Result<(), Error> {
+        let _ = (repo, dep);
+        Ok(())
+    }
+
+    /// Called before the filtered upstream tree `tree` of `dep` is merged.
+    fn pre_merge(&self, repo: &Repository, dep: &VendorDep, tree: Oid) -> Result<(), Error> {
+        let _ = (repo, dep, tree);
+        Ok(())
+    }
+
+    /// Called after `commit`, which merged `dep`, has been recorded. Runs
+    /// once per dependency name, with its first pattern, however many
+    /// patterns the name tracks.
+    fn post_commit(&self, repo: &Repository, dep: &VendorDep, commit: Oid) -> Result<(), Error> {
+        let _ = (repo, dep, commit);
+        Ok(())
+    }
+}
+
+/// Options for [`Vendor::vendor_log`], selecting a range of upstream commits.
+#[derive(Debug, Default)]
+pub struct VendorLogOpts {
+    /// Exclude this commit and its ancestors. Defaults to everything already
+    /// reachable from `HEAD`, i.e. the upstream commits merged so far.
+    pub since: Option<Oid>,
+    /// Start walking from this commit. Defaults to the fetched vendor ref.
+    pub until: Option<Oid>,
+    /// Return at most this many commits (`-n`).
+    pub max_count: Option<usize>,
+    /// Select the upstream commits brought in by the most recent merge of
+    /// the dependency into `HEAD` instead; `since` and `until` are ignored.
+    pub last_merge: bool,
+}
+
+/// Options for [`Vendor::vendor_extract`].
+#[derive(Debug, Default)]
+pub struct VendorExtractOpts {
+    /// Commit message. Defaults to `Contribute local changes to <name>`.
+    pub message: Option<String>,
+    /// Create this local branch (under `refs/heads/`) pointing at the
+    /// extracted commit. An existing branch is not overwritten.
+    pub branch: Option<String>,
+}
+
+/// The archive formats [`Vendor::vendor_archive`] writes.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum ArchiveFormat {
+    /// An uncompressed POSIX tar archive.
+    #[default]
+    Tar,
+    /// A gzip-compressed tar archive.
+    #[cfg_attr(feature = "cli", value(name = "tar.gz", alias = "tgz"))]
+    TarGz,
+    /// A zip archive.
+    Zip,
+}
+
+/// Options for [`Vendor::vendor_archive`].
+#[derive(Debug, Default)]
+pub struct VendorArchiveOpts {
+    pub format: ArchiveFormat,
+    /// Prepend this to every path in the archive, e.g. `lib-1.2/`.
+    pub prefix: Option<String>,
+    /// Archive this upstream commit instead of the one last merged into
+    /// `HEAD`.
+    pub upstream: Option<Oid>,
+}
+
+/// An upstream commit reported by [`Vendor::vendor_log`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VendorLogEntry {
+    pub id: Oid,
+    pub author: String,
+    pub email: String,
+    pub time: git2::Time,
+    pub summary: String,
+}
+
+impl VendorLogEntry {
+    /// The author date formatted as `YYYY-MM-DD` in the author's time zone.
+    pub fn date(&self) -> String {
+        format_date(self.time)
+    }
+}
+
+/// A file at `HEAD` and the dependency vendoring it, as reported by
+/// [`Vendor::vendor_owners`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VendorOwner {
+    /// Path of the file, relative to the repository root.
+    pub path: String,
+    pub name: String,
+    pub pattern: String,
+}
+
+/// An update of a vendor ref, as reported by [`Vendor::vendor_reflog`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VendorReflogEntry {
+    /// The commit the ref pointed to before the update, if it existed.
+    pub old: Option<Oid>,
+    /// The commit the ref pointed to after the update.
+    pub new: Oid,
+    pub committer: String,
+    pub email: String,
+    pub time: git2::Time,
+    /// What happened, such as `vendor fetch from <url>: <old>..<new>` or
+    /// `vendor merge into <branch>: <commit>`.
+    pub message: String,
+}
+
+impl VendorReflogEntry {
+    /// The update date formatted as `YYYY-MM-DD` in the committer's time zone.
+    pub fn date(&self) -> String {
+        format_date(self.time)
+    }
+}
+
+/// A summary of the pending update for one dependency, as reported by
+/// [`Vendor::vendor_preview`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VendorPreview {
+    /// Number of fetched upstream commits not yet merged into `HEAD`.
+    pub behind: usize,
+    /// Number of vendored files changed upstream since the last merge.
+    pub files_changed: usize,
+    pub insertions: usize,
+    pub deletions: usize,
+    /// The changes rendered like `git diff --stat`.
+    pub diffstat: String,
+}
+
+impl VendorPreview {
+    /// Return `true` if merging would not change anything.
+    pub fn is_up_to_date(&self) -> bool {
+        self.behind == 0 && self.files_changed == 0
+    }
+}
+
+/// How the vendored files of a dependency compare to its fetched upstream
+/// content, as reported by [`Vendor::vendor_state`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VendorState {
+    /// The vendored files differ from the last merged upstream content, in
+    /// `HEAD`, the index or the working tree.
+    pub locally_modified: bool,
+    /// Number of vendored files changed upstream since the last merge.
+    pub files_behind: usize,
+}
+
+impl VendorState {
+    /// Return `true` if the vendored files match the fetched upstream content.
+    pub fn is_clean(&self) -> bool {
+        !self.locally_modified && self.files_behind == 0
+    }
+
+    /// Describe the state as `clean`, `locally modified` and/or
+    /// `update available (N files differ)`.
+    pub fn summary(&self) -> String {
+        let update = format!("update available ({} files differ)", self.files_behind);
+        match (self.locally_modified, self.files_behind) {
+            (false, 0) => "clean".to_string(),
+            (true, 0) => "locally modified".to_string(),
+            (false, _) => update,
+            (true, _) => format!("locally modified, {update}"),
+        }
+    }
+}
+
+/// The status of one dependency, as reported by [`Vendor::vendor_statuses`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VendorStatus {
+    pub dep: VendorDep,
+    /// The commit `refs/vendor/<name>` points to, if fetched.
+    pub fetched: Option<Oid>,
+    /// The upstream tag last fetched, for a dependency following a
+    /// [`version`](VendorDep::version).
+    pub tag: Option<String>,
+    /// How the vendored files compare to the fetched content, unless nothing
+    /// was fetched or `HEAD` is unborn.
+    pub state: Option<VendorState>,
+    /// How the fetched fork compares to the fetched default branch of
+    /// [`VendorDep::upstream`], if both were fetched.
+    pub fork: Option<VendorForkDrift>,
+}
+
+impl VendorStatus {
+    /// Return `true` if the fetched upstream content has been merged. A
+    /// dependency that was never fetched is not up to date.
+    pub fn is_up_to_date(&self) -> bool {
+        self.state.is_some_and(|state| state.files_behind == 0)
+    }
+}
+
+/// How a dependency compares to its upstream branch tip, as reported by
+/// [`Vendor::vendor_outdated`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VendorOutdated {
+    pub name: String,
+    /// The commit the upstream branch currently points to.
+    pub upstream: Oid,
+    /// The commit `refs/vendor/<name>` points to, if fetched.
+    pub fetched: Option<Oid>,
+    /// The last upstream commit merged into `HEAD`, if known.
+    pub merged: Option<Oid>,
+    /// How the upstream branch, a fork, compares to the default branch of
+    /// [`VendorDep::upstream`].
+    pub fork: Option<VendorForkDrift>,
+}
+
+impl VendorOutdated {
+    /// Return `true` if the upstream tip has not been merged into `HEAD`.
+    pub fn is_outdated(&self) -> bool {
+        self.merged != Some(self.upstream)
+    }
+}
+
+/// How far a dependency's fork has drifted from the repository it was forked
+/// from ([`VendorDep::upstream`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VendorForkDrift {
+    /// The commit the default branch of the forked repository points to.
+    pub upstream: Oid,
+    /// Commits on the fork that the forked repository lacks, such as our own
+    /// patches, or `None` if either commit has not been fetched.
+    pub ahead: Option<usize>,
+    /// Commits of the forked repository that the fork lacks, or `None` if
+    /// either commit has not been fetched.
+    pub behind: Option<usize>,
+}
+
+impl VendorForkDrift {
+    /// Describe the drift as `N ahead, M behind upstream`, or `up to date
+    /// with upstream`.
+    pub fn summary(&self) -> String {
+        match (self.ahead, self.behind) {
+            (Some(0), Some(0)) => "up to date with upstream".to_string(),
+            (Some(ahead), Some(behind)) => format!("{ahead} ahead, {behind} behind upstream"),
+            _ => format!("upstream at {} (fetch to compare)", short_id(self.upstream)),
+        }
+    }
+}
+
+/// The vendored state of one dependency pattern at a commit, as recorded by
+/// [`Vendor::vendor_attest`].
 #[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VendorAttestation {
+    pub name: String,
+    pub pattern: String,
+    /// The upstream commit the vendored content was taken from.
+    pub upstream: Oid,
+    /// The filtered upstream tree, which the vendored paths must match.
+    pub tree: Oid,
+}
+
+/// A vendored dependency parsed from `.gitattributes`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct VendorDep {
     pub name: String,
     pub pattern: String,
     pub url: String,
     pub branch: Option<String>,
+    /// Cargo-style version requirement (`vendor-version=`), such as `^1.2`.
+    /// When set, fetches take the highest upstream tag satisfying it instead
+    /// of the branch.
+    pub version: Option<String>,
+    /// Fallback URLs (`vendor-mirror=`, repeatable) tried in order when
+    /// fetching from `url` fails.
+    pub mirrors: Vec<String>,
+    /// The repository `url` is a fork of (`vendor-upstream=`). Fetches also
+    /// fetch its default branch into `refs/vendor-upstream/<name>`, so that
+    /// status and outdated can report how far the fork has drifted from it.
+    pub upstream: Option<String>,
+    /// HTTP headers sent when fetching (`vendor-header=`, repeatable), as
+    /// `Name: value`. Attribute values cannot contain whitespace, so headers
+    /// carrying secrets belong in `http.extraHeader` or
+    /// [`VendorFetchOpts::headers`] rather than in a committed file.
+    pub headers: Vec<String>,
+    /// Allowed-signers file that the fetched upstream tip must be signed
+    /// against (`vendor-verify=`), relative to the repository root.
+    pub verify: Option<String>,
+    /// Groups the dependency belongs to (`vendor-group=`, repeatable), such as
+    /// `runtime` or `dev-tools`, selected together with `@<group>`.
+    pub groups: Vec<String>,
+    /// Why the dependency is tracked the way it is, recorded as a
+    /// `# vendor-note:` comment directly above its `.gitattributes` line.
+    pub note: Option<String>,
+    /// Patterns for upstream paths to leave out even though they match.
+    /// Only available in the `.gitvendor.toml` manifest.
+    pub excludes: Vec<String>,
+    /// Vendor the paths matching `vendor.defaultExcludes` too
+    /// (`-vendor-default-excludes`, or `default-excludes = false` in the
+    /// manifest).
+    pub no_default_excludes: bool,
+    /// Directories, relative to the declaring directory like the pattern,
+    /// kept with an empty `.gitkeep` file when none of their files are
+    /// vendored (`vendor-keep-dir=`, repeatable).
+    pub keep_dirs: Vec<String>,
+    /// Upstream directory whose contents are vendored (`vendor-src=`), as if
+    /// they were at the root of the upstream repository. Its contents land in
+    /// the declaring directory, or under [`prefix`](Self::prefix).
+    pub src: Option<String>,
+    /// Directory the upstream paths are placed under. Patterns match upstream
+    /// paths. Only available in the `.gitvendor.toml` manifest.
+    pub prefix: Option<String>,
+    /// Patch files, relative to the repository root, applied in order to the
+    /// filtered upstream tree. Only available in the `.gitvendor.toml`
+    /// manifest.
+    pub patches: Vec<String>,
+    /// Directory of the `.gitattributes` file declaring the dependency,
+    /// relative to the repository root. Empty for the root directory and for
+    /// the manifest.
+    pub source_dir: String,
+}
+
+impl VendorDep {
+    /// The pattern relative to the repository root rather than to
+    /// [`source_dir`](Self::source_dir).
+    ///
+    /// As in `.gitattributes`, a pattern without a slash matches file names
+    /// at any depth below the declaring directory, and any other pattern is
+    /// anchored to it.
+    pub fn root_pattern(&self) -> String {
+        let dir = self.source_dir.trim_matches('/');
+        if dir.is_empty() {
+            return self.pattern.clone();
+        }
+        if self.pattern.trim_end_matches('/').contains('/') {
+            format!("{dir}/{}", self.pattern.trim_start_matches('/'))
+        } else {
+            format!("{dir}/**/{}", self.pattern)
+        }
+    }
+
+    /// Whether the dependency is declared in `dir`, relative to the repository
+    /// root, or below it. Every dependency is in the scope of the root.
+    pub fn in_scope(&self, dir: &str) -> bool {
+        let dir = dir.trim_matches('/');
+        dir.is_empty()
+            || self
+                .source_dir
+                .strip_prefix(dir)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    }
+}
+
+/// Where vendored dependencies are declared, for [`Vendor::vendor_migrate`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum DepsFormat {
+    /// Vendor attributes in `.gitattributes`.
+    Gitattributes,
+    /// The `.gitvendor.toml` manifest.
+    Manifest,
 }
 
+/// Vendoring operations on a repository.
+///
+/// Methods taking `maybe_pattern` select the dependencies declared with that
+/// exact pattern. A directory ending in `/`, relative to the repository root,
+/// instead selects the dependencies declared in that directory or below it,
+/// and `@<group>` those in the group (see [`VendorDep::groups`]).
 pub trait Vendor {
+    /// Prepare the repository for vendoring.
+    ///
+    /// Unless a `.gitvendor.toml` manifest exists, the root `.gitattributes`
+    /// file is created if needed and starts with a comment describing the
+    /// vendor attributes. The `merge.ours.driver` config is defined, so that
+    /// `merge=ours` can be set on vendored paths whose local versions should
+    /// win branch merges. The libgit2 build is checked for HTTPS and SSH
+    /// support, and the `git` executable for the `git vendor` subcommand.
+    ///
+    /// Running it again leaves an initialized repository unchanged.
+    fn vendor_init(&self) -> Result<(), Error>;
+
     /// Add the pattern to the appropriate `.gitattributes` file using `git_set_attr`.
     ///
-    /// If there is a `.gitattributes` file in the current directory, that file is used.
-    /// Otherwise, the first found `.gitattributes` file when walking up the directory
-    /// tree from the current directory to the repository root directory is used.
+    /// If there is a `.gitattributes` file in [`VendorTrackOpts::at_dir`] (by default
+    /// the current directory), that file is used. Otherwise, the first found
+    /// `.gitattributes` file when walking up the directory tree from there to the
+    /// repository root directory is used.
     ///
-    /// If the pattern is already specified, the `url` and `branch` are updated if necessary.
+    /// If the pattern is already specified, its line is rewritten in place with the new
+    /// `url` and `branch`.
     ///
     /// The `maybe_name` argument overrides the dependency name. When `None`, the name is
-    /// derived from the URL as `owner/repo`. Local paths (non-URL remotes)
-    /// require an explicit name.
+    /// derived from the URL as `owner/repo`, or, for local paths and `file://`
+    /// URLs, as the repository's directory name. A relative local path is
+    /// taken relative to [`VendorTrackOpts::at_dir`], or the current directory
+    /// without one, and recorded relative to the repository root, which
+    /// fetching resolves it against.
+    ///
+    /// When a `.gitvendor.toml` manifest exists, the pattern is added to it instead,
+    /// joining the dependency's other patterns if it is already declared.
+    ///
+    /// The name must be usable in a ref name. Tracking fails if the name is
+    /// already used with a different URL or branch, or if the pattern overlaps
+    /// another dependency's, unless [`VendorTrackOpts::force`] is set, in which
+    /// case the conflicting patterns are untracked.
     fn track_pattern(
         &self,
         pattern: &str,
         url: &str,
         maybe_branch: Option<&str>,
         maybe_name: Option<&str>,
+        opts: &VendorTrackOpts,
     ) -> Result<(), Error>;
 
     /// Remove the pattern from the appropriate `.gitattributes` file using `git_set_attr`.
     ///
-    /// If there is a `.gitattributes` file in the current directory, that file is used.
-    /// Otherwise, the first found `.gitattributes` file when walking up the directory
-    /// tree from the current directory to the repository root directory is used.
-    fn untrack_pattern(&self, pattern: &str) -> Result<(), Error>;
+    /// If there is a `.gitattributes` file in `at_dir` (relative to the working tree
+    /// root), that file is used. Otherwise, the first found `.gitattributes` file
+    /// when walking up the directory tree from there to the repository root
+    /// directory is used. When `at_dir` is `None`, the walk starts from the
+    /// process's current directory ([`std::env::current_dir`]).
+    ///
+    /// When a `.gitvendor.toml` manifest exists, the pattern is removed from it instead.
+    fn untrack_pattern(&self, pattern: &str, at_dir: Option<&Path>) -> Result<(), Error>;
 
-    /// Return the status of all vendored content, or any errors encountered along the way.
-    fn vendor_status(&self, maybe_pattern: Option<&str>) -> Result<(), Error>;
+    /// Return the status of all vendored content. Nothing is printed.
+    fn vendor_statuses(&self, maybe_pattern: Option<&str>) -> Result<Vec<VendorStatus>, Error>;
 
     /// Fetch the latest content from all relevant vendor sources.
     ///
-    /// All vendor refs are stored under `/refs/vendor/`.
+    /// All vendor refs are stored under `/refs/vendor/`. Objects already
+    /// present locally, such as the history of an existing vendor ref, are
+    /// negotiated away, so repeated fetches only transfer new upstream
+    /// objects. Dependencies sharing a name are fetched once. Returns the
+    /// transfer statistics of each fetch.
     fn vendor_fetch(
         &self,
         maybe_pattern: Option<&str>,
+        opts: &VendorFetchOpts,
         fetch_opts: Option<&mut FetchOptions<'_>>,
+    ) -> Result<Vec<VendorFetchStats>, Error>;
+
+    /// Push the fetched `refs/vendor/*` refs of all relevant dependencies to
+    /// `remote` (a configured remote name or a URL), so others can fetch them
+    /// with [`VendorFetchOpts::mirror`].
+    fn vendor_push(
+        &self,
+        remote: &str,
+        maybe_pattern: Option<&str>,
+        push_opts: Option<&mut PushOptions<'_>>,
     ) -> Result<(), Error>;
 
     /// Merge the latest content from all relevant vendor sources.
     ///
     /// Behaves like `git merge`: updates the working tree and index, optionally
     /// creates a merge commit, and records `MERGE_HEAD`/`MERGE_MSG` when
-    /// appropriate.
+    /// appropriate. With [`VendorMergeOpts::index_only`], the working tree is
+    /// left untouched and bare repositories are supported.
+    ///
+    /// Each name is merged in one commit applying all the patterns declared
+    /// under it, even those `maybe_pattern` does not select.
     fn vendor_merge(
         &self,
         maybe_pattern: Option<&str>,
         opts: &VendorMergeOpts,
         merge_opts: Option<&MergeOptions>,
     ) -> Result<(), Error>;
+
+    /// Return the vendored dependencies tracked in `.gitvendor.toml` or
+    /// `.gitattributes`, optionally restricted to those whose pattern equals
+    /// `maybe_pattern`.
+    fn vendor_deps(&self, maybe_pattern: Option<&str>) -> Result<Vec<VendorDep>, Error>;
+
+    /// Fetch `dep` into `refs/vendor/<name>` and return the fetched commit.
+    ///
+    /// Unlike [`Vendor::vendor_fetch`], nothing is printed.
+    fn vendor_fetch_ref(
+        &self,
+        dep: &VendorDep,
+        fetch_opts: Option<&mut FetchOptions<'_>>,
+    ) -> Result<Oid, Error>;
+
+    /// Write the tree of the fetched upstream commit for `dep`, filtered by the
+    /// dependency's pattern, and return its OID.
+    fn vendor_filtered_tree(&self, dep: &VendorDep) -> Result<Oid, Error>;
+
+    /// Return the licenses detected in the filtered upstream tree of `dep`,
+    /// as SPDX license expressions, or `NOASSERTION` for unrecognized license
+    /// files.
+    fn vendor_licenses(&self, dep: &VendorDep) -> Result<Vec<String>, Error>;
+
+    /// Return the paths of the files in the fetched upstream commit for `dep`
+    /// that none of the patterns declared under its name vendor, sorted.
+    ///
+    /// Only files under the [`src`](VendorDep::src) directory of some pattern
+    /// are considered, and paths are upstream paths, so over-broad excludes
+    /// and too narrow patterns can be spotted.
+    fn vendor_excluded(&self, dep: &VendorDep) -> Result<Vec<String>, Error>;
+
+    /// Map every file at `HEAD` that a dependency vendors to that dependency,
+    /// sorted by path. Files vendored by no dependency are left out.
+    ///
+    /// As in `.gitattributes`, a file matched by several dependencies belongs
+    /// to the one declared last.
+    fn vendor_owners(&self) -> Result<Vec<VendorOwner>, Error>;
+
+    /// Return the staged changes to vendored files that are not part of a
+    /// vendor update, to keep vendored code from being edited by hand.
+    ///
+    /// A staged file is part of an update when it matches the fetched and
+    /// filtered upstream content of its dependency (or is absent from it when
+    /// deleted), or when `MERGE_HEAD` names an upstream commit of the
+    /// dependency. Ownership follows [`Vendor::vendor_owners`], applied to
+    /// the index as well as `HEAD`.
+    fn vendor_check_staged(&self) -> Result<Vec<VendorOwner>, Error>;
+
+    /// Merge the filtered upstream tree for `dep` into the tree at `HEAD` and
+    /// return the OID of the merged tree.
+    ///
+    /// Only objects are written: `HEAD`, the index, and the working tree are
+    /// left untouched, so callers can create commits however they see fit.
+    /// Conflicts are reported as an error.
+    fn vendor_merged_tree(
+        &self,
+        dep: &VendorDep,
+        merge_opts: Option<&MergeOptions>,
+    ) -> Result<Oid, Error>;
+
+    /// Filter the upstream tree `upstream` by `dep`'s patterns and return the
+    /// OID of the result, like [`Vendor::vendor_filtered_tree`] but without
+    /// reading `refs/vendor/`.
+    ///
+    /// Like [`Vendor::vendor_merge_trees`], this only reads and writes
+    /// objects.
+    fn vendor_filter_tree(&self, dep: &VendorDep, upstream: &Tree<'_>) -> Result<Oid, Error>;
+
+    /// Merge the upstream tree `upstream` of `dep` into the local tree `ours`
+    /// and return the OID of the merged tree. `base` is the upstream tree
+    /// merged last, or `None` if `dep` was never merged.
+    ///
+    /// Unlike [`Vendor::vendor_merged_tree`], no reference, index or working
+    /// tree is consulted: only objects are read and written. This works in
+    /// bare repositories and in repositories without any on-disk state, such
+    /// as one created with [`Repository::from_odb`] over an in-memory
+    /// object database. Conflicts are reported as an error.
+    fn vendor_merge_trees(
+        &self,
+        dep: &VendorDep,
+        ours: &Tree<'_>,
+        base: Option<&Tree<'_>>,
+        upstream: &Tree<'_>,
+        merge_opts: Option<&MergeOptions>,
+    ) -> Result<Oid, Error>;
+
+    /// Verify that the fetched upstream commit for `dep` is signed by a key in
+    /// `allowed_signers` (or the dependency's `vendor-verify=` file), returning
+    /// the signer's identity.
+    fn vendor_verify(
+        &self,
+        dep: &VendorDep,
+        allowed_signers: Option<&Path>,
+    ) -> Result<String, Error>;
+
+    /// List the upstream commits that a merge of `dep` would bring in, newest
+    /// first.
+    ///
+    /// By default this walks from the fetched vendor ref back to the last
+    /// upstream commit merged into `HEAD`; see [`VendorLogOpts`] to choose a
+    /// different range.
+    fn vendor_log(
+        &self,
+        dep: &VendorDep,
+        opts: &VendorLogOpts,
+    ) -> Result<Vec<VendorLogEntry>, Error>;
+
+    /// Render the upstream commits selected like [`Vendor::vendor_log`] as
+    /// Markdown, grouped by author, for pasting into a pull request. Commits
+    /// link to their pages when the upstream URL is on a known forge.
+    fn vendor_changelog(&self, dep: &VendorDep, opts: &VendorLogOpts) -> Result<String, Error>;
+
+    /// Return the history of `refs/vendor/<name>` from its reflog, newest
+    /// first: each fetch that updated it, and each commit that merged it.
+    fn vendor_reflog(&self, name: &str) -> Result<Vec<VendorReflogEntry>, Error>;
+
+    /// Summarize what merging the fetched upstream content for `dep` would
+    /// bring in: how many commits `HEAD` is behind, and a diffstat of the
+    /// vendored files changed upstream since the last merge.
+    fn vendor_preview(&self, dep: &VendorDep) -> Result<VendorPreview, Error>;
+
+    /// Compare `dep`'s vendored files in `HEAD`, the index and the working
+    /// tree with the fetched upstream content (see [`VendorState`]). Untracked
+    /// files are not considered.
+    fn vendor_state(&self, dep: &VendorDep) -> Result<VendorState, Error>;
+
+    /// Extract the local changes made to `dep`'s vendored files since the last
+    /// merge, in the upstream layout, as a commit on top of the fetched
+    /// `refs/vendor/<name>` that can be pushed upstream. Returns the commit.
+    fn vendor_extract(&self, dep: &VendorDep, opts: &VendorExtractOpts) -> Result<Oid, Error>;
+
+    /// Write an archive of the dependency `name`'s vendored files, as its
+    /// patterns select them from the upstream commit last merged into `HEAD`,
+    /// to `out`, and return that commit. Paths are those of the vendored
+    /// files in the repository, and every entry is dated with the upstream
+    /// commit, so archiving the same commit twice gives identical bytes.
+    fn vendor_archive(
+        &self,
+        name: &str,
+        opts: &VendorArchiveOpts,
+        out: &mut dyn std::io::Write,
+    ) -> Result<Oid, Error>;
+
+    /// For each relevant dependency with fetched upstream changes not yet in
+    /// `HEAD`, merge them on top of `HEAD` into a branch
+    /// `vendor/update-<name>`, so that each update can be pushed and reviewed
+    /// separately. `HEAD`, the index and the working tree are left untouched;
+    /// existing update branches are overwritten.
+    ///
+    /// The message, strategy, verification and filter settings of `opts`
+    /// apply. Returns the updated branches and their commits.
+    fn vendor_update_branches(
+        &self,
+        maybe_pattern: Option<&str>,
+        opts: &VendorMergeOpts,
+        merge_opts: Option<&MergeOptions>,
+    ) -> Result<Vec<(String, Oid)>, Error>;
+
+    /// Contact the upstream of every relevant dependency, without fetching,
+    /// and compare its branch tip to what was last fetched and merged.
+    /// Nothing is printed.
+    ///
+    /// Upstreams are contacted as by [`Vendor::vendor_fetch`]: falling back
+    /// to their mirrors, with the headers, retries and pacing of `opts` and
+    /// the callbacks and proxy settings of `fetch_opts`. The mirror and hooks
+    /// of `opts` are not used.
+    ///
+    /// Dependencies sharing a name are reported once.
+    fn vendor_outdated(
+        &self,
+        maybe_pattern: Option<&str>,
+        opts: &VendorFetchOpts,
+        fetch_opts: Option<&mut FetchOptions<'_>>,
+    ) -> Result<Vec<VendorOutdated>, Error>;
+
+    /// Record, in a note on `HEAD` under `refs/notes/vendor`, the last merged
+    /// upstream commit and its filtered tree for each relevant dependency.
+    /// The notes commit is signed with `user.signingkey`, like Git signs
+    /// commits. Returns the notes commit.
+    ///
+    /// Fails if the vendored paths at `HEAD` differ from the filtered upstream
+    /// tree, since the attestation would not hold.
+    fn vendor_attest(&self, maybe_pattern: Option<&str>) -> Result<Oid, Error>;
+
+    /// Check the attestation recorded for `commit` by [`Vendor::vendor_attest`]:
+    /// the notes commit must be signed by a key listed in `allowed_signers`
+    /// (or `gpg.ssh.allowedSignersFile`), and each recorded tree must match
+    /// both the filtered upstream commit and the vendored paths at `commit`.
+    fn vendor_verify_attestation(
+        &self,
+        commit: Oid,
+        allowed_signers: Option<&Path>,
+    ) -> Result<Vec<VendorAttestation>, Error>;
+
+    /// Move the dependency declarations to `format`: from `.gitattributes` into
+    /// a new `.gitvendor.toml` manifest, or from the manifest back into
+    /// `.gitattributes`, removing the manifest. Dependencies that use
+    /// excludes, a prefix, or patches cannot be moved to `.gitattributes`.
+    fn vendor_migrate(&self, format: DepsFormat) -> Result<(), Error>;
+
+    /// Check the vendoring state for inconsistencies: `.gitattributes` lines
+    /// missing the `vendored` flag, vendor refs without a dependency or
+    /// without a commit, patterns matching nothing at `HEAD`, and names
+    /// declared with different URLs or branches.
+    ///
+    /// With `fix`, the problems that can be fixed safely are (see
+    /// [`VendorFindingKind`]).
+    fn vendor_doctor(&self, fix: bool) -> Result<Vec<VendorFinding>, Error>;
 }
 
 impl Vendor for Repository {
+    fn vendor_init(&self) -> Result<(), Error> {
+        require_non_bare(self)?;
+        let workdir = self
+            .workdir()
+            .ok_or_else(|| Error::from_str("Repository has no working directory"))?;
+
+        if find_manifest(self)?.is_none() {
+            let path = workdir.join(".gitattributes");
+            let lock = LockFile::acquire(&path)?;
+            let content = match fs::read_to_string(&path) {
+                Ok(content) => content,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+                Err(e) => {
+                    return Err(Error::from_str(&format!(
+                        "Failed to read {}: {e}",
+                        path.display()
+                    )));
+                }
+            };
+            let style = LineStyle {
+                final_newline: true,
+                ..LineStyle::detect(&content)
+            };
+            let header = style.join(&GITATTRIBUTES_HEADER.lines().collect::<Vec<_>>());
+            if !content.starts_with(&header) {
+                lock.commit(&format!("{header}{content}"))?;
+                info!("Added a header to {}", path.display());
+            }
+        }
+
+        let mut config = self.config()?.open_level(git2::ConfigLevel::Local)?;
+        if config.get_string("merge.ours.driver").is_err() {
+            config.set_str("merge.ours.name", "keep the local version")?;
+            config.set_str("merge.ours.driver", "true")?;
+            info!("Defined the merge.ours merge driver");
+        }
+
+        let version = git2::Version::get();
+        let (major, minor, patch) = version.libgit2_version();
+        info!("libgit2 {major}.{minor}.{patch}");
+        if !version.https() {
+            warn!("  Warning: built without HTTPS support; https:// upstreams cannot be fetched");
+        }
+        if !version.ssh() {
+            warn!("  Warning: built without SSH support; ssh:// upstreams cannot be fetched");
+        }
+        match std::process::Command::new("git").arg("--version").output() {
+            Ok(output) if output.status.success() => {
+                info!("{}", String::from_utf8_lossy(&output.stdout).trim_end());
+            }
+            _ => warn!("Warning: git not found; `git vendor` requires it in PATH"),
+        }
+
+        Ok(())
+    }
+
     fn track_pattern(
         &self,
         pattern: &str,
         url: &str,
         maybe_branch: Option<&str>,
         maybe_name: Option<&str>,
+        opts: &VendorTrackOpts,
     ) -> Result<(), Error> {
         require_non_bare(self)?;
 
         let name = resolve_name(url, maybe_name)?;
+        let url = &root_relative_url(self, url, opts.at_dir.as_deref())?;
+        if let Some(version) = &opts.version {
+            VersionReq::parse(version).map_err(|msg| Error::from_str(&msg))?;
+        }
+        if !Reference::is_valid_name(&vendor_ref_name(&name)) {
+            return Err(Error::from_str(&format!(
+                "Invalid vendor name {name}: it must be usable in a ref name"
+            )));
+        }
+
+        let manifest_path = find_manifest(self)?;
+        let gitattributes = git_set_attr::find_gitattributes_file(self, opts.at_dir.as_deref())?;
+        let source_dir = match manifest_path {
+            Some(_) => String::new(),
+            None => declaring_dir(self, &gitattributes),
+        };
+        let tracked = VendorDep {
+            name: name.clone(),
+            url: url.to_string(),
+            branch: maybe_branch.map(str::to_string),
+            pattern: pattern.to_string(),
+            src: opts.src.clone(),
+            version: opts.version.clone(),
+            source_dir,
+            ..Default::default()
+        };
+        let replaced = track_conflicts(self, &tracked, opts.force)?;
+
+        if let Some(path) = manifest_path {
+            let content = fs::read_to_string(&path)
+                .map_err(|e| Error::from_str(&format!("Failed to read {}: {e}", path.display())))?;
+            let mut deps = manifest::parse(&content)?;
+            deps.retain(|dep| {
+                dep.pattern != pattern
+                    && !replaced
+                        .iter()
+                        .any(|r| r.name == dep.name && r.pattern == dep.pattern)
+            });
+
+            // Other patterns of the same dependency share its settings.
+            let mut dep = deps
+                .iter()
+                .find(|dep| dep.name == name)
+                .cloned()
+                .unwrap_or_default();
+            if let Some(note) = &opts.note {
+                for other in deps.iter_mut().filter(|other| other.name == name) {
+                    other.note = Some(note.clone());
+                }
+                dep.note = Some(note.clone());
+            }
+            dep.name = name;
+            dep.pattern = pattern.to_string();
+            dep.url = url.to_string();
+            dep.branch = maybe_branch.map(str::to_string);
+            dep.src = opts.src.clone();
+            dep.version = opts.version.clone();
+            deps.push(dep);
+
+            return write_manifest(&path, &deps);
+        }
+
+        let workdir = self
+            .workdir()
+            .ok_or_else(|| Error::from_str("Repository has no working directory"))?;
+        for dep in &replaced {
+            remove_vendor_lines(
+                self,
+                &workdir.join(&dep.source_dir).join(".gitattributes"),
+                &dep.pattern,
+            )?;
+        }
 
         let name_attr = format!("vendor-name={name}");
         let url_attr = format!("vendor-url={url}");
 
-        let mut attrs: Vec<&str> = vec!["vendored", &name_attr, &url_attr];
+        let branch_attr = match maybe_branch {
+            Some(branch) => format!("vendor-branch={branch}"),
+            None => "!vendor-branch".to_string(),
+        };
+        let src_attr = match &opts.src {
+            Some(src) => format!("vendor-src={src}"),
+            None => "!vendor-src".to_string(),
+        };
+        let version_attr = match &opts.version {
+            Some(version) => format!("vendor-version={}", attr_version(version)),
+            None => "!vendor-version".to_string(),
+        };
+        let attrs: Vec<&str> = vec![
+            "vendored",
+            &name_attr,
+            &url_attr,
+            &branch_attr,
+            &version_attr,
+            &src_attr,
+        ];
 
-        let branch_attr;
-        if let Some(branch) = maybe_branch {
-            branch_attr = format!("vendor-branch={branch}");
-            attrs.push(&branch_attr);
+        self.replace_attr(pattern, &attrs, Some(&gitattributes))?;
+        if let Some(note) = &opts.note {
+            set_note(&gitattributes, pattern, Some(note))?;
         }
-
-        self.set_attr(pattern, &attrs, None)
+        if opts.canonicalize {
+            self.canonicalize_attrs(Some(&gitattributes))?;
+        }
+        Ok(())
     }
 
-    fn untrack_pattern(&self, pattern: &str) -> Result<(), Error> {
+    fn untrack_pattern(&self, pattern: &str, at_dir: Option<&Path>) -> Result<(), Error> {
         require_non_bare(self)?;
 
-        let path = find_gitattributes(self)?;
+        if let Some(path) = find_manifest(self)? {
+            let content = fs::read_to_string(&path)
+                .map_err(|e| Error::from_str(&format!("Failed to read {}: {e}", path.display())))?;
+            let mut deps = manifest::parse(&content)?;
+            deps.retain(|dep| dep.pattern != pattern);
+            return write_manifest(&path, &deps);
+        }
+
+        let path = git_set_attr::find_gitattributes_file(self, at_dir)?;
         if !path.exists() {
             return Ok(());
         }
 
-        remove_vendor_lines(&path, pattern)
+        remove_vendor_lines(self, &path, pattern)
     }
 
-    fn vendor_status(&self, maybe_pattern: Option<&str>) -> Result<(), Error> {
+    fn vendor_statuses(&self, maybe_pattern: Option<&str>) -> Result<Vec<VendorStatus>, Error> {
         require_non_bare(self)?;
 
-        let path = find_gitattributes(self)?;
-        let deps = parse_vendor_deps(&path)?;
-        let deps = filter_deps(&deps, maybe_pattern);
-
-        if deps.is_empty() {
-            println!("No vendored dependencies tracked");
-            return Ok(());
-        }
-
-        for dep in deps {
-            println!("{} ({})", dep.name, dep.pattern);
-            println!("  URL: {}", dep.url);
-            match &dep.branch {
-                Some(b) => println!("  Branch: {b}"),
-                None => println!("  Branch: (default)"),
-            }
-
-            let ref_name = vendor_ref_name(&dep.name);
-            match self.find_reference(&ref_name) {
-                Ok(reference) => {
-                    if let Some(oid) = reference.target() {
-                        println!("  Ref: {ref_name} ({oid})");
-                    } else {
-                        println!("  Ref: {ref_name} (symbolic)");
+        let deps = load_deps(self)?;
+        let mut statuses = Vec::new();
+        for dep in filter_deps(&deps, maybe_pattern) {
+            let fetched = self.refname_to_id(&vendor_ref_name(&dep.name)).ok();
+            let state = match fetched {
+                Some(_) => match self.vendor_state(dep) {
+                    Ok(state) => Some(state),
+                    Err(e) if e.code() == ErrorCode::UnbornBranch => None,
+                    Err(e) => return Err(e),
+                },
+                None => None,
+            };
+            let fork = match (fetched, &dep.upstream) {
+                (Some(fetched), Some(_)) => {
+                    match self.refname_to_id(&fork_upstream_ref_name(&dep.name)) {
+                        Ok(upstream) => Some(fork_drift(self, fetched, upstream)?),
+                        Err(_) => None,
                     }
                 }
-                Err(_) => {
-                    println!("  Ref: {ref_name} (not fetched)");
-                }
-            }
-            println!();
+                _ => None,
+            };
+            let tag = match dep.version {
+                Some(_) => fetched_tag(self, dep),
+                None => None,
+            };
+            statuses.push(VendorStatus {
+                dep: dep.clone(),
+                fetched,
+                tag,
+                state,
+                fork,
+            });
         }
 
-        Ok(())
+        Ok(statuses)
     }
 
     fn vendor_fetch(
         &self,
         maybe_pattern: Option<&str>,
+        opts: &VendorFetchOpts,
         mut fetch_opts: Option<&mut FetchOptions<'_>>,
-    ) -> Result<(), Error> {
+    ) -> Result<Vec<VendorFetchStats>, Error> {
         require_non_bare(self)?;
 
-        let path = find_gitattributes(self)?;
-        let deps = parse_vendor_deps(&path)?;
+        let deps = load_deps(self)?;
         let deps = filter_deps(&deps, maybe_pattern);
 
         if deps.is_empty() {
             return Err(Error::from_str("No vendored dependencies to fetch"));
         }
 
-        for dep in deps {
-            let ref_target = vendor_ref_name(&dep.name);
+        // Patterns sharing a name are fetched once, from the first's source.
+        let mut results = Vec::new();
+        let mut contacted = HashMap::new();
+        for group in group_by_name(&deps) {
+            let dep = group[0];
+            hooks::pre_fetch(self, opts.hooks.as_deref(), dep)?;
+            let started = Instant::now();
+
+            if let Some(upstream) = &dep.upstream {
+                info!("Fetching forked repository of {} from {upstream}", dep.name);
+                let fetched = with_retries(opts, &mut contacted, upstream, || {
+                    fetch_fork_upstream(
+                        self,
+                        dep,
+                        upstream,
+                        &opts.headers,
+                        fetch_opts.as_deref_mut(),
+                    )
+                });
+                match fetched {
+                    Ok(oid) => info!(
+                        "  Fetched {} to {}",
+                        short_id(oid),
+                        fork_upstream_ref_name(&dep.name)
+                    ),
+                    Err(e) => warn!("  Forked repository unavailable: {}", e.message()),
+                }
+            }
 
-            let branch_display = dep.branch.as_deref().unwrap_or("HEAD");
-            println!(
+            if let Some(mirror) = &opts.mirror {
+                info!("Fetching {} from mirror {}", dep.name, mirror);
+                let fetched = with_retries(opts, &mut contacted, mirror, || {
+                    fetch_from_mirror(self, dep, mirror, &opts.headers, fetch_opts.as_deref_mut())
+                });
+                match fetched {
+                    Ok((_, stats)) => {
+                        info!(
+                            "  Fetched to {} ({})",
+                            vendor_ref_name(&dep.name),
+                            stats.summary()
+                        );
+                        debug!("  Fetched {} in {:.2?}", dep.name, started.elapsed());
+                        results.push(stats);
+                        continue;
+                    }
+                    Err(e) => warn!("  Mirror unavailable: {}", e.message()),
+                }
+            }
+
+            let branch_display = match (&dep.version, &dep.branch) {
+                (Some(version), _) => version.as_str(),
+                (None, Some(branch)) => branch.as_str(),
+                (None, None) => "HEAD",
+            };
+            info!(
                 "Fetching {} from {} ({})",
                 dep.name, dep.url, branch_display
             );
 
-            let mut remote = self.remote_anonymous(&dep.url)?;
-            let refspec = match &dep.branch {
-                Some(branch) => format!("+refs/heads/{branch}:{ref_target}"),
-                None => format!("+HEAD:{ref_target}"),
-            };
-            remote.fetch(&[&refspec], fetch_opts.as_mut().map(|o| &mut **o), None)?;
+            let mut sources = dep_sources(dep);
+            let mut source = sources.next().unwrap_or(&dep.url);
+            let stats = loop {
+                let fetched = with_retries(opts, &mut contacted, source, || {
+                    fetch_from_url(self, dep, source, &opts.headers, fetch_opts.as_deref_mut())
+                });
+                match fetched {
+                    Ok((_, stats)) => break stats,
+                    Err(e) => match sources.next() {
+                        Some(mirror) => {
+                            warn!("  {source} unavailable: {}", e.message());
+                            source = mirror;
+                        }
+                        None if dep.mirrors.is_empty() => return Err(e),
+                        None => return Err(all_sources_failed(dep, &e)),
+                    },
+                }
+            };
+
+            let what = match &stats.tag {
+                Some(tag) => format!("{tag} to"),
+                None => "to".to_string(),
+            };
+            if source == dep.url {
+                info!(
+                    "  Fetched {what} {} ({})",
+                    vendor_ref_name(&dep.name),
+                    stats.summary()
+                );
+            } else {
+                info!(
+                    "  Fetched {what} {} from mirror {source} ({})",
+                    vendor_ref_name(&dep.name),
+                    stats.summary()
+                );
+            }
+            debug!("  Fetched {} in {:.2?}", dep.name, started.elapsed());
+            results.push(stats);
+        }
+
+        Ok(results)
+    }
+
+    fn vendor_push(
+        &self,
+        remote: &str,
+        maybe_pattern: Option<&str>,
+        push_opts: Option<&mut PushOptions<'_>>,
+    ) -> Result<(), Error> {
+        let deps = load_deps(self)?;
+        let deps = filter_deps(&deps, maybe_pattern);
+
+        if deps.is_empty() {
+            return Err(Error::from_str("No vendored dependencies to push"));
+        }
+
+        let mut refspecs = Vec::new();
+        for group in group_by_name(&deps) {
+            let dep = group[0];
+            let ref_name = vendor_ref_name(&dep.name);
+            if self.find_reference(&ref_name).is_err() {
+                return Err(Error::from_str(&format!(
+                    "Vendor ref {ref_name} not found. Run fetch first."
+                )));
+            }
+            info!("Pushing {} to {}", ref_name, remote);
+            refspecs.push(format!("+{ref_name}:{ref_name}"));
+        }
+
+        let mut remote = match self.find_remote(remote) {
+            Ok(remote) => remote,
+            Err(_) => self.remote_anonymous(remote)?,
+        };
+
+        if let Some(push_opts) = push_opts {
+            return remote.push(&refspecs, Some(push_opts));
+        }
+
+        let mut rejected = Vec::new();
+        {
+            let mut callbacks = RemoteCallbacks::new();
+            callbacks.push_update_reference(|name, status| {
+                if let Some(status) = status {
+                    rejected.push(format!("{name} ({status})"));
+                }
+                Ok(())
+            });
+            let mut push_opts = PushOptions::new();
+            push_opts.remote_callbacks(callbacks);
+            remote.push(&refspecs, Some(&mut push_opts))?;
+        }
+
+        if !rejected.is_empty() {
+            return Err(Error::from_str(&format!(
+                "Remote rejected {}",
+                rejected.join(", ")
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn vendor_merge(
+        &self,
+        maybe_pattern: Option<&str>,
+        opts: &VendorMergeOpts,
+        merge_opts: Option<&MergeOptions>,
+    ) -> Result<(), Error> {
+        if !opts.index_only {
+            require_non_bare(self)?;
+        }
+
+        let deps = load_deps(self)?;
+        let deps = filter_dep_names(&deps, maybe_pattern);
+
+        if deps.is_empty() {
+            return Err(Error::from_str("No vendored dependencies to merge"));
+        }
+
+        let groups = group_by_name(&deps);
+        let skip_commit = opts.no_commit || opts.squash;
+        if skip_commit && groups.len() > 1 && !opts.single_commit {
+            return Err(Error::from_str(
+                "--no-commit and --squash require a single dependency; \
+                 specify a pattern to select one",
+            ));
+        }
+        if skip_commit && self.is_bare() {
+            return Err(Error::from_str(
+                "--no-commit and --squash are not supported in a bare repository",
+            ));
+        }
+
+        if opts.single_commit {
+            return merge_single_commit(self, &deps, opts, merge_opts);
+        }
+
+        // Every pattern of a name is merged into one commit, since the
+        // upstream commit becomes a parent and is then merged for all of them.
+        for group in &groups {
+            let dep = group[0];
+            let patterns: Vec<&str> = group.iter().map(|d| d.pattern.as_str()).collect();
+            info!("Merging {} ({})", dep.name, patterns.join(", "));
+
+            let vendor_commit = find_vendor_commit(self, dep)?;
+            let vendor_oid = vendor_commit.id();
+
+            if opts.allowed_signers.is_some() || dep.verify.is_some() {
+                let signer = self.vendor_verify(dep, opts.allowed_signers.as_deref())?;
+                info!("  Verified signature from {signer}");
+            }
+
+            let head = self.head()?;
+            let head_commit = head.peel_to_commit()?;
+            let head_tree = head_commit.tree()?;
+
+            let mut merged_tree = head_tree.clone();
+            let mut licenses = Vec::new();
+            for dep in group {
+                let (mut index, stats, dep_licenses) = merge_vendor_tree(
+                    self,
+                    dep,
+                    &head_commit,
+                    &merged_tree,
+                    &vendor_commit,
+                    opts,
+                    merge_opts,
+                )?;
+                for pattern in &stats.unmatched_patterns {
+                    warn!("  Warning: pattern {pattern} matched no upstream files");
+                }
+                for path in &stats.gitlinks {
+                    match opts.submodules {
+                        SubmodulePolicy::Keep => info!("  Kept submodule {path}"),
+                        _ => info!("  Skipped submodule {path}"),
+                    }
+                }
+                for license in dep_licenses {
+                    if !licenses.contains(&license) {
+                        info!("  License: {license}");
+                        licenses.push(license);
+                    }
+                }
+
+                if index.has_conflicts() {
+                    if opts.index_only {
+                        return Err(Error::from_str(&format!(
+                            "Conflicts detected while merging {}. \
+                             Index-only merges cannot record conflicts; \
+                             merge in a working tree to resolve them.",
+                            dep.name
+                        )));
+                    }
+
+                    // Write the patterns merged so far and the conflicts to
+                    // the repository so the user can resolve them in the
+                    // working tree.
+                    let conflicts = index.conflicts()?.collect::<Result<Vec<_>, _>>()?;
+                    let conflicted: Vec<PathBuf> = conflicts
+                        .iter()
+                        .filter_map(|c| c.our.as_ref().or(c.their.as_ref()))
+                        .map(|entry| path_from_bytes(&entry.path))
+                        .collect();
+                    let paths = changed_paths(self, &head_tree, &merged_tree)?;
+                    if !opts.force {
+                        check_unmodified(self, &[&paths[..], &conflicted[..]].concat(), false)?;
+                    }
+                    checkout_paths(self, &merged_tree, &paths, false)?;
+
+                    let mut repo_index = self.index()?;
+                    for path in &conflicted {
+                        repo_index.remove_path(path)?;
+                    }
+                    for conflict in &conflicts {
+                        if let Some(entry) = &conflict.our {
+                            repo_index.add(entry)?;
+                        }
+                        if let Some(entry) = &conflict.their {
+                            repo_index.add(entry)?;
+                        }
+                    }
+                    repo_index.write()?;
+
+                    let mut co = CheckoutBuilder::new();
+                    co.allow_conflicts(true)
+                        .conflict_style_merge(true)
+                        .disable_pathspec_match(true);
+                    for path in &conflicted {
+                        co.path(path);
+                    }
+                    self.checkout_index(Some(&mut repo_index), Some(&mut co))?;
+
+                    if !opts.squash {
+                        set_merge_head(self, &[vendor_oid])?;
+                    }
+                    let message = merge_message(self, dep, opts, &vendor_commit)?;
+                    set_merge_msg(self, &with_trailers(self, &[dep], message, &licenses))?;
+
+                    return Err(Error::from_str(&format!(
+                        "Conflicts detected while merging {}. \
+                         Resolve them and commit the result.",
+                        dep.name
+                    )));
+                }
+                merged_tree = self.find_tree(index.write_tree_to(self)?)?;
+            }
+            licenses.sort();
+
+            let message = merge_message(self, dep, opts, &vendor_commit)?;
+            let message = with_trailers(self, &[dep], message, &licenses);
+            let message = message.as_str();
+
+            // Clean merge — update the index and (unless index-only) the
+            // working directory where the merge changed them.
+            let paths = changed_paths(self, &head_tree, &merged_tree)?;
+            if !opts.force {
+                check_unmodified(self, &paths, opts.index_only)?;
+            }
+            checkout_paths(self, &merged_tree, &paths, opts.index_only)?;
+
+            if skip_commit {
+                if !opts.squash {
+                    set_merge_head(self, &[vendor_oid])?;
+                }
+                set_merge_msg(self, message)?;
+                info!("  Merged (not committed)");
+            } else {
+                let commit = commit_merge(
+                    self,
+                    Some("HEAD"),
+                    opts,
+                    message,
+                    &merged_tree,
+                    &[&head_commit, &vendor_commit],
+                )?;
+                info!("  Merged successfully");
+                let head = self.head()?;
+                log_vendor_merge(self, &dep.name, head.shorthand().unwrap_or("HEAD"), commit);
+                hooks::post_commit(self, opts.hooks.as_deref(), dep, commit)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn vendor_deps(&self, maybe_pattern: Option<&str>) -> Result<Vec<VendorDep>, Error> {
+        let deps = load_deps(self)?;
+        Ok(filter_deps(&deps, maybe_pattern)
+            .into_iter()
+            .cloned()
+            .collect())
+    }
+
+    fn vendor_fetch_ref(
+        &self,
+        dep: &VendorDep,
+        fetch_opts: Option<&mut FetchOptions<'_>>,
+    ) -> Result<Oid, Error> {
+        let mut fetch_opts = fetch_opts;
+        let mut last_error = None;
+        for source in dep_sources(dep) {
+            match fetch_from_url(self, dep, source, &[], fetch_opts.as_deref_mut()) {
+                Ok((oid, _)) => return Ok(oid),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        match last_error {
+            Some(e) if dep.mirrors.is_empty() => Err(e),
+            Some(e) => Err(all_sources_failed(dep, &e)),
+            None => unreachable!("a dependency always has a URL"),
+        }
+    }
+
+    fn vendor_filtered_tree(&self, dep: &VendorDep) -> Result<Oid, Error> {
+        let vendor_commit = find_vendor_commit(self, dep)?;
+        self.vendor_filter_tree(dep, &vendor_commit.tree()?)
+    }
+
+    fn vendor_filter_tree(&self, dep: &VendorDep, upstream: &Tree<'_>) -> Result<Oid, Error> {
+        let filter = dep_filter_options(&VendorMergeOpts::default());
+        Ok(filter_dep_tree(self, dep, upstream, &filter)?.id())
+    }
+
+    fn vendor_licenses(&self, dep: &VendorDep) -> Result<Vec<String>, Error> {
+        let tree = self.find_tree(self.vendor_filtered_tree(dep)?)?;
+        license::detect(self, &tree)
+    }
+
+    fn vendor_excluded(&self, dep: &VendorDep) -> Result<Vec<String>, Error> {
+        let vendor_tree = find_vendor_commit(self, dep)?.tree()?;
+        let filter = dep_filter_options(&VendorMergeOpts::default());
+        let deps = load_deps(self)?;
+        let mut excluded = BTreeSet::new();
+        let mut kept = HashSet::new();
+        for dep in deps.iter().filter(|other| other.name == dep.name) {
+            // The `src` directory is filtered as if nested in the declaring one.
+            let upstream = upstream_root(self, dep, &vendor_tree)?;
+            let diff = self.filter_diff(
+                &upstream,
+                &[&dep.root_pattern()],
+                &with_dep_filters(self, dep, &filter)?,
+            )?;
+            let nested = match &dep.src {
+                Some(_) => dep.source_dir.trim_matches('/'),
+                None => "",
+            };
+            for delta in diff.deltas() {
+                let path = delta.old_file().path_bytes().unwrap_or_default();
+                let path = String::from_utf8_lossy(path);
+                let relative = path
+                    .strip_prefix(nested)
+                    .unwrap_or(&path)
+                    .trim_start_matches('/');
+                let path = match dep.src.as_deref().map(|src| src.trim_matches('/')) {
+                    Some(src) if !src.is_empty() => format!("{src}/{relative}"),
+                    _ => relative.to_string(),
+                };
+                if delta.status() == git2::Delta::Deleted {
+                    excluded.insert(path);
+                } else {
+                    kept.insert(path);
+                }
+            }
+        }
+        Ok(excluded
+            .into_iter()
+            .filter(|path| !kept.contains(path))
+            .collect())
+    }
+
+    fn vendor_owners(&self) -> Result<Vec<VendorOwner>, Error> {
+        let head_tree = match self.head() {
+            Ok(head) => head.peel_to_tree()?,
+            Err(e) if e.code() == ErrorCode::UnbornBranch || e.code() == ErrorCode::NotFound => {
+                return Ok(Vec::new());
+            }
+            Err(e) => return Err(e),
+        };
+
+        let deps = load_deps(self)?;
+        Ok(tree_owners(self, &deps, &head_tree)?
+            .into_iter()
+            .map(|(path, i)| VendorOwner {
+                path,
+                name: deps[i].name.clone(),
+                pattern: deps[i].pattern.clone(),
+            })
+            .collect())
+    }
+
+    fn vendor_check_staged(&self) -> Result<Vec<VendorOwner>, Error> {
+        require_non_bare(self)?;
+
+        let head_tree = match self.head() {
+            Ok(head) => Some(head.peel_to_tree()?),
+            Err(e) if e.code() == ErrorCode::UnbornBranch || e.code() == ErrorCode::NotFound => {
+                None
+            }
+            Err(e) => return Err(e),
+        };
+        // Diff against the index itself rather than a tree written from it,
+        // which a conflicted index cannot produce.
+        let index = self.index()?;
+        let diff = self.diff_tree_to_index(head_tree.as_ref(), Some(&index), None)?;
+
+        let deps = load_deps(self)?;
+        let staged = tree_owners(self, &deps, &paths_tree(self, &delta_paths(&diff))?)?;
+        let committed = match &head_tree {
+            Some(tree) => tree_owners(self, &deps, tree)?,
+            None => Default::default(),
+        };
+
+        let merging = merge_heads(self)?;
+        let filter = dep_filter_options(&VendorMergeOpts::default());
+        let mut upstream_trees = HashMap::new();
+        let mut flagged = Vec::new();
+        for delta in diff.deltas() {
+            let Some(path) = delta.new_file().path().or(delta.old_file().path()) else {
+                continue;
+            };
+            let path = path.to_string_lossy().replace('\\', "/");
+            let Some(&i) = staged.get(&path).or_else(|| committed.get(&path)) else {
+                continue;
+            };
+            let dep = &deps[i];
+
+            // Staging the fetched upstream content is a vendor update, as is
+            // anything staged while merging one of its upstream commits.
+            if let Ok(vendor_commit) = find_vendor_commit(self, dep) {
+                let mut updating = false;
+                for &oid in &merging {
+                    updating |= oid == vendor_commit.id()
+                        || self.graph_descendant_of(vendor_commit.id(), oid)?;
+                }
+                if updating {
+                    continue;
+                }
+
+                let upstream = match upstream_trees.entry(i) {
+                    Entry::Occupied(entry) => *entry.get(),
+                    Entry::Vacant(entry) => {
+                        let vendor_tree = vendor_commit.tree()?;
+                        *entry.insert(filter_dep_tree(self, dep, &vendor_tree, &filter)?.id())
+                    }
+                };
+                let upstream = self.find_tree(upstream)?;
+                let upstream_id = upstream.get_path(Path::new(&path)).ok().map(|e| e.id());
+                let staged_id =
+                    (delta.status() != git2::Delta::Deleted).then(|| delta.new_file().id());
+                if upstream_id == staged_id {
+                    continue;
+                }
+            }
+
+            flagged.push(VendorOwner {
+                path,
+                name: dep.name.clone(),
+                pattern: dep.pattern.clone(),
+            });
+        }
+        Ok(flagged)
+    }
+
+    fn vendor_merged_tree(
+        &self,
+        dep: &VendorDep,
+        merge_opts: Option<&MergeOptions>,
+    ) -> Result<Oid, Error> {
+        let head_commit = self.head()?.peel_to_commit()?;
+        let vendor_commit = find_vendor_commit(self, dep)?;
+
+        if dep.verify.is_some() {
+            self.vendor_verify(dep, None)?;
+        }
+
+        let (mut index, _, _) = merge_vendor_tree(
+            self,
+            dep,
+            &head_commit,
+            &head_commit.tree()?,
+            &vendor_commit,
+            &VendorMergeOpts::default(),
+            merge_opts,
+        )?;
+        if index.has_conflicts() {
+            return Err(Error::from_str(&format!(
+                "Conflicts detected while merging {}",
+                dep.name
+            )));
+        }
+
+        index.write_tree_to(self)
+    }
+
+    fn vendor_merge_trees(
+        &self,
+        dep: &VendorDep,
+        ours: &Tree<'_>,
+        base: Option<&Tree<'_>>,
+        upstream: &Tree<'_>,
+        merge_opts: Option<&MergeOptions>,
+    ) -> Result<Oid, Error> {
+        let empty;
+        let base = match base {
+            Some(base) => base,
+            None => {
+                empty = self.find_tree(self.treebuilder(None)?.write()?)?;
+                &empty
+            }
+        };
+        let opts = VendorMergeOpts::default();
+        let (theirs, _, _) = filter_upstream(self, dep, upstream, &opts)?;
+        let mut index = merge_filtered(self, dep, ours, base, &theirs, &opts, merge_opts)?;
+        if index.has_conflicts() {
+            return Err(Error::from_str(&format!(
+                "Conflicts detected while merging {}",
+                dep.name
+            )));
+        }
+
+        index.write_tree_to(self)
+    }
+
+    fn vendor_verify(
+        &self,
+        dep: &VendorDep,
+        allowed_signers: Option<&Path>,
+    ) -> Result<String, Error> {
+        let allowed_signers = match (allowed_signers, &dep.verify) {
+            (Some(path), _) => path.to_path_buf(),
+            (None, Some(path)) => resolve_repo_path(self, path),
+            (None, None) => {
+                return Err(Error::from_str(&format!(
+                    "No allowed signers configured for {}",
+                    dep.name
+                )));
+            }
+        };
+
+        let reference = self.find_reference(&vendor_ref_name(&dep.name))?;
+        let oid = reference
+            .target()
+            .ok_or_else(|| Error::from_str("Invalid vendor reference"))?;
+
+        verify::verify_object(self, oid, &allowed_signers)
+            .map_err(|e| Error::from_str(&format!("{}: {}", dep.name, e.message())))
+    }
+
+    fn vendor_log(
+        &self,
+        dep: &VendorDep,
+        opts: &VendorLogOpts,
+    ) -> Result<Vec<VendorLogEntry>, Error> {
+        let (since, until) = log_range(self, dep, opts)?;
+
+        let mut walk = self.revwalk()?;
+        walk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::TIME)?;
+        walk.push(until)?;
+        if let Some(since) = since {
+            walk.hide(since)?;
+        }
+
+        let mut entries = Vec::new();
+        for oid in walk {
+            if opts.max_count.is_some_and(|max| entries.len() >= max) {
+                break;
+            }
+
+            let commit = self.find_commit(oid?)?;
+            let author = commit.author();
+            entries.push(VendorLogEntry {
+                id: commit.id(),
+                author: author.name().unwrap_or_default().to_string(),
+                email: author.email().unwrap_or_default().to_string(),
+                time: author.when(),
+                summary: commit.summary().unwrap_or_default().to_string(),
+            });
+        }
+
+        Ok(entries)
+    }
+
+    fn vendor_changelog(&self, dep: &VendorDep, opts: &VendorLogOpts) -> Result<String, Error> {
+        let (since, until) = log_range(self, dep, opts)?;
+        let old = match since {
+            Some(since) => match self.merge_base(since, until) {
+                Ok(base) => Some(base),
+                Err(e) if e.code() == ErrorCode::NotFound => None,
+                Err(e) => return Err(e),
+            },
+            None => None,
+        };
+        let entries = self.vendor_log(dep, opts)?;
+        Ok(changelog::render(&dep.name, &dep.url, old, until, &entries))
+    }
+
+    fn vendor_reflog(&self, name: &str) -> Result<Vec<VendorReflogEntry>, Error> {
+        let ref_name = vendor_ref_name(name);
+        if self.find_reference(&ref_name).is_err() {
+            return Err(Error::from_str(&format!(
+                "Vendor ref {ref_name} not found. Run fetch first."
+            )));
+        }
+
+        let reflog = self.reflog(&ref_name)?;
+        Ok(reflog
+            .iter()
+            .map(|entry| {
+                let committer = entry.committer();
+                VendorReflogEntry {
+                    old: Some(entry.id_old()).filter(|oid| !oid.is_zero()),
+                    new: entry.id_new(),
+                    committer: committer.name().unwrap_or_default().to_string(),
+                    email: committer.email().unwrap_or_default().to_string(),
+                    time: committer.when(),
+                    message: entry.message().unwrap_or_default().to_string(),
+                }
+            })
+            .collect())
+    }
+
+    fn vendor_preview(&self, dep: &VendorDep) -> Result<VendorPreview, Error> {
+        let head_commit = self.head()?.peel_to_commit()?;
+        let vendor_commit = find_vendor_commit(self, dep)?;
+
+        let vendor_tree = vendor_commit.tree()?;
+        let filter = dep_filter_options(&VendorMergeOpts::default());
+        let theirs = filter_dep_tree(self, dep, &vendor_tree, &filter)?;
+        let base_tree = merge_base_tree(self, &head_commit, &vendor_commit)?;
+        let ancestor = filter_dep_tree(self, dep, &base_tree, &filter)?;
+
+        let diff = self.diff_tree_to_tree(Some(&ancestor), Some(&theirs), None)?;
+        let stats = diff.stats()?;
+        let diffstat = stats.to_buf(git2::DiffStatsFormat::FULL, 80)?;
+
+        let behind = self.vendor_log(dep, &VendorLogOpts::default())?.len();
+
+        Ok(VendorPreview {
+            behind,
+            files_changed: stats.files_changed(),
+            insertions: stats.insertions(),
+            deletions: stats.deletions(),
+            diffstat: diffstat.as_str().unwrap_or_default().to_string(),
+        })
+    }
+
+    fn vendor_state(&self, dep: &VendorDep) -> Result<VendorState, Error> {
+        let head_commit = self.head()?.peel_to_commit()?;
+        let head_tree = head_commit.tree()?;
+        let vendor_commit = find_vendor_commit(self, dep)?;
+        let filter = dep_filter_options(&VendorMergeOpts::default());
+
+        let vendor_tree = vendor_commit.tree()?;
+        let theirs = filter_dep_tree(self, dep, &vendor_tree, &filter)?;
+        let base_tree = merge_base_tree(self, &head_commit, &vendor_commit)?;
+        let ancestor = filter_dep_tree(self, dep, &base_tree, &filter)?;
+        let ours = local_dep_tree(self, dep, &head_tree, &filter)?;
+
+        // Staged and unstaged changes, including the conflicts a merge left in
+        // the index, count when they touch the dependency's paths.
+        let index = self.index()?;
+        let mut changed =
+            delta_paths(&self.diff_tree_to_index(Some(&head_tree), Some(&index), None)?);
+        if !self.is_bare() {
+            changed.extend(delta_paths(
+                &self.diff_index_to_workdir(Some(&index), None)?,
+            ));
+        }
+        let owners = tree_owners(
+            self,
+            std::slice::from_ref(dep),
+            &paths_tree(self, &changed)?,
+        )?;
+        let uncommitted = changed.iter().any(|path| owners.contains_key(path));
+
+        let behind = self.diff_tree_to_tree(Some(&ancestor), Some(&theirs), None)?;
+        Ok(VendorState {
+            locally_modified: ours.id() != ancestor.id() || uncommitted,
+            files_behind: behind.deltas().len(),
+        })
+    }
+
+    fn vendor_extract(&self, dep: &VendorDep, opts: &VendorExtractOpts) -> Result<Oid, Error> {
+        let head_commit = self.head()?.peel_to_commit()?;
+        let vendor_commit = find_vendor_commit(self, dep)?;
+
+        let base = match self.merge_base(head_commit.id(), vendor_commit.id()) {
+            Ok(base) => self.find_commit(base)?,
+            Err(e) if e.code() == ErrorCode::NotFound => {
+                return Err(Error::from_str(&format!(
+                    "{} has not been merged yet; nothing to extract",
+                    dep.name
+                )));
+            }
+            Err(e) => return Err(e),
+        };
+
+        // Compare in the upstream layout: the vendored files at the merge base,
+        // patched like they were when merged, against those at HEAD.
+        let filter = with_dep_filters(self, dep, &dep_filter_options(&VendorMergeOpts::default()))?;
+        let base_tree = upstream_root(self, dep, &base.tree()?)?;
+        let ours = self.filter_with_options(&base_tree, &[&dep.root_pattern()], &filter)?;
+        let ours = apply_patches(self, dep, ours)?;
+        let head_tree = subtree_at(
+            self,
+            head_commit.tree()?,
+            dep.prefix.as_deref().unwrap_or(""),
+        )?;
+        let local = self.filter_with_options(&head_tree, &[&dep.root_pattern()], &filter)?;
+
+        if ours.id() == local.id() {
+            return Err(Error::from_str(&format!(
+                "No local changes to {} to extract",
+                dep.name
+            )));
+        }
+
+        // Replay the local changes onto the latest fetched upstream commit.
+        let mut diff_opts = git2::DiffOptions::new();
+        diff_opts.show_binary(true);
+        let diff = self.diff_tree_to_tree(Some(&ours), Some(&local), Some(&mut diff_opts))?;
+        let vendor_tree = vendor_commit.tree()?;
+        let upstream = upstream_root(self, dep, &vendor_tree)?;
+        let mut index = self.apply_to_tree(&upstream, &diff, None).map_err(|e| {
+            Error::from_str(&format!(
+                "Local changes to {} do not apply to {}: {}",
+                dep.name,
+                vendor_ref_name(&dep.name),
+                e.message()
+            ))
+        })?;
+        let mut tree = self.find_tree(index.write_tree_to(self)?)?;
+        if let Some(src) = &dep.src {
+            let contents = subtree_at(self, tree, &dep.source_dir)?;
+            tree = graft_tree(self, &vendor_tree, src, &contents)?;
+        }
+
+        let default_message = format!("Contribute local changes to {}", dep.name);
+        let message = opts.message.as_deref().unwrap_or(&default_message);
+        let commit = self.commit(
+            None,
+            &identity(self, "AUTHOR")?,
+            &identity(self, "COMMITTER")?,
+            message,
+            &tree,
+            &[&vendor_commit],
+        )?;
+
+        if let Some(branch) = &opts.branch {
+            self.branch(branch, &self.find_commit(commit)?, false)?;
+        }
+
+        Ok(commit)
+    }
+
+    fn vendor_archive(
+        &self,
+        name: &str,
+        opts: &VendorArchiveOpts,
+        out: &mut dyn std::io::Write,
+    ) -> Result<Oid, Error> {
+        let deps = load_deps(self)?;
+        let deps: Vec<&VendorDep> = deps.iter().filter(|dep| dep.name == name).collect();
+        let Some(dep) = deps.first() else {
+            return Err(Error::from_str(&format!(
+                "No vendored dependency named {name}"
+            )));
+        };
+
+        let upstream = match opts.upstream {
+            Some(upstream) => upstream,
+            None => {
+                let fetched = find_vendor_commit(self, dep)?.id();
+                let head = self.head()?.peel_to_commit()?.id();
+                self.merge_base(head, fetched).map_err(|_| {
+                    Error::from_str(&format!("{name} has not been merged into HEAD"))
+                })?
+            }
+        };
+        let commit = self.find_commit(upstream)?;
+        let upstream_tree = commit.tree()?;
+
+        // Patterns sharing a name select different areas of one upstream.
+        let filter = dep_filter_options(&VendorMergeOpts::default());
+        let mut files = BTreeMap::new();
+        for dep in &deps {
+            let tree = filter_dep_tree(self, dep, &upstream_tree, &filter)?;
+            tree.walk(git2::TreeWalkMode::PreOrder, |dir, entry| {
+                if entry.kind() == Some(git2::ObjectType::Blob) {
+                    let path = format!("{dir}{}", entry.name().unwrap_or_default());
+                    files.insert(path, (entry.filemode() as u32, entry.id()));
+                }
+                git2::TreeWalkResult::Ok
+            })?;
+        }
+
+        let prefix = opts.prefix.as_deref().unwrap_or("");
+        let mut entries = Vec::with_capacity(files.len());
+        for (path, (mode, id)) in files {
+            entries.push(archive::Entry {
+                path: format!("{prefix}{path}"),
+                mode,
+                data: self.find_blob(id)?.content().to_vec(),
+            });
+        }
+        archive::write(opts.format, &entries, commit.time().seconds(), out)
+            .map_err(|e| Error::from_str(&e.to_string()))?;
+        Ok(upstream)
+    }
+
+    fn vendor_update_branches(
+        &self,
+        maybe_pattern: Option<&str>,
+        opts: &VendorMergeOpts,
+        merge_opts: Option<&MergeOptions>,
+    ) -> Result<Vec<(String, Oid)>, Error> {
+        let deps = load_deps(self)?;
+        let deps = filter_dep_names(&deps, maybe_pattern);
+        if deps.is_empty() {
+            return Err(Error::from_str("No vendored dependencies to update"));
+        }
+
+        let head_commit = self.head()?.peel_to_commit()?;
+        let mut branches = Vec::new();
+        for group in group_by_name(&deps) {
+            let name = &group[0].name;
+            let vendor_commit = find_vendor_commit(self, group[0])?;
+            if head_commit.id() == vendor_commit.id()
+                || self.graph_descendant_of(head_commit.id(), vendor_commit.id())?
+            {
+                continue;
+            }
+            if opts.allowed_signers.is_some() || group[0].verify.is_some() {
+                self.vendor_verify(group[0], opts.allowed_signers.as_deref())?;
+            }
+
+            let mut tree = head_commit.tree()?;
+            let mut licenses = Vec::new();
+            for dep in &group {
+                let (mut index, _, dep_licenses) = merge_vendor_tree(
+                    self,
+                    dep,
+                    &head_commit,
+                    &tree,
+                    &vendor_commit,
+                    opts,
+                    merge_opts,
+                )?;
+                if index.has_conflicts() {
+                    return Err(Error::from_str(&format!(
+                        "Conflicts detected while merging {name}. \
+                         Merge it in a working tree to resolve them."
+                    )));
+                }
+                tree = self.find_tree(index.write_tree_to(self)?)?;
+                licenses.extend(dep_licenses);
+            }
+            licenses.sort();
+            licenses.dedup();
+
+            let message = merge_message(self, group[0], opts, &vendor_commit)?;
+            let message = with_trailers(self, &group[..1], message, &licenses);
+            let commit = commit_merge(
+                self,
+                None,
+                opts,
+                &message,
+                &tree,
+                &[&head_commit, &vendor_commit],
+            )?;
+
+            let branch = format!("vendor/update-{name}");
+            self.branch(&branch, &self.find_commit(commit)?, true)?;
+            log_vendor_merge(self, name, &branch, commit);
+            hooks::post_commit(self, opts.hooks.as_deref(), group[0], commit)?;
+            branches.push((branch, commit));
+        }
+
+        Ok(branches)
+    }
+
+    fn vendor_outdated(
+        &self,
+        maybe_pattern: Option<&str>,
+        opts: &VendorFetchOpts,
+        mut fetch_opts: Option<&mut FetchOptions<'_>>,
+    ) -> Result<Vec<VendorOutdated>, Error> {
+        let deps = load_deps(self)?;
+        let deps = filter_deps(&deps, maybe_pattern);
+
+        let head = match self.head() {
+            Ok(head) => Some(head.peel_to_commit()?.id()),
+            Err(e) if e.code() == ErrorCode::UnbornBranch || e.code() == ErrorCode::NotFound => {
+                None
+            }
+            Err(e) => return Err(e),
+        };
+
+        let mut report: Vec<VendorOutdated> = Vec::new();
+        let mut contacted = HashMap::new();
+        for dep in deps {
+            if report.iter().any(|entry| entry.name == dep.name) {
+                continue;
+            }
+
+            let upstream =
+                upstream_tip(self, dep, opts, &mut contacted, fetch_opts.as_deref_mut())?;
+            let fetched = self.refname_to_id(&vendor_ref_name(&dep.name)).ok();
+
+            // The upstream tip is merged if HEAD already contains it; otherwise
+            // the last merged upstream commit is found through the fetched ref.
+            let contains = |oid: Oid| match head {
+                Some(head) => head == oid || self.graph_descendant_of(head, oid).unwrap_or(false),
+                None => false,
+            };
+            let merged = if self.find_commit(upstream).is_ok() && contains(upstream) {
+                Some(upstream)
+            } else {
+                match (head, fetched) {
+                    (Some(head), Some(fetched)) => self.merge_base(head, fetched).ok(),
+                    _ => None,
+                }
+            };
+
+            let fork = match &dep.upstream {
+                Some(url) => {
+                    // The dependency's mirrors and headers are meant for the fork.
+                    let forked = VendorDep {
+                        url: url.clone(),
+                        branch: None,
+                        version: None,
+                        mirrors: Vec::new(),
+                        headers: Vec::new(),
+                        ..dep.clone()
+                    };
+                    let tip = upstream_tip(
+                        self,
+                        &forked,
+                        opts,
+                        &mut contacted,
+                        fetch_opts.as_deref_mut(),
+                    )?;
+                    Some(fork_drift(self, upstream, tip)?)
+                }
+                None => None,
+            };
+
+            report.push(VendorOutdated {
+                name: dep.name.clone(),
+                upstream,
+                fetched,
+                merged,
+                fork,
+            });
+        }
+
+        Ok(report)
+    }
+
+    fn vendor_attest(&self, maybe_pattern: Option<&str>) -> Result<Oid, Error> {
+        let deps = load_deps(self)?;
+        let deps = filter_deps(&deps, maybe_pattern);
+        if deps.is_empty() {
+            return Err(Error::from_str("No vendored dependencies to attest"));
+        }
+
+        let head_commit = self.head()?.peel_to_commit()?;
+        let head_tree = head_commit.tree()?;
+        let filter = dep_filter_options(&VendorMergeOpts::default());
+
+        let mut lines = Vec::new();
+        for dep in deps {
+            let fetched = self
+                .refname_to_id(&vendor_ref_name(&dep.name))
+                .map_err(|_| {
+                    Error::from_str(&format!(
+                        "Vendor ref {} not found. Run fetch first.",
+                        vendor_ref_name(&dep.name)
+                    ))
+                })?;
+            let upstream = self.merge_base(head_commit.id(), fetched).map_err(|_| {
+                Error::from_str(&format!("{} has not been merged into HEAD", dep.name))
+            })?;
+
+            let upstream_tree = self.find_commit(upstream)?.tree()?;
+            let tree = filter_dep_tree(self, dep, &upstream_tree, &filter)?.id();
+            if local_dep_tree(self, dep, &head_tree, &filter)?.id() != tree {
+                return Err(Error::from_str(&format!(
+                    "Vendored content of {} ({}) differs from upstream {upstream}",
+                    dep.name, dep.pattern
+                )));
+            }
+
+            info!("Attesting {} ({}) at {upstream}", dep.name, dep.pattern);
+            lines.push(format!("{upstream} {tree} {} {}", dep.name, dep.pattern));
+        }
+
+        // The note is added on a scratch ref, so that the notes ref only ever
+        // points to signed commits.
+        let old = self.refname_to_id(VENDOR_NOTES_REF).ok();
+        let scratch = format!("{VENDOR_NOTES_REF}-unsigned");
+        match old {
+            Some(old) => {
+                self.reference(&scratch, old, true, "vendor: attest")?;
+            }
+            None => {
+                if let Ok(mut reference) = self.find_reference(&scratch) {
+                    reference.delete()?;
+                }
+            }
+        }
+        let signed = sign_attestation(self, &scratch, head_commit.id(), &lines);
+        if let Ok(mut reference) = self.find_reference(&scratch) {
+            reference.delete()?;
+        }
+        let signed = signed?;
+
+        if let Some(old) = old {
+            self.reference_matching(VENDOR_NOTES_REF, signed, true, old, "vendor: attest")?;
+        } else {
+            self.reference(VENDOR_NOTES_REF, signed, false, "vendor: attest")?;
+        }
+        Ok(signed)
+    }
+
+    fn vendor_verify_attestation(
+        &self,
+        commit: Oid,
+        allowed_signers: Option<&Path>,
+    ) -> Result<Vec<VendorAttestation>, Error> {
+        let allowed_signers = match allowed_signers {
+            Some(path) => path.to_path_buf(),
+            None => match self.config()?.get_path("gpg.ssh.allowedSignersFile") {
+                Ok(path) => path,
+                Err(e) if e.code() == ErrorCode::NotFound => {
+                    return Err(Error::from_str(
+                        "No allowed signers given; set gpg.ssh.allowedSignersFile",
+                    ));
+                }
+                Err(e) => return Err(e),
+            },
+        };
+
+        let notes_commit = self
+            .find_reference(VENDOR_NOTES_REF)
+            .map_err(|_| Error::from_str("No vendor attestations recorded"))?
+            .peel_to_commit()?;
+        let signer = verify::verify_object(self, notes_commit.id(), &allowed_signers)?;
+        info!("Verified signature from {signer}");
+
+        let note = self
+            .find_note(Some(VENDOR_NOTES_REF), commit)
+            .map_err(|_| Error::from_str(&format!("No vendor attestation for {commit}")))?;
+        let deps = load_deps(self)?;
+        let tree = self.find_commit(commit)?.tree()?;
+        let filter = dep_filter_options(&VendorMergeOpts::default());
+
+        let mut attestations = Vec::new();
+        for line in note.message().unwrap_or("").lines() {
+            let attestation = parse_attestation(line)?;
+            let dep = deps
+                .iter()
+                .find(|d| d.name == attestation.name && d.pattern == attestation.pattern)
+                .ok_or_else(|| {
+                    Error::from_str(&format!(
+                        "{} ({}) is no longer a vendored dependency",
+                        attestation.name, attestation.pattern
+                    ))
+                })?;
+
+            let upstream_tree = self.find_commit(attestation.upstream)?.tree()?;
+            let filtered = filter_dep_tree(self, dep, &upstream_tree, &filter)?.id();
+            if filtered != attestation.tree {
+                return Err(Error::from_str(&format!(
+                    "Filtered tree of {} at {} is {filtered}, but {} was attested",
+                    dep.name, attestation.upstream, attestation.tree
+                )));
+            }
+            if local_dep_tree(self, dep, &tree, &filter)?.id() != attestation.tree {
+                return Err(Error::from_str(&format!(
+                    "Vendored content of {} ({}) in {commit} does not match the attestation",
+                    dep.name, dep.pattern
+                )));
+            }
+            attestations.push(attestation);
+        }
+
+        Ok(attestations)
+    }
+
+    fn vendor_doctor(&self, fix: bool) -> Result<Vec<VendorFinding>, Error> {
+        require_non_bare(self)?;
+        let mut findings = Vec::new();
+
+        if find_manifest(self)?.is_none() {
+            for (_, path) in find_all_gitattributes(self)? {
+                findings.extend(check_vendored_flags(&path, fix)?);
+            }
+        }
+        let deps = load_deps(self)?;
+
+        let mut names: Vec<&VendorDep> = Vec::new();
+        for dep in &deps {
+            match names.iter().find(|other| other.name == dep.name) {
+                Some(other) if other.url != dep.url || other.branch != dep.branch => {
+                    findings.push(VendorFinding {
+                        kind: VendorFindingKind::ConflictingName,
+                        message: format!(
+                            "{} is declared by {} and {} with different URLs or branches",
+                            dep.name, other.pattern, dep.pattern
+                        ),
+                        fixed: false,
+                    });
+                }
+                Some(_) => {}
+                None => names.push(dep),
+            }
+        }
+
+        let mut refs = Vec::new();
+        for glob in ["refs/vendor/*", "refs/vendor-upstream/*"] {
+            for reference in self.references_glob(glob)? {
+                let reference = reference?;
+                if let Some(name) = reference.name() {
+                    refs.push((name.to_string(), reference.peel_to_commit().is_ok()));
+                }
+            }
+        }
+        for (ref_name, has_commit) in refs {
+            let declared = deps.iter().any(|dep| {
+                vendor_ref_name(&dep.name) == ref_name
+                    || (dep.upstream.is_some() && fork_upstream_ref_name(&dep.name) == ref_name)
+            });
+            let (kind, message) = if !declared {
+                (
+                    VendorFindingKind::OrphanedRef,
+                    format!("{ref_name} belongs to no declared dependency"),
+                )
+            } else if !has_commit {
+                (
+                    VendorFindingKind::MissingCommit,
+                    format!("{ref_name} does not point to a commit in the repository"),
+                )
+            } else {
+                continue;
+            };
+            if fix {
+                self.find_reference(&ref_name)?.delete()?;
+            }
+            findings.push(VendorFinding {
+                kind,
+                message,
+                fixed: fix,
+            });
+        }
+
+        if let Ok(head) = self.head() {
+            let head_tree = head.peel_to_tree()?;
+            let filter = dep_filter_options(&VendorMergeOpts::default());
+            for dep in &deps {
+                if local_dep_tree(self, dep, &head_tree, &filter)?.is_empty() {
+                    findings.push(VendorFinding {
+                        kind: VendorFindingKind::UnmatchedPattern,
+                        message: format!("{} ({}) matches no files at HEAD", dep.name, dep.pattern),
+                        fixed: false,
+                    });
+                }
+            }
+        }
+
+        Ok(findings)
+    }
+
+    fn vendor_migrate(&self, format: DepsFormat) -> Result<(), Error> {
+        require_non_bare(self)?;
+
+        match format {
+            DepsFormat::Manifest => {
+                if let Some(path) = find_manifest(self)? {
+                    return Err(Error::from_str(&format!(
+                        "{} already exists",
+                        path.display()
+                    )));
+                }
+                let deps = load_deps(self)?;
+                if deps.is_empty() {
+                    return Err(Error::from_str("No vendored dependencies to migrate"));
+                }
+
+                // The manifest lives at the root, so nested patterns are rebased.
+                let workdir = self
+                    .workdir()
+                    .ok_or_else(|| Error::from_str("Repository has no working directory"))?;
+                let rebased: Vec<VendorDep> = deps
+                    .iter()
+                    .map(|dep| VendorDep {
+                        pattern: dep.root_pattern(),
+                        keep_dirs: root_keep_dirs(dep),
+                        source_dir: String::new(),
+                        ..dep.clone()
+                    })
+                    .collect();
+                write_manifest(&workdir.join(manifest::MANIFEST_FILE), &rebased)?;
+                for dep in &deps {
+                    let attributes = workdir.join(&dep.source_dir).join(".gitattributes");
+                    remove_vendor_lines(self, &attributes, &dep.pattern)?;
+                }
+            }
+            DepsFormat::Gitattributes => {
+                let path = find_manifest(self)?
+                    .ok_or_else(|| Error::from_str("No .gitvendor.toml manifest to migrate"))?;
+                let content = fs::read_to_string(&path).map_err(|e| {
+                    Error::from_str(&format!("Failed to read {}: {e}", path.display()))
+                })?;
+                let deps = manifest::parse(&content)?;
+                if let Some(dep) = deps.iter().find(|dep| {
+                    !dep.excludes.is_empty() || dep.prefix.is_some() || !dep.patches.is_empty()
+                }) {
+                    return Err(Error::from_str(&format!(
+                        "{} uses excludes, a prefix, or patches, which .gitattributes cannot express",
+                        dep.name
+                    )));
+                }
+                if let Some(dep) = deps.iter().find(|dep| {
+                    dep.headers
+                        .iter()
+                        .any(|header| attr_header(header).contains(char::is_whitespace))
+                }) {
+                    return Err(Error::from_str(&format!(
+                        "{} sends a header containing whitespace, which .gitattributes cannot express",
+                        dep.name
+                    )));
+                }
+
+                // Manifest patterns are relative to the root of the working tree.
+                let workdir = self
+                    .workdir()
+                    .ok_or_else(|| Error::from_str("Repository has no working directory"))?;
+                let gitattributes = workdir.join(".gitattributes");
+                let mut dep_attrs = Vec::new();
+                for dep in &deps {
+                    let mut attrs = vec![
+                        "vendored".to_string(),
+                        format!("vendor-name={}", dep.name),
+                        format!("vendor-url={}", dep.url),
+                    ];
+                    if let Some(branch) = &dep.branch {
+                        attrs.push(format!("vendor-branch={branch}"));
+                    }
+                    for mirror in &dep.mirrors {
+                        attrs.push(format!("vendor-mirror={mirror}"));
+                    }
+                    if let Some(upstream) = &dep.upstream {
+                        attrs.push(format!("vendor-upstream={upstream}"));
+                    }
+                    for header in &dep.headers {
+                        attrs.push(format!("vendor-header={}", attr_header(header)));
+                    }
+                    if let Some(verify) = &dep.verify {
+                        attrs.push(format!("vendor-verify={verify}"));
+                    }
+                    if let Some(version) = &dep.version {
+                        attrs.push(format!("vendor-version={}", attr_version(version)));
+                    }
+                    if let Some(src) = &dep.src {
+                        attrs.push(format!("vendor-src={src}"));
+                    }
+                    for dir in &dep.keep_dirs {
+                        attrs.push(format!("vendor-keep-dir={dir}"));
+                    }
+                    for group in &dep.groups {
+                        attrs.push(format!("vendor-group={group}"));
+                    }
+                    if dep.no_default_excludes {
+                        attrs.push("-vendor-default-excludes".to_string());
+                    }
+                    dep_attrs.push(attrs);
+                }
+                let dep_attrs: Vec<Vec<&str>> = dep_attrs
+                    .iter()
+                    .map(|attrs| attrs.iter().map(String::as_str).collect())
+                    .collect();
+                let edits: Vec<(&str, &[&str])> = deps
+                    .iter()
+                    .zip(&dep_attrs)
+                    .map(|(dep, attrs)| (dep.pattern.as_str(), attrs.as_slice()))
+                    .collect();
+                self.set_attrs_batch(&edits, Some(&gitattributes))?;
+                for dep in &deps {
+                    if let Some(note) = &dep.note {
+                        set_note(&gitattributes, &dep.pattern, Some(note))?;
+                    }
+                }
+                fs::remove_file(&path).map_err(|e| {
+                    Error::from_str(&format!("Failed to remove {}: {e}", path.display()))
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Helpers
+// ---------------------------------------------------------------------------
+
+// ---------------------------------------------------------------------------
+// Merge helpers
+// ---------------------------------------------------------------------------
+
+/// Look up the commit that `refs/vendor/<name>` points to for `dep`.
+fn find_vendor_commit<'r>(
+    repo: &'r Repository,
+    dep: &VendorDep,
+) -> Result<git2::Commit<'r>, Error> {
+    let ref_name = vendor_ref_name(&dep.name);
+
+    let reference = repo.find_reference(&ref_name).map_err(|_| {
+        Error::from_str(&format!(
+            "Vendor ref {ref_name} not found. Run fetch first."
+        ))
+    })?;
+
+    let vendor_oid = reference
+        .target()
+        .ok_or_else(|| Error::from_str("Invalid vendor reference"))?;
+    repo.find_commit(vendor_oid)
+}
+
+/// Resolve the range of upstream commits `opts` selects for `dep`: the
+/// commit whose ancestors are excluded, if any, and the commit to walk from.
+fn log_range(
+    repo: &Repository,
+    dep: &VendorDep,
+    opts: &VendorLogOpts,
+) -> Result<(Option<Oid>, Oid), Error> {
+    if opts.last_merge {
+        return last_merge_range(repo, dep);
+    }
+
+    let until = match opts.until {
+        Some(oid) => oid,
+        None => find_vendor_commit(repo, dep)?.id(),
+    };
+    let since = match opts.since {
+        Some(oid) => Some(oid),
+        None => match repo.head() {
+            Ok(head) => Some(head.peel_to_commit()?.id()),
+            Err(e) if e.code() == ErrorCode::UnbornBranch || e.code() == ErrorCode::NotFound => {
+                None
+            }
+            Err(e) => return Err(e),
+        },
+    };
+    Ok((since, until))
+}
+
+/// The range of upstream commits brought in by the most recent merge of
+/// `dep` into `HEAD`: from the upstream commit merged before it, if any, to
+/// the one it merged.
+///
+/// The merge is found by walking the first parents of `HEAD` back to the
+/// first commit that does not contain the last merged upstream commit.
+fn last_merge_range(repo: &Repository, dep: &VendorDep) -> Result<(Option<Oid>, Oid), Error> {
+    let vendor = find_vendor_commit(repo, dep)?.id();
+    let mut commit = repo.head()?.peel_to_commit()?;
+    let merged = match repo.merge_base(commit.id(), vendor) {
+        Ok(merged) => merged,
+        Err(e) if e.code() == ErrorCode::NotFound => {
+            return Err(Error::from_str(&format!(
+                "{} has not been merged into HEAD",
+                dep.name
+            )));
+        }
+        Err(e) => return Err(e),
+    };
+
+    while let Ok(parent) = commit.parent(0) {
+        match repo.merge_base(parent.id(), merged) {
+            Ok(base) if base == merged => commit = parent,
+            Ok(base) => return Ok((Some(base), merged)),
+            Err(e) if e.code() == ErrorCode::NotFound => break,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok((None, merged))
+}
+
+/// List the upstream refs of `dep`, like `git ls-remote`, and return the
+/// commit its matching tag, or else its branch (or `HEAD`), points to. Its
+/// sources are contacted in order, as `opts` asks, until one answers.
+fn upstream_tip(
+    repo: &Repository,
+    dep: &VendorDep,
+    opts: &VendorFetchOpts,
+    contacted: &mut HashMap<String, Instant>,
+    mut fetch_opts: Option<&mut FetchOptions<'_>>,
+) -> Result<Oid, Error> {
+    let wanted = match &dep.branch {
+        Some(branch) => format!("refs/heads/{branch}"),
+        None => "HEAD".to_string(),
+    };
+    let mut last_error = None;
+    for source in dep_sources(dep) {
+        let tip = with_retries(opts, contacted, source, || {
+            let mut remote = repo.remote_anonymous(&remote_url(repo, source))?;
+            let headers = fetch_headers(repo, source, Some(dep), &opts.headers)?;
+            let heads = list_remote(&mut remote, &headers, fetch_opts.as_deref_mut())?;
+            match &dep.version {
+                Some(version) => matching_tag(dep, version, heads).map(|(_, oid)| oid),
+                None => heads
+                    .iter()
+                    .find(|head| head.name() == wanted)
+                    .map(|head| head.oid())
+                    .ok_or_else(|| Error::from_str(&format!("{wanted} not found on {source}"))),
+            }
+        });
+        match tip {
+            Ok(tip) => return Ok(tip),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    match last_error {
+        Some(e) if dep.mirrors.is_empty() => Err(e),
+        Some(e) => Err(all_sources_failed(dep, &e)),
+        None => unreachable!("a dependency always has a URL"),
+    }
+}
+
+/// Connect to `remote` as a fetch with `fetch_opts` and `headers` would, and
+/// list its refs like `git ls-remote`, without downloading anything.
+fn list_remote<'r>(
+    remote: &'r mut git2::Remote<'_>,
+    headers: &[String],
+    fetch_opts: Option<&mut FetchOptions<'_>>,
+) -> Result<&'r [git2::RemoteHead<'r>], Error> {
+    let mut default_opts = FetchOptions::new();
+    let fetch_opts = fetch_opts.unwrap_or(&mut default_opts);
+    let headers: Vec<&str> = headers.iter().map(String::as_str).collect();
+    fetch_opts.custom_headers(&headers);
+    // Downloading a ref no remote has connects without transferring objects;
+    // the refs stay listed after disconnecting.
+    remote.download(&["refs/git-vendor/ls-remote"], Some(fetch_opts))?;
+    remote.disconnect()?;
+    remote.list()
+}
+
+/// Return the highest tag among the upstream `heads` of `dep` satisfying the
+/// requirement `version`, with the commit it points to.
+fn matching_tag(
+    dep: &VendorDep,
+    version: &str,
+    heads: &[git2::RemoteHead<'_>],
+) -> Result<(String, Oid), Error> {
+    let req = VersionReq::parse(version)
+        .map_err(|msg| Error::from_str(&format!("{}: {msg}", dep.name)))?;
+
+    let mut best: Option<(Version, &str, Oid)> = None;
+    for head in heads {
+        let Some(tag) = head.name().strip_prefix("refs/tags/") else {
+            continue;
+        };
+        // Annotated tags are listed twice; the `^{}` entry is their commit.
+        let (tag, peeled) = match tag.strip_suffix("^{}") {
+            Some(tag) => (tag, true),
+            None => (tag, false),
+        };
+        let Some(candidate) = Version::parse(tag).filter(|v| req.matches(v)) else {
+            continue;
+        };
+        match &mut best {
+            Some((_, best_tag, oid)) if *best_tag == tag => {
+                if peeled {
+                    *oid = head.oid();
+                }
+            }
+            Some((best_version, ..)) if *best_version >= candidate => {}
+            _ => best = Some((candidate, tag, head.oid())),
+        }
+    }
+
+    best.map(|(_, tag, oid)| (tag.to_string(), oid))
+        .ok_or_else(|| Error::from_str(&format!("No tag on {} matches version {version}", dep.url)))
+}
+
+/// The start of the reflog message of a vendor ref updated to an upstream tag,
+/// followed by the tag name.
+const TAG_REFLOG_PREFIX: &str = "vendor fetch tag ";
+
+/// Return the upstream tag `refs/vendor/<name>` was last fetched from, if it
+/// was fetched for [`VendorDep::version`].
+fn fetched_tag(repo: &Repository, dep: &VendorDep) -> Option<String> {
+    let reflog = repo.reflog(&vendor_ref_name(&dep.name)).ok()?;
+    let message = reflog
+        .iter()
+        .filter_map(|entry| entry.message().map(str::to_string))
+        .find(|message| message.starts_with("vendor fetch "))?;
+    let tag = message.strip_prefix(TAG_REFLOG_PREFIX)?;
+    Some(tag.split_once(' ').map_or(tag, |(tag, _)| tag).to_string())
+}
+
+/// Add the attestation `lines` as the note of `commit` on `notes_ref`, then
+/// re-create the resulting notes commit with a signature, returning it.
+fn sign_attestation(
+    repo: &Repository,
+    notes_ref: &str,
+    commit: Oid,
+    lines: &[String],
+) -> Result<Oid, Error> {
+    repo.note(
+        &identity(repo, "AUTHOR")?,
+        &identity(repo, "COMMITTER")?,
+        Some(notes_ref),
+        commit,
+        &format!("{}\n", lines.join("\n")),
+        true,
+    )?;
+
+    let notes_commit = repo.find_reference(notes_ref)?.peel_to_commit()?;
+    let parents: Vec<git2::Commit<'_>> = notes_commit.parents().collect();
+    let parents: Vec<&git2::Commit<'_>> = parents.iter().collect();
+    let buffer = repo.commit_create_buffer(
+        &notes_commit.author(),
+        &notes_commit.committer(),
+        notes_commit.message().unwrap_or(""),
+        &notes_commit.tree()?,
+        &parents,
+    )?;
+    let buffer = buffer
+        .as_str()
+        .ok_or_else(|| Error::from_str("Notes commit is not valid UTF-8"))?;
+    repo.commit_signed(buffer, &verify::sign(repo, buffer)?, None)
+}
+
+/// Abbreviate `oid` to seven hexadecimal digits, as in progress output,
+/// reflog messages and commit messages.
+pub fn short_id(oid: Oid) -> String {
+    oid.to_string()[..7].to_string()
+}
+
+/// Point `refs/vendor/<name>` at `new` in a reference transaction, logging
+/// `action` followed by the `<old>..<new>` range of the update.
+fn update_vendor_ref(repo: &Repository, name: &str, new: Oid, action: &str) -> Result<(), Error> {
+    let ref_name = vendor_ref_name(name);
+    let mut transaction = repo.transaction()?;
+    transaction.lock_ref(&ref_name)?;
+    let old = repo
+        .refname_to_id(&ref_name)
+        .unwrap_or_else(|_| Oid::zero());
+    let message = format!("{action}: {}..{}", short_id(old), short_id(new));
+
+    // Git only logs updates to refs outside refs/heads/ if a log exists.
+    repo.reference_ensure_log(&ref_name)?;
+    transaction.set_target(&ref_name, new, None, &message)?;
+    transaction.commit()
+}
+
+/// Record in the reflog of `refs/vendor/<name>` that `commit` on `branch`
+/// merged its current target, as the committer of `commit`.
+///
+/// The merge is already committed, so failing to log it is only warned about.
+fn log_vendor_merge(repo: &Repository, name: &str, branch: &str, commit: Oid) {
+    fn append(repo: &Repository, ref_name: &str, branch: &str, commit: Oid) -> Result<(), Error> {
+        let target = repo.refname_to_id(ref_name)?;
+        let committer = repo.find_commit(commit)?.committer().to_owned();
+        let mut reflog = repo.reflog(ref_name)?;
+        let message = format!("vendor merge into {branch}: {}", short_id(commit));
+        reflog.append(target, &committer, Some(&message))?;
+        reflog.write()
+    }
+
+    let ref_name = vendor_ref_name(name);
+    if let Err(e) = append(repo, &ref_name, branch, commit) {
+        warn!(
+            "  Warning: failed to log the merge in {ref_name}: {}",
+            e.message()
+        );
+    }
+}
+
+/// `version` without the optional whitespace around its comparators, such as
+/// in `>=1.2, <1.5`, so that it can be written as a `vendor-version=`
+/// attribute.
+fn attr_version(version: &str) -> String {
+    version.split_whitespace().collect()
+}
+
+/// `header` without the optional whitespace after its colon, so that it can
+/// be written as a `vendor-header=` attribute.
+fn attr_header(header: &str) -> String {
+    match header.split_once(':') {
+        Some((name, value)) => format!("{name}:{}", value.trim_start()),
+        None => header.to_string(),
+    }
+}
+
+/// The HTTP headers to send when fetching from `url`: those of
+/// `http.extraHeader` and of `http.<prefix>.extraHeader` for each URL prefix
+/// of `url`, where an empty value clears the ones before it as in Git, then
+/// those of `dep` unless `url` is on another host than the dependency's URL,
+/// then `extra`.
+fn fetch_headers(
+    repo: &Repository,
+    url: &str,
+    dep: Option<&VendorDep>,
+    extra: &[String],
+) -> Result<Vec<String>, Error> {
+    let mut headers = Vec::new();
+    let config = repo.config()?;
+    let mut entries = config.entries(Some(r"^http\..*extraheader$"))?;
+    while let Some(entry) = entries.next() {
+        let entry = entry?;
+        let Some(name) = entry.name() else {
+            continue;
+        };
+        let Some(scope) = name
+            .strip_prefix("http.")
+            .and_then(|name| name.strip_suffix("extraheader"))
+        else {
+            continue;
+        };
+        let applies = match scope.strip_suffix('.') {
+            Some(prefix) => url_has_prefix(url, prefix),
+            None => scope.is_empty(),
+        };
+        match entry.value() {
+            _ if !applies => {}
+            Some("") => headers.clear(),
+            Some(value) => headers.push(value.to_string()),
+            None => {}
+        }
+    }
+    if let Some(dep) = dep
+        && url_host(url) == url_host(&dep.url)
+    {
+        headers.extend(dep.headers.iter().cloned());
+    }
+    headers.extend(extra.iter().cloned());
+    Ok(headers)
+}
+
+/// Return `true` if `url` is `prefix` or below it, such as
+/// `https://example.com/org/repo.git` below `https://example.com/org`.
+fn url_has_prefix(url: &str, prefix: &str) -> bool {
+    let prefix = prefix.trim_end_matches('/');
+    match url.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+/// Fetch the remote ref `source` into `FETCH_HEAD` only, without updating
+/// any ref, and return the object it points to, or `None` if the remote does
+/// not have it. `headers` replace the custom headers of `fetch_opts`, which
+/// may be shared with fetches from other URLs.
+fn fetch_ref(
+    repo: &Repository,
+    remote: &mut git2::Remote<'_>,
+    source: &str,
+    headers: &[String],
+    fetch_opts: Option<&mut FetchOptions<'_>>,
+) -> Result<Option<Oid>, Error> {
+    let path = repo.path().join("FETCH_HEAD");
+    match fs::remove_file(&path) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(Error::from_str(&e.to_string())),
+    }
+    let mut default_opts = FetchOptions::new();
+    let fetch_opts = fetch_opts.unwrap_or(&mut default_opts);
+    let headers: Vec<&str> = headers.iter().map(String::as_str).collect();
+    fetch_opts.custom_headers(&headers);
+    remote.fetch(&[source], Some(fetch_opts), None)?;
+
+    // Read FETCH_HEAD directly, since `fetchhead_foreach` cannot report a
+    // fetched `HEAD`. Lines of refs not for merging, like followed tags, say
+    // so in their second field.
+    let Ok(content) = fs::read_to_string(&path) else {
+        return Ok(None);
+    };
+    Ok(content.lines().find_map(|line| {
+        let (oid, rest) = line.split_once('\t')?;
+        rest.starts_with('\t')
+            .then(|| Oid::from_str(oid).ok())
+            .flatten()
+    }))
+}
+
+/// Fetch the upstream branch of `dep` from `url` into its vendor ref, sending
+/// the [`fetch_headers`] of `url` followed by `extra`.
+fn fetch_from_url(
+    repo: &Repository,
+    dep: &VendorDep,
+    url: &str,
+    extra: &[String],
+    mut fetch_opts: Option<&mut FetchOptions<'_>>,
+) -> Result<(Oid, VendorFetchStats), Error> {
+    let old = repo.refname_to_id(&vendor_ref_name(&dep.name)).ok();
+
+    let mut remote = repo.remote_anonymous(&remote_url(repo, url))?;
+    let headers = fetch_headers(repo, url, Some(dep), extra)?;
+    let tag = match &dep.version {
+        Some(version) => {
+            let heads = list_remote(&mut remote, &headers, fetch_opts.as_deref_mut())?;
+            let (tag, _) = matching_tag(dep, version, heads)?;
+            Some(tag)
+        }
+        None => None,
+    };
+    let source = match (&tag, &dep.branch) {
+        (Some(tag), _) => format!("refs/tags/{tag}"),
+        (None, Some(branch)) => format!("refs/heads/{branch}"),
+        (None, None) => "HEAD".to_string(),
+    };
+
+    // The vendor ref is updated and logged in one step below.
+    let fetched = fetch_ref(repo, &mut remote, &source, &headers, fetch_opts)?
+        .ok_or_else(|| Error::from_str(&format!("{source} not found on {url}")))?;
+
+    // Vendor refs point to commits, not annotated tags, and log the tag they
+    // were fetched from for `fetched_tag`.
+    let new = repo.find_object(fetched, None)?.peel_to_commit()?.id();
+    let action = match &tag {
+        Some(tag) => format!("{TAG_REFLOG_PREFIX}{tag} from {url}"),
+        None => format!("vendor fetch from {url}"),
+    };
+    update_vendor_ref(repo, &dep.name, new, &action)?;
+
+    let mut stats = VendorFetchStats::new(dep, &remote, old, new);
+    stats.tag = tag;
+    Ok((new, stats))
+}
+
+/// Fetch the default branch of the repository `dep`'s fork was forked from,
+/// `url`, into `refs/vendor-upstream/<name>`. The dependency's headers are
+/// meant for the fork and are not sent, only the configured ones for `url`
+/// and `extra`.
+fn fetch_fork_upstream(
+    repo: &Repository,
+    dep: &VendorDep,
+    url: &str,
+    extra: &[String],
+    fetch_opts: Option<&mut FetchOptions<'_>>,
+) -> Result<Oid, Error> {
+    let mut remote = repo.remote_anonymous(&remote_url(repo, url))?;
+    let headers = fetch_headers(repo, url, None, extra)?;
+    let fetched = fetch_ref(repo, &mut remote, "HEAD", &headers, fetch_opts)?
+        .ok_or_else(|| Error::from_str(&format!("HEAD not found on {url}")))?;
+    let new = repo.find_object(fetched, None)?.peel_to_commit()?.id();
+    repo.reference(
+        &fork_upstream_ref_name(&dep.name),
+        new,
+        true,
+        &format!("vendor fetch from {url}"),
+    )?;
+    Ok(new)
+}
+
+/// Compare the fork commit `fork` with the commit `upstream` of the
+/// repository it was forked from.
+fn fork_drift(repo: &Repository, fork: Oid, upstream: Oid) -> Result<VendorForkDrift, Error> {
+    let counts = if repo.find_commit(fork).is_ok() && repo.find_commit(upstream).is_ok() {
+        Some(repo.graph_ahead_behind(fork, upstream)?)
+    } else {
+        None
+    };
+    Ok(VendorForkDrift {
+        upstream,
+        ahead: counts.map(|(ahead, _)| ahead),
+        behind: counts.map(|(_, behind)| behind),
+    })
+}
+
+/// The URLs `dep` can be fetched from, in order: its URL, then its mirrors.
+fn dep_sources(dep: &VendorDep) -> impl Iterator<Item = &str> {
+    std::iter::once(dep.url.as_str()).chain(dep.mirrors.iter().map(String::as_str))
+}
+
+/// Run `fetch`, which contacts `url`, retrying transient errors with
+/// exponential backoff and pacing connections to each host as `opts` asks.
+/// Fetches from local paths and configured remote names are run once.
+/// `contacted` records when each host was last contacted.
+fn with_retries<T>(
+    opts: &VendorFetchOpts,
+    contacted: &mut HashMap<String, Instant>,
+    url: &str,
+    mut fetch: impl FnMut() -> Result<T, Error>,
+) -> Result<T, Error> {
+    let host = url_host(url);
+    let attempts = opts.attempts.max(1);
+    let mut delay = opts.retry_delay.unwrap_or(Duration::from_secs(1));
+    let mut attempt = 1;
+    loop {
+        if let (Some(host), Some(interval)) = (host, opts.host_interval) {
+            if let Some(last) = contacted.get(host) {
+                let wait = interval.saturating_sub(last.elapsed());
+                if !wait.is_zero() {
+                    debug!("  Waiting {wait:.2?} before contacting {host} again");
+                    std::thread::sleep(wait);
+                }
+            }
+            contacted.insert(host.to_string(), Instant::now());
+        }
+
+        match fetch() {
+            Err(e) if attempt < attempts && host.is_some() && is_transient(&e) => {
+                warn!(
+                    "  Fetching {url} failed: {}; retrying in {delay:.2?} (attempt {} of {attempts})",
+                    e.message(),
+                    attempt + 1
+                );
+                std::thread::sleep(delay);
+                delay = delay.saturating_mul(2);
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Return `true` if `e`, raised while fetching from a network URL, may not
+/// recur: a timeout, a reset connection, or an HTTP 5xx or 429 response.
+/// Anything else, such as a 404, an unknown host key or a refused connection,
+/// would only fail again.
+fn is_transient(e: &Error) -> bool {
+    use git2::ErrorClass;
+    if e.code() == ErrorCode::Timeout {
+        return true;
+    }
+    if e.code() == ErrorCode::Auth
+        || !matches!(
+            e.class(),
+            ErrorClass::Net | ErrorClass::Http | ErrorClass::Ssl | ErrorClass::Ssh | ErrorClass::Os
+        )
+    {
+        return false;
+    }
+    let message = e.message().to_ascii_lowercase();
+    if let Some((_, status)) = message.split_once("status code: ") {
+        let status: String = status.chars().take_while(char::is_ascii_digit).collect();
+        return matches!(status.parse::<u16>(), Ok(429 | 500..=599));
+    }
+    ["timed out", "connection reset", "broken pipe", "early eof"]
+        .iter()
+        .any(|transient| message.contains(transient))
+}
+
+/// Return the host of a remote URL, or `None` for local paths and remote
+/// names.
+fn url_host(url: &str) -> Option<&str> {
+    if !is_remote_url(url) {
+        return None;
+    }
+    let rest = match url.split_once("://") {
+        Some(("file", _)) => return None,
+        Some((_, rest)) => rest.split('/').next()?,
+        None => url.split(':').next()?,
+    };
+    let host = rest.rsplit_once('@').map_or(rest, |(_, host)| host);
+    // Drop a port, keeping bracketed IPv6 addresses whole.
+    let host = match host.rsplit_once(':') {
+        Some((name, port)) if port.chars().all(|c| c.is_ascii_digit()) => name,
+        _ => host,
+    };
+    (!host.is_empty()).then_some(host)
+}
 
-            println!("  Fetched to {ref_target}");
-        }
+fn all_sources_failed(dep: &VendorDep, last: &Error) -> Error {
+    Error::from_str(&format!(
+        "Failed to fetch {} from {} and its {} mirror(s): {}",
+        dep.name,
+        dep.url,
+        dep.mirrors.len(),
+        last.message()
+    ))
+}
 
-        Ok(())
+/// Fetch `refs/vendor/<name>` for `dep` from `mirror`, a remote name or URL,
+/// sending the [`fetch_headers`] of its URL followed by `extra`.
+fn fetch_from_mirror(
+    repo: &Repository,
+    dep: &VendorDep,
+    mirror: &str,
+    extra: &[String],
+    fetch_opts: Option<&mut FetchOptions<'_>>,
+) -> Result<(Oid, VendorFetchStats), Error> {
+    let ref_name = vendor_ref_name(&dep.name);
+    let old = repo.refname_to_id(&ref_name).ok();
+    let mut remote = match repo.find_remote(mirror) {
+        Ok(remote) => remote,
+        Err(_) => repo.remote_anonymous(&remote_url(repo, mirror))?,
+    };
+
+    // A ref missing on the mirror is not an error for the fetch itself, and
+    // must not be mistaken for the local one.
+    let url = remote.url().unwrap_or(mirror).to_string();
+    let headers = fetch_headers(repo, &url, Some(dep), extra)?;
+    let oid = fetch_ref(repo, &mut remote, &ref_name, &headers, fetch_opts)?
+        .ok_or_else(|| Error::from_str(&format!("{ref_name} not found on {mirror}")))?;
+
+    update_vendor_ref(
+        repo,
+        &dep.name,
+        oid,
+        &format!("vendor fetch from mirror {mirror}"),
+    )?;
+    Ok((oid, VendorFetchStats::new(dep, &remote, old, oid)))
+}
+
+/// Build the tree vendored by `dep` from an upstream `tree`: the paths
+/// matching its pattern but none of its excludes, with its patches applied,
+/// placed under its prefix.
+fn filter_dep_tree<'r>(
+    repo: &'r Repository,
+    dep: &VendorDep,
+    tree: &'r git2::Tree<'r>,
+    options: &FilterOptions,
+) -> Result<git2::Tree<'r>, Error> {
+    let upstream = upstream_root(repo, dep, tree)?;
+    let filtered = repo.filter_with_options(
+        &upstream,
+        &[&dep.root_pattern()],
+        &with_dep_filters(repo, dep, options)?,
+    )?;
+    let filtered = repo.find_tree(filtered.id())?;
+    let patched = apply_patches(repo, dep, filtered)?;
+    nest_tree(repo, patched, dep.prefix.as_deref().unwrap_or(""))
+}
+
+/// Like [`filter_dep_tree`], also returning the filter statistics.
+fn filter_dep_tree_with_stats<'r>(
+    repo: &'r Repository,
+    dep: &VendorDep,
+    tree: &'r git2::Tree<'r>,
+    options: &FilterOptions,
+) -> Result<(git2::Tree<'r>, FilterStats), Error> {
+    let upstream = upstream_root(repo, dep, tree)?;
+    let (filtered, stats) = repo.filter_by_patterns_with_stats(
+        &upstream,
+        &[&dep.root_pattern()],
+        &with_dep_filters(repo, dep, options)?,
+    )?;
+    let filtered = repo.find_tree(filtered.id())?;
+    let patched = apply_patches(repo, dep, filtered)?;
+    let nested = nest_tree(repo, patched, dep.prefix.as_deref().unwrap_or(""))?;
+    Ok((nested, stats))
+}
+
+/// Return the upstream `tree` in the layout `dep`'s pattern is matched
+/// against: with [`VendorDep::src`], that directory's contents placed in the
+/// declaring directory; otherwise `tree` itself.
+fn upstream_root<'r>(
+    repo: &'r Repository,
+    dep: &VendorDep,
+    tree: &git2::Tree<'_>,
+) -> Result<git2::Tree<'r>, Error> {
+    let tree = repo.find_tree(tree.id())?;
+    match &dep.src {
+        Some(src) => nest_tree(repo, subtree_at(repo, tree, src)?, &dep.source_dir),
+        None => Ok(tree),
     }
+}
 
-    fn vendor_merge(
-        &self,
-        maybe_pattern: Option<&str>,
-        opts: &VendorMergeOpts,
-        merge_opts: Option<&MergeOptions>,
-    ) -> Result<(), Error> {
-        require_non_bare(self)?;
+/// Return `tree` with the directory at `path` replaced by `subtree`, or
+/// removed if `subtree` is empty.
+fn graft_tree<'r>(
+    repo: &'r Repository,
+    tree: &git2::Tree<'_>,
+    path: &str,
+    subtree: &git2::Tree<'_>,
+) -> Result<git2::Tree<'r>, Error> {
+    let path = path.trim_matches('/');
+    if path.is_empty() {
+        return repo.find_tree(subtree.id());
+    }
 
-        let path = find_gitattributes(self)?;
-        let deps = parse_vendor_deps(&path)?;
-        let deps = filter_deps(&deps, maybe_pattern);
+    let (name, rest) = path.split_once('/').unwrap_or((path, ""));
+    let child = subtree_at(repo, repo.find_tree(tree.id())?, name)?;
+    let grafted = graft_tree(repo, &child, rest, subtree)?;
+    let mut builder = repo.treebuilder(Some(tree))?;
+    if !grafted.is_empty() {
+        builder.insert(name, grafted.id(), 0o040000)?;
+    } else if builder.get(name)?.is_some() {
+        builder.remove(name)?;
+    }
+    repo.find_tree(builder.write()?)
+}
 
-        if deps.is_empty() {
-            return Err(Error::from_str("No vendored dependencies to merge"));
+/// Return `options` extended with the excludes and kept directories of
+/// `dep`, and with the patterns of `vendor.defaultExcludes` unless `dep`
+/// opts out of them.
+fn with_dep_filters(
+    repo: &Repository,
+    dep: &VendorDep,
+    options: &FilterOptions,
+) -> Result<FilterOptions, Error> {
+    let mut options = options.clone();
+    if !dep.no_default_excludes {
+        options.excludes.extend(default_excludes(repo)?);
+    }
+    options.excludes.extend(dep.excludes.iter().cloned());
+    options.keep_dirs.extend(root_keep_dirs(dep));
+    Ok(options)
+}
+
+/// The patterns of `vendor.defaultExcludes`, separated by commas or
+/// whitespace, such as `**/.github/** **/*.md`.
+fn default_excludes(repo: &Repository) -> Result<Vec<String>, Error> {
+    let patterns = match repo.config()?.get_string("vendor.defaultExcludes") {
+        Ok(patterns) => patterns,
+        Err(e) if e.code() == ErrorCode::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    Ok(split_list(&patterns).map(str::to_string).collect())
+}
+
+/// The items of a configuration value listing them separated by commas or
+/// whitespace.
+fn split_list(value: &str) -> impl Iterator<Item = &str> {
+    value
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|item| !item.is_empty())
+}
+
+/// The kept directories of `dep` relative to the repository root rather than
+/// to its [`source_dir`](VendorDep::source_dir).
+fn root_keep_dirs(dep: &VendorDep) -> Vec<String> {
+    let source_dir = dep.source_dir.trim_matches('/');
+    dep.keep_dirs
+        .iter()
+        .map(|dir| match source_dir {
+            "" => dir.clone(),
+            _ => format!("{source_dir}/{}", dir.trim_start_matches('/')),
+        })
+        .collect()
+}
+
+/// Apply the patches of `dep`, in order, to `tree`. The empty tree, used as
+/// the merge base before the first merge, is left alone.
+fn apply_patches<'r>(
+    repo: &'r Repository,
+    dep: &VendorDep,
+    mut tree: git2::Tree<'r>,
+) -> Result<git2::Tree<'r>, Error> {
+    if tree.is_empty() {
+        return Ok(tree);
+    }
+    for patch in &dep.patches {
+        let path = resolve_repo_path(repo, patch);
+        let content = fs::read(&path)
+            .map_err(|e| Error::from_str(&format!("Failed to read {}: {e}", path.display())))?;
+        let diff = git2::Diff::from_buffer(&content)?;
+        let mut index = repo.apply_to_tree(&tree, &diff, None).map_err(|e| {
+            Error::from_str(&format!(
+                "Patch {patch} for {} does not apply: {}",
+                dep.name,
+                e.message()
+            ))
+        })?;
+        tree = repo.find_tree(index.write_tree_to(repo)?)?;
+    }
+    Ok(tree)
+}
+
+/// Return `tree` nested under the directory `prefix`.
+fn nest_tree<'r>(
+    repo: &'r Repository,
+    tree: git2::Tree<'r>,
+    prefix: &str,
+) -> Result<git2::Tree<'r>, Error> {
+    let mut tree = tree;
+    for component in prefix.rsplit('/').filter(|c| !c.is_empty()) {
+        if tree.is_empty() {
+            break;
         }
+        let mut builder = repo.treebuilder(None)?;
+        builder.insert(component, tree.id(), 0o040000)?;
+        tree = repo.find_tree(builder.write()?)?;
+    }
+    Ok(tree)
+}
 
-        let skip_commit = opts.no_commit || opts.squash;
-        if skip_commit && deps.len() > 1 {
-            return Err(Error::from_str(
-                "--no-commit and --squash require a single dependency; \
-                 specify a pattern to select one",
-            ));
+/// Return the subtree of `tree` at the directory `prefix`, or the empty tree
+/// if there is none.
+fn subtree_at<'r>(
+    repo: &'r Repository,
+    tree: git2::Tree<'r>,
+    prefix: &str,
+) -> Result<git2::Tree<'r>, Error> {
+    if prefix.trim_matches('/').is_empty() {
+        return Ok(tree);
+    }
+    match tree.get_path(Path::new(prefix.trim_matches('/'))) {
+        Ok(entry) if entry.kind() == Some(git2::ObjectType::Tree) => repo.find_tree(entry.id()),
+        Ok(_) => repo.find_tree(repo.treebuilder(None)?.write()?),
+        Err(e) if e.code() == ErrorCode::NotFound => {
+            repo.find_tree(repo.treebuilder(None)?.write()?)
         }
+        Err(e) => Err(e),
+    }
+}
 
-        for dep in &deps {
-            let ref_name = vendor_ref_name(&dep.name);
+/// Build the filter options for a dependency merge. Dependency patterns come
+/// from `.gitattributes`, so they are matched the same way.
+fn dep_filter_options(opts: &VendorMergeOpts) -> FilterOptions {
+    FilterOptions {
+        syntax: PatternSyntax::GitAttributes,
+        submodules: opts.submodules,
+        symlinks: opts.symlinks,
+        normalize_executable: opts.normalize_executable,
+        ..Default::default()
+    }
+}
 
-            println!("Merging {} ({})", dep.name, dep.pattern);
+/// Three-way merge the filtered upstream tree of `vendor_commit` into the
+/// tree `ours`, normally the tree of `head_commit`.
+///
+/// The merge base is the last upstream commit already merged into `HEAD`,
+/// filtered by the same pattern, so only upstream changes made since then are
+/// applied and paths outside the dependency are left alone. When nothing has
+/// been merged yet, the empty tree is used as the base. With
+/// [`MergeStrategy::Upstream`], the vendored paths of `ours` are used as the
+/// base instead, so the upstream content replaces them outright.
+///
+/// Also returns the statistics of filtering the upstream tree and the licenses
+/// detected in it, which must satisfy `vendor.allowedLicenses` when set. Its
+/// files must also satisfy `vendor.maxFileSize` and `vendor.denyBinary`.
+fn merge_vendor_tree(
+    repo: &Repository,
+    dep: &VendorDep,
+    head_commit: &git2::Commit<'_>,
+    ours: &git2::Tree<'_>,
+    vendor_commit: &git2::Commit<'_>,
+    opts: &VendorMergeOpts,
+    merge_opts: Option<&MergeOptions>,
+) -> Result<(Index, FilterStats, Vec<String>), Error> {
+    let vendor_tree = vendor_commit.tree()?;
+    let (theirs, stats, licenses) = filter_upstream(repo, dep, &vendor_tree, opts)?;
+    hooks::pre_merge(repo, opts.hooks.as_deref(), dep, theirs.id())?;
+    let base_tree = merge_base_tree(repo, head_commit, vendor_commit)?;
+    let index = merge_filtered(repo, dep, ours, &base_tree, &theirs, opts, merge_opts)?;
+    Ok((index, stats, licenses))
+}
 
-            let reference = self.find_reference(&ref_name).map_err(|_| {
-                Error::from_str(&format!(
-                    "Vendor ref {ref_name} not found. Run fetch first."
-                ))
-            })?;
+/// Filter the upstream tree `upstream` of `dep`, enforcing the license and
+/// file policies on the result. Returns the filtered tree, the statistics of
+/// filtering it and the licenses detected in it.
+fn filter_upstream<'r>(
+    repo: &'r Repository,
+    dep: &VendorDep,
+    upstream: &'r git2::Tree<'r>,
+    opts: &VendorMergeOpts,
+) -> Result<(git2::Tree<'r>, FilterStats, Vec<String>), Error> {
+    let filter = dep_filter_options(opts);
+    let started = Instant::now();
+    let (theirs, stats) = filter_dep_tree_with_stats(repo, dep, upstream, &filter)?;
+    debug!("  Filtered {} in {:.2?}", dep.name, started.elapsed());
+    let licenses = check_licenses(repo, dep, &theirs)?;
+    check_blobs(repo, dep, &theirs)?;
+    Ok((theirs, stats, licenses))
+}
 
-            let vendor_oid = reference
-                .target()
-                .ok_or_else(|| Error::from_str("Invalid vendor reference"))?;
-            let vendor_commit = self.find_commit(vendor_oid)?;
-            let vendor_tree = vendor_commit.tree()?;
+/// Merge the filtered upstream tree `theirs` of `dep` into `ours`, given the
+/// unfiltered upstream tree `base_tree` merged last. Only objects are read.
+fn merge_filtered(
+    repo: &Repository,
+    dep: &VendorDep,
+    ours: &git2::Tree<'_>,
+    base_tree: &git2::Tree<'_>,
+    theirs: &git2::Tree<'_>,
+    opts: &VendorMergeOpts,
+    merge_opts: Option<&MergeOptions>,
+) -> Result<Index, Error> {
+    let filter = dep_filter_options(opts);
+    let ancestor = if opts.strategy == MergeStrategy::Upstream {
+        local_dep_tree(repo, dep, ours, &filter)?
+    } else {
+        filter_dep_tree(repo, dep, base_tree, &filter)?
+    };
 
-            let filtered_tree = self.filter_by_patterns(&vendor_tree, &[&dep.pattern])?;
+    let mut favored = MergeOptions::new();
+    let merge_opts = match (merge_opts, opts.strategy.file_favor()) {
+        (None, Some(favor)) => Some(&*favored.file_favor(favor)),
+        (merge_opts, _) => merge_opts,
+    };
+    let started = Instant::now();
+    let index = repo.merge_trees(&ancestor, ours, theirs, merge_opts)?;
+    debug!(
+        "  Merged trees of {} in {:.2?}",
+        dep.name,
+        started.elapsed()
+    );
+    Ok(index)
+}
 
-            let head = self.head()?;
-            let head_commit = head.peel_to_commit()?;
-            let head_tree = head_commit.tree()?;
+/// Return the vendored paths of the local tree `tree`: those under the
+/// dependency's prefix matching its pattern but none of its excludes.
+fn local_dep_tree<'r>(
+    repo: &'r Repository,
+    dep: &VendorDep,
+    tree: &git2::Tree<'_>,
+    options: &FilterOptions,
+) -> Result<git2::Tree<'r>, Error> {
+    let prefix = dep.prefix.as_deref().unwrap_or("");
+    let subtree = subtree_at(repo, repo.find_tree(tree.id())?, prefix)?;
+    let filtered = repo.filter_with_options(
+        &subtree,
+        &[&dep.root_pattern()],
+        &with_dep_filters(repo, dep, options)?,
+    )?;
+    let filtered = repo.find_tree(filtered.id())?;
+    nest_tree(repo, filtered, prefix)
+}
 
-            let mut index = self.merge_trees(&head_tree, &head_tree, &filtered_tree, merge_opts)?;
+/// Merge every dependency in `deps` into one tree and record the result as a
+/// single commit whose parents are `HEAD` and each merged upstream commit
+/// (see [`VendorMergeOpts::single_commit`]).
+///
+/// Each dependency is merged on top of the tree produced by the previous one.
+/// A conflict in any dependency aborts the whole merge before anything is
+/// written, so the repository is never left partially updated.
+fn merge_single_commit(
+    repo: &Repository,
+    deps: &[&VendorDep],
+    opts: &VendorMergeOpts,
+    merge_opts: Option<&MergeOptions>,
+) -> Result<(), Error> {
+    let head_commit = repo.head()?.peel_to_commit()?;
+    let mut tree = head_commit.tree()?;
+    let mut vendor_commits: Vec<git2::Commit<'_>> = Vec::new();
+    let mut summary = Vec::new();
+    let mut all_licenses = Vec::new();
+
+    for dep in deps {
+        info!("Merging {} ({})", dep.name, dep.pattern);
+
+        let vendor_commit = find_vendor_commit(repo, dep)?;
+        if opts.allowed_signers.is_some() || dep.verify.is_some() {
+            let signer = repo.vendor_verify(dep, opts.allowed_signers.as_deref())?;
+            info!("  Verified signature from {signer}");
+        }
 
-            let default_message = format!("Merge vendored dependency: {}", dep.name);
-            let message = opts.message.as_deref().unwrap_or(&default_message);
+        let (mut index, stats, licenses) = merge_vendor_tree(
+            repo,
+            dep,
+            &head_commit,
+            &tree,
+            &vendor_commit,
+            opts,
+            merge_opts,
+        )?;
+        for pattern in &stats.unmatched_patterns {
+            warn!("  Warning: pattern {pattern} matched no upstream files");
+        }
+        for path in &stats.gitlinks {
+            match opts.submodules {
+                SubmodulePolicy::Keep => info!("  Kept submodule {path}"),
+                _ => info!("  Skipped submodule {path}"),
+            }
+        }
 
-            if index.has_conflicts() {
-                // Write the conflicted index to the repository so the user can
-                // resolve in the working tree.
-                let mut repo_index = self.index()?;
-                repo_index.read_tree(&head_tree)?;
-                for conflict in index.conflicts()? {
-                    let conflict = conflict?;
-                    if let Some(entry) = &conflict.our {
-                        repo_index.add(entry)?;
-                    }
-                    if let Some(entry) = &conflict.their {
-                        repo_index.add(entry)?;
-                    }
+        if index.has_conflicts() {
+            return Err(Error::from_str(&format!(
+                "Conflicts detected while merging {}. Nothing was merged; \
+                 merge it on its own to resolve them.",
+                dep.name
+            )));
+        }
+        tree = repo.find_tree(index.write_tree_to(repo)?)?;
+
+        all_licenses.extend(licenses);
+        if !vendor_commits.iter().any(|c| c.id() == vendor_commit.id()) {
+            // Each dependency is summarized by the first line of its own
+            // configured message, or its name.
+            let line = match message_template(repo, dep)? {
+                Some(template) => {
+                    let message = expand_message_template(&template, dep, &vendor_commit);
+                    message.lines().next().unwrap_or_default().to_string()
                 }
-                repo_index.write()?;
+                None => dep.name.clone(),
+            };
+            summary.push(format!("- {line} ({})", short_id(vendor_commit.id())));
+            vendor_commits.push(vendor_commit);
+        }
+    }
+    all_licenses.sort();
+    all_licenses.dedup();
 
-                let mut co = CheckoutBuilder::new();
-                co.allow_conflicts(true).conflict_style_merge(true);
-                self.checkout_index(Some(&mut repo_index), Some(&mut co))?;
+    let message = match &opts.message {
+        Some(message) => message.clone(),
+        None => format!("Update vendored dependencies\n\n{}", summary.join("\n")),
+    };
+    let message = with_trailers(repo, deps, message, &all_licenses);
 
-                if !opts.squash {
-                    set_merge_head(self, vendor_oid)?;
-                }
-                set_merge_msg(self, message)?;
+    let paths = changed_paths(repo, &head_commit.tree()?, &tree)?;
+    if !opts.force {
+        check_unmodified(repo, &paths, opts.index_only)?;
+    }
+    checkout_paths(repo, &tree, &paths, opts.index_only)?;
 
-                return Err(Error::from_str(&format!(
-                    "Conflicts detected while merging {}. \
-                     Resolve them and commit the result.",
-                    dep.name
-                )));
-            }
+    if opts.no_commit || opts.squash {
+        if !opts.squash {
+            let oids: Vec<Oid> = vendor_commits.iter().map(|c| c.id()).collect();
+            set_merge_head(repo, &oids)?;
+        }
+        set_merge_msg(repo, &message)?;
+        info!(
+            "Merged {} dependencies (not committed)",
+            vendor_commits.len()
+        );
+    } else {
+        let mut parents = vec![&head_commit];
+        parents.extend(vendor_commits.iter());
+        let commit = commit_merge(repo, Some("HEAD"), opts, &message, &tree, &parents)?;
+        info!("Merged {} dependencies in one commit", vendor_commits.len());
+        let head = repo.head()?;
+        let branch = head.shorthand().unwrap_or("HEAD");
+        for group in group_by_name(deps) {
+            log_vendor_merge(repo, &group[0].name, branch, commit);
+            hooks::post_commit(repo, opts.hooks.as_deref(), group[0], commit)?;
+        }
+    }
+
+    Ok(())
+}
 
-            // Clean merge — write the tree, update index and working directory.
-            let merged_oid = index.write_tree_to(self)?;
-            let merged_tree = self.find_tree(merged_oid)?;
+/// Record a merge commit with the author, committer and signing settings of
+/// `opts`, updating `update_ref` like [`Repository::commit`].
+fn commit_merge(
+    repo: &Repository,
+    update_ref: Option<&str>,
+    opts: &VendorMergeOpts,
+    message: &str,
+    tree: &git2::Tree<'_>,
+    parents: &[&git2::Commit<'_>],
+) -> Result<Oid, Error> {
+    let author = match &opts.author {
+        Some(author) => parse_identity(author)?,
+        None => identity(repo, "AUTHOR")?,
+    };
+    let committer = match &opts.committer {
+        Some(committer) => parse_identity(committer)?,
+        None => identity(repo, "COMMITTER")?,
+    };
+    let sign = match opts.sign {
+        Some(sign) => sign,
+        None => opts.signer.is_some() || repo.config()?.get_bool("commit.gpgsign").unwrap_or(false),
+    };
+    if !sign {
+        return repo.commit(update_ref, &author, &committer, message, tree, parents);
+    }
 
-            let mut repo_index = self.index()?;
-            repo_index.read_tree(&merged_tree)?;
-            repo_index.write()?;
+    let buffer = repo.commit_create_buffer(&author, &committer, message, tree, parents)?;
+    let buffer = buffer
+        .as_str()
+        .ok_or_else(|| Error::from_str("Merge commit is not valid UTF-8"))?;
+    let signature = match &opts.signer {
+        Some(signer) => signer.sign(buffer)?,
+        None => verify::sign(repo, buffer)?,
+    };
+    let commit = repo.commit_signed(buffer, &signature, None)?;
+    if let Some(update_ref) = update_ref {
+        let summary = message.lines().next().unwrap_or_default();
+        repo.find_reference(update_ref)?
+            .resolve()?
+            .set_target(commit, &format!("commit (merge): {summary}"))?;
+    }
+    Ok(commit)
+}
 
-            let mut co = CheckoutBuilder::new();
-            co.force();
-            self.checkout_tree(merged_tree.as_object(), Some(&mut co))?;
+/// Parse a `Name <email>` identity into a signature dated now.
+fn parse_identity(identity: &str) -> Result<Signature<'static>, Error> {
+    let (name, email) = identity
+        .trim()
+        .strip_suffix('>')
+        .and_then(|rest| rest.split_once('<'))
+        .ok_or_else(|| {
+            Error::from_str(&format!(
+                "Invalid identity {identity:?}; expected \"Name <email>\""
+            ))
+        })?;
+    Signature::now(name.trim(), email.trim())
+}
 
-            if skip_commit {
-                if !opts.squash {
-                    set_merge_head(self, vendor_oid)?;
-                }
-                set_merge_msg(self, message)?;
-                println!("  Merged (not committed)");
-            } else {
-                let signature = self.signature()?;
-                self.commit(
-                    Some("HEAD"),
-                    &signature,
-                    &signature,
-                    message,
-                    &merged_tree,
-                    &[&head_commit, &vendor_commit],
-                )?;
-                println!("  Merged successfully");
-            }
+/// The identity Git records as `kind` (`AUTHOR` or `COMMITTER`): from the
+/// `GIT_<kind>_NAME` and `GIT_<kind>_EMAIL` environment variables, falling
+/// back to `user.name` and `user.email`.
+fn identity(repo: &Repository, kind: &str) -> Result<Signature<'static>, Error> {
+    let name = std::env::var(format!("GIT_{kind}_NAME")).ok();
+    let email = std::env::var(format!("GIT_{kind}_EMAIL")).ok();
+    if name.is_none() && email.is_none() {
+        return repo.signature();
+    }
+
+    let (name, email) = match (name, email) {
+        (Some(name), Some(email)) => (name, email),
+        (name, email) => {
+            let default = repo.signature()?;
+            (
+                name.unwrap_or_else(|| default.name().unwrap_or_default().to_string()),
+                email.unwrap_or_else(|| default.email().unwrap_or_default().to_string()),
+            )
         }
+    };
+    Signature::now(&name, &email)
+}
 
-        Ok(())
+/// Map each file of `tree` that one of `deps` vendors to the index of that
+/// dependency in `deps`, the last declared winning.
+fn tree_owners(
+    repo: &Repository,
+    deps: &[VendorDep],
+    tree: &git2::Tree<'_>,
+) -> Result<BTreeMap<String, usize>, Error> {
+    let filter = dep_filter_options(&VendorMergeOpts::default());
+    let mut owners = BTreeMap::new();
+    for (i, dep) in deps.iter().enumerate() {
+        let vendored = local_dep_tree(repo, dep, tree, &filter)?;
+        vendored.walk(git2::TreeWalkMode::PreOrder, |dir, entry| {
+            if entry.kind() != Some(git2::ObjectType::Tree) {
+                owners.insert(format!("{dir}{}", entry.name().unwrap_or_default()), i);
+            }
+            git2::TreeWalkResult::Ok
+        })?;
     }
+    Ok(owners)
 }
 
-// ---------------------------------------------------------------------------
-// Helpers
-// ---------------------------------------------------------------------------
+/// Return the paths of the old and new files of every delta in `diff`.
+fn delta_paths(diff: &git2::Diff<'_>) -> BTreeSet<String> {
+    diff.deltas()
+        .flat_map(|delta| [delta.old_file().path(), delta.new_file().path()])
+        .flatten()
+        .map(|path| path.to_string_lossy().replace('\\', "/"))
+        .collect()
+}
+
+/// Return a tree with an empty file at each of `paths`, so that they can be
+/// matched against dependency patterns with [`tree_owners`].
+fn paths_tree<'r>(repo: &'r Repository, paths: &BTreeSet<String>) -> Result<Tree<'r>, Error> {
+    let blob = repo.blob(b"")?;
+    let mut index = Index::new()?;
+    for path in paths {
+        index.add(&bare_index_entry(path.as_bytes().to_vec(), 0o100644, blob))?;
+    }
+    repo.find_tree(index.write_tree_to(repo)?)
+}
+
+/// Return the (unfiltered) tree of the last upstream commit merged into
+/// `head_commit`, or the empty tree when nothing has been merged yet.
+fn merge_base_tree<'r>(
+    repo: &'r Repository,
+    head_commit: &git2::Commit<'_>,
+    vendor_commit: &git2::Commit<'_>,
+) -> Result<git2::Tree<'r>, Error> {
+    match repo.merge_base(head_commit.id(), vendor_commit.id()) {
+        Ok(base) => repo.find_commit(base)?.tree(),
+        Err(e) if e.code() == ErrorCode::NotFound => {
+            let empty = repo.treebuilder(None)?.write()?;
+            repo.find_tree(empty)
+        }
+        Err(e) => Err(e),
+    }
+}
 
 // ---------------------------------------------------------------------------
 // Merge state helpers
 // ---------------------------------------------------------------------------
 
+/// Return the paths whose entries differ between the trees `old` and `new`.
+fn changed_paths(
+    repo: &Repository,
+    old: &git2::Tree<'_>,
+    new: &git2::Tree<'_>,
+) -> Result<Vec<PathBuf>, Error> {
+    let diff = repo.diff_tree_to_tree(Some(old), Some(new), None)?;
+    Ok(diff
+        .deltas()
+        .filter_map(|delta| delta.new_file().path().or(delta.old_file().path()))
+        .map(Path::to_path_buf)
+        .collect())
+}
+
+/// Convert a path read from an index entry to a filesystem path.
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
+/// Convert a path relative to the repository root to an index entry path.
+#[cfg(unix)]
+fn path_to_bytes(path: &Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+fn path_to_bytes(path: &Path) -> Vec<u8> {
+    path.to_string_lossy().replace('\\', "/").into_bytes()
+}
+
+/// Return an index entry for the file at `path` in `tree`, or `None` if there
+/// is none. Its stat data is left empty, so Git rehashes the file once.
+fn index_entry(tree: &git2::Tree<'_>, path: &Path) -> Result<Option<git2::IndexEntry>, Error> {
+    let entry = match tree.get_path(path) {
+        Ok(entry) => entry,
+        Err(e) if e.code() == ErrorCode::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    Ok(Some(bare_index_entry(
+        path_to_bytes(path),
+        entry.filemode() as u32,
+        entry.id(),
+    )))
+}
+
+/// Return an index entry for `id` at `path` with `mode` and empty stat data.
+fn bare_index_entry(path: Vec<u8>, mode: u32, id: Oid) -> git2::IndexEntry {
+    git2::IndexEntry {
+        ctime: git2::IndexTime::new(0, 0),
+        mtime: git2::IndexTime::new(0, 0),
+        dev: 0,
+        ino: 0,
+        mode,
+        uid: 0,
+        gid: 0,
+        file_size: 0,
+        id,
+        // The low bits hold the path length, saturated like Git does.
+        flags: path.len().min(0xfff) as u16,
+        flags_extended: 0,
+        path,
+    }
+}
+
+/// Fail if a merge writing `paths` would overwrite uncommitted changes:
+/// staged changes and, unless `index_only`, modified or untracked files in
+/// the working tree.
+fn check_unmodified(repo: &Repository, paths: &[PathBuf], index_only: bool) -> Result<(), Error> {
+    if repo.is_bare() || paths.is_empty() {
+        return Ok(());
+    }
+
+    let mut status_opts = git2::StatusOptions::new();
+    status_opts
+        .include_untracked(true)
+        .recurse_untracked_dirs(true)
+        .disable_pathspec_match(true);
+    for path in paths {
+        status_opts.pathspec(path);
+    }
+
+    let staged = git2::Status::INDEX_NEW
+        | git2::Status::INDEX_MODIFIED
+        | git2::Status::INDEX_DELETED
+        | git2::Status::INDEX_RENAMED
+        | git2::Status::INDEX_TYPECHANGE
+        | git2::Status::CONFLICTED;
+    let unstaged = git2::Status::WT_NEW
+        | git2::Status::WT_MODIFIED
+        | git2::Status::WT_DELETED
+        | git2::Status::WT_RENAMED
+        | git2::Status::WT_TYPECHANGE;
+    let dirty: Vec<String> = repo
+        .statuses(Some(&mut status_opts))?
+        .iter()
+        .filter(|entry| {
+            entry.status().intersects(staged)
+                || (!index_only && entry.status().intersects(unstaged))
+        })
+        .map(|entry| String::from_utf8_lossy(entry.path_bytes()).into_owned())
+        .collect();
+    if dirty.is_empty() {
+        return Ok(());
+    }
+
+    Err(Error::from_str(&format!(
+        "Uncommitted changes to vendored paths would be overwritten by the merge:\n  {}\n\
+         Commit or stash them, or force the merge.",
+        dirty.join("\n  ")
+    )))
+}
+
+/// Update the index and, unless `index_only`, the working tree to `tree`
+/// at `paths` only, leaving any other changes alone. Only the entries at
+/// `paths` are read, so the cost does not grow with the size of the tree.
+fn checkout_paths(
+    repo: &Repository,
+    tree: &git2::Tree<'_>,
+    paths: &[PathBuf],
+    index_only: bool,
+) -> Result<(), Error> {
+    if repo.is_bare() || paths.is_empty() {
+        return Ok(());
+    }
+
+    let mut repo_index = repo.index()?;
+    for path in paths {
+        match index_entry(tree, path)? {
+            Some(entry) => repo_index.add(&entry)?,
+            None => repo_index.remove_path(path)?,
+        }
+    }
+    repo_index.write()?;
+
+    if !index_only {
+        let mut co = CheckoutBuilder::new();
+        co.force().disable_pathspec_match(true);
+        for path in paths {
+            co.path(path);
+        }
+        repo.checkout_tree(tree.as_object(), Some(&mut co))?;
+    }
+    Ok(())
+}
+
 /// Write `MERGE_HEAD` so that a subsequent `git commit` creates a merge commit.
-fn set_merge_head(repo: &Repository, oid: Oid) -> Result<(), Error> {
+fn set_merge_head(repo: &Repository, oids: &[Oid]) -> Result<(), Error> {
     let path = repo.path().join("MERGE_HEAD");
-    fs::write(&path, format!("{oid}\n")).map_err(|e| Error::from_str(&e.to_string()))
+    let content: String = oids.iter().map(|oid| format!("{oid}\n")).collect();
+    fs::write(&path, content).map_err(|e| Error::from_str(&e.to_string()))
+}
+
+/// Return the commits listed in `MERGE_HEAD`, if a merge is in progress.
+fn merge_heads(repo: &Repository) -> Result<Vec<Oid>, Error> {
+    let content = match fs::read_to_string(repo.path().join("MERGE_HEAD")) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(Error::from_str(&e.to_string())),
+    };
+    content
+        .lines()
+        .map(|line| Oid::from_str(line.trim()))
+        .collect()
 }
 
 /// Write `MERGE_MSG` so that `git commit` picks up the message.
@@ -349,6 +3996,225 @@ fn set_merge_msg(repo: &Repository, msg: &str) -> Result<(), Error> {
     fs::write(&path, format!("{msg}\n")).map_err(|e| Error::from_str(&e.to_string()))
 }
 
+// ---------------------------------------------------------------------------
+// Formatting helpers
+// ---------------------------------------------------------------------------
+
+/// Format a Git timestamp as `YYYY-MM-DD` in its own time zone.
+fn format_date(time: git2::Time) -> String {
+    let local = time.seconds() + i64::from(time.offset_minutes()) * 60;
+    let (year, month, day) = civil_from_days(local.div_euclid(86_400));
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+/// Return the commit message for merging `dep` at upstream `commit`: the
+/// explicit message, the configured template, or a default.
+fn merge_message(
+    repo: &Repository,
+    dep: &VendorDep,
+    opts: &VendorMergeOpts,
+    commit: &git2::Commit<'_>,
+) -> Result<String, Error> {
+    Ok(match &opts.message {
+        Some(message) => message.clone(),
+        None => match message_template(repo, dep)? {
+            Some(template) => expand_message_template(&template, dep, commit),
+            None => format!("Merge vendored dependency: {}", dep.name),
+        },
+    })
+}
+
+/// Append a `Vendor-License:` trailer to `message` for each of `licenses`,
+/// and a `Vendor-Version:` trailer naming the upstream tag merged for each
+/// of `deps` that follows a version.
+fn with_trailers(
+    repo: &Repository,
+    deps: &[&VendorDep],
+    message: String,
+    licenses: &[String],
+) -> String {
+    let mut trailers: Vec<String> = licenses
+        .iter()
+        .map(|license| format!("Vendor-License: {license}"))
+        .collect();
+    for group in group_by_name(deps) {
+        if group[0].version.is_some()
+            && let Some(tag) = fetched_tag(repo, group[0])
+        {
+            trailers.push(format!("Vendor-Version: {tag}"));
+        }
+    }
+    if trailers.is_empty() {
+        return message;
+    }
+    format!("{}\n\n{}\n", message.trim_end(), trailers.join("\n"))
+}
+
+/// Check the files of the filtered upstream `tree` of `dep` against
+/// `vendor.maxFileSize` and the binary files matching `vendor.denyBinary`,
+/// when set. Offending files fail the merge, or are only warned about when
+/// `vendor.blobPolicy` is `warn`.
+fn check_blobs(repo: &Repository, dep: &VendorDep, tree: &git2::Tree<'_>) -> Result<(), Error> {
+    let config = repo.config()?;
+    let max_size = match config.get_i64("vendor.maxFileSize") {
+        Ok(size) => Some(u64::try_from(size).map_err(|_| {
+            Error::from_str(&format!(
+                "Invalid vendor.maxFileSize '{size}': expected a non-negative size"
+            ))
+        })?),
+        Err(e) if e.code() == ErrorCode::NotFound => None,
+        Err(e) => return Err(e),
+    };
+    let deny_binary = match config.get_string("vendor.denyBinary") {
+        Ok(patterns) => patterns,
+        Err(e) if e.code() == ErrorCode::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+    let deny_binary: Vec<&str> = split_list(&deny_binary).collect();
+    if max_size.is_none() && deny_binary.is_empty() {
+        return Ok(());
+    }
+    let warn_only = match config.get_string("vendor.blobPolicy") {
+        Ok(policy) if policy == "warn" => true,
+        Ok(policy) if policy == "fail" => false,
+        Ok(policy) => {
+            return Err(Error::from_str(&format!(
+                "Invalid vendor.blobPolicy '{policy}': expected 'fail' or 'warn'"
+            )));
+        }
+        Err(e) if e.code() == ErrorCode::NotFound => false,
+        Err(e) => return Err(e),
+    };
+
+    let mut blocked = Vec::new();
+    if let Some(max_size) = max_size {
+        let odb = repo.odb()?;
+        let mut result = Ok(());
+        let walked = tree.walk(git2::TreeWalkMode::PreOrder, |dir, entry| {
+            if entry.kind() != Some(git2::ObjectType::Blob) {
+                return git2::TreeWalkResult::Ok;
+            }
+            match odb.read_header(entry.id()) {
+                Ok((size, _)) if size as u64 > max_size => blocked.push(format!(
+                    "{dir}{} ({size} bytes, over vendor.maxFileSize)",
+                    String::from_utf8_lossy(entry.name_bytes())
+                )),
+                Ok(_) => {}
+                Err(e) => {
+                    result = Err(e);
+                    return git2::TreeWalkResult::Abort;
+                }
+            }
+            git2::TreeWalkResult::Ok
+        });
+        // An aborted walk fails too, but with a less useful error.
+        result?;
+        walked?;
+    }
+    if !deny_binary.is_empty() {
+        let options = FilterOptions {
+            syntax: PatternSyntax::GitAttributes,
+            ..Default::default()
+        };
+        let denied = repo.filter_with_options(tree, &deny_binary, &options)?;
+        let mut result = Ok(());
+        let walked = denied.walk(git2::TreeWalkMode::PreOrder, |dir, entry| {
+            if entry.kind() != Some(git2::ObjectType::Blob) {
+                return git2::TreeWalkResult::Ok;
+            }
+            match repo.find_blob(entry.id()) {
+                Ok(blob) if blob.is_binary() => blocked.push(format!(
+                    "{dir}{} (binary, matches vendor.denyBinary)",
+                    String::from_utf8_lossy(entry.name_bytes())
+                )),
+                Ok(_) => {}
+                Err(e) => {
+                    result = Err(e);
+                    return git2::TreeWalkResult::Abort;
+                }
+            }
+            git2::TreeWalkResult::Ok
+        });
+        result?;
+        walked?;
+    }
+
+    if blocked.is_empty() {
+        return Ok(());
+    }
+    if warn_only {
+        for file in &blocked {
+            warn!("  {} vendors {file}", dep.name);
+        }
+        return Ok(());
+    }
+    Err(Error::from_str(&format!(
+        "{} would vendor files blocked by policy:\n  {}",
+        dep.name,
+        blocked.join("\n  ")
+    )))
+}
+
+/// Detect the licenses of `tree`, the filtered upstream tree of `dep`, and
+/// check them against the `vendor.allowedLicenses` allow-list when it is set.
+fn check_licenses(
+    repo: &Repository,
+    dep: &VendorDep,
+    tree: &git2::Tree<'_>,
+) -> Result<Vec<String>, Error> {
+    let licenses = license::detect(repo, tree)?;
+
+    let allowed = match repo.config()?.get_string("vendor.allowedLicenses") {
+        Ok(allowed) => allowed,
+        Err(e) if e.code() == ErrorCode::NotFound => return Ok(licenses),
+        Err(e) => return Err(e),
+    };
+    let allowed: Vec<String> = split_list(&allowed).map(String::from).collect();
+
+    if licenses.is_empty() {
+        return Err(Error::from_str(&format!(
+            "No license detected for {}; vendor.allowedLicenses requires one",
+            dep.name
+        )));
+    }
+    if let Some(denied) = licenses
+        .iter()
+        .find(|expr| !license::is_allowed(expr, &allowed))
+    {
+        return Err(Error::from_str(&format!(
+            "License {denied} of {} is not in vendor.allowedLicenses",
+            dep.name
+        )));
+    }
+    Ok(licenses)
+}
+
+/// Expand the placeholders of a merge message template for `dep` merged at
+/// upstream `commit`. Unknown placeholders are left untouched.
+fn expand_message_template(template: &str, dep: &VendorDep, commit: &git2::Commit<'_>) -> String {
+    template
+        .replace("{name}", &dep.name)
+        .replace("{url}", &dep.url)
+        .replace("{commit}", &commit.id().to_string())
+        .replace("{branch}", dep.branch.as_deref().unwrap_or("HEAD"))
+        .replace("{date}", &format_date(commit.time()))
+}
+
+/// Convert days since the Unix epoch to a proleptic Gregorian `(year, month,
+/// day)`, following Howard Hinnant's `civil_from_days` algorithm.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
 // ---------------------------------------------------------------------------
 // Repository helpers
 // ---------------------------------------------------------------------------
@@ -363,12 +4229,40 @@ fn require_non_bare(repo: &Repository) -> Result<(), Error> {
     }
 }
 
+/// Look up the merge message template for `dep`: `vendor.<name>.message`
+/// takes precedence over the repository-wide `vendor.message`.
+fn message_template(repo: &Repository, dep: &VendorDep) -> Result<Option<String>, Error> {
+    let config = repo.config()?;
+    for key in [
+        format!("vendor.{}.message", dep.name),
+        "vendor.message".into(),
+    ] {
+        match config.get_string(&key) {
+            Ok(template) => return Ok(Some(template)),
+            Err(e) if e.code() == ErrorCode::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(None)
+}
+
+/// Resolve a path from an attribute value: absolute paths are kept, relative
+/// ones are taken from the repository root (the Git directory when bare).
+fn resolve_repo_path(repo: &Repository, path: &str) -> PathBuf {
+    let path = Path::new(path);
+    if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        repo.workdir().unwrap_or_else(|| repo.path()).join(path)
+    }
+}
+
 /// Resolve the vendor dependency name.
 ///
 /// If `maybe_name` is provided, it is used as-is. Otherwise the name is
 /// derived from the URL by extracting the last two path segments (typically
-/// `owner/repo`), stripping any `.git` suffix. Local (non-URL) remotes
-/// **must** supply an explicit name.
+/// `owner/repo`), stripping any `.git` suffix. Local repositories are named
+/// after their directory.
 fn resolve_name(url: &str, maybe_name: Option<&str>) -> Result<String, Error> {
     if let Some(name) = maybe_name {
         if name.is_empty() {
@@ -378,10 +4272,9 @@ fn resolve_name(url: &str, maybe_name: Option<&str>) -> Result<String, Error> {
     }
 
     name_from_url(url).ok_or_else(|| {
-        Error::from_str(
-            "Cannot derive a vendor name from a local path. \
-             Please provide an explicit name.",
-        )
+        Error::from_str(&format!(
+            "Cannot derive a vendor name from {url}. Please provide an explicit name."
+        ))
     })
 }
 
@@ -394,30 +4287,100 @@ fn is_remote_url(url: &str) -> bool {
         return true;
     }
     // SCP-style: git@host:path  (must have @ before : and no path separators before @)
-    if let Some(at) = url.find('@') {
-        if let Some(colon) = url[at..].find(':') {
-            let colon_pos = at + colon;
-            // Make sure the part before @ has no slashes (not a path)
-            if !url[..at].contains('/') && colon_pos + 1 < url.len() {
-                return true;
-            }
+    if let Some(at) = url.find('@')
+        && let Some(colon) = url[at..].find(':')
+    {
+        let colon_pos = at + colon;
+        // Make sure the part before @ has no slashes (not a path)
+        if !url[..at].contains('/') && colon_pos + 1 < url.len() {
+            return true;
         }
     }
     false
 }
 
-/// Try to extract `owner/repo` from a remote URL.
+/// Return the local path `url` names, if it is a plain path or a `file://`
+/// URL.
+fn local_path(url: &str) -> Option<&str> {
+    match url.strip_prefix("file://") {
+        Some(path) => Some(path),
+        None => (!is_remote_url(url)).then_some(url),
+    }
+}
+
+/// Return the URL to fetch `url` from: relative local paths are relative to
+/// the repository root (the Git directory when bare), wherever the command
+/// runs.
+fn remote_url(repo: &Repository, url: &str) -> String {
+    if is_remote_url(url) || Path::new(url).is_absolute() {
+        return url.to_string();
+    }
+    resolve_repo_path(repo, url).display().to_string()
+}
+
+/// Rewrite `url`, if it is a relative local path, from relative to `at_dir`
+/// (relative to the root of the working tree), or the current directory
+/// without one, to relative to the repository root.
+fn root_relative_url(repo: &Repository, url: &str, at_dir: Option<&Path>) -> Result<String, Error> {
+    if is_remote_url(url) || Path::new(url).is_absolute() {
+        return Ok(url.to_string());
+    }
+    let workdir = repo
+        .workdir()
+        .ok_or_else(|| Error::from_str("Repository has no working directory"))?;
+    let base = match at_dir {
+        Some(dir) => workdir.join(dir),
+        None => std::env::current_dir().map_err(|e| Error::from_str(&e.to_string()))?,
+    };
+    let root = git_set_attr::normalize_path(workdir);
+    let target = git_set_attr::normalize_path(&base.join(url));
+
+    let common = root
+        .components()
+        .zip(target.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut segments: Vec<String> = root
+        .components()
+        .skip(common)
+        .map(|_| "..".into())
+        .collect();
+    segments.extend(
+        target
+            .components()
+            .skip(common)
+            .map(|c| c.as_os_str().to_string_lossy().into_owned()),
+    );
+    if segments.is_empty() {
+        return Ok(".".to_string());
+    }
+    Ok(segments.join("/"))
+}
+
+/// Try to extract a dependency name from a URL: `owner/repo` for remote URLs,
+/// or the directory name for local repositories.
 ///
 /// Supports:
 /// - `https://host/owner/repo.git`
 /// - `https://host/owner/repo`
 /// - `git@host:owner/repo.git`
 /// - `ssh://git@host/owner/repo.git`
+/// - `/path/to/repo`, `../repo.git`, `file:///path/to/repo`
 ///
-/// Returns `None` for local paths or URLs with fewer than two path segments.
+/// A path to a `.git` directory is named after the directory containing it.
+/// Returns `None` for URLs with fewer than two path segments and local paths
+/// without a directory name.
 fn name_from_url(url: &str) -> Option<String> {
-    if !is_remote_url(url) {
-        return None;
+    if let Some(path) = local_path(url) {
+        let mut segments = path
+            .split(['/', '\\'])
+            .filter(|s| !s.is_empty() && *s != ".");
+        let mut last = segments.next_back()?;
+        if last == ".git" {
+            last = segments.next_back()?;
+        }
+        let name = last.strip_suffix(".git").unwrap_or(last);
+        return (!name.is_empty() && name != "..").then(|| name.to_string());
     }
 
     // Normalize: strip trailing `/` and `.git` suffix.
@@ -446,44 +4409,281 @@ fn name_from_url(url: &str) -> Option<String> {
     Some(format!("{owner}/{repo}"))
 }
 
+/// The comment `vendor_init` starts the root `.gitattributes` file with.
+const GITATTRIBUTES_HEADER: &str = "\
+# Vendored dependencies, managed by git-vendor:
+#
+#   <pattern> vendored vendor-name=<name> vendor-url=<url> [vendor-branch=<branch>]
+#
+# Fetched upstream content is stored under refs/vendor/<name>.
+
+";
+
+/// The notes ref holding vendor attestations.
+const VENDOR_NOTES_REF: &str = "refs/notes/vendor";
+
+/// Parse an attestation note line: `<upstream> <tree> <name> <pattern>`.
+fn parse_attestation(line: &str) -> Result<VendorAttestation, Error> {
+    let invalid = || Error::from_str(&format!("Invalid vendor attestation: {line}"));
+    let mut fields = line.splitn(4, ' ');
+    let mut next = || fields.next().ok_or_else(invalid);
+    Ok(VendorAttestation {
+        upstream: Oid::from_str(next()?)?,
+        tree: Oid::from_str(next()?)?,
+        name: next()?.to_string(),
+        pattern: next()?.to_string(),
+    })
+}
+
 /// Build the full ref path for a vendor dependency, e.g. `refs/vendor/owner/repo`.
 fn vendor_ref_name(name: &str) -> String {
     format!("refs/vendor/{name}")
 }
 
-/// Find the appropriate `.gitattributes` file by walking from the current
-/// directory up to the repository root.
+/// Build the ref path the forked repository of a dependency's fork
+/// ([`VendorDep::upstream`]) is fetched into.
+fn fork_upstream_ref_name(name: &str) -> String {
+    format!("refs/vendor-upstream/{name}")
+}
+
+/// Return the directory declaring the `.gitattributes` file at `path`,
+/// relative to the root of the working tree.
+fn declaring_dir(repo: &Repository, path: &Path) -> String {
+    let dir = git_set_attr::normalize_path(path.parent().unwrap_or(path));
+    let Some(workdir) = repo.workdir().map(git_set_attr::normalize_path) else {
+        return String::new();
+    };
+    match dir.strip_prefix(&workdir) {
+        Ok(rel) => rel
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/"),
+        Err(_) => String::new(),
+    }
+}
+
+/// Return the declared dependencies that tracking `tracked` conflicts with:
+/// patterns of the same name with a different URL or branch, and patterns of
+/// other dependencies overlapping it. Re-tracking the same declaration is not
+/// a conflict. Without `force`, any conflict is an error.
+fn track_conflicts(
+    repo: &Repository,
+    tracked: &VendorDep,
+    force: bool,
+) -> Result<Vec<VendorDep>, Error> {
+    let head_tree = match repo.head() {
+        Ok(head) => Some(head.peel_to_tree()?),
+        Err(e) if e.code() == ErrorCode::UnbornBranch || e.code() == ErrorCode::NotFound => None,
+        Err(e) => return Err(e),
+    };
+
+    let mut conflicts = Vec::new();
+    for dep in load_deps(repo)? {
+        if dep.pattern == tracked.pattern && dep.source_dir == tracked.source_dir {
+            continue;
+        }
+        let reason = if dep.name == tracked.name {
+            if dep.url == tracked.url && dep.branch == tracked.branch {
+                continue;
+            }
+            format!(
+                "{} is already tracked from {}{}",
+                dep.name,
+                dep.url,
+                dep.branch
+                    .as_deref()
+                    .map(|b| format!(" ({b})"))
+                    .unwrap_or_default()
+            )
+        } else if patterns_overlap(repo, head_tree.as_ref(), &dep, tracked)? {
+            format!(
+                "{} overlaps {} ({})",
+                tracked.pattern, dep.name, dep.pattern
+            )
+        } else {
+            continue;
+        };
+        if !force {
+            return Err(Error::from_str(&format!(
+                "{reason}; use --force to replace it"
+            )));
+        }
+        info!("Replacing {} ({}): {reason}", dep.name, dep.pattern);
+        conflicts.push(dep);
+    }
+    Ok(conflicts)
+}
+
+/// Whether the patterns of `a` and `b` are the same or both match a file in
+/// `tree`. Patterns relative to a `prefix` are only compared literally.
+fn patterns_overlap(
+    repo: &Repository,
+    tree: Option<&git2::Tree<'_>>,
+    a: &VendorDep,
+    b: &VendorDep,
+) -> Result<bool, Error> {
+    if a.root_pattern() == b.root_pattern() {
+        return Ok(true);
+    }
+    let Some(tree) = tree else {
+        return Ok(false);
+    };
+    if a.prefix.is_some() || b.prefix.is_some() {
+        return Ok(false);
+    }
+
+    let options = FilterOptions {
+        syntax: PatternSyntax::GitAttributes,
+        ..Default::default()
+    };
+    let matched = repo.filter_with_options(tree, &[&a.root_pattern()], &options)?;
+    let both = repo.filter_with_options(&matched, &[&b.root_pattern()], &options)?;
+    Ok(!both.is_empty())
+}
+
+/// Load the vendor dependencies visible to `repo`.
 ///
-/// Returns the path of the first `.gitattributes` file found, or defaults to
-/// `<current_dir>/.gitattributes` (which will be created on first write).
-fn find_gitattributes(repo: &Repository) -> Result<PathBuf, Error> {
+/// A `.gitvendor.toml` manifest at the repository root takes precedence.
+/// Otherwise the dependencies of every `.gitattributes` file in the working
+/// tree are collected, root first. Bare repositories have no working tree, so
+/// the files committed in `HEAD` are used instead.
+fn load_deps(repo: &Repository) -> Result<Vec<VendorDep>, Error> {
+    if !repo.is_bare() {
+        if let Some(path) = find_manifest(repo)? {
+            let content = fs::read_to_string(&path)
+                .map_err(|e| Error::from_str(&format!("Failed to read {}: {e}", path.display())))?;
+            return manifest::parse(&content);
+        }
+
+        let mut deps = Vec::new();
+        for (dir, path) in find_all_gitattributes(repo)? {
+            for mut dep in parse_vendor_deps(&path)? {
+                dep.source_dir = dir.clone();
+                deps.push(dep);
+            }
+        }
+        return Ok(deps);
+    }
+
+    if let Some(content) = read_head_file(repo, manifest::MANIFEST_FILE)? {
+        return manifest::parse(&content);
+    }
+
+    let head_tree = match repo.head() {
+        Ok(head) => head.peel_to_tree()?,
+        Err(e) if e.code() == ErrorCode::UnbornBranch || e.code() == ErrorCode::NotFound => {
+            return Ok(Vec::new());
+        }
+        Err(e) => return Err(e),
+    };
+
+    let mut files = Vec::new();
+    head_tree.walk(git2::TreeWalkMode::PreOrder, |dir, entry| {
+        if entry.name() == Some(".gitattributes") && entry.kind() == Some(git2::ObjectType::Blob) {
+            files.push((dir.trim_end_matches('/').to_string(), entry.id()));
+        }
+        git2::TreeWalkResult::Ok
+    })?;
+    files.sort();
+
+    let mut deps = Vec::new();
+    for (dir, id) in files {
+        let blob = repo.find_blob(id)?;
+        let content = std::str::from_utf8(blob.content())
+            .map_err(|e| Error::from_str(&format!("Failed to read .gitattributes: {e}")))?;
+        for mut dep in parse_vendor_deps_str(content) {
+            dep.source_dir = dir.clone();
+            deps.push(dep);
+        }
+    }
+    Ok(deps)
+}
+
+/// Find every `.gitattributes` file in the working tree, skipping ignored
+/// directories. Returns each file's directory relative to the repository root
+/// along with its path, root first.
+fn find_all_gitattributes(repo: &Repository) -> Result<Vec<(String, PathBuf)>, Error> {
     let workdir = repo
         .workdir()
         .ok_or_else(|| Error::from_str("Repository has no working directory"))?;
 
-    let current_dir = std::env::current_dir()
-        .map_err(|e| Error::from_str(&format!("Failed to get current directory: {e}")))?;
-
-    let mut dir = current_dir.as_path();
-    while dir.starts_with(workdir) {
-        let candidate = dir.join(".gitattributes");
-        if candidate.exists() {
-            return Ok(candidate);
+    let mut files = Vec::new();
+    let mut pending = vec![String::new()];
+    while let Some(dir) = pending.pop() {
+        let path = workdir.join(&dir);
+        let candidate = path.join(".gitattributes");
+        if candidate.is_file() {
+            files.push((dir.clone(), candidate));
         }
-        match dir.parent() {
-            Some(parent) => dir = parent,
-            None => break,
+
+        let entries = fs::read_dir(&path)
+            .map_err(|e| Error::from_str(&format!("Failed to read {}: {e}", path.display())))?;
+        for entry in entries {
+            let entry = entry
+                .map_err(|e| Error::from_str(&format!("Failed to read {}: {e}", path.display())))?;
+            let Ok(name) = entry.file_name().into_string() else {
+                continue;
+            };
+            if name == ".git" || !entry.file_type().is_ok_and(|t| t.is_dir()) {
+                continue;
+            }
+            let rel = if dir.is_empty() {
+                name
+            } else {
+                format!("{dir}/{name}")
+            };
+            if repo.is_path_ignored(&rel)? {
+                continue;
+            }
+            pending.push(rel);
         }
     }
 
-    Ok(current_dir.join(".gitattributes"))
+    files.sort();
+    Ok(files)
+}
+
+/// Return the path of the `.gitvendor.toml` manifest at the root of the
+/// working tree, if there is one.
+fn find_manifest(repo: &Repository) -> Result<Option<PathBuf>, Error> {
+    let workdir = repo
+        .workdir()
+        .ok_or_else(|| Error::from_str("Repository has no working directory"))?;
+    let path = workdir.join(manifest::MANIFEST_FILE);
+    Ok(path.exists().then_some(path))
+}
+
+/// Read the file `name` committed at the root of `HEAD`, if any.
+fn read_head_file(repo: &Repository, name: &str) -> Result<Option<String>, Error> {
+    let head_tree = match repo.head() {
+        Ok(head) => head.peel_to_tree()?,
+        Err(e) if e.code() == ErrorCode::UnbornBranch || e.code() == ErrorCode::NotFound => {
+            return Ok(None);
+        }
+        Err(e) => return Err(e),
+    };
+
+    let entry = match head_tree.get_name(name) {
+        Some(entry) => entry,
+        None => return Ok(None),
+    };
+    let blob = entry.to_object(repo)?.peel_to_blob()?;
+    let content = std::str::from_utf8(blob.content())
+        .map_err(|e| Error::from_str(&format!("Failed to read {name}: {e}")))?;
+
+    Ok(Some(content.to_string()))
+}
+
+/// Write `deps` to the manifest at `path`.
+fn write_manifest(path: &Path, deps: &[VendorDep]) -> Result<(), Error> {
+    let content = manifest::render(deps)?;
+    LockFile::acquire(path)?.commit(&content)
 }
 
 /// Parse vendor dependencies from a `.gitattributes` file.
 ///
-/// A line is recognized as a vendor dependency when it carries at least
-/// `vendor-name=` and `vendor-url=`. The `vendor-branch=` attribute is
-/// optional — when absent, the dependency tracks the remote's default branch.
+/// A missing file yields no dependencies. See [`parse_vendor_deps_str`].
 fn parse_vendor_deps(path: &Path) -> Result<Vec<VendorDep>, Error> {
     if !path.exists() {
         return Ok(Vec::new());
@@ -492,29 +4692,51 @@ fn parse_vendor_deps(path: &Path) -> Result<Vec<VendorDep>, Error> {
     let file = fs::File::open(path)
         .map_err(|e| Error::from_str(&format!("Failed to open {}: {e}", path.display())))?;
 
-    let mut deps = Vec::new();
-
+    let mut content = String::new();
     for line in BufReader::new(file).lines() {
         let line =
             line.map_err(|e| Error::from_str(&format!("Failed to read .gitattributes: {e}")))?;
-        let trimmed = line.trim();
+        content.push_str(&line);
+        content.push('\n');
+    }
+
+    Ok(parse_vendor_deps_str(&content))
+}
+
+/// Parse vendor dependencies from `.gitattributes` content.
+///
+/// A line is recognized as a vendor dependency when it carries at least
+/// `vendor-name=` and `vendor-url=`. The `vendor-branch=` attribute is
+/// optional — when absent, the dependency tracks the remote's default branch.
+fn parse_vendor_deps_str(content: &str) -> Vec<VendorDep> {
+    let mut deps = Vec::new();
+    let mut note = None;
 
-        if trimmed.is_empty() || trimmed.starts_with('#') {
+    for line in content.lines() {
+        let previous_note = note.take();
+        if let Some(text) = line.trim().strip_prefix(NOTE_PREFIX) {
+            note = Some(text.trim().to_string());
             continue;
         }
-
-        let mut parts = trimmed.split_whitespace();
-        let pattern = match parts.next() {
-            Some(p) => p,
-            None => continue,
+        let Some((pattern, attrs)) = git_set_attr::split_line(line) else {
+            continue;
         };
 
         let mut name = None;
         let mut url = None;
         let mut branch = None;
+        let mut verify = None;
+        let mut src = None;
+        let mut version = None;
+        let mut upstream = None;
+        let mut mirrors = Vec::new();
+        let mut headers = Vec::new();
+        let mut keep_dirs = Vec::new();
+        let mut groups = Vec::new();
+        let mut no_default_excludes = false;
         let mut is_vendored = false;
 
-        for attr in parts {
+        for attr in attrs.split_whitespace() {
             if attr == "vendored" {
                 is_vendored = true;
             } else if let Some(v) = attr.strip_prefix("vendor-name=") {
@@ -523,6 +4745,24 @@ fn parse_vendor_deps(path: &Path) -> Result<Vec<VendorDep>, Error> {
                 url = Some(v.to_string());
             } else if let Some(v) = attr.strip_prefix("vendor-branch=") {
                 branch = Some(v.to_string());
+            } else if let Some(v) = attr.strip_prefix("vendor-verify=") {
+                verify = Some(v.to_string());
+            } else if let Some(v) = attr.strip_prefix("vendor-src=") {
+                src = Some(v.to_string());
+            } else if let Some(v) = attr.strip_prefix("vendor-version=") {
+                version = Some(v.to_string());
+            } else if let Some(v) = attr.strip_prefix("vendor-mirror=") {
+                mirrors.push(v.to_string());
+            } else if let Some(v) = attr.strip_prefix("vendor-upstream=") {
+                upstream = Some(v.to_string());
+            } else if let Some(v) = attr.strip_prefix("vendor-header=") {
+                headers.push(v.to_string());
+            } else if let Some(v) = attr.strip_prefix("vendor-keep-dir=") {
+                keep_dirs.push(v.to_string());
+            } else if let Some(v) = attr.strip_prefix("vendor-group=") {
+                groups.push(v.to_string());
+            } else if attr == "-vendor-default-excludes" {
+                no_default_excludes = true;
             }
         }
 
@@ -533,82 +4773,189 @@ fn parse_vendor_deps(path: &Path) -> Result<Vec<VendorDep>, Error> {
         if let (Some(name), Some(url)) = (name, url) {
             deps.push(VendorDep {
                 name,
-                pattern: pattern.to_string(),
+                pattern,
                 url,
                 branch,
+                version,
+                mirrors,
+                upstream,
+                headers,
+                keep_dirs,
+                groups,
+                no_default_excludes,
+                verify,
+                src,
+                note: previous_note,
+                ..Default::default()
             });
         }
     }
 
-    Ok(deps)
+    deps
 }
 
-/// Remove all lines from a `.gitattributes` file that match `pattern` **and**
-/// carry vendor attributes.  Non-vendor lines for the same pattern are kept.
-fn remove_vendor_lines(path: &Path, pattern: &str) -> Result<(), Error> {
+/// The comment line recording [`VendorDep::note`] above a vendor line. Git
+/// reads anything after the pattern as attributes, so the note cannot trail
+/// the line itself.
+const NOTE_PREFIX: &str = "# vendor-note:";
+
+/// Set or, when `note` is `None`, remove the note comment above the vendor
+/// line for `pattern` in the `.gitattributes` file at `path`.
+fn set_note(path: &Path, pattern: &str, note: Option<&str>) -> Result<(), Error> {
     if !path.exists() {
         return Ok(());
     }
 
+    let lock = LockFile::acquire(path)?;
     let content = fs::read_to_string(path)
         .map_err(|e| Error::from_str(&format!("Failed to read {}: {e}", path.display())))?;
+    let style = LineStyle::detect(&content);
+    let mut lines: Vec<String> = content.lines().map(str::to_string).collect();
+    let Some(mut index) = lines
+        .iter()
+        .position(|line| is_vendor_line_for_pattern(line, pattern))
+    else {
+        return Ok(());
+    };
 
-    let mut kept = Vec::new();
-    for line in content.lines() {
-        if is_vendor_line_for_pattern(line, pattern) {
-            // FIXME: what if other non-vendor-related attributes are on this line?
-            continue;
-        }
-        kept.push(line);
+    if index > 0 && lines[index - 1].trim().starts_with(NOTE_PREFIX) {
+        lines.remove(index - 1);
+        index -= 1;
+    }
+    if let Some(note) = note {
+        let note = note.split_whitespace().collect::<Vec<_>>().join(" ");
+        lines.insert(index, format!("{NOTE_PREFIX} {note}"));
     }
 
-    let mut file = fs::File::create(path)
-        .map_err(|e| Error::from_str(&format!("Failed to write {}: {e}", path.display())))?;
+    lock.commit(&style.join(&lines))
+}
 
-    for line in &kept {
-        writeln!(file, "{line}")
-            .map_err(|e| Error::from_str(&format!("Failed to write .gitattributes: {e}")))?;
+/// Strip the `vendored` and `vendor-*` attributes from the lines of a
+/// `.gitattributes` file that match `pattern` **and** carry vendor attributes.
+/// Other attributes stay in place; lines left without any are removed.
+fn remove_vendor_lines(repo: &Repository, path: &Path, pattern: &str) -> Result<(), Error> {
+    if !path.exists() {
+        return Ok(());
     }
 
-    file.flush()
-        .map_err(|e| Error::from_str(&format!("Failed to flush .gitattributes: {e}")))?;
+    set_note(path, pattern, None)?;
+    let content = fs::read_to_string(path)
+        .map_err(|e| Error::from_str(&format!("Failed to read {}: {e}", path.display())))?;
 
-    Ok(())
+    let mut names = Vec::new();
+    for line in content.lines() {
+        if !is_vendor_line_for_pattern(line, pattern) {
+            continue;
+        }
+        let attrs = git_set_attr::split_line(line).map(|(_, attrs)| attrs);
+        for attr in attrs.unwrap_or_default().split_whitespace() {
+            let name = attr.split_once('=').map_or(attr, |(name, _)| name);
+            if (name == "vendored" || name.starts_with("vendor-")) && !names.contains(&name) {
+                names.push(name);
+            }
+        }
+    }
+
+    repo.unset_attr(pattern, &names, Some(path))
 }
 
 /// Return `true` if `line` starts with `pattern` and contains at least one
-/// vendor attribute (`vendored`, `vendor-name=`, `vendor-url=`, or
-/// `vendor-branch=`).
+/// vendor attribute (`vendored` or any `vendor-*` attribute).
 fn is_vendor_line_for_pattern(line: &str, pattern: &str) -> bool {
-    let trimmed = line.trim();
-    if trimmed.is_empty() || trimmed.starts_with('#') {
+    let Some((line_pattern, attrs)) = git_set_attr::split_line(line) else {
         return false;
-    }
-
-    let mut parts = trimmed.split_whitespace();
-    let line_pattern = match parts.next() {
-        Some(p) => p,
-        None => return false,
     };
 
     if line_pattern != pattern {
         return false;
     }
 
-    parts.any(|attr| {
-        attr == "vendored"
-            || attr.starts_with("vendor-name=")
-            || attr.starts_with("vendor-url=")
-            || attr.starts_with("vendor-branch=")
-    })
+    attrs
+        .split_whitespace()
+        .any(|attr| attr == "vendored" || attr.starts_with("vendor-"))
+}
+
+/// Report the lines of the `.gitattributes` file at `path` that carry vendor
+/// attributes without `vendored`, adding the flag when `fix` is set.
+fn check_vendored_flags(path: &Path, fix: bool) -> Result<Vec<VendorFinding>, Error> {
+    let lock = LockFile::acquire(path)?;
+    let content = fs::read_to_string(path)
+        .map_err(|e| Error::from_str(&format!("Failed to read {}: {e}", path.display())))?;
+
+    let mut findings = Vec::new();
+    let mut lines: Vec<String> = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        let (pattern, attrs) = match git_set_attr::split_line(line) {
+            Some((pattern, attrs)) => (Some(pattern), attrs.split_whitespace().collect()),
+            None => (None, Vec::new()),
+        };
+        match pattern {
+            Some(pattern)
+                if !attrs.contains(&"vendored")
+                    && attrs.iter().any(|a| a.starts_with("vendor-")) =>
+            {
+                findings.push(VendorFinding {
+                    kind: VendorFindingKind::MissingVendoredFlag,
+                    message: format!(
+                        "{}:{}: {pattern} has vendor attributes but is not marked vendored",
+                        path.display(),
+                        index + 1
+                    ),
+                    fixed: fix,
+                });
+                lines.push(format!(
+                    "{} vendored {}",
+                    git_set_attr::quote_pattern(&pattern),
+                    attrs.join(" ")
+                ));
+            }
+            _ => lines.push(line.to_string()),
+        }
+    }
+
+    if fix && !findings.is_empty() {
+        lock.commit(&LineStyle::detect(&content).join(&lines))?;
+    }
+    Ok(findings)
 }
 
-/// Filter dependencies by exact pattern match.
+/// Filter dependencies by exact pattern match, by scope when the filter is
+/// a directory ending in `/` (see [`VendorDep::in_scope`]), or by group when
+/// it is `@<group>`.
 fn filter_deps<'a>(deps: &'a [VendorDep], filter: Option<&str>) -> Vec<&'a VendorDep> {
     match filter {
         None => deps.iter().collect(),
-        Some(f) => deps.iter().filter(|d| d.pattern == f).collect(),
+        Some(scope) if scope.ends_with('/') => deps.iter().filter(|d| d.in_scope(scope)).collect(),
+        Some(f) => match f.strip_prefix('@') {
+            Some(group) if !deps.iter().any(|d| d.pattern == f) => deps
+                .iter()
+                .filter(|d| d.groups.iter().any(|g| g == group))
+                .collect(),
+            _ => deps.iter().filter(|d| d.pattern == f).collect(),
+        },
+    }
+}
+
+/// Like [`filter_deps`], but also select the other patterns declared under
+/// the name of each selected dependency, since a name is merged as a whole.
+fn filter_dep_names<'a>(deps: &'a [VendorDep], filter: Option<&str>) -> Vec<&'a VendorDep> {
+    let selected = filter_deps(deps, filter);
+    deps.iter()
+        .filter(|d| selected.iter().any(|s| s.name == d.name))
+        .collect()
+}
+
+/// Group dependencies by name, in order of first appearance. Patterns sharing
+/// a name map different areas of one upstream, fetched into one vendor ref.
+fn group_by_name<'a>(deps: &[&'a VendorDep]) -> Vec<Vec<&'a VendorDep>> {
+    let mut groups: Vec<Vec<&VendorDep>> = Vec::new();
+    for &dep in deps {
+        match groups.iter_mut().find(|group| group[0].name == dep.name) {
+            Some(group) => group.push(dep),
+            None => groups.push(vec![dep]),
+        }
     }
+    groups
 }
 
 // ---------------------------------------------------------------------------
@@ -705,12 +5052,85 @@ mod tests {
 
     #[test]
     fn name_from_url_local_path() {
-        assert_eq!(name_from_url("/home/user/repos/mylib"), None);
+        assert_eq!(
+            name_from_url("/home/user/repos/mylib"),
+            Some("mylib".into())
+        );
     }
 
     #[test]
     fn name_from_url_relative_path() {
-        assert_eq!(name_from_url("../repos/mylib"), None);
+        assert_eq!(name_from_url("../repos/mylib.git/"), Some("mylib".into()));
+    }
+
+    #[test]
+    fn name_from_url_file_url() {
+        assert_eq!(
+            name_from_url("file:///srv/git/mylib.git"),
+            Some("mylib".into())
+        );
+    }
+
+    #[test]
+    fn name_from_url_git_dir_uses_parent() {
+        assert_eq!(name_from_url("../mylib/.git"), Some("mylib".into()));
+    }
+
+    #[test]
+    fn name_from_url_path_without_name() {
+        assert_eq!(name_from_url("/"), None);
+        assert_eq!(name_from_url(".."), None);
+    }
+
+    // -- url_host -----------------------------------------------------------
+
+    #[test]
+    fn url_host_forms() {
+        assert_eq!(url_host("https://github.com/o/r.git"), Some("github.com"));
+        assert_eq!(url_host("ssh://git@host:2222/o/r"), Some("host"));
+        assert_eq!(url_host("git@github.com:o/r.git"), Some("github.com"));
+        assert_eq!(url_host("https://[::1]:8080/r"), Some("[::1]"));
+    }
+
+    #[test]
+    fn url_host_local() {
+        assert_eq!(url_host("/srv/git/r"), None);
+        assert_eq!(url_host("file:///srv/git/r"), None);
+        assert_eq!(url_host("origin"), None);
+    }
+
+    // -- is_transient -------------------------------------------------------
+
+    #[test]
+    fn is_transient_only_for_failures_worth_retrying() {
+        use git2::ErrorClass::*;
+        let error = |class, message| Error::new(ErrorCode::GenericError, class, message);
+        assert!(is_transient(&error(
+            Http,
+            "unexpected http status code: 503"
+        )));
+        assert!(is_transient(&error(
+            Http,
+            "unexpected http status code: 429"
+        )));
+        assert!(is_transient(&error(
+            Net,
+            "could not read from socket: timed out"
+        )));
+        assert!(is_transient(&error(Os, "Connection reset by peer")));
+        assert!(!is_transient(&error(
+            Http,
+            "unexpected http status code: 404"
+        )));
+        assert!(!is_transient(&error(
+            Os,
+            "failed to connect: Connection refused"
+        )));
+        assert!(!is_transient(&error(
+            Ssh,
+            "invalid or unknown remote ssh hostkey"
+        )));
+        assert!(!is_transient(&error(Reference, "reference not found")));
     }
 
     // -- resolve_name -------------------------------------------------------
@@ -732,8 +5152,8 @@ mod tests {
     }
 
     #[test]
-    fn resolve_name_local_path_requires_explicit() {
-        assert!(resolve_name("/local/path", None).is_err());
+    fn resolve_name_local_root_requires_explicit() {
+        assert!(resolve_name("/", None).is_err());
     }
 
     #[test]
@@ -741,6 +5161,28 @@ mod tests {
         assert!(resolve_name("https://github.com/a/b.git", Some("")).is_err());
     }
 
+    // -- format_date --------------------------------------------------------
+
+    #[test]
+    fn format_date_epoch() {
+        assert_eq!(format_date(git2::Time::new(0, 0)), "1970-01-01");
+    }
+
+    #[test]
+    fn format_date_applies_offset() {
+        // 2024-02-29T23:30:00Z is already March 1st at UTC+01:00.
+        assert_eq!(format_date(git2::Time::new(1_709_249_400, 0)), "2024-02-29");
+        assert_eq!(
+            format_date(git2::Time::new(1_709_249_400, 60)),
+            "2024-03-01"
+        );
+    }
+
+    #[test]
+    fn format_date_before_epoch() {
+        assert_eq!(format_date(git2::Time::new(-86_400, 0)), "1969-12-31");
+    }
+
     // -- vendor_ref_name ----------------------------------------------------
 
     #[test]
@@ -832,6 +5274,116 @@ mod tests {
         assert!(parse_vendor_deps(&path).unwrap().is_empty());
     }
 
+    #[test]
+    fn parse_vendor_deps_reads_verify() {
+        let deps = parse_vendor_deps_str(
+            "*.txt vendored vendor-name=o/r vendor-url=https://a.com/o/r.git vendor-verify=.allowed\n",
+        );
+        assert_eq!(deps.len(), 1);
+        assert_eq!(deps[0].verify.as_deref(), Some(".allowed"));
+    }
+
+    #[test]
+    fn parse_vendor_deps_reads_note_above_line() {
+        let deps = parse_vendor_deps_str(
+            "# vendor-note: pinned\n\
+             *.txt vendored vendor-name=o/r vendor-url=https://a.com/o/r.git\n\
+             # vendor-note: stale\n\
+             # unrelated\n\
+             *.rs vendored vendor-name=o/s vendor-url=https://a.com/o/s.git\n",
+        );
+        assert_eq!(deps[0].note.as_deref(), Some("pinned"));
+        assert_eq!(deps[1].note, None);
+    }
+
+    #[test]
+    fn parse_vendor_deps_reads_version() {
+        let deps = parse_vendor_deps_str(
+            "lib/** vendored vendor-name=o/r vendor-url=https://a.com/o/r.git vendor-version=^1.2\n",
+        );
+        assert_eq!(deps[0].version.as_deref(), Some("^1.2"));
+    }
+
+    #[test]
+    fn parse_vendor_deps_reads_src() {
+        let deps = parse_vendor_deps_str(
+            "*.h vendored vendor-name=o/r vendor-url=https://a.com/o/r.git vendor-src=src/include\n",
+        );
+        assert_eq!(deps[0].src.as_deref(), Some("src/include"));
+    }
+
+    #[test]
+    fn parse_vendor_deps_reads_headers() {
+        let deps = parse_vendor_deps_str(
+            "*.h vendored vendor-name=o/r vendor-url=https://a.com/o/r.git vendor-header=X-A:1 vendor-header=X-B:2\n",
+        );
+        assert_eq!(deps[0].headers, ["X-A:1", "X-B:2"]);
+    }
+
+    #[test]
+    fn fetch_headers_follow_http_extra_header() {
+        let dir = TempDir::new().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let mut config = fs::OpenOptions::new()
+            .append(true)
+            .open(repo.path().join("config"))
+            .unwrap();
+        write!(
+            config,
+            "[http]\n\textraHeader = X-Dropped: 1\n\textraHeader =\n\textraHeader = Authorization: Bearer t\n"
+        )
+        .unwrap();
+        write!(
+            config,
+            "[http \"https://a.com/o\"]\n\textraHeader = X-Scoped: 4\n"
+        )
+        .unwrap();
+        let dep = VendorDep {
+            url: "https://a.com/o/r.git".into(),
+            headers: vec!["X-Dep:2".into()],
+            ..Default::default()
+        };
+        let headers = fetch_headers(&repo, &dep.url, Some(&dep), &["X-Cli: 3".into()]).unwrap();
+        assert_eq!(
+            headers,
+            [
+                "Authorization: Bearer t",
+                "X-Scoped: 4",
+                "X-Dep:2",
+                "X-Cli: 3"
+            ]
+        );
+        // Neither the scoped headers nor the dependency's go to other hosts.
+        let headers = fetch_headers(&repo, "https://b.com/o/r.git", Some(&dep), &[]).unwrap();
+        assert_eq!(headers, ["Authorization: Bearer t"]);
+        let headers = fetch_headers(&repo, "https://a.com/other.git", None, &[]).unwrap();
+        assert_eq!(headers, ["Authorization: Bearer t"]);
+        assert!(url_has_prefix("https://a.com/o/r.git", "https://a.com/o/"));
+        assert!(!url_has_prefix("https://a.com/org", "https://a.com/o"));
+        assert_eq!(attr_header("X-Cli: 3"), "X-Cli:3");
+    }
+
+    #[test]
+    fn parse_vendor_deps_reads_groups() {
+        let deps = parse_vendor_deps_str(
+            "*.h vendored vendor-name=o/r vendor-url=https://a.com/o/r.git vendor-group=runtime vendor-group=ci\n",
+        );
+        assert_eq!(deps[0].groups, ["runtime", "ci"]);
+    }
+
+    #[test]
+    fn parse_vendor_deps_unquotes_patterns() {
+        let deps = parse_vendor_deps_str(
+            "\"my dir/*.txt\" vendored vendor-name=o/r vendor-url=https://a.com/o/r.git\n",
+        );
+        assert_eq!(deps.len(), 1);
+        assert_eq!(deps[0].pattern, "my dir/*.txt");
+        assert!(is_vendor_line_for_pattern(
+            "\"my dir/*.txt\" vendored vendor-name=o/r",
+            "my dir/*.txt"
+        ));
+    }
+
     #[test]
     fn parse_vendor_deps_branch_is_optional() {
         let dir = TempDir::new().unwrap();
@@ -863,6 +5415,14 @@ mod tests {
         assert!(is_vendor_line_for_pattern("*.txt vendored", "*.txt"));
     }
 
+    #[test]
+    fn is_vendor_line_matches_any_vendor_attribute() {
+        assert!(is_vendor_line_for_pattern(
+            "*.txt vendor-verify=.allowed",
+            "*.txt"
+        ));
+    }
+
     #[test]
     fn is_vendor_line_ignores_other_patterns() {
         assert!(!is_vendor_line_for_pattern(
@@ -890,26 +5450,70 @@ mod tests {
         let dir = TempDir::new().unwrap();
         let path = dir.path().join(".gitattributes");
 
+        let repo = Repository::init(dir.path()).unwrap();
+
         let original = "\
 *.txt vendored vendor-name=o/r vendor-url=https://a.com vendor-branch=main
 *.txt diff
 *.rs vendored vendor-name=x/y vendor-url=https://b.com vendor-branch=dev
+*.md vendored vendor-name=o/r vendor-url=https://a.com -text
 # comment
 ";
         fs::write(&path, original).unwrap();
 
-        remove_vendor_lines(&path, "*.txt").unwrap();
+        remove_vendor_lines(&repo, &path, "*.txt").unwrap();
+        remove_vendor_lines(&repo, &path, "*.md").unwrap();
 
         let content = fs::read_to_string(&path).unwrap();
         assert!(!content.contains("vendor-url=https://a.com"));
         assert!(content.contains("*.txt diff"));
+        assert!(content.contains("*.md -text\n"));
         assert!(content.contains("*.rs vendored vendor-name=x/y"));
         assert!(content.contains("# comment"));
     }
 
+    #[test]
+    fn remove_vendor_lines_preserves_line_endings() {
+        let dir = TempDir::new().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let path = dir.path().join(".gitattributes");
+
+        fs::write(
+            &path,
+            "# vendor-note: pinned\r\n*.txt vendored vendor-name=o/r vendor-url=https://a.com\r\n*.md -text\r\n*.rs diff",
+        )
+        .unwrap();
+        remove_vendor_lines(&repo, &path, "*.txt").unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "*.md -text\r\n*.rs diff"
+        );
+    }
+
     #[test]
     fn remove_vendor_lines_noop_for_missing_file() {
-        assert!(remove_vendor_lines(Path::new("/nonexistent/.gitattributes"), "*.txt").is_ok());
+        let dir = TempDir::new().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let path = dir.path().join(".gitattributes");
+        assert!(remove_vendor_lines(&repo, &path, "*.txt").is_ok());
+        assert!(!path.exists());
+    }
+
+    // -- root_pattern -------------------------------------------------------
+
+    #[test]
+    fn root_pattern_is_relative_to_source_dir() {
+        let dep = |pattern: &str, source_dir: &str| VendorDep {
+            pattern: pattern.into(),
+            source_dir: source_dir.into(),
+            ..Default::default()
+        };
+        assert_eq!(dep("*.txt", "").root_pattern(), "*.txt");
+        assert_eq!(dep("*.txt", "sub").root_pattern(), "sub/**/*.txt");
+        assert_eq!(dep("lib/", "sub").root_pattern(), "sub/**/lib/");
+        assert_eq!(dep("/lib/*.rs", "a/b").root_pattern(), "a/b/lib/*.rs");
+        assert_eq!(dep("**/x", "sub").root_pattern(), "sub/**/x");
     }
 
     // -- filter_deps --------------------------------------------------------
@@ -922,12 +5526,14 @@ mod tests {
                 pattern: "a".into(),
                 url: "u".into(),
                 branch: Some("b".into()),
+                ..Default::default()
             },
             VendorDep {
                 name: "c/d".into(),
                 pattern: "b".into(),
                 url: "u".into(),
                 branch: None,
+                ..Default::default()
             },
         ];
         assert_eq!(filter_deps(&deps, None).len(), 2);
@@ -941,12 +5547,14 @@ mod tests {
                 pattern: "*.txt".into(),
                 url: "u".into(),
                 branch: Some("b".into()),
+                ..Default::default()
             },
             VendorDep {
                 name: "c/d".into(),
                 pattern: "*.rs".into(),
                 url: "u".into(),
                 branch: None,
+                ..Default::default()
             },
         ];
         let filtered = filter_deps(&deps, Some("*.txt"));
@@ -961,7 +5569,77 @@ mod tests {
             pattern: "*.txt".into(),
             url: "u".into(),
             branch: Some("b".into()),
+            ..Default::default()
         }];
         assert!(filter_deps(&deps, Some("*.rs")).is_empty());
     }
+
+    #[test]
+    fn filter_deps_by_scope() {
+        let dep = |name: &str, source_dir: &str| VendorDep {
+            name: name.into(),
+            pattern: "*.txt".into(),
+            source_dir: source_dir.into(),
+            ..Default::default()
+        };
+        let deps = vec![
+            dep("root", ""),
+            dep("foo", "services/foo"),
+            dep("foo-lib", "services/foo/lib"),
+            dep("foobar", "services/foobar"),
+        ];
+        let names = |scope| -> Vec<String> {
+            filter_deps(&deps, Some(scope))
+                .iter()
+                .map(|d| d.name.clone())
+                .collect()
+        };
+        assert_eq!(names("services/foo/"), vec!["foo", "foo-lib"]);
+        assert_eq!(names("services/"), vec!["foo", "foo-lib", "foobar"]);
+        assert_eq!(names("/"), vec!["root", "foo", "foo-lib", "foobar"]);
+    }
+
+    #[test]
+    fn filter_deps_by_group() {
+        let dep = |name: &str, pattern: &str, groups: &[&str]| VendorDep {
+            name: name.into(),
+            pattern: pattern.into(),
+            groups: groups.iter().map(|g| g.to_string()).collect(),
+            ..Default::default()
+        };
+        let deps = vec![
+            dep("a", "*.h", &["runtime"]),
+            dep("b", "*.txt", &["dev-tools"]),
+            dep("c", "*.c", &["runtime", "dev-tools"]),
+            dep("d", "@runtime", &[]),
+        ];
+        let names = |filter| -> Vec<String> {
+            filter_deps(&deps, Some(filter))
+                .iter()
+                .map(|d| d.name.clone())
+                .collect()
+        };
+        assert_eq!(names("@dev-tools"), vec!["b", "c"]);
+        assert!(names("@none").is_empty());
+        // A pattern spelled like a group selector wins.
+        assert_eq!(names("@runtime"), vec!["d"]);
+    }
+
+    #[test]
+    fn filter_dep_names_selects_and_groups_shared_names() {
+        let dep = |name: &str, pattern: &str| VendorDep {
+            name: name.into(),
+            pattern: pattern.into(),
+            ..Default::default()
+        };
+        let deps = vec![dep("a", "*.h"), dep("b", "*.txt"), dep("a", "*.c")];
+        let selected = filter_dep_names(&deps, Some("*.c"));
+        let patterns: Vec<&str> = selected.iter().map(|d| d.pattern.as_str()).collect();
+        assert_eq!(patterns, vec!["*.h", "*.c"]);
+
+        let groups = group_by_name(&filter_deps(&deps, None));
+        let sizes: Vec<usize> = groups.iter().map(Vec::len).collect();
+        assert_eq!(sizes, vec![2, 1]);
+        assert_eq!(groups[0][1].pattern, "*.c");
+    }
 }
@@ -0,0 +1,208 @@
+//! License detection for vendored content.
+//!
+//! Licenses are detected from `LICENSE`, `LICENCE` and `COPYING` files, by
+//! their `SPDX-License-Identifier` line or by recognizing well-known license
+//! texts, and from `SPDX-License-Identifier` headers near the top of any other
+//! file. Unrecognized license files are reported as `NOASSERTION`.
+
+use git2::{Error, ObjectType, Repository, Tree, TreeWalkMode, TreeWalkResult};
+
+const SPDX_TAG: &str = "SPDX-License-Identifier:";
+
+/// How many leading bytes of a file are searched for an SPDX header.
+const HEADER_LEN: usize = 4096;
+
+/// Well-known license texts, as identifying phrases that must all appear.
+const KNOWN_TEXTS: &[(&str, &[&str])] = &[
+    ("Apache-2.0", &["Apache License", "Version 2.0"]),
+    ("MPL-2.0", &["Mozilla Public License", "2.0"]),
+    (
+        "AGPL-3.0",
+        &["GNU AFFERO GENERAL PUBLIC LICENSE", "Version 3"],
+    ),
+    (
+        "LGPL-3.0",
+        &["GNU LESSER GENERAL PUBLIC LICENSE", "Version 3"],
+    ),
+    (
+        "LGPL-2.1",
+        &["GNU LESSER GENERAL PUBLIC LICENSE", "Version 2.1"],
+    ),
+    ("GPL-3.0", &["GNU GENERAL PUBLIC LICENSE", "Version 3"]),
+    ("GPL-2.0", &["GNU GENERAL PUBLIC LICENSE", "Version 2"]),
+    ("Unlicense", &["This is free and unencumbered software"]),
+    (
+        "ISC",
+        &["Permission to use, copy, modify, and/or distribute"],
+    ),
+    ("MIT", &["Permission is hereby granted, free of charge"]),
+    (
+        "BSD-3-Clause",
+        &[
+            "Redistribution and use in source and binary forms",
+            "Neither the name",
+        ],
+    ),
+    (
+        "BSD-2-Clause",
+        &["Redistribution and use in source and binary forms"],
+    ),
+];
+
+/// Return the sorted, distinct licenses detected in `tree`.
+pub(crate) fn detect(repo: &Repository, tree: &Tree<'_>) -> Result<Vec<String>, Error> {
+    let mut licenses = Vec::new();
+    let mut error = None;
+
+    tree.walk(TreeWalkMode::PreOrder, |_, entry| {
+        if entry.kind() != Some(ObjectType::Blob) {
+            return TreeWalkResult::Ok;
+        }
+        let blob = match repo.find_blob(entry.id()) {
+            Ok(blob) => blob,
+            Err(e) => {
+                error = Some(e);
+                return TreeWalkResult::Abort;
+            }
+        };
+        if blob.is_binary() {
+            return TreeWalkResult::Ok;
+        }
+
+        let content = blob.content();
+        if is_license_file(entry.name().unwrap_or("")) {
+            let content = String::from_utf8_lossy(content);
+            licenses.push(match spdx_header(&content) {
+                Some(id) => id,
+                None => identify_text(&content).unwrap_or("NOASSERTION").to_string(),
+            });
+        } else {
+            let header = &content[..content.len().min(HEADER_LEN)];
+            licenses.extend(spdx_header(&String::from_utf8_lossy(header)));
+        }
+        TreeWalkResult::Ok
+    })?;
+
+    if let Some(e) = error {
+        return Err(e);
+    }
+    licenses.sort();
+    licenses.dedup();
+    Ok(licenses)
+}
+
+/// Return whether the SPDX license expression `expr` is satisfied by the
+/// `allowed` license identifiers, with `AND` binding tighter than `OR` and
+/// parentheses grouping as in SPDX. `WITH` exceptions follow their license.
+/// A malformed expression is not allowed.
+pub(crate) fn is_allowed(expr: &str, allowed: &[String]) -> bool {
+    let spaced = expr.replace('(', " ( ").replace(')', " ) ");
+    let mut tokens = spaced.split_whitespace().peekable();
+    parse_or(&mut tokens, allowed) == Some(true) && tokens.next().is_none()
+}
+
+type Tokens<'a> = std::iter::Peekable<std::str::SplitWhitespace<'a>>;
+
+/// Evaluate `<and> (OR <and>)*`, or return `None` if malformed.
+fn parse_or(tokens: &mut Tokens<'_>, allowed: &[String]) -> Option<bool> {
+    let mut result = parse_and(tokens, allowed)?;
+    while tokens.next_if_eq(&"OR").is_some() {
+        result |= parse_and(tokens, allowed)?;
+    }
+    Some(result)
+}
+
+/// Evaluate `<term> (AND <term>)*`, or return `None` if malformed.
+fn parse_and(tokens: &mut Tokens<'_>, allowed: &[String]) -> Option<bool> {
+    let mut result = parse_term(tokens, allowed)?;
+    while tokens.next_if_eq(&"AND").is_some() {
+        result &= parse_term(tokens, allowed)?;
+    }
+    Some(result)
+}
+
+/// Evaluate a parenthesized expression or a license identifier with an
+/// optional `WITH` exception, or return `None` if malformed.
+fn parse_term(tokens: &mut Tokens<'_>, allowed: &[String]) -> Option<bool> {
+    match tokens.next()? {
+        "(" => {
+            let result = parse_or(tokens, allowed)?;
+            tokens.next_if_eq(&")")?;
+            Some(result)
+        }
+        ")" | "OR" | "AND" | "WITH" => None,
+        id => {
+            if tokens.next_if_eq(&"WITH").is_some() {
+                tokens
+                    .next()
+                    .filter(|exception| !matches!(*exception, "(" | ")"))?;
+            }
+            Some(allowed.iter().any(|a| a.eq_ignore_ascii_case(id)))
+        }
+    }
+}
+
+fn is_license_file(name: &str) -> bool {
+    let name = name.to_ascii_uppercase();
+    ["LICENSE", "LICENCE", "COPYING"]
+        .iter()
+        .any(|prefix| name.starts_with(prefix))
+}
+
+/// Return the expression of the first `SPDX-License-Identifier` line.
+fn spdx_header(content: &str) -> Option<String> {
+    content.lines().find_map(|line| {
+        let (_, expr) = line.split_once(SPDX_TAG)?;
+        let expr = expr
+            .trim()
+            .trim_end_matches("*/")
+            .trim_end_matches("-->")
+            .trim();
+        (!expr.is_empty()).then(|| expr.to_string())
+    })
+}
+
+fn identify_text(content: &str) -> Option<&'static str> {
+    let content = content.split_whitespace().collect::<Vec<_>>().join(" ");
+    KNOWN_TEXTS
+        .iter()
+        .find(|(_, phrases)| phrases.iter().all(|p| content.contains(p)))
+        .map(|(id, _)| *id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identifies_common_license_texts() {
+        let mit = "Permission is hereby granted, free of charge, to any\nperson obtaining a copy";
+        assert_eq!(identify_text(mit), Some("MIT"));
+        let apache = "                                 Apache License\n                           Version 2.0, January 2004";
+        assert_eq!(identify_text(apache), Some("Apache-2.0"));
+        assert_eq!(identify_text("All rights reserved."), None);
+    }
+
+    #[test]
+    fn reads_spdx_headers() {
+        assert_eq!(
+            spdx_header("/* SPDX-License-Identifier: MIT OR Apache-2.0 */\nint x;").as_deref(),
+            Some("MIT OR Apache-2.0")
+        );
+        assert_eq!(spdx_header("no header here"), None);
+    }
+
+    #[test]
+    fn expressions_are_checked_against_the_allow_list() {
+        let allowed = vec!["MIT".to_string(), "apache-2.0".to_string()];
+        assert!(is_allowed("MIT", &allowed));
+        assert!(is_allowed("GPL-3.0 OR Apache-2.0", &allowed));
+        assert!(is_allowed("(MIT AND Apache-2.0)", &allowed));
+        assert!(is_allowed("Apache-2.0 WITH LLVM-exception", &allowed));
+        assert!(!is_allowed("(MIT OR Apache-2.0) AND GPL-3.0", &allowed));
+        assert!(is_allowed("GPL-3.0 AND MIT OR Apache-2.0", &allowed));
+        assert!(!is_allowed("(MIT OR Apache-2.0", &allowed));
+        assert!(!is_allowed("MIT AND GPL-2.0", &allowed));
+        assert!(!is_allowed("NOASSERTION", &allowed));
+    }
+}
@@ -1,3 +1,819 @@
+use clap::Parser;
+use git_vendor::cli::{Cli, Command};
+use git_vendor::{
+    ArchiveFormat, Vendor, VendorArchiveOpts, VendorDep, VendorExtractOpts, VendorFetchOpts,
+    VendorLogOpts, VendorMergeOpts, VendorStatus, VendorTrackOpts, short_id,
+};
+use git2 as git;
+use std::io::{self, BufRead, Read, Write};
+use std::process;
+
+/// Exit status of `status` and `outdated` when updates are available.
+const EXIT_UPDATES: i32 = 1;
+/// Exit status when a command fails.
+const EXIT_ERROR: i32 = 2;
+
 fn main() {
-    println!("Hello, world!");
+    match run() {
+        Ok(true) => process::exit(EXIT_UPDATES),
+        Ok(false) => {}
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(EXIT_ERROR);
+        }
+    }
+}
+
+/// Prints the library's progress messages as plain lines on stdout.
+struct StdoutLogger;
+
+impl log::Log for StdoutLogger {
+    fn enabled(&self, metadata: &log::Metadata<'_>) -> bool {
+        metadata.target().starts_with("git_vendor") && metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &log::Record<'_>) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        if record.level() <= log::Level::Warn {
+            eprintln!("{}", record.args());
+        } else {
+            println!("{}", record.args());
+        }
+    }
+
+    fn flush(&self) {
+        let _ = io::stdout().flush();
+        let _ = io::stderr().flush();
+    }
+}
+
+static LOGGER: StdoutLogger = StdoutLogger;
+
+/// Run the command, returning whether `status` or `outdated` found updates.
+fn run() -> Result<bool, Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
+
+    log::set_logger(&LOGGER).map_err(|e| e.to_string())?;
+    log::set_max_level(match (cli.quiet, cli.verbose) {
+        (true, _) => log::LevelFilter::Warn,
+        (false, 0) => log::LevelFilter::Info,
+        (false, 1) => log::LevelFilter::Debug,
+        (false, _) => log::LevelFilter::Trace,
+    });
+
+    // Discover the repository from the current directory
+    let repo = git::Repository::discover(".")?;
+
+    // Without a pattern, commands select the dependencies declared in the
+    // current directory or below.
+    let scope = if cli.all { None } else { current_scope(&repo)? };
+
+    // With --stdin, commands run once per selector read from standard input.
+    let from_stdin = if cli.stdin {
+        Some(read_selectors(&repo, cli.nul)?)
+    } else {
+        None
+    };
+    let group = cli.group.map(|group| format!("@{group}"));
+    let targets = |pattern: Option<String>| -> Result<Vec<Option<String>>, String> {
+        match (&from_stdin, &group, pattern) {
+            (Some(_), Some(_), _) => Err("--stdin cannot be combined with --group".to_string()),
+            (Some(_), _, Some(_)) => Err("--stdin cannot be combined with a pattern".to_string()),
+            (_, Some(_), Some(_)) => Err("--group cannot be combined with a pattern".to_string()),
+            (Some(selectors), None, None) => Ok(selectors.iter().cloned().map(Some).collect()),
+            (None, Some(group), None) => Ok(vec![Some(group.clone())]),
+            (None, None, pattern) => Ok(vec![pattern.or_else(|| scope.clone())]),
+        }
+    };
+    let terminator = if cli.nul { '\0' } else { '\n' };
+    let mut updates = false;
+
+    match cli.command {
+        Command::Init => {
+            repo.vendor_init()?;
+        }
+        Command::Track {
+            pattern,
+            url,
+            branch,
+            name,
+            force,
+            version,
+            src,
+            note,
+            canonicalize,
+        } => {
+            let opts = VendorTrackOpts {
+                force,
+                note,
+                canonicalize,
+                src,
+                version,
+                at_dir: None,
+            };
+            repo.track_pattern(&pattern, &url, branch.as_deref(), name.as_deref(), &opts)?;
+        }
+        Command::Untrack { pattern } => {
+            repo.untrack_pattern(&pattern, None)?;
+        }
+        Command::List { pattern } => {
+            for pattern in targets(pattern)? {
+                for dep in repo.vendor_deps(pattern.as_deref())? {
+                    print!("{}{terminator}", dep.pattern);
+                }
+            }
+        }
+        Command::Status { pattern } => {
+            for pattern in targets(pattern)? {
+                let statuses = if cli.quiet {
+                    repo.vendor_statuses(pattern.as_deref())?
+                } else if cli.nul {
+                    print_status_records(&repo, pattern.as_deref())?
+                } else {
+                    print_status(&repo, pattern.as_deref())?
+                };
+                updates |= statuses.iter().any(|status| !status.is_up_to_date());
+            }
+        }
+        Command::Fetch {
+            pattern,
+            from_mirror,
+            headers,
+            attempts,
+            retry_delay,
+            host_interval,
+        } => {
+            let opts = VendorFetchOpts {
+                mirror: from_mirror,
+                hooks: None,
+                headers,
+                attempts,
+                retry_delay,
+                host_interval,
+            };
+            for pattern in targets(pattern)? {
+                repo.vendor_fetch(pattern.as_deref(), &opts, None)?;
+            }
+        }
+        Command::Push { remote, pattern } => {
+            for pattern in targets(pattern)? {
+                repo.vendor_push(&remote, pattern.as_deref(), None)?;
+            }
+        }
+        Command::Merge {
+            pattern,
+            no_commit,
+            squash,
+            message,
+            index_only,
+            allowed_signers,
+            submodules,
+            symlinks,
+            normalize_executable,
+            single_commit,
+            strategy,
+            force,
+            interactive,
+            author,
+            gpg_sign,
+            no_gpg_sign,
+        } => {
+            let opts = VendorMergeOpts {
+                no_commit,
+                squash,
+                message,
+                index_only,
+                allowed_signers,
+                submodules,
+                symlinks,
+                normalize_executable,
+                single_commit,
+                force,
+                strategy,
+                hooks: None,
+                author,
+                committer: None,
+                sign: signing(gpg_sign, no_gpg_sign),
+                signer: None,
+            };
+            let patterns = targets(pattern)?;
+            if interactive {
+                if cli.stdin {
+                    return Err("--interactive cannot be combined with --stdin".into());
+                }
+                // Merging one pattern of a name merges all of them.
+                let mut selected = select_deps(
+                    &repo,
+                    patterns[0].as_deref(),
+                    &mut io::stdin().lock(),
+                    &mut io::stdout(),
+                )?;
+                let mut names = Vec::new();
+                selected.retain(|dep| {
+                    let new = !names.contains(&dep.name);
+                    names.push(dep.name.clone());
+                    new
+                });
+                if selected.len() > 1 && (opts.no_commit || opts.squash) {
+                    return Err("--no-commit and --squash require a single dependency".into());
+                }
+                for dep in selected {
+                    repo.vendor_merge(Some(&dep.pattern), &opts, None)?;
+                }
+            } else {
+                for pattern in patterns {
+                    repo.vendor_merge(pattern.as_deref(), &opts, None)?;
+                }
+            }
+        }
+        Command::Extract {
+            pattern,
+            message,
+            branch,
+        } => {
+            let dep = repo
+                .vendor_deps(Some(&pattern))?
+                .into_iter()
+                .next()
+                .ok_or_else(|| format!("No vendored dependency with pattern {pattern}"))?;
+            let opts = VendorExtractOpts { message, branch };
+            let commit = repo.vendor_extract(&dep, &opts)?;
+            println!("{commit}");
+        }
+        Command::Archive {
+            name,
+            output,
+            format,
+            prefix,
+        } => {
+            let format = format
+                .or_else(|| output.as_deref().and_then(archive_format))
+                .unwrap_or_default();
+            let opts = VendorArchiveOpts {
+                format,
+                prefix,
+                upstream: None,
+            };
+            match output {
+                Some(path) => {
+                    let mut file = io::BufWriter::new(std::fs::File::create(&path)?);
+                    let archived = repo
+                        .vendor_archive(&name, &opts, &mut file)
+                        .map_err(|e| e.into())
+                        .and_then(|_| file.flush().map_err(|e| e.into()));
+                    if let Err(e) = archived {
+                        drop(file);
+                        let _ = std::fs::remove_file(&path);
+                        return Err(e);
+                    }
+                }
+                None => {
+                    let mut stdout = io::stdout().lock();
+                    repo.vendor_archive(&name, &opts, &mut stdout)?;
+                    stdout.flush()?;
+                }
+            }
+        }
+        Command::Update {
+            pattern,
+            headers,
+            attempts,
+            retry_delay,
+            host_interval,
+            branch_per_dep,
+            strategy,
+            author,
+            gpg_sign,
+            no_gpg_sign,
+        } => {
+            let opts = VendorMergeOpts {
+                strategy,
+                author,
+                sign: signing(gpg_sign, no_gpg_sign),
+                ..Default::default()
+            };
+            let fetch_opts = VendorFetchOpts {
+                headers,
+                attempts,
+                retry_delay,
+                host_interval,
+                ..Default::default()
+            };
+            for pattern in targets(pattern)? {
+                repo.vendor_fetch(pattern.as_deref(), &fetch_opts, None)?;
+                if branch_per_dep {
+                    for (branch, commit) in
+                        repo.vendor_update_branches(pattern.as_deref(), &opts, None)?
+                    {
+                        println!("{branch} {commit}");
+                    }
+                } else {
+                    repo.vendor_merge(pattern.as_deref(), &opts, None)?;
+                }
+            }
+        }
+        Command::Outdated {
+            pattern,
+            headers,
+            attempts,
+            retry_delay,
+            host_interval,
+        } => {
+            let opts = VendorFetchOpts {
+                headers,
+                attempts,
+                retry_delay,
+                host_interval,
+                ..Default::default()
+            };
+            let mut report = Vec::new();
+            for pattern in targets(pattern)? {
+                report.extend(repo.vendor_outdated(pattern.as_deref(), &opts, None)?);
+            }
+            updates = report.iter().any(|entry| entry.is_outdated());
+            if cli.quiet {
+                report.clear();
+            }
+            for entry in report {
+                if let Some(fork) = &entry.fork {
+                    println!("{}: fork {}", entry.name, fork.summary());
+                }
+                if !entry.is_outdated() {
+                    println!("{}: up to date", entry.name);
+                    continue;
+                }
+                let merged = entry.merged.map_or("never merged".to_string(), short_id);
+                let fetched = match entry.fetched {
+                    Some(oid) if oid == entry.upstream => " (fetched)",
+                    _ => "",
+                };
+                println!(
+                    "{}: {} -> {}{}",
+                    entry.name,
+                    merged,
+                    short_id(entry.upstream),
+                    fetched
+                );
+            }
+        }
+        Command::Attest { pattern } => {
+            for pattern in targets(pattern)? {
+                let commit = repo.vendor_attest(pattern.as_deref())?;
+                println!("{commit}");
+            }
+        }
+        Command::VerifyAttestation {
+            commit,
+            allowed_signers,
+        } => {
+            let commit = repo.revparse_single(&commit)?.peel_to_commit()?.id();
+            for entry in repo.vendor_verify_attestation(commit, allowed_signers.as_deref())? {
+                println!(
+                    "{} ({}): {} tree {}",
+                    entry.name, entry.pattern, entry.upstream, entry.tree
+                );
+            }
+        }
+        Command::Doctor { fix } => {
+            let findings = repo.vendor_doctor(fix)?;
+            for finding in &findings {
+                let status = if finding.fixed { "fixed" } else { "error" };
+                println!("{status}: {}", finding.message);
+            }
+            let remaining = findings.iter().filter(|f| !f.fixed).count();
+            if remaining > 0 {
+                return Err(format!("{remaining} problem(s) found").into());
+            }
+        }
+        Command::Migrate { to } => {
+            repo.vendor_migrate(to)?;
+        }
+        Command::Log {
+            pattern,
+            oneline,
+            max_count,
+            last,
+        } => {
+            let opts = VendorLogOpts {
+                max_count,
+                last_merge: last,
+                ..Default::default()
+            };
+            let mut deps = Vec::new();
+            for pattern in targets(pattern)? {
+                deps.extend(repo.vendor_deps(pattern.as_deref())?);
+            }
+            for (i, dep) in deps.iter().enumerate() {
+                let entries = repo.vendor_log(dep, &opts)?;
+                if deps.len() > 1 {
+                    if i > 0 {
+                        println!();
+                    }
+                    println!("{} ({})", dep.name, dep.pattern);
+                }
+
+                for entry in entries {
+                    if oneline {
+                        println!("{} {}", short_id(entry.id), entry.summary);
+                    } else {
+                        println!("commit {}", entry.id);
+                        println!("Author: {} <{}>", entry.author, entry.email);
+                        println!("Date:   {}", entry.date());
+                        println!();
+                        println!("    {}", entry.summary);
+                        println!();
+                    }
+                }
+            }
+        }
+        Command::Changelog { pattern, last } => {
+            let opts = VendorLogOpts {
+                last_merge: last,
+                ..Default::default()
+            };
+            let mut deps: Vec<VendorDep> = Vec::new();
+            for pattern in targets(pattern)? {
+                for dep in repo.vendor_deps(pattern.as_deref())? {
+                    if !deps.iter().any(|other| other.name == dep.name) {
+                        deps.push(dep);
+                    }
+                }
+            }
+            for (i, dep) in deps.iter().enumerate() {
+                if i > 0 {
+                    println!();
+                }
+                print!("{}", repo.vendor_changelog(dep, &opts)?);
+            }
+        }
+        Command::Excluded { pattern } => {
+            let mut deps: Vec<VendorDep> = Vec::new();
+            for pattern in targets(pattern)? {
+                for dep in repo.vendor_deps(pattern.as_deref())? {
+                    if !deps.iter().any(|other| other.name == dep.name) {
+                        deps.push(dep);
+                    }
+                }
+            }
+            for (i, dep) in deps.iter().enumerate() {
+                let excluded = repo.vendor_excluded(dep)?;
+                if deps.len() > 1 {
+                    if i > 0 {
+                        println!();
+                    }
+                    println!("{}", dep.name);
+                }
+                for path in excluded {
+                    println!("{path}");
+                }
+            }
+        }
+        Command::Owners { codeowners, owners } => {
+            let entries = repo.vendor_owners()?;
+            if !codeowners {
+                for entry in &entries {
+                    println!("{}\t{}", entry.path, entry.name);
+                }
+            } else {
+                let deps = repo.vendor_deps(None)?;
+                let mut names: Vec<&str> = entries.iter().map(|e| e.name.as_str()).collect();
+                names.sort();
+                names.dedup();
+                for (i, name) in names.into_iter().enumerate() {
+                    if i > 0 {
+                        println!();
+                    }
+                    let url = deps.iter().find(|dep| dep.name == name).map(|dep| &dep.url);
+                    match url {
+                        Some(url) => {
+                            println!("# Vendored from {url} ({name}); do not edit by hand")
+                        }
+                        None => println!("# Vendored ({name}); do not edit by hand"),
+                    }
+                    for entry in entries.iter().filter(|e| e.name == name) {
+                        let mut line = format!("/{}", codeowners_path(&entry.path));
+                        for owner in &owners {
+                            line.push(' ');
+                            line.push_str(owner);
+                        }
+                        println!("{line}");
+                    }
+                }
+            }
+        }
+        Command::CheckStaged => {
+            let flagged = repo.vendor_check_staged()?;
+            for entry in &flagged {
+                eprintln!("{} (vendored by {})", entry.path, entry.name);
+            }
+            if !flagged.is_empty() {
+                return Err(format!(
+                    "{} staged change(s) to vendored files; update them with \
+                     `git vendor merge` or contribute them upstream",
+                    flagged.len()
+                )
+                .into());
+            }
+        }
+        Command::Reflog { name } => {
+            for (i, entry) in repo.vendor_reflog(&name)?.iter().enumerate() {
+                println!(
+                    "{} refs/vendor/{name}@{{{i}}}: {} {}",
+                    short_id(entry.new),
+                    entry.date(),
+                    entry.message
+                );
+            }
+        }
+    }
+
+    Ok(updates)
+}
+
+/// Escape `path` for a CODEOWNERS pattern, in which spaces separate fields and
+/// glob characters are special.
+fn codeowners_path(path: &str) -> String {
+    let mut escaped = String::with_capacity(path.len());
+    for c in path.chars() {
+        if matches!(c, ' ' | '*' | '?' | '[' | ']' | '\\' | '#' | '!') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// The archive format that the extension of `path` names, if any.
+fn archive_format(path: &std::path::Path) -> Option<ArchiveFormat> {
+    let name = path.file_name()?.to_str()?.to_ascii_lowercase();
+    if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        Some(ArchiveFormat::TarGz)
+    } else if name.ends_with(".zip") {
+        Some(ArchiveFormat::Zip)
+    } else if name.ends_with(".tar") {
+        Some(ArchiveFormat::Tar)
+    } else {
+        None
+    }
+}
+
+/// Whether `-S` or `--no-gpg-sign` chose to sign merge commits.
+fn signing(gpg_sign: bool, no_gpg_sign: bool) -> Option<bool> {
+    match (gpg_sign, no_gpg_sign) {
+        (true, _) => Some(true),
+        (_, true) => Some(false),
+        _ => None,
+    }
+}
+
+/// Return the scope selector for the current directory: its path relative to
+/// the working tree root, ending in `/`, or `None` at the root.
+fn current_scope(repo: &git::Repository) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let Some(workdir) = repo.workdir() else {
+        return Ok(None);
+    };
+    let current = git_set_attr::normalize_path(&std::env::current_dir()?);
+    let relative = current
+        .strip_prefix(git_set_attr::normalize_path(workdir))
+        .map_err(|_| "The current directory is outside the working tree")?;
+    if relative.as_os_str().is_empty() {
+        return Ok(None);
+    }
+
+    let parts: Vec<String> = relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+        .collect();
+    Ok(Some(format!("{}/", parts.join("/"))))
+}
+
+/// Read dependency selectors from standard input, separated by newlines or,
+/// with `nul`, NUL characters. A name selects every pattern of that
+/// dependency; anything else is passed on as a pattern.
+fn read_selectors(
+    repo: &git::Repository,
+    nul: bool,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut input = String::new();
+    io::stdin().read_to_string(&mut input)?;
+    let separator = if nul { '\0' } else { '\n' };
+
+    let deps = repo.vendor_deps(None)?;
+    let mut selectors: Vec<String> = Vec::new();
+    for selector in input.split(separator) {
+        let selector = if nul {
+            selector
+        } else {
+            selector.trim_end_matches('\r')
+        };
+        if selector.is_empty() {
+            continue;
+        }
+        let patterns: Vec<String> = if deps.iter().any(|dep| dep.pattern == selector) {
+            vec![selector.to_string()]
+        } else {
+            let named: Vec<String> = deps
+                .iter()
+                .filter(|dep| dep.name == selector)
+                .map(|dep| dep.pattern.clone())
+                .collect();
+            if named.is_empty() {
+                vec![selector.to_string()]
+            } else {
+                named
+            }
+        };
+        for pattern in patterns {
+            if !selectors.contains(&pattern) {
+                selectors.push(pattern);
+            }
+        }
+    }
+    Ok(selectors)
+}
+
+/// Print one NUL-terminated `<ref>\t<state>\t<name>\t<pattern>` record per
+/// dependency, where `<ref>` is the fetched commit or `-`, and `<state>` is
+/// the [`VendorState`](git_vendor::VendorState) summary, `not fetched` or
+/// `unborn`.
+fn print_status_records(
+    repo: &git::Repository,
+    pattern: Option<&str>,
+) -> Result<Vec<VendorStatus>, Box<dyn std::error::Error>> {
+    let statuses = repo.vendor_statuses(pattern)?;
+    let mut out = io::stdout().lock();
+    for status in &statuses {
+        let state = match (status.fetched, &status.state) {
+            (None, _) => "not fetched".to_string(),
+            (Some(_), Some(state)) => state.summary(),
+            (Some(_), None) => "unborn".to_string(),
+        };
+        let fetched = status
+            .fetched
+            .map_or("-".to_string(), |oid| oid.to_string());
+        let dep = &status.dep;
+        write!(out, "{fetched}\t{state}\t{}\t{}\0", dep.name, dep.pattern)?;
+    }
+    Ok(statuses)
+}
+
+/// Print a block describing each dependency matching `pattern`.
+fn print_status(
+    repo: &git::Repository,
+    pattern: Option<&str>,
+) -> Result<Vec<VendorStatus>, Box<dyn std::error::Error>> {
+    let statuses = repo.vendor_statuses(pattern)?;
+    if statuses.is_empty() {
+        println!("No vendored dependencies tracked");
+        return Ok(statuses);
+    }
+
+    for status in &statuses {
+        let dep = &status.dep;
+        println!("{} ({})", dep.name, dep.pattern);
+        if !dep.source_dir.is_empty() {
+            println!("  Declared in: {}/.gitattributes", dep.source_dir);
+        }
+        println!("  URL: {}", dep.url);
+        if let Some(upstream) = &dep.upstream {
+            match &status.fork {
+                Some(fork) => println!("  Fork of: {upstream} ({})", fork.summary()),
+                None => println!("  Fork of: {upstream} (not fetched)"),
+            }
+        }
+        match &dep.branch {
+            Some(b) => println!("  Branch: {b}"),
+            None => println!("  Branch: (default)"),
+        }
+        if let Some(version) = &dep.version {
+            match &status.tag {
+                Some(tag) => println!("  Version: {version} (fetched {tag})"),
+                None => println!("  Version: {version}"),
+            }
+        }
+        if let Some(src) = &dep.src {
+            println!("  Upstream directory: {src}");
+        }
+        if let Some(note) = &dep.note {
+            println!("  Note: {note}");
+        }
+
+        let ref_name = format!("refs/vendor/{}", dep.name);
+        match status.fetched {
+            Some(oid) => println!("  Ref: {ref_name} ({oid})"),
+            None => println!("  Ref: {ref_name} (not fetched)"),
+        }
+        if let Some(state) = &status.state {
+            println!("  State: {}", state.summary());
+        }
+        println!();
+    }
+
+    Ok(statuses)
+}
+
+/// Prompt for each outdated dependency on `output`, reading the answers from `input`, and
+/// return those the user accepts.
+fn select_deps(
+    repo: &git::Repository,
+    pattern: Option<&str>,
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+) -> Result<Vec<VendorDep>, Box<dyn std::error::Error>> {
+    let mut selected = Vec::new();
+
+    for dep in repo.vendor_deps(pattern)? {
+        let preview = repo.vendor_preview(&dep)?;
+        if preview.is_up_to_date() {
+            writeln!(output, "{} ({}): up to date", dep.name, dep.pattern)?;
+            continue;
+        }
+
+        writeln!(
+            output,
+            "{} ({}): {} commit(s) behind",
+            dep.name, dep.pattern, preview.behind
+        )?;
+        write!(output, "{}", preview.diffstat)?;
+
+        match ask(&dep.name, input, output)? {
+            Answer::Yes => selected.push(dep),
+            Answer::No => {}
+            Answer::Quit => break,
+        }
+    }
+
+    Ok(selected)
+}
+
+/// An answer to [`ask`].
+#[derive(Debug, PartialEq, Eq)]
+enum Answer {
+    Yes,
+    No,
+    Quit,
+}
+
+/// Ask whether to merge `name` until `input` gives a valid answer. The end of
+/// `input` counts as quitting.
+fn ask(name: &str, input: &mut impl BufRead, output: &mut impl Write) -> io::Result<Answer> {
+    loop {
+        write!(output, "Merge {name}? [y,n,q] ")?;
+        output.flush()?;
+
+        let mut answer = String::new();
+        if input.read_line(&mut answer)? == 0 {
+            return Ok(Answer::Quit);
+        }
+
+        match answer.trim() {
+            "y" | "yes" => return Ok(Answer::Yes),
+            "n" | "no" => return Ok(Answer::No),
+            "q" | "quit" => return Ok(Answer::Quit),
+            _ => writeln!(
+                output,
+                "y - merge this dependency\nn - skip it\nq - stop asking"
+            )?,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ask_with(input: &str) -> (Answer, String) {
+        let mut output = Vec::new();
+        let answer = ask("lib", &mut input.as_bytes(), &mut output).unwrap();
+        (answer, String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn ask_accepts_short_and_long_answers() {
+        assert_eq!(ask_with("y\n").0, Answer::Yes);
+        assert_eq!(ask_with("yes\n").0, Answer::Yes);
+        assert_eq!(ask_with(" n \n").0, Answer::No);
+        assert_eq!(ask_with("no\n").0, Answer::No);
+        assert_eq!(ask_with("q\n").0, Answer::Quit);
+        assert_eq!(ask_with("quit\n").0, Answer::Quit);
+    }
+
+    #[test]
+    fn ask_repeats_until_a_valid_answer() {
+        let (answer, output) = ask_with("maybe\ny\n");
+        assert_eq!(answer, Answer::Yes);
+        assert_eq!(
+            output,
+            "Merge lib? [y,n,q] y - merge this dependency\nn - skip it\nq - stop asking\n\
+             Merge lib? [y,n,q] "
+        );
+    }
+
+    #[test]
+    fn ask_quits_at_end_of_input() {
+        assert_eq!(ask_with("").0, Answer::Quit);
+        assert_eq!(ask_with("maybe\n").0, Answer::Quit);
+    }
 }
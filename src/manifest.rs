@@ -0,0 +1,536 @@
+//! The `.gitvendor.toml` manifest, an alternative to `.gitattributes` for
+//! declaring vendored dependencies:
+//!
+//! ```toml
+//! [dependencies."owner/repo"]
+//! url = "https://example.com/owner/repo.git"
+//! branch = "main"
+//! # Follow the highest upstream tag matching this requirement instead.
+//! version = "^1.2"
+//! # Tried in order when fetching from url fails.
+//! mirrors = ["https://mirror.example.com/owner/repo.git"]
+//! # The repository url is a fork of, which status compares it against.
+//! upstream = "https://example.com/original/repo.git"
+//! # Sent with every fetch.
+//! headers = ["X-Proxy-Project: vendoring"]
+//! patterns = ["src/**", "LICENSE"]
+//! # Dropped even though they match a pattern.
+//! excludes = ["src/**/tests"]
+//! # Also vendor the paths matching vendor.defaultExcludes.
+//! default-excludes = false
+//! # Kept, with an empty .gitkeep file, even when none of their files match.
+//! keep-dirs = ["src/generated"]
+//! # Only this upstream directory is vendored; patterns match paths in it.
+//! src = "lib"
+//! # Upstream paths are placed under this directory.
+//! prefix = "third_party/repo"
+//! # Applied, in order, to the filtered upstream tree.
+//! patches = ["patches/repo/0001-fix-build.patch"]
+//! verify = ".allowed_signers"
+//! # Selected together with @runtime.
+//! groups = ["runtime"]
+//! note = "pinned until the CVE fix is released"
+//! ```
+//!
+//! Only the subset of TOML this layout needs is understood: table headers,
+//! comments, strings, booleans, and arrays of strings.
+
+use crate::VendorDep;
+use git2::Error;
+use std::fmt::Write;
+
+/// The manifest file name, looked up at the repository root.
+pub(crate) const MANIFEST_FILE: &str = ".gitvendor.toml";
+
+/// Parse manifest `content` into dependencies, one per pattern.
+pub(crate) fn parse(content: &str) -> Result<Vec<VendorDep>, Error> {
+    let mut entries: Vec<Entry> = Vec::new();
+    let mut lines = content.lines().enumerate();
+
+    while let Some((index, line)) = lines.next() {
+        let lineno = index + 1;
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(header) = line.strip_prefix('[') {
+            let header = header
+                .strip_suffix(']')
+                .ok_or_else(|| syntax_error(lineno, "unterminated table header"))?;
+            let name = header
+                .trim()
+                .strip_prefix("dependencies.")
+                .ok_or_else(|| syntax_error(lineno, "expected [dependencies.<name>]"))?;
+            let name = parse_key(name.trim()).map_err(|msg| syntax_error(lineno, msg))?;
+            if entries.iter().any(|entry| entry.name == name) {
+                return Err(syntax_error(
+                    lineno,
+                    &format!("duplicate dependency '{name}'"),
+                ));
+            }
+            entries.push(Entry {
+                name,
+                ..Default::default()
+            });
+            continue;
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| syntax_error(lineno, "expected key = value"))?;
+        let key = key.trim();
+        let mut value = value.trim().to_string();
+
+        // Arrays may span several lines.
+        if value.starts_with('[') {
+            while !array_closed(&value) {
+                let (_, next) = lines
+                    .next()
+                    .ok_or_else(|| syntax_error(lineno, "unterminated array"))?;
+                value.push(' ');
+                value.push_str(strip_comment(next).trim());
+            }
+        }
+
+        let entry = entries
+            .last_mut()
+            .ok_or_else(|| syntax_error(lineno, "key outside of a [dependencies.<name>] table"))?;
+        let value = parse_value(&value).map_err(|msg| syntax_error(lineno, msg))?;
+        entry
+            .set(key, value)
+            .map_err(|msg| syntax_error(lineno, &msg))?;
+    }
+
+    let mut deps = Vec::new();
+    for entry in entries {
+        let url = entry.url.ok_or_else(|| {
+            Error::from_str(&format!("{MANIFEST_FILE}: '{}' has no url", entry.name))
+        })?;
+        if entry.patterns.is_empty() {
+            return Err(Error::from_str(&format!(
+                "{MANIFEST_FILE}: '{}' has no patterns",
+                entry.name
+            )));
+        }
+        for pattern in entry.patterns {
+            deps.push(VendorDep {
+                name: entry.name.clone(),
+                pattern,
+                url: url.clone(),
+                branch: entry.branch.clone(),
+                version: entry.version.clone(),
+                mirrors: entry.mirrors.clone(),
+                upstream: entry.upstream.clone(),
+                headers: entry.headers.clone(),
+                verify: entry.verify.clone(),
+                note: entry.note.clone(),
+                excludes: entry.excludes.clone(),
+                no_default_excludes: !entry.default_excludes.unwrap_or(true),
+                keep_dirs: entry.keep_dirs.clone(),
+                groups: entry.groups.clone(),
+                src: entry.src.clone(),
+                prefix: entry.prefix.clone(),
+                patches: entry.patches.clone(),
+                ..Default::default()
+            });
+        }
+    }
+    Ok(deps)
+}
+
+/// Render `deps` as manifest content, grouping dependencies that share a
+/// name into one table.
+pub(crate) fn render(deps: &[VendorDep]) -> Result<String, Error> {
+    let mut groups: Vec<(&VendorDep, Vec<&str>)> = Vec::new();
+    for dep in deps {
+        match groups.iter_mut().find(|(first, _)| first.name == dep.name) {
+            Some((first, patterns)) => {
+                let same = VendorDep {
+                    pattern: first.pattern.clone(),
+                    ..dep.clone()
+                };
+                if same != **first {
+                    return Err(Error::from_str(&format!(
+                        "Conflicting settings for '{}' across its patterns",
+                        dep.name
+                    )));
+                }
+                patterns.push(&dep.pattern);
+            }
+            None => groups.push((dep, vec![&dep.pattern])),
+        }
+    }
+
+    let mut out = String::new();
+    for (i, (dep, patterns)) in groups.iter().enumerate() {
+        if i > 0 {
+            out.push('\n');
+        }
+        let _ = writeln!(out, "[dependencies.{}]", quote(&dep.name));
+        let _ = writeln!(out, "url = {}", quote(&dep.url));
+        if let Some(branch) = &dep.branch {
+            let _ = writeln!(out, "branch = {}", quote(branch));
+        }
+        if let Some(version) = &dep.version {
+            let _ = writeln!(out, "version = {}", quote(version));
+        }
+        if !dep.mirrors.is_empty() {
+            let _ = writeln!(out, "mirrors = {}", array(&dep.mirrors));
+        }
+        if let Some(upstream) = &dep.upstream {
+            let _ = writeln!(out, "upstream = {}", quote(upstream));
+        }
+        if !dep.headers.is_empty() {
+            let _ = writeln!(out, "headers = {}", array(&dep.headers));
+        }
+        let _ = writeln!(out, "patterns = {}", array(patterns));
+        if !dep.excludes.is_empty() {
+            let _ = writeln!(out, "excludes = {}", array(&dep.excludes));
+        }
+        if dep.no_default_excludes {
+            let _ = writeln!(out, "default-excludes = false");
+        }
+        if !dep.keep_dirs.is_empty() {
+            let _ = writeln!(out, "keep-dirs = {}", array(&dep.keep_dirs));
+        }
+        if let Some(src) = &dep.src {
+            let _ = writeln!(out, "src = {}", quote(src));
+        }
+        if let Some(prefix) = &dep.prefix {
+            let _ = writeln!(out, "prefix = {}", quote(prefix));
+        }
+        if !dep.patches.is_empty() {
+            let _ = writeln!(out, "patches = {}", array(&dep.patches));
+        }
+        if let Some(verify) = &dep.verify {
+            let _ = writeln!(out, "verify = {}", quote(verify));
+        }
+        if !dep.groups.is_empty() {
+            let _ = writeln!(out, "groups = {}", array(&dep.groups));
+        }
+        if let Some(note) = &dep.note {
+            let _ = writeln!(out, "note = {}", quote(note));
+        }
+    }
+    Ok(out)
+}
+
+#[derive(Default)]
+struct Entry {
+    name: String,
+    url: Option<String>,
+    branch: Option<String>,
+    version: Option<String>,
+    mirrors: Vec<String>,
+    upstream: Option<String>,
+    headers: Vec<String>,
+    patterns: Vec<String>,
+    excludes: Vec<String>,
+    default_excludes: Option<bool>,
+    keep_dirs: Vec<String>,
+    src: Option<String>,
+    prefix: Option<String>,
+    patches: Vec<String>,
+    verify: Option<String>,
+    groups: Vec<String>,
+    note: Option<String>,
+}
+
+enum Value {
+    String(String),
+    Array(Vec<String>),
+    Bool(bool),
+}
+
+impl Entry {
+    fn set(&mut self, key: &str, value: Value) -> Result<(), String> {
+        match (key, value) {
+            ("url", Value::String(s)) => self.url = Some(s),
+            ("branch", Value::String(s)) => self.branch = Some(s),
+            ("version", Value::String(s)) => self.version = Some(s),
+            ("upstream", Value::String(s)) => self.upstream = Some(s),
+            ("src", Value::String(s)) => self.src = Some(s),
+            ("prefix", Value::String(s)) => self.prefix = Some(s),
+            ("verify", Value::String(s)) => self.verify = Some(s),
+            ("note", Value::String(s)) => self.note = Some(s),
+            ("mirrors", Value::Array(a)) => self.mirrors = a,
+            ("headers", Value::Array(a)) => self.headers = a,
+            ("patterns", Value::Array(a)) => self.patterns = a,
+            ("excludes", Value::Array(a)) => self.excludes = a,
+            ("keep-dirs", Value::Array(a)) => self.keep_dirs = a,
+            ("groups", Value::Array(a)) => self.groups = a,
+            ("patches", Value::Array(a)) => self.patches = a,
+            ("default-excludes", Value::Bool(b)) => self.default_excludes = Some(b),
+            (
+                "url" | "branch" | "version" | "upstream" | "src" | "prefix" | "verify" | "note",
+                _,
+            ) => {
+                return Err(format!("'{key}' must be a string"));
+            }
+            (
+                "mirrors" | "headers" | "patterns" | "excludes" | "keep-dirs" | "patches"
+                | "groups",
+                _,
+            ) => {
+                return Err(format!("'{key}' must be an array of strings"));
+            }
+            ("default-excludes", _) => return Err(format!("'{key}' must be a boolean")),
+            _ => return Err(format!("unknown key '{key}'")),
+        }
+        Ok(())
+    }
+}
+
+fn syntax_error(lineno: usize, msg: &str) -> Error {
+    Error::from_str(&format!("{MANIFEST_FILE}:{lineno}: {msg}"))
+}
+
+/// Remove a trailing `#` comment, ignoring `#` inside strings.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (Some('"'), '\\') if !escaped => {
+                escaped = true;
+                continue;
+            }
+            (Some(q), c) if c == q && !escaped => quote = None,
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '#') => return &line[..i],
+            _ => {}
+        }
+        escaped = false;
+    }
+    line
+}
+
+/// Return `true` once the `]` closing an array outside of any string is seen.
+fn array_closed(value: &str) -> bool {
+    let mut rest = value;
+    while !rest.is_empty() {
+        match rest.chars().next() {
+            Some(']') => return true,
+            Some('"' | '\'') => match parse_string(rest) {
+                Ok((_, tail)) => rest = tail,
+                Err(_) => return false,
+            },
+            Some(c) => rest = &rest[c.len_utf8()..],
+            None => break,
+        }
+    }
+    false
+}
+
+/// Parse a bare or quoted key.
+fn parse_key(key: &str) -> Result<String, &'static str> {
+    if key.starts_with('"') || key.starts_with('\'') {
+        match parse_string(key)? {
+            (name, "") => Ok(name),
+            _ => Err("unexpected characters after key"),
+        }
+    } else if !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        Ok(key.to_string())
+    } else {
+        Err("keys with special characters must be quoted")
+    }
+}
+
+fn parse_value(value: &str) -> Result<Value, &'static str> {
+    if let Some(mut rest) = value.strip_prefix('[') {
+        let mut items = Vec::new();
+        loop {
+            rest = rest.trim_start();
+            if let Some(tail) = rest.strip_prefix(']') {
+                rest = tail;
+                break;
+            }
+            let (item, tail) = parse_string(rest)?;
+            items.push(item);
+            rest = tail.trim_start();
+            if let Some(tail) = rest.strip_prefix(',') {
+                rest = tail;
+            } else if !rest.starts_with(']') {
+                return Err("expected ',' or ']' in array");
+            }
+        }
+        if !rest.trim().is_empty() {
+            return Err("unexpected characters after array");
+        }
+        return Ok(Value::Array(items));
+    }
+
+    match value {
+        "true" => return Ok(Value::Bool(true)),
+        "false" => return Ok(Value::Bool(false)),
+        _ => {}
+    }
+
+    match parse_string(value)? {
+        (s, "") => Ok(Value::String(s)),
+        _ => Err("unexpected characters after string"),
+    }
+}
+
+/// Parse a basic (`"..."`) or literal (`'...'`) string at the start of
+/// `input`, returning it and the remaining input.
+fn parse_string(input: &str) -> Result<(String, &str), &'static str> {
+    let mut chars = input.char_indices();
+    let quote = match chars.next() {
+        Some((_, q @ ('"' | '\''))) => q,
+        _ => return Err("expected a quoted string"),
+    };
+
+    let mut out = String::new();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            c if c == quote => return Ok((out, input[i + 1..].trim_end())),
+            '\\' if quote == '"' => match chars.next() {
+                Some((_, '"')) => out.push('"'),
+                Some((_, '\\')) => out.push('\\'),
+                Some((_, 'n')) => out.push('\n'),
+                Some((_, 't')) => out.push('\t'),
+                _ => return Err("unsupported escape sequence"),
+            },
+            c => out.push(c),
+        }
+    }
+    Err("unterminated string")
+}
+
+/// Quote `s` as a basic string.
+fn quote(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn array<S: AsRef<str>>(items: &[S]) -> String {
+    let items: Vec<String> = items.iter().map(|s| quote(s.as_ref())).collect();
+    format!("[{}]", items.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_expands_patterns() {
+        let deps = parse(
+            r#"
+# Vendored dependencies
+[dependencies."owner/repo"]
+url = "https://example.com/owner/repo.git"  # upstream
+branch = 'main'
+patterns = [
+    "src/**",
+    "LICENSE", # keep the license
+]
+excludes = ["src/**/tests"]
+src = "lib"
+prefix = "third_party/repo"
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(deps.len(), 2);
+        assert_eq!(deps[0].name, "owner/repo");
+        assert_eq!(deps[0].pattern, "src/**");
+        assert_eq!(deps[1].pattern, "LICENSE");
+        assert_eq!(deps[1].branch.as_deref(), Some("main"));
+        assert_eq!(deps[1].excludes, vec!["src/**/tests"]);
+        assert_eq!(deps[1].src.as_deref(), Some("lib"));
+        assert_eq!(deps[1].prefix.as_deref(), Some("third_party/repo"));
+        assert!(deps[1].patches.is_empty());
+    }
+
+    #[test]
+    fn parse_reports_errors_with_line_numbers() {
+        let err = parse("[dependencies.a]\nurl = \"u\"\nbogus = \"x\"\n").unwrap_err();
+        assert_eq!(err.message(), ".gitvendor.toml:3: unknown key 'bogus'");
+
+        let err = parse("[dependencies.a/b]\n").unwrap_err();
+        assert!(err.message().contains("must be quoted"));
+
+        let err = parse("[dependencies.a]\nurl = \"u\"\n").unwrap_err();
+        assert!(err.message().contains("has no patterns"));
+
+        let err = parse("[dependencies.a]\npatterns = [\"x\",\n").unwrap_err();
+        assert!(err.message().contains("unterminated array"));
+
+        let err = parse("[dependencies.a]\ndefault-excludes = \"no\"\n").unwrap_err();
+        assert!(err.message().contains("must be a boolean"));
+    }
+
+    #[test]
+    fn render_round_trips() {
+        let deps = vec![
+            VendorDep {
+                name: "owner/repo".into(),
+                pattern: "src/**".into(),
+                url: "https://example.com/owner/repo.git".into(),
+                branch: Some("main".into()),
+                patches: vec!["fix \"quoted\".patch".into()],
+                ..Default::default()
+            },
+            VendorDep {
+                name: "owner/repo".into(),
+                pattern: "LICENSE".into(),
+                url: "https://example.com/owner/repo.git".into(),
+                branch: Some("main".into()),
+                patches: vec!["fix \"quoted\".patch".into()],
+                ..Default::default()
+            },
+            VendorDep {
+                name: "other".into(),
+                pattern: "*.txt".into(),
+                url: "/srv/other".into(),
+                mirrors: vec!["/srv/mirror/other".into()],
+                upstream: Some("/srv/original/other".into()),
+                headers: vec!["X-Proxy-Project: vendoring".into()],
+                keep_dirs: vec!["out".into()],
+                groups: vec!["runtime".into()],
+                no_default_excludes: true,
+                note: Some("pinned # for now".into()),
+                ..Default::default()
+            },
+        ];
+
+        let content = render(&deps).unwrap();
+        assert!(content.contains("patterns = [\"src/**\", \"LICENSE\"]"));
+        assert_eq!(parse(&content).unwrap(), deps);
+    }
+
+    #[test]
+    fn render_rejects_conflicting_settings() {
+        let deps = vec![
+            VendorDep {
+                name: "a".into(),
+                pattern: "x".into(),
+                url: "u1".into(),
+                ..Default::default()
+            },
+            VendorDep {
+                name: "a".into(),
+                pattern: "y".into(),
+                url: "u2".into(),
+                ..Default::default()
+            },
+        ];
+        assert!(render(&deps).is_err());
+    }
+}
@@ -0,0 +1,36 @@
+//! Progress output of the library.
+//!
+//! With the `log` feature, messages are emitted as [`log`] records under the
+//! `git_vendor` target, so applications embedding the crate route them
+//! through their own logger and the CLI filters them by verbosity. Without
+//! it, info and warning messages are printed to stdout and debug messages
+//! (timings and transfer sizes) are dropped.
+
+macro_rules! info {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "log")]
+        ::log::info!($($arg)*);
+        #[cfg(not(feature = "log"))]
+        println!($($arg)*);
+    }};
+}
+
+macro_rules! warn {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "log")]
+        ::log::warn!($($arg)*);
+        #[cfg(not(feature = "log"))]
+        println!($($arg)*);
+    }};
+}
+
+macro_rules! debug {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "log")]
+        ::log::debug!($($arg)*);
+        #[cfg(not(feature = "log"))]
+        if false {
+            println!($($arg)*);
+        }
+    }};
+}
@@ -0,0 +1,310 @@
+//! Semantic versions of upstream tags and the `vendor-version=` requirements
+//! that select them.
+//!
+//! Requirements follow Cargo: a bare version or `^1.2` allows compatible
+//! updates, `~1.2` allows patch updates, `=`, `>`, `>=`, `<` and `<=` compare,
+//! `*`, `1.*` and `1.2.*` are wildcards, and comparators separated by commas
+//! must all match. Pre-release versions are only selected by a comparator
+//! naming a pre-release of the same `major.minor.patch`.
+
+use std::cmp::Ordering;
+
+/// A `major.minor.patch[-pre][+build]` version, optionally prefixed by `v`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Version {
+    major: u64,
+    minor: u64,
+    patch: u64,
+    pre: Option<String>,
+}
+
+impl Version {
+    /// Parse a tag name as a version, or return `None` if it is not one.
+    pub(crate) fn parse(tag: &str) -> Option<Self> {
+        let tag = tag.strip_prefix('v').unwrap_or(tag);
+        let tag = tag.split_once('+').map_or(tag, |(version, _)| version);
+        let (core, pre) = match tag.split_once('-') {
+            Some((core, pre)) if !pre.is_empty() => (core, Some(pre.to_string())),
+            Some(_) => return None,
+            None => (tag, None),
+        };
+        let mut parts = core.split('.').map(parse_number);
+        let version = Version {
+            major: parts.next()??,
+            minor: parts.next()??,
+            patch: parts.next()??,
+            pre,
+        };
+        parts.next().is_none().then_some(version)
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.major, self.minor, self.patch)
+            .cmp(&(other.major, other.minor, other.patch))
+            .then_with(|| compare_pre(self.pre.as_deref(), other.pre.as_deref()))
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// A version requirement such as `^1.2` or `>=1.2, <1.5`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct VersionReq(Vec<Comparator>);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Comparator {
+    op: Op,
+    major: u64,
+    minor: Option<u64>,
+    patch: Option<u64>,
+    pre: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Exact,
+    Greater,
+    GreaterEq,
+    Less,
+    LessEq,
+    Tilde,
+    Caret,
+    Wildcard,
+}
+
+impl VersionReq {
+    /// Parse a requirement, describing the problem if it is malformed.
+    pub(crate) fn parse(req: &str) -> Result<Self, String> {
+        let comparators = req
+            .split(',')
+            .map(|part| Comparator::parse(part.trim()))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| format!("invalid version requirement '{req}'"))?;
+        Ok(VersionReq(comparators))
+    }
+
+    /// Return `true` if `version` satisfies every comparator.
+    pub(crate) fn matches(&self, version: &Version) -> bool {
+        let pre_allowed = version.pre.is_none()
+            || self.0.iter().any(|c| {
+                c.pre.is_some()
+                    && (c.major, c.minor, c.patch)
+                        == (version.major, Some(version.minor), Some(version.patch))
+            });
+        pre_allowed && self.0.iter().all(|c| c.matches(version))
+    }
+}
+
+impl Comparator {
+    fn parse(s: &str) -> Option<Self> {
+        if s == "*" {
+            return Some(Comparator {
+                op: Op::GreaterEq,
+                major: 0,
+                minor: None,
+                patch: None,
+                pre: None,
+            });
+        }
+        let (op, rest) = [
+            (">=", Op::GreaterEq),
+            ("<=", Op::LessEq),
+            (">", Op::Greater),
+            ("<", Op::Less),
+            ("=", Op::Exact),
+            ("~", Op::Tilde),
+            ("^", Op::Caret),
+        ]
+        .into_iter()
+        .find_map(|(prefix, op)| s.strip_prefix(prefix).map(|rest| (op, rest)))
+        .unwrap_or((Op::Caret, s));
+        let rest = rest.trim();
+        let rest = rest.strip_prefix('v').unwrap_or(rest);
+
+        let (core, pre) = match rest.split_once('-') {
+            Some((core, pre)) if !pre.is_empty() => (core, Some(pre.to_string())),
+            Some(_) => return None,
+            None => (rest, None),
+        };
+        let mut op = op;
+        let mut parts = Vec::new();
+        for part in core.split('.') {
+            if matches!(part, "*" | "x" | "X") {
+                if op != Op::Caret || pre.is_some() {
+                    return None;
+                }
+                op = Op::Wildcard;
+            } else if op == Op::Wildcard {
+                return None;
+            } else {
+                parts.push(parse_number(part)?);
+            }
+        }
+        if parts.is_empty() || parts.len() > 3 || (pre.is_some() && parts.len() < 3) {
+            return None;
+        }
+        Some(Comparator {
+            op,
+            major: parts[0],
+            minor: parts.get(1).copied(),
+            patch: parts.get(2).copied(),
+            pre,
+        })
+    }
+
+    fn matches(&self, v: &Version) -> bool {
+        // Compare only the parts the comparator names.
+        let partial = v.major.cmp(&self.major).then_with(|| {
+            let minor = self.minor.map_or(Ordering::Equal, |m| v.minor.cmp(&m));
+            minor.then_with(|| match self.patch {
+                Some(p) => v
+                    .patch
+                    .cmp(&p)
+                    .then_with(|| compare_pre(v.pre.as_deref(), self.pre.as_deref())),
+                None => Ordering::Equal,
+            })
+        });
+        let lower = Version {
+            major: self.major,
+            minor: self.minor.unwrap_or(0),
+            patch: self.patch.unwrap_or(0),
+            pre: self.pre.clone(),
+        };
+        match self.op {
+            Op::Exact | Op::Wildcard => partial == Ordering::Equal,
+            Op::Greater => partial == Ordering::Greater,
+            Op::GreaterEq => partial != Ordering::Less,
+            Op::Less => partial == Ordering::Less,
+            Op::LessEq => partial != Ordering::Greater,
+            Op::Tilde => {
+                *v >= lower && v.major == self.major && self.minor.is_none_or(|m| v.minor == m)
+            }
+            Op::Caret => {
+                // Everything up to the first non-zero part must stay the same.
+                let same = if self.major > 0 || self.minor.is_none() {
+                    v.major == self.major
+                } else if self.minor > Some(0) || self.patch.is_none() {
+                    v.major == self.major && Some(v.minor) == self.minor
+                } else {
+                    v.major == self.major
+                        && Some(v.minor) == self.minor
+                        && Some(v.patch) == self.patch
+                };
+                same && *v >= lower
+            }
+        }
+    }
+}
+
+fn parse_number(s: &str) -> Option<u64> {
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    s.parse().ok()
+}
+
+/// Order pre-release tags: a release sorts after its pre-releases, and
+/// identifiers compare numerically when both are numbers.
+fn compare_pre(a: Option<&str>, b: Option<&str>) -> Ordering {
+    match (a, b) {
+        (None, None) => Ordering::Equal,
+        (None, Some(_)) => Ordering::Greater,
+        (Some(_), None) => Ordering::Less,
+        (Some(a), Some(b)) => {
+            let mut a = a.split('.');
+            let mut b = b.split('.');
+            loop {
+                match (a.next(), b.next()) {
+                    (None, None) => return Ordering::Equal,
+                    (None, Some(_)) => return Ordering::Less,
+                    (Some(_), None) => return Ordering::Greater,
+                    (Some(x), Some(y)) => {
+                        let order = match (parse_number(x), parse_number(y)) {
+                            (Some(x), Some(y)) => x.cmp(&y),
+                            (Some(_), None) => Ordering::Less,
+                            (None, Some(_)) => Ordering::Greater,
+                            (None, None) => x.cmp(y),
+                        };
+                        if order != Ordering::Equal {
+                            return order;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(req: &str, version: &str) -> bool {
+        VersionReq::parse(req)
+            .unwrap()
+            .matches(&Version::parse(version).unwrap())
+    }
+
+    #[test]
+    fn parse_version_accepts_tags() {
+        assert!(Version::parse("v1.2.3").is_some());
+        assert!(Version::parse("1.2.3-rc.1+build.5").is_some());
+        assert!(Version::parse("1.2").is_none());
+        assert!(Version::parse("release-1").is_none());
+        assert!(Version::parse("1.2.3.4").is_none());
+    }
+
+    #[test]
+    fn versions_order_with_pre_releases_first() {
+        let v = |s| Version::parse(s).unwrap();
+        assert!(v("1.2.3-alpha") < v("1.2.3-alpha.1"));
+        assert!(v("1.2.3-alpha.2") < v("1.2.3-alpha.10"));
+        assert!(v("1.2.3-rc.1") < v("1.2.3"));
+        assert!(v("1.2.3") < v("1.10.0"));
+    }
+
+    #[test]
+    fn caret_allows_compatible_updates() {
+        assert!(matches("^1.2", "1.2.0"));
+        assert!(matches("^1.2", "1.9.4"));
+        assert!(!matches("^1.2", "1.1.9"));
+        assert!(!matches("^1.2", "2.0.0"));
+        assert!(matches("1.2.3", "1.3.0"));
+        assert!(matches("^0.2.3", "0.2.9"));
+        assert!(!matches("^0.2.3", "0.3.0"));
+        assert!(!matches("^0.0.3", "0.0.4"));
+    }
+
+    #[test]
+    fn tilde_comparisons_and_wildcards() {
+        assert!(matches("~1.2", "1.2.7"));
+        assert!(!matches("~1.2.3", "1.3.0"));
+        assert!(matches(">=1.2, <1.5", "1.4.9"));
+        assert!(!matches(">=1.2, <1.5", "1.5.0"));
+        assert!(matches("=1.2", "1.2.5"));
+        assert!(matches("1.*", "1.7.0"));
+        assert!(!matches("1.2.*", "1.3.0"));
+        assert!(matches("*", "3.0.0"));
+    }
+
+    #[test]
+    fn pre_releases_need_an_explicit_comparator() {
+        assert!(!matches("^1.2", "1.3.0-rc.1"));
+        assert!(matches("^1.3.0-rc.1", "1.3.0-rc.2"));
+        assert!(!matches("^1.3.0-rc.1", "1.4.0-rc.1"));
+    }
+
+    #[test]
+    fn parse_req_rejects_malformed() {
+        assert!(VersionReq::parse("^").is_err());
+        assert!(VersionReq::parse("1.2.3.4").is_err());
+        assert!(VersionReq::parse(">=1.*").is_err());
+        assert!(VersionReq::parse("latest").is_err());
+    }
+}
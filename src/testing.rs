@@ -0,0 +1,241 @@
+//! Fixtures for testing code built on this crate, behind the `testing`
+//! feature.
+//!
+//! A [`TestHarness`] is a repository in its own temporary directory, removed
+//! when the harness is dropped, next to any number of local upstream
+//! repositories that its dependencies are vendored from:
+//!
+//! ```
+//! use git_vendor::{Vendor, VendorFetchOpts, VendorMergeOpts, testing::TestHarness};
+//!
+//! let harness = TestHarness::builder()
+//!     .file("README.md", "app\n")
+//!     .upstream("lib", &[("src/lib.rs", "// lib\n"), ("tests/t.rs", "")])
+//!     .dep("src/**", "lib")
+//!     .build()?;
+//!
+//! let repo = harness.repo();
+//! repo.vendor_fetch(None, &VendorFetchOpts::default(), None)?;
+//! repo.vendor_merge(None, &VendorMergeOpts::default(), None)?;
+//! assert_eq!(harness.head_file("src/lib.rs")?.as_deref(), Some("// lib\n"));
+//! assert_eq!(harness.head_file("tests/t.rs")?, None);
+//! # Ok::<(), git2::Error>(())
+//! ```
+//!
+//! Nothing depends on the current directory. The repositories set their own
+//! `user.name` and `user.email` and disable commit signing, and the commits
+//! made by the harness have fixed authors and increasing fixed timestamps,
+//! so the upstream commits of the same fixture have the same IDs on every
+//! run. Other settings are still read from the global, XDG and system Git
+//! configuration, such as `vendor.defaultExcludes` or `core.hooksPath`; set
+//! those a test depends on in the repository's own configuration.
+
+use crate::{Vendor, VendorTrackOpts};
+use git2::{Error, Oid, Repository, Signature, Time};
+use std::{
+    cell::Cell,
+    fs,
+    path::{Path, PathBuf},
+};
+use tempfile::TempDir;
+
+/// The time of the first commit made by a harness, in seconds since the
+/// epoch. Each further commit is one second later.
+const EPOCH: i64 = 1_700_000_000;
+
+/// Builds a [`TestHarness`].
+#[derive(Debug, Default)]
+pub struct TestHarnessBuilder {
+    files: Vec<(String, String)>,
+    upstreams: Vec<(String, Vec<(String, String)>)>,
+    deps: Vec<(String, String)>,
+}
+
+impl TestHarnessBuilder {
+    /// Add a file to the initial commit of the repository.
+    pub fn file(mut self, path: &str, content: &str) -> Self {
+        self.files.push((path.to_string(), content.to_string()));
+        self
+    }
+
+    /// Create an upstream repository called `name` with one commit of
+    /// `files`.
+    pub fn upstream(mut self, name: &str, files: &[(&str, &str)]) -> Self {
+        let files = files
+            .iter()
+            .map(|(path, content)| (path.to_string(), content.to_string()))
+            .collect();
+        self.upstreams.push((name.to_string(), files));
+        self
+    }
+
+    /// Track `pattern` as vendored from the upstream repository `upstream`,
+    /// under the dependency name `upstream`.
+    pub fn dep(mut self, pattern: &str, upstream: &str) -> Self {
+        self.deps.push((pattern.to_string(), upstream.to_string()));
+        self
+    }
+
+    /// Create the repositories. The repository gets a commit of its files,
+    /// followed by one tracking the dependencies if there are any.
+    pub fn build(self) -> Result<TestHarness, Error> {
+        let dir = tempfile::Builder::new()
+            .prefix("git-vendor-test-")
+            .tempdir()
+            .map_err(|e| {
+                Error::from_str(&format!("Failed to create a temporary directory: {e}"))
+            })?;
+        let repo = init(&dir.path().join("repo"))?;
+        let harness = TestHarness {
+            dir,
+            repo,
+            commits: Cell::new(0),
+        };
+
+        for (name, files) in &self.upstreams {
+            let upstream = init(&harness.upstream_path(name))?;
+            harness.commit_to(&upstream, &as_refs(files), "upstream")?;
+        }
+
+        harness.commit(&as_refs(&self.files), "initial")?;
+        if !self.deps.is_empty() {
+            for (pattern, name) in &self.deps {
+                let url = harness.upstream_path(name);
+                harness.repo.track_pattern(
+                    pattern,
+                    &url.display().to_string(),
+                    None,
+                    Some(name),
+                    &VendorTrackOpts {
+                        at_dir: Some(PathBuf::new()),
+                        ..Default::default()
+                    },
+                )?;
+            }
+            harness.commit(&[], "track")?;
+        }
+        Ok(harness)
+    }
+}
+
+/// A repository with vendored dependencies in an isolated temporary
+/// directory. See the [module documentation](self).
+pub struct TestHarness {
+    dir: TempDir,
+    repo: Repository,
+    commits: Cell<i64>,
+}
+
+impl TestHarness {
+    /// Start building a harness.
+    pub fn builder() -> TestHarnessBuilder {
+        TestHarnessBuilder::default()
+    }
+
+    /// The repository under test.
+    pub fn repo(&self) -> &Repository {
+        &self.repo
+    }
+
+    /// The root of the repository's working tree.
+    pub fn path(&self) -> &Path {
+        self.repo.workdir().unwrap_or_else(|| self.repo.path())
+    }
+
+    /// The directory of the upstream repository `name`, which is also its
+    /// URL.
+    pub fn upstream_path(&self, name: &str) -> PathBuf {
+        self.dir.path().join("upstreams").join(name)
+    }
+
+    /// Open the upstream repository `name`.
+    pub fn upstream(&self, name: &str) -> Result<Repository, Error> {
+        Repository::open(self.upstream_path(name))
+    }
+
+    /// Write `files` to the working tree and commit them, with everything
+    /// else already changed in it, on top of `HEAD`.
+    pub fn commit(&self, files: &[(&str, &str)], message: &str) -> Result<Oid, Error> {
+        self.commit_to(&self.repo, files, message)
+    }
+
+    /// Like [`commit`](Self::commit), in the upstream repository `name`.
+    pub fn commit_upstream(
+        &self,
+        name: &str,
+        files: &[(&str, &str)],
+        message: &str,
+    ) -> Result<Oid, Error> {
+        self.commit_to(&self.upstream(name)?, files, message)
+    }
+
+    /// Read the file at `path` in the tree of `HEAD`, or `None` if there is
+    /// none.
+    pub fn head_file(&self, path: &str) -> Result<Option<String>, Error> {
+        let tree = self.repo.head()?.peel_to_tree()?;
+        let entry = match tree.get_path(Path::new(path)) {
+            Ok(entry) => entry,
+            Err(e) if e.code() == git2::ErrorCode::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let blob = self.repo.find_blob(entry.id())?;
+        Ok(Some(String::from_utf8_lossy(blob.content()).into_owned()))
+    }
+
+    fn commit_to(
+        &self,
+        repo: &Repository,
+        files: &[(&str, &str)],
+        message: &str,
+    ) -> Result<Oid, Error> {
+        let workdir = repo
+            .workdir()
+            .ok_or_else(|| Error::from_str("Repository has no working directory"))?;
+        for (path, content) in files {
+            let full = workdir.join(path);
+            if let Some(parent) = full.parent() {
+                fs::create_dir_all(parent).map_err(|e| {
+                    Error::from_str(&format!("Failed to create {}: {e}", parent.display()))
+                })?;
+            }
+            fs::write(&full, content).map_err(|e| {
+                Error::from_str(&format!("Failed to write {}: {e}", full.display()))
+            })?;
+        }
+
+        let mut index = repo.index()?;
+        index.add_all(["*"], git2::IndexAddOption::DEFAULT, None)?;
+        index.update_all(["*"], None)?;
+        index.write()?;
+        let tree = repo.find_tree(index.write_tree()?)?;
+
+        let time = EPOCH + self.commits.get();
+        self.commits.set(self.commits.get() + 1);
+        let sig = Signature::new("Test", "test@example.com", &Time::new(time, 0))?;
+        let parent = match repo.head() {
+            Ok(head) => Some(head.peel_to_commit()?),
+            Err(e) if e.code() == git2::ErrorCode::UnbornBranch => None,
+            Err(e) => return Err(e),
+        };
+        let parents: Vec<&git2::Commit<'_>> = parent.iter().collect();
+        repo.commit(Some("HEAD"), &sig, &sig, message, &tree, &parents)
+    }
+}
+
+/// Initialize a repository at `path` configured independently of the user's
+/// Git configuration.
+fn init(path: &Path) -> Result<Repository, Error> {
+    let repo = Repository::init(path)?;
+    let mut config = repo.config()?;
+    config.set_str("user.name", "Test")?;
+    config.set_str("user.email", "test@example.com")?;
+    config.set_bool("commit.gpgsign", false)?;
+    Ok(repo)
+}
+
+fn as_refs(files: &[(String, String)]) -> Vec<(&str, &str)> {
+    files
+        .iter()
+        .map(|(path, content)| (path.as_str(), content.as_str()))
+        .collect()
+}
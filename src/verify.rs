@@ -0,0 +1,277 @@
+//! Signature verification for upstream commits and tags, and signing of
+//! vendor attestations.
+//!
+//! Signatures are checked against an allowed-signers file in the format used
+//! by `gpg.ssh.allowedSignersFile`:
+//!
+//! ```text
+//! alice@example.com ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAI...
+//! ```
+//!
+//! SSH signatures are verified with `ssh-keygen -Y verify`. OpenPGP signatures
+//! are verified with `gpg --verify`, and the signing key's fingerprint must
+//! appear as the last field of a line in the same file:
+//!
+//! ```text
+//! bob@example.com openpgp 0123456789ABCDEF0123456789ABCDEF01234567
+//! ```
+//!
+//! Signatures are made like Git's own: with `ssh-keygen -Y sign` when
+//! `gpg.format` is `ssh`, otherwise with `gpg`, using `user.signingkey`.
+
+use git2::{Error, ObjectType, Oid, Repository};
+use std::{
+    ffi::OsStr,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+const SSH_SIGNATURE_HEADER: &str = "-----BEGIN SSH SIGNATURE-----";
+const PGP_SIGNATURE_HEADER: &str = "-----BEGIN PGP SIGNATURE-----";
+
+/// Verify that the commit or tag `oid` carries a signature made by a key
+/// listed in `allowed_signers`, returning the signer's identity.
+pub(crate) fn verify_object(
+    repo: &Repository,
+    oid: Oid,
+    allowed_signers: &Path,
+) -> Result<String, Error> {
+    if !allowed_signers.exists() {
+        return Err(Error::from_str(&format!(
+            "Allowed signers file {} does not exist",
+            allowed_signers.display()
+        )));
+    }
+
+    let (signature, signed_data) = match repo.find_object(oid, None)?.kind() {
+        Some(ObjectType::Tag) => extract_tag_signature(repo, oid)?,
+        _ => match repo.extract_signature(&oid, None) {
+            Ok((signature, data)) => (signature.to_vec(), data.to_vec()),
+            Err(e) if e.code() == git2::ErrorCode::NotFound => (Vec::new(), Vec::new()),
+            Err(e) => return Err(e),
+        },
+    };
+
+    if signature.is_empty() {
+        return Err(Error::from_str(&format!("Object {oid} is not signed")));
+    }
+
+    let signature = String::from_utf8_lossy(&signature).into_owned();
+    if signature.contains(SSH_SIGNATURE_HEADER) {
+        verify_ssh(repo, oid, &signature, &signed_data, allowed_signers)
+    } else if signature.contains(PGP_SIGNATURE_HEADER) {
+        verify_gpg(repo, oid, &signature, &signed_data, allowed_signers)
+    } else {
+        Err(Error::from_str(&format!(
+            "Object {oid} has an unsupported signature format"
+        )))
+    }
+}
+
+/// Sign `data` with the key configured in `user.signingkey`, returning an
+/// armored signature suitable for [`Repository::commit_signed`].
+pub(crate) fn sign(repo: &Repository, data: &str) -> Result<String, Error> {
+    let config = repo.config()?;
+    let key = match config.get_string("user.signingkey") {
+        Ok(key) => key,
+        Err(e) if e.code() == git2::ErrorCode::NotFound => {
+            return Err(Error::from_str(
+                "No signing key configured; set user.signingkey",
+            ));
+        }
+        Err(e) => return Err(e),
+    };
+    let ssh = config
+        .get_string("gpg.format")
+        .is_ok_and(|format| format == "ssh");
+
+    let (program, args) = if ssh {
+        (
+            "ssh-keygen",
+            ["-Y", "sign", "-n", "git", "-f", key.as_str()].to_vec(),
+        )
+    } else {
+        (
+            "gpg",
+            ["--detach-sign", "--armor", "--local-user", key.as_str()].to_vec(),
+        )
+    };
+    let args: Vec<&OsStr> = args.iter().map(OsStr::new).collect();
+    let (success, signature) = run_with_stdin(program, &args, data.as_bytes())?;
+    if !success || signature.is_empty() {
+        return Err(Error::from_str(&format!(
+            "Failed to sign with {program} using key {key}"
+        )));
+    }
+    Ok(signature)
+}
+
+/// Split a raw tag object into its trailing signature and the signed payload.
+fn extract_tag_signature(repo: &Repository, oid: Oid) -> Result<(Vec<u8>, Vec<u8>), Error> {
+    let odb = repo.odb()?;
+    let object = odb.read(oid)?;
+    let data = object.data();
+    let text = String::from_utf8_lossy(data);
+
+    let start = [SSH_SIGNATURE_HEADER, PGP_SIGNATURE_HEADER]
+        .iter()
+        .filter_map(|header| text.find(header))
+        .min();
+
+    match start {
+        Some(start) => Ok((data[start..].to_vec(), data[..start].to_vec())),
+        None => Ok((Vec::new(), Vec::new())),
+    }
+}
+
+fn verify_ssh(
+    repo: &Repository,
+    oid: Oid,
+    signature: &str,
+    signed_data: &[u8],
+    allowed_signers: &Path,
+) -> Result<String, Error> {
+    let sig_path = write_signature_file(repo, oid, signature)?;
+    let result = verify_ssh_signature_file(oid, &sig_path, signed_data, allowed_signers);
+    let _ = fs::remove_file(&sig_path);
+    result
+}
+
+fn verify_ssh_signature_file(
+    oid: Oid,
+    sig_path: &Path,
+    signed_data: &[u8],
+    allowed_signers: &Path,
+) -> Result<String, Error> {
+    let output = Command::new("ssh-keygen")
+        .args(["-Y", "find-principals", "-f"])
+        .arg(allowed_signers)
+        .arg("-s")
+        .arg(sig_path)
+        .output()
+        .map_err(|e| Error::from_str(&format!("Failed to run ssh-keygen: {e}")))?;
+
+    let principals = String::from_utf8_lossy(&output.stdout);
+    let principal = match principals.lines().find(|l| !l.trim().is_empty()) {
+        Some(p) if output.status.success() => p.trim().to_string(),
+        _ => {
+            return Err(Error::from_str(&format!(
+                "Object {oid} is signed by a key not in {}",
+                allowed_signers.display()
+            )));
+        }
+    };
+
+    let args = [
+        OsStr::new("-Y"),
+        OsStr::new("verify"),
+        OsStr::new("-f"),
+        allowed_signers.as_os_str(),
+        OsStr::new("-I"),
+        OsStr::new(&principal),
+        OsStr::new("-n"),
+        OsStr::new("git"),
+        OsStr::new("-s"),
+        sig_path.as_os_str(),
+    ];
+
+    let (success, _) = run_with_stdin("ssh-keygen", &args, signed_data)?;
+    if success {
+        Ok(principal)
+    } else {
+        Err(Error::from_str(&format!(
+            "Object {oid} has an invalid signature"
+        )))
+    }
+}
+
+fn verify_gpg(
+    repo: &Repository,
+    oid: Oid,
+    signature: &str,
+    signed_data: &[u8],
+    allowed_signers: &Path,
+) -> Result<String, Error> {
+    let sig_path = write_signature_file(repo, oid, signature)?;
+    let args = [
+        OsStr::new("--status-fd=1"),
+        OsStr::new("--verify"),
+        sig_path.as_os_str(),
+        OsStr::new("-"),
+    ];
+    let result = run_with_stdin("gpg", &args, signed_data);
+    let _ = fs::remove_file(&sig_path);
+    let (_, status) = result?;
+
+    let fingerprint = status
+        .lines()
+        .filter_map(|line| line.strip_prefix("[GNUPG:] VALIDSIG "))
+        .find_map(|rest| rest.split_whitespace().next())
+        .ok_or_else(|| Error::from_str(&format!("Object {oid} has an invalid signature")))?;
+
+    let allowed = fs::read_to_string(allowed_signers).map_err(|e| {
+        Error::from_str(&format!(
+            "Failed to read {}: {e}",
+            allowed_signers.display()
+        ))
+    })?;
+
+    allowed
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .find(|line| {
+            line.split_whitespace()
+                .last()
+                .is_some_and(|key| key.eq_ignore_ascii_case(fingerprint))
+        })
+        .map(|line| {
+            line.split_whitespace()
+                .next()
+                .unwrap_or(fingerprint)
+                .to_string()
+        })
+        .ok_or_else(|| {
+            Error::from_str(&format!(
+                "Object {oid} is signed by a key not in {}",
+                allowed_signers.display()
+            ))
+        })
+}
+
+/// Write `signature` to a scratch file in the repository's Git directory.
+fn write_signature_file(repo: &Repository, oid: Oid, signature: &str) -> Result<PathBuf, Error> {
+    let path = repo.path().join(format!("VENDOR_SIGNATURE_{oid}"));
+    fs::write(&path, signature)
+        .map_err(|e| Error::from_str(&format!("Failed to write {}: {e}", path.display())))?;
+    Ok(path)
+}
+
+/// Run `program` with `input` on stdin, returning whether it succeeded and
+/// its standard output.
+fn run_with_stdin(program: &str, args: &[&OsStr], input: &[u8]) -> Result<(bool, String), Error> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| Error::from_str(&format!("Failed to run {program}: {e}")))?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(input)
+            .map_err(|e| Error::from_str(&format!("Failed to write to {program}: {e}")))?;
+    }
+
+    let output = child
+        .wait_with_output()
+        .map_err(|e| Error::from_str(&format!("Failed to run {program}: {e}")))?;
+
+    Ok((
+        output.status.success(),
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+    ))
+}
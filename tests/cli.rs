@@ -0,0 +1,359 @@
+//! Integration tests for the `git-vendor` binary.
+
+use git2::{Repository, Signature};
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+};
+use tempfile::TempDir;
+
+/// A repository and an upstream it can vendor from, with an empty home
+/// directory so that no global Git configuration is read.
+struct Fixture {
+    dir: TempDir,
+    repo: Repository,
+}
+
+impl Fixture {
+    fn new() -> Self {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir(dir.path().join("home")).unwrap();
+        let repo = Repository::init(dir.path().join("repo")).unwrap();
+        let mut config = repo.config().unwrap();
+        config.set_str("user.name", "Test").unwrap();
+        config.set_str("user.email", "test@test").unwrap();
+        let fixture = Fixture { dir, repo };
+        fixture.commit(&fixture.repo, &[("README.md", "app\n")]);
+        fixture
+    }
+
+    fn path(&self) -> &Path {
+        self.repo.workdir().unwrap()
+    }
+
+    /// Create an upstream repository called `name` with one commit of
+    /// `files`, returning its path.
+    fn upstream(&self, name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let path = self.dir.path().join(name);
+        let upstream = Repository::init(&path).unwrap();
+        self.commit(&upstream, files);
+        path
+    }
+
+    /// Write `files` into the working tree of `repo` and commit everything
+    /// in it on top of `HEAD`.
+    fn commit(&self, repo: &Repository, files: &[(&str, &str)]) {
+        let workdir = repo.workdir().unwrap();
+        for (path, content) in files {
+            let full = workdir.join(path);
+            fs::create_dir_all(full.parent().unwrap()).unwrap();
+            fs::write(full, content).unwrap();
+        }
+        let mut index = repo.index().unwrap();
+        index
+            .add_all(["*"], git2::IndexAddOption::DEFAULT, None)
+            .unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let sig = Signature::now("Test", "test@test").unwrap();
+        let parent = repo.head().ok().map(|h| h.peel_to_commit().unwrap());
+        let parents: Vec<&git2::Commit> = parent.iter().collect();
+        repo.commit(Some("HEAD"), &sig, &sig, "commit", &tree, &parents)
+            .unwrap();
+    }
+
+    /// A `git-vendor` command run in the repository, with no identity in
+    /// its environment.
+    fn command(&self, args: &[&str]) -> Command {
+        let home = self.dir.path().join("home");
+        let mut command = Command::new(env!("CARGO_BIN_EXE_git-vendor"));
+        command
+            .args(args)
+            .current_dir(self.path())
+            .env("HOME", &home)
+            .env("XDG_CONFIG_HOME", &home)
+            .env("GIT_CONFIG_NOSYSTEM", "1");
+        for kind in ["AUTHOR", "COMMITTER"] {
+            command
+                .env_remove(format!("GIT_{kind}_NAME"))
+                .env_remove(format!("GIT_{kind}_EMAIL"));
+        }
+        command
+    }
+
+    /// Run `git-vendor` with `args`, failing unless it succeeds.
+    fn run(&self, args: &[&str]) -> Output {
+        let output = self.command(args).output().unwrap();
+        assert!(output.status.success(), "{}", describe(&output));
+        output
+    }
+
+    /// Run `git-vendor` with `args` and `input` on standard input, failing
+    /// unless it succeeds.
+    fn run_with_input(&self, args: &[&str], input: &str) -> Output {
+        let mut child = self
+            .command(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(input.as_bytes())
+            .unwrap();
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.success(), "{}", describe(&output));
+        output
+    }
+
+    /// Track `pattern` from a new upstream of `files` called `name` and
+    /// commit the change.
+    fn track(&self, pattern: &str, name: &str, files: &[(&str, &str)]) {
+        let upstream = self.upstream(name, files);
+        self.run(&["track", pattern, upstream.to_str().unwrap(), "--name", name]);
+        self.commit(&self.repo, &[]);
+    }
+}
+
+fn describe(output: &Output) -> String {
+    format!(
+        "status {:?}\nstdout:\n{}\nstderr:\n{}",
+        output.status.code(),
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    )
+}
+
+#[test]
+fn merge_with_an_identity_only_in_the_environment() {
+    let fixture = Fixture::new();
+    fixture.track("lib/**", "lib", &[("lib/a.txt", "a\n")]);
+    fixture.run(&["fetch"]);
+
+    let mut config = fixture.repo.config().unwrap();
+    config.remove("user.name").unwrap();
+    config.remove("user.email").unwrap();
+    let output = fixture
+        .command(&["merge"])
+        .env("GIT_AUTHOR_NAME", "Env Author")
+        .env("GIT_AUTHOR_EMAIL", "author@env")
+        .env("GIT_COMMITTER_NAME", "Env Committer")
+        .env("GIT_COMMITTER_EMAIL", "committer@env")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", describe(&output));
+    assert!(output.stderr.is_empty(), "{}", describe(&output));
+
+    let head = fixture.repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(head.author().name(), Some("Env Author"));
+    assert_eq!(head.committer().name(), Some("Env Committer"));
+    let reflog = fixture.repo.reflog("refs/vendor/lib").unwrap();
+    let entry = reflog.get(0).unwrap();
+    assert!(entry.message().unwrap().starts_with("vendor merge into "));
+    assert_eq!(entry.committer().name(), Some("Env Committer"));
+}
+
+#[test]
+fn warnings_go_to_stderr_and_status_to_stdout() {
+    let fixture = Fixture::new();
+    fixture.track("missing/**", "lib", &[("lib/a.txt", "a\n")]);
+    fixture.run(&["fetch"]);
+
+    let output = fixture.run(&["merge"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("pattern missing/** matched no upstream files"),
+        "{}",
+        describe(&output)
+    );
+    assert!(
+        !stdout.contains("matched no upstream files"),
+        "{}",
+        describe(&output)
+    );
+
+    let output = fixture.run(&["status"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.starts_with("lib (missing/**)\n"),
+        "{}",
+        describe(&output)
+    );
+    assert!(
+        stdout.contains("  Ref: refs/vendor/lib ("),
+        "{}",
+        describe(&output)
+    );
+    assert!(output.stderr.is_empty(), "{}", describe(&output));
+}
+
+#[test]
+fn list_reads_names_from_stdin() {
+    let fixture = Fixture::new();
+    fixture.track("a/**", "a", &[("a/x.txt", "x\n")]);
+    fixture.track("b/**", "b", &[("b/y.txt", "y\n")]);
+    fixture.track("c/**", "c", &[("c/z.txt", "z\n")]);
+
+    let output = fixture.run_with_input(&["list", "--stdin"], "c\n\na\n");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "c/**\na/**\n");
+
+    let output = fixture.run_with_input(&["list", "--stdin", "-z"], "b\0c\0");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "b/**\0c/**\0");
+}
+
+#[test]
+fn stdin_cannot_be_combined_with_a_pattern() {
+    let fixture = Fixture::new();
+    fixture.track("a/**", "a", &[("a/x.txt", "x\n")]);
+
+    let mut child = fixture
+        .command(&["list", "--stdin", "a/**"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"a\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(!output.status.success(), "{}", describe(&output));
+    assert!(
+        String::from_utf8_lossy(&output.stderr)
+            .contains("--stdin cannot be combined with a pattern"),
+        "{}",
+        describe(&output)
+    );
+}
+
+#[test]
+fn status_prints_nul_terminated_records() {
+    let fixture = Fixture::new();
+    fixture.track("a/**", "a", &[("a/x.txt", "x\n")]);
+    fixture.track("b/**", "b", &[("b/y.txt", "y\n")]);
+    fixture.run(&["fetch", "a/**"]);
+    fixture.run(&["merge", "a/**"]);
+
+    let output = fixture.command(&["status", "-z"]).output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let records: Vec<Vec<&str>> = stdout
+        .split_terminator('\0')
+        .map(|record| record.split('\t').collect())
+        .collect();
+    assert_eq!(records.len(), 2, "{}", describe(&output));
+
+    let fetched = fixture.repo.refname_to_id("refs/vendor/a").unwrap();
+    assert_eq!(records[0][0], fetched.to_string());
+    assert_eq!(records[0][2..], ["a", "a/**"]);
+    assert_eq!(records[1], ["-", "not fetched", "b", "b/**"]);
+}
+
+#[test]
+fn status_exit_code_reports_updates() {
+    let fixture = Fixture::new();
+    fixture.track("lib/**", "lib", &[("lib/a.txt", "a\n")]);
+
+    let output = fixture.command(&["status"]).output().unwrap();
+    assert_eq!(output.status.code(), Some(1), "{}", describe(&output));
+
+    fixture.run(&["fetch"]);
+    fixture.run(&["merge"]);
+    let output = fixture.command(&["status"]).output().unwrap();
+    assert_eq!(output.status.code(), Some(0), "{}", describe(&output));
+}
+
+#[test]
+fn outdated_exit_code_reports_updates() {
+    let fixture = Fixture::new();
+    fixture.track("lib/**", "lib", &[("lib/a.txt", "a\n")]);
+    fixture.run(&["fetch"]);
+    fixture.run(&["merge"]);
+
+    let output = fixture.command(&["outdated"]).output().unwrap();
+    assert_eq!(output.status.code(), Some(0), "{}", describe(&output));
+
+    let upstream = Repository::open(fixture.dir.path().join("lib")).unwrap();
+    fixture.commit(&upstream, &[("lib/a.txt", "b\n")]);
+    let output = fixture.command(&["outdated"]).output().unwrap();
+    assert_eq!(output.status.code(), Some(1), "{}", describe(&output));
+}
+
+#[test]
+fn errors_exit_with_code_two() {
+    let fixture = Fixture::new();
+
+    let output = fixture.command(&["fetch"]).output().unwrap();
+    assert_eq!(output.status.code(), Some(2), "{}", describe(&output));
+    assert!(
+        String::from_utf8_lossy(&output.stderr).starts_with("Error: "),
+        "{}",
+        describe(&output)
+    );
+
+    let output = fixture
+        .command(&["status"])
+        .current_dir(fixture.dir.path().join("home"))
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2), "{}", describe(&output));
+}
+
+#[test]
+fn interactive_merge_reads_answers_from_stdin() {
+    let fixture = Fixture::new();
+    fixture.track("a/**", "a", &[("a/x.txt", "x\n")]);
+    fixture.track("b/**", "b", &[("b/y.txt", "y\n")]);
+    fixture.run(&["fetch"]);
+
+    let output = fixture.run_with_input(&["merge", "--interactive"], "n\ny\n");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("Merge a? [y,n,q] "),
+        "{}",
+        describe(&output)
+    );
+    assert!(
+        stdout.contains("Merge b? [y,n,q] "),
+        "{}",
+        describe(&output)
+    );
+    assert!(!fixture.path().join("a/x.txt").exists());
+    assert_eq!(
+        fs::read_to_string(fixture.path().join("b/y.txt")).unwrap(),
+        "y\n"
+    );
+}
+
+#[test]
+fn track_a_relative_path_from_a_subdirectory() {
+    let fixture = Fixture::new();
+    let upstream = fixture.upstream("lib", &[("a.txt", "a\n")]);
+    let head = Repository::open(&upstream)
+        .unwrap()
+        .refname_to_id("HEAD")
+        .unwrap();
+    fs::create_dir_all(fixture.path().join("vendor/lib")).unwrap();
+
+    let output = fixture
+        .command(&["track", "*.txt", "../../../lib"])
+        .current_dir(fixture.path().join("vendor/lib"))
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", describe(&output));
+    let content = fs::read_to_string(fixture.path().join("vendor/lib/.gitattributes")).unwrap();
+    assert!(content.contains("vendor-url=../lib"), "{content}");
+
+    // Fetching resolves the recorded path against the repository root, from
+    // wherever it runs.
+    let output = fixture
+        .command(&["fetch", "--all"])
+        .current_dir(fixture.path().join("vendor"))
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", describe(&output));
+    assert_eq!(fixture.repo.refname_to_id("refs/vendor/lib").unwrap(), head);
+}
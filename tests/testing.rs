@@ -0,0 +1,57 @@
+use git_vendor::testing::TestHarness;
+use git_vendor::{Vendor, VendorFetchOpts, VendorMergeOpts};
+
+#[test]
+fn harness_vendors_upstream_changes() {
+    let harness = TestHarness::builder()
+        .upstream("lib", &[("src/a.rs", "a\n"), ("docs/guide.md", "guide\n")])
+        .dep("src/**", "lib")
+        .build()
+        .unwrap();
+    let repo = harness.repo();
+
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+    repo.vendor_merge(None, &VendorMergeOpts::default(), None)
+        .unwrap();
+    assert_eq!(
+        harness.head_file("src/a.rs").unwrap().as_deref(),
+        Some("a\n")
+    );
+    assert_eq!(harness.head_file("docs/guide.md").unwrap(), None);
+
+    harness
+        .commit_upstream("lib", &[("src/a.rs", "b\n")], "change a")
+        .unwrap();
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+    repo.vendor_merge(None, &VendorMergeOpts::default(), None)
+        .unwrap();
+    assert_eq!(
+        harness.head_file("src/a.rs").unwrap().as_deref(),
+        Some("b\n")
+    );
+}
+
+#[test]
+fn harnesses_are_isolated_and_reproducible() {
+    let build = || {
+        TestHarness::builder()
+            .file("README.md", "app\n")
+            .upstream("lib", &[("a.txt", "a\n")])
+            .build()
+            .unwrap()
+    };
+    let (first, second) = (build(), build());
+
+    assert_ne!(first.path(), second.path());
+    let tip = |harness: &TestHarness| {
+        let upstream = harness.upstream("lib").unwrap();
+        upstream.head().unwrap().target().unwrap()
+    };
+    assert_eq!(tip(&first), tip(&second));
+    assert_eq!(
+        first.repo().head().unwrap().target(),
+        second.repo().head().unwrap().target()
+    );
+}
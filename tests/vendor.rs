@@ -1,8 +1,19 @@
 //! Integration tests for the `Vendor` trait methods on `Repository`.
 
-use git_vendor::{Vendor, VendorMergeOpts};
-use git2::Repository;
-use std::{fs, io::Write, path::Path, sync::Mutex};
+use git_vendor::{
+    ArchiveFormat, DepsFormat, MergeStrategy, SubmodulePolicy, SymlinkPolicy, Vendor,
+    VendorArchiveOpts, VendorDep, VendorExtractOpts, VendorFetchOpts, VendorFindingKind,
+    VendorHooks, VendorLogOpts, VendorMergeOpts, VendorSigner, VendorTrackOpts,
+};
+use git2::{Oid, Repository};
+use std::{
+    fs,
+    io::Write,
+    path::Path,
+    process::Command,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 use tempfile::TempDir;
 
 /// Mutex to serialize tests that call `std::env::set_current_dir`, since
@@ -38,10 +49,80 @@ fn write_gitattributes(dir: &Path, content: &str) {
     write!(f, "{content}").unwrap();
 }
 
+/// Write `files` into the working tree of `repo`, stage them, and commit on
+/// top of `HEAD` (if any).
+fn commit_files(repo: &Repository, files: &[(&str, &str)], message: &str) -> git2::Oid {
+    let workdir = repo.workdir().unwrap();
+    let mut idx = repo.index().unwrap();
+    for (path, content) in files {
+        let full = workdir.join(path);
+        fs::create_dir_all(full.parent().unwrap()).unwrap();
+        fs::write(&full, content).unwrap();
+        idx.add_path(Path::new(path)).unwrap();
+    }
+    idx.write().unwrap();
+
+    let tree = repo.find_tree(idx.write_tree().unwrap()).unwrap();
+    let sig = repo.signature().unwrap();
+    let parent = repo.head().ok().map(|h| h.peel_to_commit().unwrap());
+    let parents: Vec<&git2::Commit> = parent.iter().collect();
+    repo.commit(Some("HEAD"), &sig, &sig, message, &tree, &parents)
+        .unwrap()
+}
+
+/// Create an upstream repository with a single commit containing `files`.
+fn setup_upstream(files: &[(&str, &str)]) -> (Repository, TempDir) {
+    let dir = TempDir::new().unwrap();
+    let repo = Repository::init(dir.path()).unwrap();
+
+    let mut config = repo.config().unwrap();
+    config.set_str("user.name", "Upstream").unwrap();
+    config.set_str("user.email", "upstream@test").unwrap();
+
+    commit_files(&repo, files, "upstream");
+    (repo, dir)
+}
+
+/// Read the blob at `path` in the `HEAD` tree of `repo`.
+fn head_file(repo: &Repository, path: &str) -> Option<String> {
+    let tree = repo.head().unwrap().peel_to_tree().unwrap();
+    let entry = tree.get_path(Path::new(path)).ok()?;
+    let blob = repo.find_blob(entry.id()).unwrap();
+    Some(String::from_utf8(blob.content().to_vec()).unwrap())
+}
+
 // ---------------------------------------------------------------------------
 // track_pattern
 // ---------------------------------------------------------------------------
 
+#[test]
+fn init_adds_header_and_merge_driver_once() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+    write_gitattributes(dir.path(), "*.png binary\n");
+
+    repo.vendor_init().unwrap();
+    repo.vendor_init().unwrap();
+
+    let content = fs::read_to_string(dir.path().join(".gitattributes")).unwrap();
+    assert!(content.starts_with("# Vendored dependencies"));
+    assert_eq!(content.matches("# Vendored dependencies").count(), 1);
+    assert!(content.ends_with("\n*.png binary\n"));
+    let config = repo.config().unwrap().snapshot().unwrap();
+    assert_eq!(config.get_str("merge.ours.driver").unwrap(), "true");
+
+    repo.track_pattern(
+        "*.txt",
+        "https://example.com/o/r.git",
+        None,
+        None,
+        &VendorTrackOpts::default(),
+    )
+    .unwrap();
+    assert_eq!(repo.vendor_deps(None).unwrap().len(), 1);
+}
+
 #[test]
 fn track_pattern_writes_gitattributes() {
     let _guard = CWD_LOCK.lock().unwrap();
@@ -53,6 +134,7 @@ fn track_pattern_writes_gitattributes() {
         "https://github.com/owner/repo.git",
         Some("main"),
         None,
+        &VendorTrackOpts::default(),
     )
     .unwrap();
 
@@ -70,8 +152,14 @@ fn track_pattern_omits_branch_when_none() {
     let (repo, dir) = setup_repo();
     std::env::set_current_dir(dir.path()).unwrap();
 
-    repo.track_pattern("*.rs", "https://github.com/owner/repo.git", None, None)
-        .unwrap();
+    repo.track_pattern(
+        "*.rs",
+        "https://github.com/owner/repo.git",
+        None,
+        None,
+        &VendorTrackOpts::default(),
+    )
+    .unwrap();
 
     let content = fs::read_to_string(dir.path().join(".gitattributes")).unwrap();
     assert!(content.contains("vendored"));
@@ -81,19 +169,157 @@ fn track_pattern_omits_branch_when_none() {
 }
 
 #[test]
-fn track_pattern_local_path_requires_name() {
+fn track_pattern_local_path_derives_name() {
     let _guard = CWD_LOCK.lock().unwrap();
     let (repo, dir) = setup_repo();
     std::env::set_current_dir(dir.path()).unwrap();
 
+    repo.track_pattern(
+        "*.txt",
+        "/local/path.git",
+        Some("main"),
+        None,
+        &VendorTrackOpts::default(),
+    )
+    .unwrap();
+    let content = fs::read_to_string(dir.path().join(".gitattributes")).unwrap();
+    assert!(content.contains("vendor-name=path"));
+
     assert!(
-        repo.track_pattern("*.txt", "/local/path", Some("main"), None)
+        repo.track_pattern("*.md", "/", Some("main"), None, &VendorTrackOpts::default())
             .is_err()
     );
+}
+
+#[test]
+fn local_remotes_resolve_relative_to_the_repository_root() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    // Temporary directory names start with a dot, which a vendor name
+    // cannot, so nest the upstream in a named directory.
+    let up_dir = TempDir::new().unwrap();
+    let up_path = up_dir.path().join("lib");
+    let upstream = Repository::init(&up_path).unwrap();
+    let mut config = upstream.config().unwrap();
+    config.set_str("user.name", "Upstream").unwrap();
+    config.set_str("user.email", "upstream@test").unwrap();
+    let head = commit_files(&upstream, &[("a.txt", "a\n")], "upstream");
+    let (repo, dir) = setup_repo();
+    let up_name = format!(
+        "{}/lib",
+        up_dir.path().file_name().unwrap().to_str().unwrap()
+    );
+    fs::create_dir_all(dir.path().join("sub/deep")).unwrap();
+    std::env::set_current_dir(dir.path().join("sub/deep")).unwrap();
+
+    // The path is given relative to the current directory, but recorded
+    // relative to the repository root.
+    let relative = format!("../../../{up_name}");
+    repo.track_pattern("*.txt", &relative, None, None, &VendorTrackOpts::default())
+        .unwrap();
+    let content = fs::read_to_string(dir.path().join("sub/deep/.gitattributes")).unwrap();
+    assert!(content.contains("vendor-name=lib"));
+    assert!(content.contains(&format!("vendor-url=../{up_name}")));
+
+    let file_url = format!("file://{}", up_path.display());
+    repo.track_pattern(
+        "*.md",
+        &file_url,
+        None,
+        Some("by-url"),
+        &VendorTrackOpts::default(),
+    )
+    .unwrap();
+
+    std::env::set_current_dir(dir.path().join("sub")).unwrap();
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+    assert_eq!(repo.refname_to_id("refs/vendor/lib").unwrap(), head);
+    assert_eq!(repo.refname_to_id("refs/vendor/by-url").unwrap(), head);
+}
+
+#[test]
+fn track_and_untrack_at_dir_ignore_the_current_directory() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let up_dir = TempDir::new().unwrap();
+    let up_path = up_dir.path().join("lib");
+    let upstream = Repository::init(&up_path).unwrap();
+    let mut config = upstream.config().unwrap();
+    config.set_str("user.name", "Upstream").unwrap();
+    config.set_str("user.email", "upstream@test").unwrap();
+    let head = commit_files(&upstream, &[("a.txt", "a\n")], "upstream");
+    let (repo, dir) = setup_repo();
+    let up_name = format!(
+        "{}/lib",
+        up_dir.path().file_name().unwrap().to_str().unwrap()
+    );
+    fs::create_dir_all(dir.path().join("sub/deep")).unwrap();
+    std::env::set_current_dir(&up_path).unwrap();
+
+    // Both the .gitattributes file and the relative URL are taken from
+    // at_dir, not from the unrelated current directory.
+    let opts = VendorTrackOpts {
+        at_dir: Some("sub/deep".into()),
+        ..Default::default()
+    };
+    let relative = format!("../../../{up_name}");
+    repo.track_pattern("*.txt", &relative, None, None, &opts)
+        .unwrap();
+    let gitattributes = dir.path().join("sub/deep/.gitattributes");
+    let content = fs::read_to_string(&gitattributes).unwrap();
+    assert!(content.contains("vendor-name=lib"), "{content}");
+    assert!(
+        content.contains(&format!("vendor-url=../{up_name}")),
+        "{content}"
+    );
+    assert!(!up_path.join(".gitattributes").exists());
+
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+    assert_eq!(repo.refname_to_id("refs/vendor/lib").unwrap(), head);
+
+    repo.untrack_pattern("*.txt", Some(Path::new("sub/deep")))
+        .unwrap();
+    let content = fs::read_to_string(&gitattributes).unwrap();
+    assert!(!content.contains("vendor-name=lib"), "{content}");
+}
+
+#[test]
+fn track_at_dir_resolves_relative_urls_from_another_subdirectory() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let up_dir = TempDir::new().unwrap();
+    let up_path = up_dir.path().join("lib");
+    let upstream = Repository::init(&up_path).unwrap();
+    let mut config = upstream.config().unwrap();
+    config.set_str("user.name", "Upstream").unwrap();
+    config.set_str("user.email", "upstream@test").unwrap();
+    let head = commit_files(&upstream, &[("a.txt", "a\n")], "upstream");
+    let (repo, dir) = setup_repo();
+    let up_name = format!(
+        "{}/lib",
+        up_dir.path().file_name().unwrap().to_str().unwrap()
+    );
+    fs::create_dir_all(dir.path().join("vendor/lib")).unwrap();
+    fs::create_dir_all(dir.path().join("tools")).unwrap();
+    std::env::set_current_dir(dir.path().join("tools")).unwrap();
+
+    // The path is relative to vendor/lib, two levels down, while the current
+    // directory is only one level down.
+    let opts = VendorTrackOpts {
+        at_dir: Some("vendor/lib".into()),
+        ..Default::default()
+    };
+    let relative = format!("../../../{up_name}");
+    repo.track_pattern("*.txt", &relative, None, None, &opts)
+        .unwrap();
+    let content = fs::read_to_string(dir.path().join("vendor/lib/.gitattributes")).unwrap();
     assert!(
-        repo.track_pattern("*.txt", "/local/path", Some("main"), Some("my-dep"))
-            .is_ok()
+        content.contains(&format!("vendor-url=../{up_name}")),
+        "{content}"
     );
+
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+    assert_eq!(repo.refname_to_id("refs/vendor/lib").unwrap(), head);
 }
 
 #[test]
@@ -107,6 +333,7 @@ fn track_pattern_explicit_name_overrides_derived() {
         "https://github.com/owner/repo.git",
         Some("main"),
         Some("custom-name"),
+        &VendorTrackOpts::default(),
     )
     .unwrap();
 
@@ -125,6 +352,7 @@ fn track_pattern_includes_branch_when_specified() {
         "https://github.com/owner/repo.git",
         Some("develop"),
         None,
+        &VendorTrackOpts::default(),
     )
     .unwrap();
 
@@ -132,6 +360,141 @@ fn track_pattern_includes_branch_when_specified() {
     assert!(content.contains("vendor-branch=develop"));
 }
 
+#[test]
+fn track_pattern_updates_existing_line_in_place() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+
+    let url = "https://github.com/owner/repo.git";
+    let opts = VendorTrackOpts::default();
+    repo.track_pattern("*.rs", url, Some("main"), None, &opts)
+        .unwrap();
+    repo.track_pattern("*.rs", url, Some("develop"), None, &opts)
+        .unwrap();
+
+    let content = fs::read_to_string(dir.path().join(".gitattributes")).unwrap();
+    assert_eq!(content.matches("*.rs ").count(), 1, "{content}");
+    assert!(content.contains("vendor-branch=develop"));
+    assert!(!content.contains("vendor-branch=main"));
+    assert_eq!(
+        repo.vendor_deps(None).unwrap()[0].branch.as_deref(),
+        Some("develop")
+    );
+
+    repo.track_pattern("*.rs", url, None, None, &opts).unwrap();
+    let content = fs::read_to_string(dir.path().join(".gitattributes")).unwrap();
+    assert!(!content.contains("vendor-branch"), "{content}");
+}
+
+#[test]
+fn track_pattern_records_notes_above_the_line() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+
+    let url = "https://github.com/owner/repo.git";
+    let noted = |note: &str| VendorTrackOpts {
+        note: Some(note.into()),
+        ..Default::default()
+    };
+    repo.track_pattern("*.rs", url, None, None, &noted("first"))
+        .unwrap();
+    repo.track_pattern(
+        "*.rs",
+        url,
+        Some("v1"),
+        None,
+        &noted("pinned until CVE fix"),
+    )
+    .unwrap();
+    repo.track_pattern("*.rs", url, Some("v1"), None, &VendorTrackOpts::default())
+        .unwrap();
+
+    let path = dir.path().join(".gitattributes");
+    let content = fs::read_to_string(&path).unwrap();
+    assert!(
+        content.contains("# vendor-note: pinned until CVE fix\n*.rs vendored"),
+        "{content}"
+    );
+    assert!(!content.contains("first"));
+    assert_eq!(
+        repo.vendor_deps(None).unwrap()[0].note.as_deref(),
+        Some("pinned until CVE fix")
+    );
+
+    repo.untrack_pattern("*.rs", None).unwrap();
+    assert!(!fs::read_to_string(&path).unwrap().contains("vendor-note"));
+}
+
+#[test]
+fn track_pattern_can_canonicalize_gitattributes() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+
+    let canonical = VendorTrackOpts {
+        canonicalize: true,
+        ..Default::default()
+    };
+    repo.track_pattern(
+        "src/*.rs",
+        "https://github.com/o/a.git",
+        None,
+        None,
+        &canonical,
+    )
+    .unwrap();
+    repo.track_pattern("*.md", "https://github.com/o/b.git", None, None, &canonical)
+        .unwrap();
+
+    let content = fs::read_to_string(dir.path().join(".gitattributes")).unwrap();
+    assert_eq!(
+        content,
+        "*.md     vendored vendor-name=o/b vendor-url=https://github.com/o/b.git\n\
+         src/*.rs vendored vendor-name=o/a vendor-url=https://github.com/o/a.git\n"
+    );
+}
+
+#[test]
+fn track_pattern_rejects_conflicts_unless_forced() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+    commit_files(&repo, &[("docs/a.txt", "a")], "add docs");
+
+    let url = "https://github.com/owner/repo.git";
+    let track = |pattern: &str, url: &str, name: &str, force: bool| {
+        repo.track_pattern(
+            pattern,
+            url,
+            None,
+            Some(name),
+            &VendorTrackOpts {
+                force,
+                ..Default::default()
+            },
+        )
+    };
+    track("*.txt", url, "dep", false).unwrap();
+
+    // Names must be valid in refs/vendor/.
+    assert!(track("*.md", url, "bad..name", false).is_err());
+    // A name is bound to one upstream.
+    assert!(track("*.md", "https://github.com/other/repo.git", "dep", false).is_err());
+    // Patterns of different dependencies must not match the same files.
+    assert!(track("docs/**", url, "other", false).is_err());
+    // Re-tracking the same declaration is fine.
+    track("*.txt", url, "dep", false).unwrap();
+    assert_eq!(repo.vendor_deps(None).unwrap().len(), 1);
+
+    track("docs/**", url, "other", true).unwrap();
+    let deps = repo.vendor_deps(None).unwrap();
+    assert_eq!(deps.len(), 1);
+    assert_eq!(deps[0].name, "other");
+    assert_eq!(deps[0].pattern, "docs/**");
+}
+
 // ---------------------------------------------------------------------------
 // untrack_pattern
 // ---------------------------------------------------------------------------
@@ -147,6 +510,7 @@ fn untrack_pattern_removes_vendor_lines() {
         "https://github.com/owner/repo.git",
         Some("main"),
         None,
+        &VendorTrackOpts::default(),
     )
     .unwrap();
 
@@ -154,7 +518,7 @@ fn untrack_pattern_removes_vendor_lines() {
     let content = fs::read_to_string(&ga).unwrap();
     assert!(content.contains("vendor-name=owner/repo"));
 
-    repo.untrack_pattern("*.txt").unwrap();
+    repo.untrack_pattern("*.txt", None).unwrap();
 
     let content = fs::read_to_string(&ga).unwrap();
     assert!(!content.contains("vendor-name=owner/repo"));
@@ -167,7 +531,7 @@ fn untrack_pattern_is_noop_without_gitattributes() {
     let (repo, dir) = setup_repo();
     std::env::set_current_dir(dir.path()).unwrap();
 
-    assert!(repo.untrack_pattern("*.txt").is_ok());
+    assert!(repo.untrack_pattern("*.txt", None).is_ok());
 }
 
 // ---------------------------------------------------------------------------
@@ -180,7 +544,7 @@ fn status_ok_with_no_deps() {
     let (repo, dir) = setup_repo();
     std::env::set_current_dir(dir.path()).unwrap();
 
-    assert!(repo.vendor_status(None).is_ok());
+    assert!(repo.vendor_statuses(None).is_ok());
 }
 
 #[test]
@@ -194,7 +558,7 @@ fn status_ok_with_tracked_dep() {
         "*.txt vendored vendor-name=o/r vendor-url=https://example.com/o/r.git vendor-branch=main\n",
     );
 
-    assert!(repo.vendor_status(None).is_ok());
+    assert!(repo.vendor_statuses(None).is_ok());
 }
 
 #[test]
@@ -208,7 +572,158 @@ fn status_ok_with_tracked_dep_no_branch() {
         "*.txt vendored vendor-name=o/r vendor-url=https://example.com/o/r.git\n",
     );
 
-    assert!(repo.vendor_status(None).is_ok());
+    assert!(repo.vendor_statuses(None).is_ok());
+}
+
+#[test]
+fn statuses_report_whether_updates_are_merged() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (upstream, up_dir) = setup_upstream(&[("a.txt", "upstream\n")]);
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+
+    let attrs = format!(
+        "*.txt vendored vendor-name=up vendor-url={}\n",
+        up_dir.path().display()
+    );
+    commit_files(&repo, &[(".gitattributes", &attrs)], "track");
+    let status = &repo.vendor_statuses(None).unwrap()[0];
+    assert_eq!((status.fetched, status.state), (None, None));
+    assert!(!status.is_up_to_date());
+
+    let fetched = repo
+        .vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap()[0]
+        .new;
+    let status = &repo.vendor_statuses(None).unwrap()[0];
+    assert_eq!(status.fetched, Some(fetched));
+    assert_eq!(status.state.unwrap().files_behind, 1);
+    assert!(!status.is_up_to_date());
+
+    repo.vendor_merge(None, &VendorMergeOpts::default(), None)
+        .unwrap();
+    assert!(repo.vendor_statuses(None).unwrap()[0].is_up_to_date());
+    drop(upstream);
+}
+
+#[test]
+fn deps_are_collected_from_every_gitattributes_file() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (repo, dir) = setup_repo();
+
+    write_gitattributes(
+        dir.path(),
+        "*.txt vendored vendor-name=o/root vendor-url=https://example.com/o/root.git\n",
+    );
+    fs::create_dir_all(dir.path().join("lib/nested")).unwrap();
+    write_gitattributes(
+        &dir.path().join("lib/nested"),
+        "*.rs vendored vendor-name=o/nested vendor-url=https://example.com/o/nested.git\n",
+    );
+    fs::write(dir.path().join(".gitignore"), "target/\n").unwrap();
+    fs::create_dir_all(dir.path().join("target")).unwrap();
+    write_gitattributes(
+        &dir.path().join("target"),
+        "*.md vendored vendor-name=o/ignored vendor-url=https://example.com/o/ignored.git\n",
+    );
+
+    // Invoked from a sibling directory, neither file is on the way up.
+    fs::create_dir_all(dir.path().join("docs")).unwrap();
+    std::env::set_current_dir(dir.path().join("docs")).unwrap();
+
+    let deps = repo.vendor_deps(None).unwrap();
+    let names: Vec<_> = deps
+        .iter()
+        .map(|dep| (dep.name.as_str(), dep.source_dir.as_str()))
+        .collect();
+    assert_eq!(names, vec![("o/root", ""), ("o/nested", "lib/nested")]);
+}
+
+#[test]
+fn track_and_untrack_at_an_explicit_directory() {
+    // No CWD_LOCK: the location is an input, not the current directory.
+    let (repo, dir) = setup_repo();
+    fs::create_dir_all(dir.path().join("lib")).unwrap();
+    write_gitattributes(&dir.path().join("lib"), "*.md diff\n");
+
+    let opts = VendorTrackOpts {
+        at_dir: Some("lib".into()),
+        ..Default::default()
+    };
+    repo.track_pattern("*.txt", "https://example.com/o/r.git", None, None, &opts)
+        .unwrap();
+
+    let content = fs::read_to_string(dir.path().join("lib/.gitattributes")).unwrap();
+    assert!(content.contains("*.txt vendored vendor-name=o/r"));
+    assert!(!dir.path().join(".gitattributes").exists());
+    let deps = repo.vendor_deps(None).unwrap();
+    assert_eq!(deps[0].source_dir, "lib");
+
+    repo.untrack_pattern("*.txt", Some(Path::new("lib")))
+        .unwrap();
+    let content = fs::read_to_string(dir.path().join("lib/.gitattributes")).unwrap();
+    assert_eq!(content, "*.md diff\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn track_resolves_an_at_dir_reached_through_a_symlink() {
+    let (repo, dir) = setup_repo();
+    fs::create_dir_all(dir.path().join("lib/sub")).unwrap();
+    write_gitattributes(&dir.path().join("lib"), "*.md diff\n");
+
+    // The directory is named through a link, so it does not start with the
+    // working directory libgit2 reports until both are normalized.
+    let links = TempDir::new().unwrap();
+    let link = links.path().join("repo");
+    std::os::unix::fs::symlink(dir.path(), &link).unwrap();
+    let opts = VendorTrackOpts {
+        at_dir: Some(link.join("lib/sub")),
+        ..Default::default()
+    };
+    repo.track_pattern("*.txt", "https://example.com/o/r.git", None, None, &opts)
+        .unwrap();
+
+    let content = fs::read_to_string(dir.path().join("lib/.gitattributes")).unwrap();
+    assert!(content.contains("*.txt vendored vendor-name=o/r"));
+    assert!(!dir.path().join("lib/sub/.gitattributes").exists());
+    assert_eq!(repo.vendor_deps(None).unwrap()[0].source_dir, "lib");
+}
+
+#[test]
+fn directory_selector_scopes_operations_to_its_subtree() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (_root_up, root_dir) = setup_upstream(&[("a.txt", "root\n")]);
+    let (_foo_up, foo_dir) = setup_upstream(&[("b.txt", "foo\n")]);
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+
+    let root_attrs = format!(
+        "/a.txt vendored vendor-name=root vendor-url={}\n",
+        root_dir.path().display()
+    );
+    let foo_attrs = format!(
+        "*.txt vendored vendor-name=foo vendor-url={}\n",
+        foo_dir.path().display()
+    );
+    commit_files(
+        &repo,
+        &[
+            (".gitattributes", &root_attrs),
+            ("services/foo/.gitattributes", &foo_attrs),
+        ],
+        "track",
+    );
+
+    let deps = repo.vendor_deps(Some("services/foo/")).unwrap();
+    let names: Vec<_> = deps.iter().map(|dep| dep.name.as_str()).collect();
+    assert_eq!(names, vec!["foo"]);
+    assert!(repo.vendor_deps(Some("services/bar/")).unwrap().is_empty());
+
+    repo.vendor_fetch(Some("services/foo/"), &VendorFetchOpts::default(), None)
+        .unwrap();
+    assert!(repo.refname_to_id("refs/vendor/foo").is_ok());
+    assert!(repo.refname_to_id("refs/vendor/root").is_err());
 }
 
 // ---------------------------------------------------------------------------
@@ -221,39 +736,2734 @@ fn fetch_errors_with_no_deps() {
     let (repo, dir) = setup_repo();
     std::env::set_current_dir(dir.path()).unwrap();
 
-    let err = repo.vendor_fetch(None, None).unwrap_err();
+    let err = repo
+        .vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap_err();
     assert!(err.message().contains("No vendored dependencies to fetch"));
 }
 
-// ---------------------------------------------------------------------------
-// merge
-// ---------------------------------------------------------------------------
+#[test]
+fn fetch_prefers_mirror_populated_by_push() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (upstream, up_dir) = setup_upstream(&[("a.txt", "upstream\n")]);
+    let mirror_dir = TempDir::new().unwrap();
+    Repository::init_bare(mirror_dir.path()).unwrap();
+    let mirror = mirror_dir.path().display().to_string();
+
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+    let attrs = format!(
+        "*.txt vendored vendor-name=up vendor-url={}\n",
+        up_dir.path().display()
+    );
+    commit_files(&repo, &[(".gitattributes", &attrs)], "track");
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+    repo.vendor_push(&mirror, None, None).unwrap();
+
+    // The upstream URL is unreachable, so the ref can only come from the mirror.
+    let (other, other_dir) = setup_repo();
+    std::env::set_current_dir(other_dir.path()).unwrap();
+    commit_files(
+        &other,
+        &[(
+            ".gitattributes",
+            "*.txt vendored vendor-name=up vendor-url=/nonexistent\n",
+        )],
+        "track",
+    );
+    let opts = VendorFetchOpts {
+        mirror: Some(mirror),
+        ..Default::default()
+    };
+    other.vendor_fetch(None, &opts, None).unwrap();
+
+    let head = upstream.head().unwrap().target().unwrap();
+    assert_eq!(other.refname_to_id("refs/vendor/up").unwrap(), head);
+
+    // A mirror without the ref falls back to the upstream URL.
+    let empty_dir = TempDir::new().unwrap();
+    Repository::init_bare(empty_dir.path()).unwrap();
+    std::env::set_current_dir(dir.path()).unwrap();
+    let opts = VendorFetchOpts {
+        mirror: Some(empty_dir.path().display().to_string()),
+        ..Default::default()
+    };
+    commit_files(&upstream, &[("a.txt", "updated\n")], "update");
+    repo.vendor_fetch(None, &opts, None).unwrap();
+    assert_eq!(
+        repo.refname_to_id("refs/vendor/up").unwrap(),
+        upstream.head().unwrap().target().unwrap()
+    );
+}
 
 #[test]
-fn merge_errors_with_no_deps() {
+fn fetch_retries_network_errors_with_backoff() {
     let _guard = CWD_LOCK.lock().unwrap();
     let (repo, dir) = setup_repo();
     std::env::set_current_dir(dir.path()).unwrap();
+    let unavailable = serve_http_status("503 Service Unavailable");
+    let missing = serve_http_status("404 Not Found");
+    let attrs = format!(
+        "a/** vendored vendor-name=a vendor-url=http://{}/a.git\n\
+         b/** vendored vendor-name=b vendor-url=http://{}/b.git\n",
+        unavailable.0, missing.0
+    );
+    commit_files(&repo, &[(".gitattributes", &attrs)], "track");
 
-    let err = Vendor::vendor_merge(&repo, None, &Default::default(), None).unwrap_err();
-    assert!(err.message().contains("No vendored dependencies to merge"));
+    let opts = VendorFetchOpts {
+        attempts: 3,
+        retry_delay: Some(Duration::from_millis(20)),
+        ..Default::default()
+    };
+    let started = Instant::now();
+    assert!(repo.vendor_fetch(Some("a/**"), &opts, None).is_err());
+    assert!(started.elapsed() >= Duration::from_millis(60));
+    assert_eq!(unavailable.1.lock().unwrap().len(), 3);
+
+    // Permanent failures are not retried.
+    let opts = VendorFetchOpts {
+        attempts: 3,
+        retry_delay: Some(Duration::from_secs(60)),
+        ..Default::default()
+    };
+    assert!(repo.vendor_fetch(Some("b/**"), &opts, None).is_err());
+    assert_eq!(missing.1.lock().unwrap().len(), 1);
+
+    for url in ["/nonexistent", "file:///nonexistent"] {
+        let attrs = format!("c/** vendored vendor-name=c vendor-url={url}\n");
+        commit_files(&repo, &[(".gitattributes", &attrs)], "retrack");
+        let started = Instant::now();
+        assert!(repo.vendor_fetch(None, &opts, None).is_err());
+        assert!(started.elapsed() < Duration::from_secs(60));
+    }
 }
 
-// ---------------------------------------------------------------------------
-// bare repository
-// ---------------------------------------------------------------------------
+/// Serve HTTP responses with `status` on a local port, returning its address
+/// and the requests received so far, one per connection.
+fn serve_http_status(status: &'static str) -> (String, Arc<Mutex<Vec<String>>>) {
+    use std::io::Read;
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let received = Arc::clone(&requests);
+    std::thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            let mut request = [0; 4096];
+            let len = stream.read(&mut request).unwrap_or(0);
+            received
+                .lock()
+                .unwrap()
+                .push(String::from_utf8_lossy(&request[..len]).into_owned());
+            let _ = write!(
+                stream,
+                "HTTP/1.1 {status}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+            );
+        }
+    });
+    (addr, requests)
+}
 
 #[test]
-fn bare_repo_rejects_all_operations() {
-    let dir = TempDir::new().unwrap();
-    let repo = Repository::init_bare(dir.path()).unwrap();
+fn fetch_sends_headers_only_to_their_dependency() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+    let first = serve_http_status("404 Not Found");
+    let mirror = serve_http_status("404 Not Found");
+    let second = serve_http_status("404 Not Found");
+    // The mirror is on another host than the first dependency's URL.
+    let mirror_addr = mirror.0.replace("127.0.0.1", "localhost");
+    let attrs = format!(
+        "a/** vendored vendor-name=a vendor-url=http://{}/a.git vendor-header=X-Token:secret \
+         vendor-mirror=http://{mirror_addr}/a.git\n\
+         b/** vendored vendor-name=b vendor-url=http://{}/b.git\n",
+        first.0, second.0
+    );
+    commit_files(&repo, &[(".gitattributes", &attrs)], "track");
+    let mut config = repo.config().unwrap();
+    config
+        .set_str(
+            &format!("http.http://{}.extraHeader", second.0),
+            "X-Scoped: b",
+        )
+        .unwrap();
 
+    // Fetches share the custom headers of one `FetchOptions`.
+    let mut fetch_opts = git2::FetchOptions::new();
+    let opts = VendorFetchOpts::default();
     assert!(
-        repo.track_pattern("*.txt", "https://github.com/o/r.git", None, None)
+        repo.vendor_fetch(Some("a/**"), &opts, Some(&mut fetch_opts))
             .is_err()
     );
-    assert!(repo.untrack_pattern("*.txt").is_err());
-    assert!(repo.vendor_status(None).is_err());
-    assert!(repo.vendor_fetch(None, None).is_err());
-    assert!(Vendor::vendor_merge(&repo, None, &VendorMergeOpts::default(), None).is_err());
+    assert!(
+        repo.vendor_fetch(Some("b/**"), &opts, Some(&mut fetch_opts))
+            .is_err()
+    );
+
+    let first = first.1.lock().unwrap();
+    assert_eq!(first.len(), 1);
+    assert!(first[0].contains("X-Token:secret"), "{}", first[0]);
+    assert!(!first[0].contains("X-Scoped"), "{}", first[0]);
+    let mirror = mirror.1.lock().unwrap();
+    assert_eq!(mirror.len(), 1);
+    assert!(!mirror[0].contains("X-Token"), "{}", mirror[0]);
+    let second = second.1.lock().unwrap();
+    assert_eq!(second.len(), 1);
+    assert!(!second[0].contains("X-Token"), "{}", second[0]);
+    assert!(second[0].contains("X-Scoped: b"), "{}", second[0]);
+}
+
+#[test]
+fn fetch_paces_connections_to_the_same_host() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (upstream, up_dir) = setup_upstream(&[("a.txt", "a\n")]);
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+    let url = format!("file://{}", up_dir.path().display());
+    let (server, _) = serve_http_status("503 Service Unavailable");
+    let attrs = format!(
+        "a/** vendored vendor-name=a vendor-url={url}\n\
+         b/** vendored vendor-name=b vendor-url=http://{server}/b.git\n"
+    );
+    commit_files(&repo, &[(".gitattributes", &attrs)], "track");
+    // Local upstreams are not paced.
+    let opts = VendorFetchOpts {
+        host_interval: Some(Duration::from_secs(60)),
+        ..Default::default()
+    };
+    repo.vendor_fetch(Some("a/**"), &opts, None).unwrap();
+    assert_eq!(
+        repo.refname_to_id("refs/vendor/a").unwrap(),
+        upstream.head().unwrap().target().unwrap()
+    );
+
+    // Connections to a remote host are, including retries.
+    let opts = VendorFetchOpts {
+        attempts: 2,
+        retry_delay: Some(Duration::ZERO),
+        host_interval: Some(Duration::from_millis(100)),
+        ..Default::default()
+    };
+    let started = Instant::now();
+    assert!(repo.vendor_fetch(Some("b/**"), &opts, None).is_err());
+    assert!(started.elapsed() >= Duration::from_millis(100));
+}
+
+#[test]
+fn fetch_only_transfers_new_upstream_objects() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let files: Vec<(String, String)> = (0..10)
+        .map(|i| (format!("f{i}.txt"), format!("file {i}\n")))
+        .collect();
+    let files: Vec<(&str, &str)> = files
+        .iter()
+        .map(|(path, content)| (path.as_str(), content.as_str()))
+        .collect();
+    let (upstream, up_dir) = setup_upstream(&files);
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+
+    let attrs = format!(
+        "*.txt vendored vendor-name=up vendor-url={}\n",
+        up_dir.path().display()
+    );
+    commit_files(&repo, &[(".gitattributes", &attrs)], "track");
+
+    let first = repo
+        .vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+    assert_eq!(first.len(), 1);
+    assert!(!first[0].incremental);
+    assert_eq!(first[0].received_objects, 12);
+    assert_eq!(first[0].old, None);
+    assert!(first[0].changed());
+
+    let update = commit_files(&upstream, &[("f0.txt", "changed\n")], "update");
+    let second = repo
+        .vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+    assert!(second[0].incremental);
+    assert_eq!(second[0].received_objects, 3);
+    assert!(second[0].received_bytes < first[0].received_bytes);
+    assert_eq!(second[0].old, Some(first[0].new));
+    assert_eq!(second[0].new, update);
+
+    let third = repo
+        .vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+    assert!(!third[0].changed());
+}
+
+#[test]
+fn fetch_falls_back_to_vendor_mirrors_in_order() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (upstream, up_dir) = setup_upstream(&[("a.txt", "upstream\n")]);
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+
+    let attrs = format!(
+        "*.txt vendored vendor-name=up vendor-url=/nonexistent \
+         vendor-mirror=/nonexistent-mirror vendor-mirror={}\n",
+        up_dir.path().display()
+    );
+    commit_files(&repo, &[(".gitattributes", &attrs)], "track");
+
+    let dep = repo.vendor_deps(None).unwrap().remove(0);
+    assert_eq!(dep.mirrors.len(), 2);
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+    assert_eq!(
+        repo.refname_to_id("refs/vendor/up").unwrap(),
+        upstream.head().unwrap().target().unwrap()
+    );
+
+    let attrs = "*.txt vendored vendor-name=up vendor-url=/nonexistent \
+                 vendor-mirror=/nonexistent-mirror\n";
+    commit_files(&repo, &[(".gitattributes", attrs)], "drop mirror");
+    let err = repo
+        .vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap_err();
+    assert!(
+        err.message().contains("and its 1 mirror(s)"),
+        "{}",
+        err.message()
+    );
+}
+
+#[test]
+fn fetch_follows_highest_tag_matching_version() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (upstream, up_dir) = setup_upstream(&[("a.txt", "1.0\n")]);
+    let tag = |name: &str, annotated: bool| {
+        let head = upstream.head().unwrap().peel_to_commit().unwrap();
+        if annotated {
+            let sig = upstream.signature().unwrap();
+            upstream
+                .tag(name, head.as_object(), &sig, name, false)
+                .unwrap();
+        } else {
+            upstream
+                .tag_lightweight(name, head.as_object(), false)
+                .unwrap();
+        }
+        head.id()
+    };
+    tag("v1.0.0", false);
+    commit_files(&upstream, &[("a.txt", "1.3\n")], "1.3");
+    let wanted = tag("v1.3.1", true);
+    commit_files(&upstream, &[("a.txt", "1.4-rc\n")], "1.4-rc");
+    tag("v1.4.0-rc.1", false);
+    commit_files(&upstream, &[("a.txt", "2.0\n")], "2.0");
+    tag("v2.0.0", false);
+
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+    let attrs = format!(
+        "*.txt vendored vendor-name=up vendor-url={} vendor-version=^1.2\n",
+        up_dir.path().display()
+    );
+    commit_files(&repo, &[(".gitattributes", &attrs)], "track");
+
+    let stats = repo
+        .vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+    assert_eq!(stats[0].tag.as_deref(), Some("v1.3.1"));
+    assert_eq!(repo.refname_to_id("refs/vendor/up").unwrap(), wanted);
+    let outdated = repo
+        .vendor_outdated(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+    assert_eq!(outdated[0].upstream, wanted);
+
+    repo.vendor_merge(None, &VendorMergeOpts::default(), None)
+        .unwrap();
+    assert_eq!(head_file(&repo, "a.txt").as_deref(), Some("1.3\n"));
+    let head = repo.head().unwrap().peel_to_commit().unwrap();
+    assert!(
+        head.message().unwrap().contains("Vendor-Version: v1.3.1"),
+        "{}",
+        head.message().unwrap()
+    );
+
+    let attrs = format!(
+        "*.txt vendored vendor-name=up vendor-url={} vendor-version=^3\n",
+        up_dir.path().display()
+    );
+    commit_files(&repo, &[(".gitattributes", &attrs)], "bump");
+    let err = repo
+        .vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap_err();
+    assert!(err.message().contains("No tag"), "{}", err.message());
+}
+
+#[test]
+fn fetch_lists_version_tags_with_the_fetch_headers() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+    let server = serve_http_status("404 Not Found");
+    let attrs = format!(
+        "*.txt vendored vendor-name=up vendor-url=http://{}/up.git vendor-version=^1 \
+         vendor-header=X-Token:secret\n",
+        server.0
+    );
+    commit_files(&repo, &[(".gitattributes", &attrs)], "track");
+
+    let opts = VendorFetchOpts {
+        headers: vec!["X-Cli: 1".into()],
+        ..Default::default()
+    };
+    assert!(repo.vendor_fetch(None, &opts, None).is_err());
+    let requests = server.1.lock().unwrap();
+    assert_eq!(requests.len(), 1);
+    assert!(requests[0].contains("X-Token:secret"), "{}", requests[0]);
+    assert!(requests[0].contains("X-Cli: 1"), "{}", requests[0]);
+}
+
+#[test]
+fn track_then_fetch_with_a_multi_comparator_version() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (upstream, up_dir) = setup_upstream(&[("a.txt", "1.2\n")]);
+    let tag = |name: &str| {
+        let head = upstream.head().unwrap().peel_to_commit().unwrap();
+        upstream
+            .tag_lightweight(name, head.as_object(), false)
+            .unwrap();
+        head.id()
+    };
+    tag("v1.2.0");
+    commit_files(&upstream, &[("a.txt", "1.4\n")], "1.4");
+    let wanted = tag("v1.4.0");
+    commit_files(&upstream, &[("a.txt", "1.5\n")], "1.5");
+    tag("v1.5.0");
+
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+    repo.track_pattern(
+        "*.txt",
+        &up_dir.path().display().to_string(),
+        None,
+        Some("up"),
+        &VendorTrackOpts {
+            version: Some(">= 1.2, <1.5".into()),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let attrs = fs::read_to_string(dir.path().join(".gitattributes")).unwrap();
+    assert!(attrs.contains("vendor-version=>=1.2,<1.5"), "{attrs}");
+    commit_files(&repo, &[(".gitattributes", &attrs)], "track");
+
+    let stats = repo
+        .vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+    assert_eq!(stats[0].tag.as_deref(), Some("v1.4.0"));
+    assert_eq!(repo.refname_to_id("refs/vendor/up").unwrap(), wanted);
+}
+
+#[test]
+fn reflog_records_fetches_and_merges() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (upstream, up_dir) = setup_upstream(&[("a.txt", "upstream\n")]);
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+
+    let url = up_dir.path().display().to_string();
+    let attrs = format!("*.txt vendored vendor-name=up vendor-url={url}\n");
+    commit_files(&repo, &[(".gitattributes", &attrs)], "track");
+    assert!(repo.vendor_reflog("up").is_err());
+
+    let first = repo
+        .vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap()[0]
+        .new;
+    repo.vendor_merge(None, &VendorMergeOpts::default(), None)
+        .unwrap();
+    let merge = repo.head().unwrap().target().unwrap();
+    let second = commit_files(&upstream, &[("a.txt", "updated\n")], "update");
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+
+    let short = |oid: Oid| oid.to_string()[..7].to_string();
+    let branch = repo.head().unwrap().shorthand().unwrap().to_string();
+    let entries = repo.vendor_reflog("up").unwrap();
+    let messages: Vec<&str> = entries.iter().map(|e| e.message.as_str()).collect();
+    assert_eq!(
+        messages,
+        vec![
+            format!(
+                "vendor fetch from {url}: {}..{}",
+                short(first),
+                short(second)
+            ),
+            format!("vendor merge into {branch}: {}", short(merge)),
+            format!("vendor fetch from {url}: 0000000..{}", short(first)),
+        ]
+    );
+    assert_eq!((entries[0].old, entries[0].new), (Some(first), second));
+    assert_eq!(entries[2].old, None);
+}
+
+#[test]
+fn push_errors_before_fetch() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+    commit_files(
+        &repo,
+        &[(
+            ".gitattributes",
+            "*.txt vendored vendor-name=up vendor-url=/nonexistent\n",
+        )],
+        "track",
+    );
+
+    let err = repo.vendor_push("/nonexistent", None, None).unwrap_err();
+    assert!(err.message().contains("Run fetch first"));
+}
+
+// ---------------------------------------------------------------------------
+// merge
+// ---------------------------------------------------------------------------
+
+#[test]
+fn merge_errors_with_no_deps() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+
+    let err = Vendor::vendor_merge(&repo, None, &Default::default(), None).unwrap_err();
+    assert!(err.message().contains("No vendored dependencies to merge"));
+}
+
+#[test]
+fn merge_keeps_files_outside_the_dependency() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (upstream, up_dir) = setup_upstream(&[("a.txt", "upstream\n"), ("b.rs", "skip\n")]);
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+
+    let attrs = format!(
+        "*.txt vendored vendor-name=up vendor-url={}\n",
+        up_dir.path().display()
+    );
+    commit_files(
+        &repo,
+        &[(".gitattributes", &attrs), ("local.rs", "mine\n")],
+        "track",
+    );
+
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+    repo.vendor_merge(None, &VendorMergeOpts::default(), None)
+        .unwrap();
+
+    assert_eq!(head_file(&repo, "a.txt").as_deref(), Some("upstream\n"));
+    assert_eq!(head_file(&repo, "local.rs").as_deref(), Some("mine\n"));
+    assert!(head_file(&repo, ".gitattributes").is_some());
+    assert!(head_file(&repo, "b.rs").is_none());
+
+    // A second upstream change applies cleanly on top of the first merge.
+    commit_files(&upstream, &[("a.txt", "updated\n")], "update");
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+    repo.vendor_merge(None, &VendorMergeOpts::default(), None)
+        .unwrap();
+
+    assert_eq!(head_file(&repo, "a.txt").as_deref(), Some("updated\n"));
+    assert_eq!(head_file(&repo, "local.rs").as_deref(), Some("mine\n"));
+}
+
+/// Vendor `a.txt` from a new upstream, then change its middle line both
+/// upstream and locally so that the next merge conflicts.
+fn setup_diverged() -> (Repository, TempDir, Repository, TempDir) {
+    let (upstream, up_dir) = setup_upstream(&[("a.txt", "1\n2\n3\n")]);
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+
+    let attrs = format!(
+        "*.txt vendored vendor-name=up vendor-url={}\n",
+        up_dir.path().display()
+    );
+    commit_files(&repo, &[(".gitattributes", &attrs)], "track");
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+    repo.vendor_merge(None, &VendorMergeOpts::default(), None)
+        .unwrap();
+
+    commit_files(&repo, &[("a.txt", "1\nlocal\n3\n")], "local edit");
+    commit_files(&upstream, &[("a.txt", "1\nupstream\n3\n")], "update");
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+    (repo, dir, upstream, up_dir)
+}
+
+#[test]
+fn merge_strategy_resolves_conflicts() {
+    let _guard = CWD_LOCK.lock().unwrap();
+
+    for (strategy, expected) in [
+        (MergeStrategy::Theirs, "1\nupstream\n3\n"),
+        (MergeStrategy::Ours, "1\nlocal\n3\n"),
+    ] {
+        let (repo, _dir, _upstream, _up_dir) = setup_diverged();
+        let opts = VendorMergeOpts {
+            strategy,
+            ..Default::default()
+        };
+        repo.vendor_merge(None, &opts, None).unwrap();
+        assert_eq!(head_file(&repo, "a.txt").as_deref(), Some(expected));
+    }
+
+    let (repo, dir, _upstream, _up_dir) = setup_diverged();
+    let err = repo
+        .vendor_merge(None, &VendorMergeOpts::default(), None)
+        .unwrap_err();
+    assert!(err.message().contains("Conflicts detected"));
+    assert!(repo.index().unwrap().has_conflicts());
+    let content = fs::read_to_string(dir.path().join("a.txt")).unwrap();
+    assert!(content.contains("<<<<<<<"), "{content}");
+}
+
+#[test]
+fn state_reports_conflicts_left_by_a_merge() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (repo, _dir, _upstream, _up_dir) = setup_diverged();
+    repo.vendor_merge(None, &VendorMergeOpts::default(), None)
+        .unwrap_err();
+    assert!(repo.index().unwrap().has_conflicts());
+
+    let dep = &repo.vendor_deps(None).unwrap()[0];
+    assert!(repo.vendor_state(dep).unwrap().locally_modified);
+    assert!(repo.vendor_statuses(None).is_ok());
+}
+
+#[test]
+fn merge_refuses_to_overwrite_uncommitted_changes() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (upstream, up_dir) = setup_upstream(&[("a.txt", "1\n"), ("c.txt", "gone\n")]);
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+
+    let attrs = format!(
+        "*.txt vendored vendor-name=up vendor-url={}\n",
+        up_dir.path().display()
+    );
+    commit_files(
+        &repo,
+        &[(".gitattributes", &attrs), ("local.rs", "mine\n")],
+        "track",
+    );
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+    repo.vendor_merge(None, &VendorMergeOpts::default(), None)
+        .unwrap();
+
+    let mut idx = upstream.index().unwrap();
+    idx.remove_path(Path::new("c.txt")).unwrap();
+    idx.write().unwrap();
+    commit_files(&upstream, &[("a.txt", "2\n")], "update");
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+
+    // Uncommitted changes to merged paths stop the merge.
+    fs::write(dir.path().join("a.txt"), "edited\n").unwrap();
+    fs::write(dir.path().join("local.rs"), "unsaved\n").unwrap();
+    let err = repo
+        .vendor_merge(None, &VendorMergeOpts::default(), None)
+        .unwrap_err();
+    assert!(err.message().contains("a.txt"), "{}", err.message());
+    assert_eq!(head_file(&repo, "a.txt").as_deref(), Some("1\n"));
+
+    // Other changes survive a merge, which only touches the paths it changes.
+    fs::write(dir.path().join("a.txt"), "1\n").unwrap();
+    repo.vendor_merge(None, &VendorMergeOpts::default(), None)
+        .unwrap();
+    let read = |path: &str| fs::read_to_string(dir.path().join(path)).ok();
+    assert_eq!(read("a.txt").as_deref(), Some("2\n"));
+    assert_eq!(read("c.txt"), None);
+    assert_eq!(read("local.rs").as_deref(), Some("unsaved\n"));
+
+    // Forcing overwrites them.
+    commit_files(&upstream, &[("a.txt", "3\n")], "update again");
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+    fs::write(dir.path().join("a.txt"), "edited\n").unwrap();
+    let opts = VendorMergeOpts {
+        force: true,
+        ..Default::default()
+    };
+    repo.vendor_merge(None, &opts, None).unwrap();
+    assert_eq!(read("a.txt").as_deref(), Some("3\n"));
+    assert_eq!(read("local.rs").as_deref(), Some("unsaved\n"));
+}
+
+#[test]
+fn merge_strategy_upstream_discards_local_changes() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (repo, _dir, upstream, _up_dir) = setup_diverged();
+    commit_files(&repo, &[("extra.txt", "local only\n")], "add");
+    commit_files(&upstream, &[("a.txt", "1\nupstream\n3\n4\n")], "more");
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+
+    let opts = VendorMergeOpts {
+        strategy: MergeStrategy::Upstream,
+        ..Default::default()
+    };
+    repo.vendor_merge(None, &opts, None).unwrap();
+
+    assert_eq!(
+        head_file(&repo, "a.txt").as_deref(),
+        Some("1\nupstream\n3\n4\n")
+    );
+    assert!(head_file(&repo, "extra.txt").is_none());
+    assert!(head_file(&repo, ".gitattributes").is_some());
+}
+
+#[test]
+fn merge_single_commit_combines_all_deps() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (one, one_dir) = setup_upstream(&[
+        ("a.txt", "one\n"),
+        ("c.txt", "SPDX-License-Identifier: MIT\n"),
+    ]);
+    let (two, two_dir) = setup_upstream(&[("b.md", "two\n")]);
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+
+    let attrs = format!(
+        "*.txt vendored vendor-name=one vendor-url={}\n\
+         *.md vendored vendor-name=two vendor-url={}\n",
+        one_dir.path().display(),
+        two_dir.path().display()
+    );
+    let track = commit_files(&repo, &[(".gitattributes", &attrs)], "track");
+    let mut config = repo.config().unwrap();
+    config
+        .set_str("vendor.two.message", "Bump {name} to {branch}\n\nDetails")
+        .unwrap();
+
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+    let opts = VendorMergeOpts {
+        single_commit: true,
+        ..Default::default()
+    };
+    repo.vendor_merge(None, &opts, None).unwrap();
+
+    let head = repo.head().unwrap().peel_to_commit().unwrap();
+    let parents: Vec<_> = head.parent_ids().collect();
+    assert_eq!(
+        parents,
+        vec![
+            track,
+            one.head().unwrap().target().unwrap(),
+            two.head().unwrap().target().unwrap()
+        ]
+    );
+    let message = head.message().unwrap();
+    let one_id = one.head().unwrap().target().unwrap().to_string();
+    let two_id = two.head().unwrap().target().unwrap().to_string();
+    assert_eq!(
+        message,
+        format!(
+            "Update vendored dependencies\n\n\
+             - one ({})\n\
+             - Bump two to HEAD ({})\n\n\
+             Vendor-License: MIT\n",
+            &one_id[..7],
+            &two_id[..7]
+        )
+    );
+    assert_eq!(head_file(&repo, "a.txt").as_deref(), Some("one\n"));
+    assert_eq!(head_file(&repo, "b.md").as_deref(), Some("two\n"));
+}
+
+#[test]
+fn update_branches_merge_each_dep_without_touching_head() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (one, one_dir) = setup_upstream(&[("a.txt", "one\n")]);
+    let (_two, two_dir) = setup_upstream(&[("b.md", "two\n")]);
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+
+    let attrs = format!(
+        "*.txt vendored vendor-name=one vendor-url={}\n\
+         *.md vendored vendor-name=two vendor-url={}\n",
+        one_dir.path().display(),
+        two_dir.path().display()
+    );
+    let track = commit_files(&repo, &[(".gitattributes", &attrs)], "track");
+
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+    let branches = repo
+        .vendor_update_branches(None, &VendorMergeOpts::default(), None)
+        .unwrap();
+
+    let names: Vec<_> = branches.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(names, vec!["vendor/update-one", "vendor/update-two"]);
+    assert_eq!(repo.head().unwrap().target().unwrap(), track);
+    assert!(!dir.path().join("a.txt").exists());
+
+    let branch = repo
+        .find_branch("vendor/update-one", git2::BranchType::Local)
+        .unwrap();
+    let commit = branch.get().peel_to_commit().unwrap();
+    assert_eq!(commit.id(), branches[0].1);
+    let parents: Vec<_> = commit.parent_ids().collect();
+    assert_eq!(parents, vec![track, one.head().unwrap().target().unwrap()]);
+    let tree = commit.tree().unwrap();
+    assert!(tree.get_path(Path::new("a.txt")).is_ok());
+    assert!(tree.get_path(Path::new("b.md")).is_err());
+
+    // Once merged into HEAD, a dependency no longer gets a branch
+    repo.vendor_merge(Some("*.txt"), &VendorMergeOpts::default(), None)
+        .unwrap();
+    let branches = repo
+        .vendor_update_branches(None, &VendorMergeOpts::default(), None)
+        .unwrap();
+    let names: Vec<_> = branches.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(names, vec!["vendor/update-two"]);
+}
+
+#[test]
+fn merge_resolves_patterns_relative_to_their_gitattributes() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (_upstream, up_dir) = setup_upstream(&[
+        ("a.txt", "root\n"),
+        ("sub/b.txt", "nested\n"),
+        ("sub/deep/c.txt", "deeper\n"),
+    ]);
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+
+    let attrs = format!(
+        "*.txt vendored vendor-name=up vendor-url={}\n",
+        up_dir.path().display()
+    );
+    commit_files(&repo, &[("sub/.gitattributes", &attrs)], "track");
+
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+    repo.vendor_merge(None, &VendorMergeOpts::default(), None)
+        .unwrap();
+
+    assert!(head_file(&repo, "a.txt").is_none());
+    assert_eq!(head_file(&repo, "sub/b.txt").as_deref(), Some("nested\n"));
+    assert_eq!(
+        head_file(&repo, "sub/deep/c.txt").as_deref(),
+        Some("deeper\n")
+    );
+}
+
+#[test]
+fn merge_and_extract_vendor_only_the_src_directory() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (upstream, up_dir) = setup_upstream(&[
+        ("include/a.h", "a\n"),
+        ("include/sub/b.h", "b\n"),
+        ("src/main.c", "main\n"),
+        ("c.h", "outside\n"),
+    ]);
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+
+    let attrs = format!(
+        "*.h vendored vendor-name=up vendor-url={} vendor-src=include\n",
+        up_dir.path().display()
+    );
+    commit_files(&repo, &[("third_party/.gitattributes", &attrs)], "track");
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+    repo.vendor_merge(None, &VendorMergeOpts::default(), None)
+        .unwrap();
+
+    assert_eq!(head_file(&repo, "third_party/a.h").as_deref(), Some("a\n"));
+    assert_eq!(
+        head_file(&repo, "third_party/sub/b.h").as_deref(),
+        Some("b\n")
+    );
+    assert!(head_file(&repo, "third_party/c.h").is_none());
+    assert!(head_file(&repo, "include/a.h").is_none());
+    let dep = &repo.vendor_deps(None).unwrap()[0];
+    assert!(repo.vendor_state(dep).unwrap().is_clean());
+
+    // Local changes are extracted back into the upstream directory.
+    commit_files(&repo, &[("third_party/a.h", "changed\n")], "edit");
+    let commit = repo
+        .vendor_extract(dep, &VendorExtractOpts::default())
+        .unwrap();
+    let tree = repo.find_commit(commit).unwrap().tree().unwrap();
+    let blob = |path: &str| {
+        let entry = tree.get_path(Path::new(path)).unwrap();
+        repo.find_blob(entry.id()).unwrap().content().to_vec()
+    };
+    assert_eq!(blob("include/a.h"), b"changed\n");
+    assert_eq!(blob("src/main.c"), b"main\n");
+    assert_eq!(blob("c.h"), b"outside\n");
+    drop(upstream);
+}
+
+#[test]
+fn patterns_sharing_a_name_are_fetched_once_and_merged_together() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (upstream, up_dir) = setup_upstream(&[("include/a.h", "a\n"), ("src/main.c", "main\n")]);
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+
+    let url = up_dir.path().display();
+    let headers = format!("*.h vendored vendor-name=up vendor-url={url} vendor-src=include\n");
+    let sources = format!("*.c vendored vendor-name=up vendor-url={url} vendor-src=src\n");
+    let track = commit_files(
+        &repo,
+        &[
+            ("inc/.gitattributes", &headers),
+            ("lib/.gitattributes", &sources),
+        ],
+        "track",
+    );
+    let stats = repo
+        .vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+    assert_eq!(stats.len(), 1);
+
+    // Selecting one pattern merges every mapping of its name in one commit.
+    repo.vendor_merge(Some("*.h"), &VendorMergeOpts::default(), None)
+        .unwrap();
+    assert_eq!(head_file(&repo, "inc/a.h").as_deref(), Some("a\n"));
+    assert_eq!(head_file(&repo, "lib/main.c").as_deref(), Some("main\n"));
+    let head = repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(head.parent_ids().count(), 2);
+    assert_eq!(head.parent_id(0).unwrap(), track);
+    drop(upstream);
+}
+
+#[test]
+fn merge_uses_configured_message_template() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (upstream, up_dir) = setup_upstream(&[("a.txt", "upstream\n")]);
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+
+    let attrs = format!(
+        "*.txt vendored vendor-name=up vendor-url={} vendor-branch=main\n",
+        up_dir.path().display()
+    );
+    commit_files(&repo, &[(".gitattributes", &attrs)], "track");
+    upstream
+        .reference(
+            "refs/heads/main",
+            upstream.head().unwrap().target().unwrap(),
+            true,
+            "",
+        )
+        .unwrap();
+
+    let mut config = repo.config().unwrap();
+    config
+        .set_str("vendor.message", "chore: update {name} from {url}")
+        .unwrap();
+    config
+        .set_str(
+            "vendor.up.message",
+            "chore(vendor): {name}@{branch} {commit} ({date})",
+        )
+        .unwrap();
+
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+    repo.vendor_merge(None, &VendorMergeOpts::default(), None)
+        .unwrap();
+
+    let upstream_commit = upstream.head().unwrap().peel_to_commit().unwrap();
+    let message = repo
+        .head()
+        .unwrap()
+        .peel_to_commit()
+        .unwrap()
+        .message()
+        .unwrap()
+        .to_string();
+    assert!(message.starts_with(&format!(
+        "chore(vendor): up@main {} (",
+        upstream_commit.id()
+    )));
+    assert!(!message.contains("{date}"));
+
+    // An explicit message still wins over the template.
+    commit_files(&upstream, &[("a.txt", "updated\n")], "update");
+    upstream
+        .reference(
+            "refs/heads/main",
+            upstream.head().unwrap().target().unwrap(),
+            true,
+            "",
+        )
+        .unwrap();
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+    let opts = VendorMergeOpts {
+        message: Some("explicit".into()),
+        ..Default::default()
+    };
+    repo.vendor_merge(None, &opts, None).unwrap();
+    let head = repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(head.message(), Some("explicit"));
+}
+
+#[test]
+fn merge_applies_submodule_policy() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (upstream, up_dir) = setup_upstream(&[("deps/a.txt", "a\n")]);
+
+    // Add a gitlink next to the regular file.
+    let head = upstream.head().unwrap().peel_to_commit().unwrap();
+    let deps = head.tree().unwrap().get_name("deps").unwrap().id();
+    let mut builder = upstream
+        .treebuilder(Some(&upstream.find_tree(deps).unwrap()))
+        .unwrap();
+    let gitlink = git2::Oid::from_str("0123456789abcdef0123456789abcdef01234567").unwrap();
+    builder.insert("sub", gitlink, 0o160000).unwrap();
+    let deps = builder.write().unwrap();
+    let mut root = upstream.treebuilder(None).unwrap();
+    root.insert("deps", deps, 0o040000).unwrap();
+    let tree = upstream.find_tree(root.write().unwrap()).unwrap();
+    let sig = upstream.signature().unwrap();
+    upstream
+        .commit(Some("HEAD"), &sig, &sig, "add submodule", &tree, &[&head])
+        .unwrap();
+
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+    let attrs = format!(
+        "deps/** vendored vendor-name=up vendor-url={}\n",
+        up_dir.path().display()
+    );
+    commit_files(&repo, &[(".gitattributes", &attrs)], "track");
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+
+    let opts = VendorMergeOpts {
+        submodules: SubmodulePolicy::Error,
+        ..Default::default()
+    };
+    assert!(repo.vendor_merge(None, &opts, None).is_err());
+
+    let opts = VendorMergeOpts {
+        submodules: SubmodulePolicy::Keep,
+        index_only: true,
+        ..Default::default()
+    };
+    repo.vendor_merge(None, &opts, None).unwrap();
+    let head_tree = repo.head().unwrap().peel_to_tree().unwrap();
+    let entry = head_tree.get_path(Path::new("deps/sub")).unwrap();
+    assert_eq!(entry.filemode(), 0o160000);
+    assert_eq!(head_file(&repo, "deps/a.txt").as_deref(), Some("a\n"));
+}
+
+#[test]
+fn merge_materializes_symlinks_and_normalizes_modes() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (upstream, up_dir) = setup_upstream(&[("lib/a.txt", "a\n")]);
+
+    let head = upstream.head().unwrap().peel_to_commit().unwrap();
+    let lib = head.tree().unwrap().get_name("lib").unwrap().id();
+    let mut builder = upstream
+        .treebuilder(Some(&upstream.find_tree(lib).unwrap()))
+        .unwrap();
+    let link = upstream.blob(b"a.txt").unwrap();
+    builder.insert("link.txt", link, 0o120000).unwrap();
+    let script = upstream.blob(b"#!/bin/sh\n").unwrap();
+    builder.insert("run.sh", script, 0o100755).unwrap();
+    let lib = builder.write().unwrap();
+    let mut root = upstream.treebuilder(None).unwrap();
+    root.insert("lib", lib, 0o040000).unwrap();
+    let tree = upstream.find_tree(root.write().unwrap()).unwrap();
+    let sig = upstream.signature().unwrap();
+    upstream
+        .commit(Some("HEAD"), &sig, &sig, "add link", &tree, &[&head])
+        .unwrap();
+
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+    let attrs = format!(
+        "lib/** vendored vendor-name=up vendor-url={}\n",
+        up_dir.path().display()
+    );
+    commit_files(&repo, &[(".gitattributes", &attrs)], "track");
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+
+    let opts = VendorMergeOpts {
+        symlinks: SymlinkPolicy::Reject,
+        ..Default::default()
+    };
+    assert!(repo.vendor_merge(None, &opts, None).is_err());
+
+    let opts = VendorMergeOpts {
+        symlinks: SymlinkPolicy::Materialize,
+        normalize_executable: true,
+        ..Default::default()
+    };
+    repo.vendor_merge(None, &opts, None).unwrap();
+
+    let head_tree = repo.head().unwrap().peel_to_tree().unwrap();
+    let link = head_tree.get_path(Path::new("lib/link.txt")).unwrap();
+    assert_eq!(link.filemode(), 0o100644);
+    assert_eq!(head_file(&repo, "lib/link.txt").as_deref(), Some("a\n"));
+    let script = head_tree.get_path(Path::new("lib/run.sh")).unwrap();
+    assert_eq!(script.filemode(), 0o100644);
+}
+
+#[test]
+fn merge_index_only_leaves_worktree_untouched() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (_upstream, up_dir) = setup_upstream(&[("a.txt", "upstream\n")]);
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+
+    let attrs = format!(
+        "*.txt vendored vendor-name=up vendor-url={}\n",
+        up_dir.path().display()
+    );
+    commit_files(&repo, &[(".gitattributes", &attrs)], "track");
+
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+    let opts = VendorMergeOpts {
+        index_only: true,
+        ..Default::default()
+    };
+    repo.vendor_merge(None, &opts, None).unwrap();
+
+    assert_eq!(head_file(&repo, "a.txt").as_deref(), Some("upstream\n"));
+    assert!(!dir.path().join("a.txt").exists());
+    assert!(
+        repo.index()
+            .unwrap()
+            .get_path(Path::new("a.txt"), 0)
+            .is_some()
+    );
+}
+
+#[test]
+fn merge_index_only_works_in_bare_repo() {
+    let (_upstream, up_dir) = setup_upstream(&[("a.txt", "upstream\n")]);
+
+    // Populate a bare repository from a non-bare one tracking the dependency.
+    let (work, _work_dir) = setup_repo();
+    let attrs = format!(
+        "*.txt vendored vendor-name=up vendor-url={}\n",
+        up_dir.path().display()
+    );
+    commit_files(&work, &[(".gitattributes", &attrs)], "track");
+
+    let bare_dir = TempDir::new().unwrap();
+    let repo = Repository::init_bare(bare_dir.path()).unwrap();
+    let mut config = repo.config().unwrap();
+    config.set_str("user.name", "Test").unwrap();
+    config.set_str("user.email", "test@test").unwrap();
+
+    let work_path = work.path().display().to_string();
+    repo.remote_anonymous(&work_path)
+        .unwrap()
+        .fetch(&["+refs/heads/*:refs/heads/*"], None, None)
+        .unwrap();
+    repo.remote_anonymous(&up_dir.path().display().to_string())
+        .unwrap()
+        .fetch(&["+HEAD:refs/vendor/up"], None, None)
+        .unwrap();
+
+    let opts = VendorMergeOpts {
+        index_only: true,
+        ..Default::default()
+    };
+    repo.vendor_merge(None, &opts, None).unwrap();
+
+    assert_eq!(head_file(&repo, "a.txt").as_deref(), Some("upstream\n"));
+    assert!(head_file(&repo, ".gitattributes").is_some());
+
+    let no_commit = VendorMergeOpts {
+        index_only: true,
+        no_commit: true,
+        ..Default::default()
+    };
+    assert!(repo.vendor_merge(None, &no_commit, None).is_err());
+}
+
+#[derive(Debug)]
+struct FakeSigner;
+
+impl VendorSigner for FakeSigner {
+    fn sign(&self, buffer: &str) -> Result<String, git2::Error> {
+        assert!(buffer.contains("author Release Bot <bot@example.com>"));
+        Ok("-----BEGIN SSH SIGNATURE-----\nZmFrZQ==\n-----END SSH SIGNATURE-----\n".to_string())
+    }
+}
+
+#[test]
+fn merge_records_identity_overrides_and_signs() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (_upstream, up_dir) = setup_upstream(&[("a.txt", "upstream\n")]);
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+
+    let attrs = format!(
+        "*.txt vendored vendor-name=up vendor-url={}\n",
+        up_dir.path().display()
+    );
+    commit_files(&repo, &[(".gitattributes", &attrs)], "track");
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+
+    let opts = VendorMergeOpts {
+        author: Some("Release Bot <bot@example.com>".to_string()),
+        committer: Some("CI <ci@example.com>".to_string()),
+        signer: Some(Arc::new(FakeSigner)),
+        ..Default::default()
+    };
+    repo.vendor_merge(None, &opts, None).unwrap();
+
+    let head = repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(head.parent_count(), 2);
+    assert_eq!(head.author().name(), Some("Release Bot"));
+    assert_eq!(head.committer().email(), Some("ci@example.com"));
+    assert_eq!(head_file(&repo, "a.txt").as_deref(), Some("upstream\n"));
+    let (signature, _) = repo.extract_signature(&head.id(), None).unwrap();
+    assert!(
+        std::str::from_utf8(&signature)
+            .unwrap()
+            .starts_with("-----BEGIN SSH SIGNATURE-----")
+    );
+}
+
+// ---------------------------------------------------------------------------
+// tree-level API
+// ---------------------------------------------------------------------------
+
+#[test]
+fn tree_level_api_returns_oids_without_touching_head() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (upstream, up_dir) = setup_upstream(&[("a.txt", "upstream\n"), ("b.rs", "skip\n")]);
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+
+    let attrs = format!(
+        "*.txt vendored vendor-name=up vendor-url={}\n",
+        up_dir.path().display()
+    );
+    let head = commit_files(
+        &repo,
+        &[(".gitattributes", &attrs), ("local.rs", "mine\n")],
+        "track",
+    );
+
+    let deps = repo.vendor_deps(None).unwrap();
+    assert_eq!(deps.len(), 1);
+    assert!(repo.vendor_deps(Some("*.rs")).unwrap().is_empty());
+    let dep = &deps[0];
+
+    assert!(repo.vendor_filtered_tree(dep).is_err(), "not fetched yet");
+
+    let fetched = repo.vendor_fetch_ref(dep, None).unwrap();
+    assert_eq!(fetched, upstream.head().unwrap().target().unwrap());
+    assert_eq!(repo.refname_to_id("refs/vendor/up").unwrap(), fetched);
+
+    let filtered = repo
+        .find_tree(repo.vendor_filtered_tree(dep).unwrap())
+        .unwrap();
+    assert_eq!(filtered.len(), 1);
+    assert!(filtered.get_name("a.txt").is_some());
+
+    let merged = repo
+        .find_tree(repo.vendor_merged_tree(dep, None).unwrap())
+        .unwrap();
+    assert!(merged.get_name("a.txt").is_some());
+    assert!(merged.get_name("local.rs").is_some());
+    assert!(merged.get_name("b.rs").is_none());
+
+    assert_eq!(repo.head().unwrap().target().unwrap(), head);
+    assert!(!dir.path().join("a.txt").exists());
+}
+
+#[test]
+fn owners_maps_vendored_files_to_their_dependency() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+
+    commit_files(
+        &repo,
+        &[
+            (
+                ".gitattributes",
+                "*.txt vendored vendor-name=up vendor-url=https://example.com/up\n",
+            ),
+            (
+                "sub/.gitattributes",
+                "*.txt vendored vendor-name=sub vendor-url=https://example.com/sub\n",
+            ),
+            ("a.txt", "up\n"),
+            ("sub/b.txt", "sub\n"),
+            ("local.rs", "mine\n"),
+        ],
+        "vendor",
+    );
+
+    let owners: Vec<(String, String)> = repo
+        .vendor_owners()
+        .unwrap()
+        .into_iter()
+        .map(|owner| (owner.path, owner.name))
+        .collect();
+    assert_eq!(
+        owners,
+        [
+            ("a.txt".to_string(), "up".to_string()),
+            ("sub/b.txt".to_string(), "sub".to_string()),
+        ]
+    );
+}
+
+/// List the regular files of a tar `archive` with their contents.
+fn tar_files(archive: &[u8]) -> Vec<(String, String)> {
+    let mut files = Vec::new();
+    let mut offset = 0;
+    while archive[offset] != 0 {
+        let header = &archive[offset..offset + 512];
+        let field = |range: std::ops::Range<usize>| {
+            let bytes = &header[range];
+            let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+            String::from_utf8(bytes[..end].to_vec()).unwrap()
+        };
+        let size = usize::from_str_radix(&field(124..136), 8).unwrap();
+        let data = &archive[offset + 512..offset + 512 + size];
+        files.push((field(0..100), String::from_utf8(data.to_vec()).unwrap()));
+        offset += 512 + size.div_ceil(512) * 512;
+    }
+    files
+}
+
+#[test]
+fn archive_writes_the_merged_upstream_files() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (upstream, up_dir) =
+        setup_upstream(&[("a.txt", "a\n"), ("docs/b.txt", "b\n"), ("c.rs", "c\n")]);
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+    let attrs = format!(
+        "*.txt vendored vendor-name=up vendor-url={}\n",
+        up_dir.path().display()
+    );
+    commit_files(&repo, &[(".gitattributes", &attrs)], "track");
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+    repo.vendor_merge(None, &VendorMergeOpts::default(), None)
+        .unwrap();
+    let merged = upstream.head().unwrap().target().unwrap();
+
+    // Fetched but unmerged changes are not archived.
+    commit_files(&upstream, &[("a.txt", "updated\n")], "update");
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+
+    let opts = VendorArchiveOpts {
+        prefix: Some("up/".into()),
+        ..Default::default()
+    };
+    let mut tar = Vec::new();
+    assert_eq!(repo.vendor_archive("up", &opts, &mut tar).unwrap(), merged);
+    assert_eq!(
+        tar_files(&tar),
+        [
+            ("up/a.txt".to_string(), "a\n".to_string()),
+            ("up/docs/b.txt".to_string(), "b\n".to_string()),
+        ]
+    );
+    let mut again = Vec::new();
+    repo.vendor_archive("up", &opts, &mut again).unwrap();
+    assert_eq!(tar, again);
+
+    let opts = VendorArchiveOpts {
+        upstream: Some(upstream.head().unwrap().target().unwrap()),
+        ..Default::default()
+    };
+    let mut tar = Vec::new();
+    repo.vendor_archive("up", &opts, &mut tar).unwrap();
+    assert_eq!(tar_files(&tar)[0], ("a.txt".into(), "updated\n".into()));
+
+    assert!(
+        repo.vendor_archive("missing", &opts, &mut Vec::new())
+            .is_err()
+    );
+}
+
+#[test]
+fn archive_compressed_formats_extract_with_system_tools() {
+    if Command::new("tar").arg("--version").output().is_err()
+        || Command::new("unzip").arg("-v").output().is_err()
+    {
+        eprintln!("tar or unzip not available; skipping");
+        return;
+    }
+    let _guard = CWD_LOCK.lock().unwrap();
+    let text: String = (0..2000).map(|i| format!("line {}\n", i % 70)).collect();
+    let (_upstream, up_dir) = setup_upstream(&[("big.txt", &text), ("empty.txt", "")]);
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+    let attrs = format!(
+        "*.txt vendored vendor-name=up vendor-url={}\n",
+        up_dir.path().display()
+    );
+    commit_files(&repo, &[(".gitattributes", &attrs)], "track");
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+    repo.vendor_merge(None, &VendorMergeOpts::default(), None)
+        .unwrap();
+
+    let out = TempDir::new().unwrap();
+    for (format, file, extract) in [
+        (ArchiveFormat::TarGz, "up.tar.gz", ["tar", "-xzf"]),
+        (ArchiveFormat::Zip, "up.zip", ["unzip", "-q"]),
+    ] {
+        let opts = VendorArchiveOpts {
+            format,
+            prefix: Some(format!("{file}.d/")),
+            ..Default::default()
+        };
+        let mut archive = Vec::new();
+        repo.vendor_archive("up", &opts, &mut archive).unwrap();
+        assert!(archive.len() < text.len() / 2, "{file} is not compressed");
+        fs::write(out.path().join(file), &archive).unwrap();
+
+        let status = Command::new(extract[0])
+            .arg(extract[1])
+            .arg(file)
+            .current_dir(out.path())
+            .status()
+            .unwrap();
+        assert!(status.success(), "{file} does not extract");
+        let extracted = out.path().join(format!("{file}.d"));
+        assert_eq!(fs::read_to_string(extracted.join("big.txt")).unwrap(), text);
+        assert_eq!(fs::read_to_string(extracted.join("empty.txt")).unwrap(), "");
+    }
+}
+
+#[test]
+fn check_staged_flags_hand_edits_to_vendored_files() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (upstream, up_dir) = setup_upstream(&[("a.txt", "one\n")]);
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+
+    let attrs = format!(
+        "*.txt vendored vendor-name=up vendor-url={}\n",
+        up_dir.path().display()
+    );
+    commit_files(&repo, &[(".gitattributes", &attrs)], "track");
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+    repo.vendor_merge(None, &VendorMergeOpts::default(), None)
+        .unwrap();
+
+    let stage = |path: &str, content: &str| {
+        fs::write(dir.path().join(path), content).unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new(path)).unwrap();
+        index.write().unwrap();
+    };
+    stage("local.rs", "mine\n");
+    assert!(repo.vendor_check_staged().unwrap().is_empty());
+
+    stage("a.txt", "edited\n");
+    let flagged = repo.vendor_check_staged().unwrap();
+    assert_eq!(flagged.len(), 1);
+    assert_eq!(flagged[0].path, "a.txt");
+    assert_eq!(flagged[0].name, "up");
+
+    // A squashed update stages the upstream content without MERGE_HEAD.
+    stage("a.txt", "one\n");
+    commit_files(&upstream, &[("a.txt", "two\n")], "second");
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+    let squash = VendorMergeOpts {
+        squash: true,
+        ..Default::default()
+    };
+    repo.vendor_merge(None, &squash, None).unwrap();
+    assert!(repo.vendor_check_staged().unwrap().is_empty());
+}
+
+#[test]
+fn check_staged_handles_a_conflicted_index() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (repo, _dir, _upstream, _up_dir) = setup_diverged();
+    repo.vendor_merge(None, &VendorMergeOpts::default(), None)
+        .unwrap_err();
+    assert!(repo.index().unwrap().has_conflicts());
+
+    let flagged = repo.vendor_check_staged().unwrap();
+    assert!(flagged.is_empty(), "{flagged:?}");
+}
+
+/// Write a tree of `files` (flat names only) to `repo`'s object database.
+fn write_tree<'r>(repo: &'r Repository, files: &[(&str, &str)]) -> git2::Tree<'r> {
+    let mut builder = repo.treebuilder(None).unwrap();
+    for (name, content) in files {
+        let blob = repo.blob(content.as_bytes()).unwrap();
+        builder.insert(name, blob, 0o100644).unwrap();
+    }
+    repo.find_tree(builder.write().unwrap()).unwrap()
+}
+
+#[test]
+fn tree_merge_runs_in_memory() {
+    let odb = git2::Odb::new().unwrap();
+    odb.add_new_mempack_backend(1000).unwrap();
+    let repo = Repository::from_odb(odb).unwrap();
+    let dep = VendorDep {
+        name: "up".to_string(),
+        pattern: "*.txt".to_string(),
+        url: "https://example.com/up".to_string(),
+        ..Default::default()
+    };
+
+    let base = write_tree(&repo, &[("a.txt", "one\n"), ("b.rs", "skip\n")]);
+    let upstream = write_tree(&repo, &[("a.txt", "two\n"), ("b.rs", "skip\n")]);
+    let ours = write_tree(&repo, &[("a.txt", "one\n"), ("local.rs", "mine\n")]);
+
+    let filtered = repo
+        .find_tree(repo.vendor_filter_tree(&dep, &upstream).unwrap())
+        .unwrap();
+    assert_eq!(filtered.len(), 1);
+
+    let merged = repo
+        .vendor_merge_trees(&dep, &ours, Some(&base), &upstream, None)
+        .unwrap();
+    let merged = repo.find_tree(merged).unwrap();
+    let a = merged.get_name("a.txt").unwrap().id();
+    assert_eq!(repo.find_blob(a).unwrap().content(), b"two\n");
+    assert!(merged.get_name("local.rs").is_some());
+    assert!(merged.get_name("b.rs").is_none());
+
+    let local = write_tree(&repo, &[("a.txt", "mine\n")]);
+    let err = repo
+        .vendor_merge_trees(&dep, &local, Some(&base), &upstream, None)
+        .unwrap_err();
+    assert!(err.message().contains("Conflicts"));
+}
+
+// ---------------------------------------------------------------------------
+// log
+// ---------------------------------------------------------------------------
+
+#[test]
+fn log_lists_upstream_commits_not_yet_merged() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (upstream, up_dir) = setup_upstream(&[("a.txt", "one\n")]);
+    commit_files(&upstream, &[("a.txt", "two\n")], "second");
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+
+    let attrs = format!(
+        "*.txt vendored vendor-name=up vendor-url={}\n",
+        up_dir.path().display()
+    );
+    commit_files(&repo, &[(".gitattributes", &attrs)], "track");
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+    let dep = &repo.vendor_deps(None).unwrap()[0];
+
+    let log = repo.vendor_log(dep, &VendorLogOpts::default()).unwrap();
+    let summaries: Vec<&str> = log.iter().map(|e| e.summary.as_str()).collect();
+    assert_eq!(summaries, ["second", "upstream"]);
+    assert_eq!(log[0].author, "Upstream");
+    assert_eq!(log[0].date().len(), "YYYY-MM-DD".len());
+
+    let limited = VendorLogOpts {
+        max_count: Some(1),
+        ..Default::default()
+    };
+    assert_eq!(repo.vendor_log(dep, &limited).unwrap().len(), 1);
+
+    repo.vendor_merge(None, &VendorMergeOpts::default(), None)
+        .unwrap();
+    assert!(
+        repo.vendor_log(dep, &VendorLogOpts::default())
+            .unwrap()
+            .is_empty()
+    );
+
+    commit_files(&upstream, &[("a.txt", "three\n")], "third");
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+    let log = repo.vendor_log(dep, &VendorLogOpts::default()).unwrap();
+    assert_eq!(log.len(), 1);
+    assert_eq!(log[0].summary, "third");
+}
+
+#[test]
+fn changelog_describes_pending_and_last_merge() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (upstream, up_dir) = setup_upstream(&[("a.txt", "one\n")]);
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+
+    let attrs = format!(
+        "*.txt vendored vendor-name=up vendor-url={}\n",
+        up_dir.path().display()
+    );
+    commit_files(&repo, &[(".gitattributes", &attrs)], "track");
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+    repo.vendor_merge(None, &VendorMergeOpts::default(), None)
+        .unwrap();
+    let first = upstream.head().unwrap().target().unwrap();
+
+    commit_files(&upstream, &[("a.txt", "two\n")], "second");
+    commit_files(&upstream, &[("a.txt", "three\n")], "third");
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+    let dep = &repo.vendor_deps(None).unwrap()[0];
+
+    let pending = repo
+        .vendor_changelog(dep, &VendorLogOpts::default())
+        .unwrap();
+    assert!(pending.starts_with(&format!("## up `{}`..", &first.to_string()[..7])));
+    assert!(pending.contains("2 commits by 1 author.\n\n### Upstream\n\n"));
+    assert!(pending.contains(" third\n"));
+    assert!(pending.contains(" second\n"));
+
+    repo.vendor_merge(None, &VendorMergeOpts::default(), None)
+        .unwrap();
+    commit_files(&repo, &[("local.md", "x\n")], "local work");
+    commit_files(&upstream, &[("a.txt", "four\n")], "fourth");
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+
+    let last = VendorLogOpts {
+        last_merge: true,
+        ..Default::default()
+    };
+    let summaries: Vec<String> = repo
+        .vendor_log(dep, &last)
+        .unwrap()
+        .into_iter()
+        .map(|e| e.summary)
+        .collect();
+    assert_eq!(summaries, ["third", "second"]);
+    assert_eq!(repo.vendor_changelog(dep, &last).unwrap(), pending);
+}
+
+#[test]
+fn status_and_outdated_report_fork_drift() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (original, orig_dir) = setup_upstream(&[("a.txt", "v1\n")]);
+    let fork_dir = TempDir::new().unwrap();
+    let fork = Repository::clone(orig_dir.path().to_str().unwrap(), fork_dir.path()).unwrap();
+    let mut config = fork.config().unwrap();
+    config.set_str("user.name", "Fork").unwrap();
+    config.set_str("user.email", "fork@test").unwrap();
+    commit_files(&fork, &[("b.txt", "ours\n")], "our patch");
+    commit_files(&original, &[("a.txt", "v2\n")], "v2");
+    let v3 = commit_files(&original, &[("a.txt", "v3\n")], "v3");
+
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+    let attrs = format!(
+        "*.txt vendored vendor-name=up vendor-url={} vendor-upstream={}\n",
+        fork_dir.path().display(),
+        orig_dir.path().display()
+    );
+    commit_files(&repo, &[(".gitattributes", &attrs)], "track");
+    assert_eq!(repo.vendor_statuses(None).unwrap()[0].fork, None);
+
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+    assert_eq!(repo.refname_to_id("refs/vendor-upstream/up").unwrap(), v3);
+    let drift = repo.vendor_statuses(None).unwrap()[0].fork.unwrap();
+    assert_eq!(
+        (drift.upstream, drift.ahead, drift.behind),
+        (v3, Some(1), Some(2))
+    );
+    assert_eq!(drift.summary(), "1 ahead, 2 behind upstream");
+    assert_eq!(
+        repo.vendor_outdated(None, &VendorFetchOpts::default(), None)
+            .unwrap()[0]
+            .fork,
+        Some(drift)
+    );
+    let findings = repo.vendor_doctor(false).unwrap();
+    assert!(
+        !findings
+            .iter()
+            .any(|f| f.kind == VendorFindingKind::OrphanedRef)
+    );
+
+    // Outdated sees new commits of the forked repository without fetching
+    // them, but cannot count them.
+    let v4 = commit_files(&original, &[("a.txt", "v4\n")], "v4");
+    let drift = repo
+        .vendor_outdated(None, &VendorFetchOpts::default(), None)
+        .unwrap()[0]
+        .fork
+        .unwrap();
+    assert_eq!(
+        (drift.upstream, drift.ahead, drift.behind),
+        (v4, None, None)
+    );
+}
+
+#[test]
+fn outdated_contacts_upstreams_like_fetch() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (upstream, up_dir) = setup_upstream(&[("a.txt", "v1\n")]);
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+    let unavailable = serve_http_status("503 Service Unavailable");
+    let attrs = format!(
+        "*.txt vendored vendor-name=up vendor-url=http://{}/up.git vendor-header=X-Token:secret \
+         vendor-mirror={}\n",
+        unavailable.0,
+        up_dir.path().display()
+    );
+    commit_files(&repo, &[(".gitattributes", &attrs)], "track");
+
+    // The upstream is retried with the dependency's headers, then its mirror
+    // answers.
+    let opts = VendorFetchOpts {
+        headers: vec!["X-Cli: 1".into()],
+        attempts: 2,
+        retry_delay: Some(Duration::ZERO),
+        ..Default::default()
+    };
+    let mut fetch_opts = git2::FetchOptions::new();
+    let report = repo
+        .vendor_outdated(None, &opts, Some(&mut fetch_opts))
+        .unwrap();
+    assert_eq!(
+        report[0].upstream,
+        upstream.head().unwrap().target().unwrap()
+    );
+    let requests = unavailable.1.lock().unwrap();
+    assert_eq!(requests.len(), 2);
+    assert!(requests[0].contains("X-Token:secret"), "{}", requests[0]);
+    assert!(requests[0].contains("X-Cli: 1"), "{}", requests[0]);
+    // Nothing was fetched.
+    assert!(repo.find_commit(report[0].upstream).is_err());
+}
+
+#[test]
+fn outdated_compares_upstream_tip_to_merged_commit() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (upstream, up_dir) = setup_upstream(&[("a.txt", "v1\n")]);
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+
+    let attrs = format!(
+        "*.txt vendored vendor-name=up vendor-url={}\n",
+        up_dir.path().display()
+    );
+    commit_files(&repo, &[(".gitattributes", &attrs)], "track");
+    let v1 = upstream.head().unwrap().target().unwrap();
+
+    let report = repo
+        .vendor_outdated(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+    assert_eq!(report.len(), 1);
+    assert_eq!(report[0].upstream, v1);
+    assert_eq!(report[0].fetched, None);
+    assert_eq!(report[0].merged, None);
+    assert!(report[0].is_outdated());
+
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+    repo.vendor_merge(None, &VendorMergeOpts::default(), None)
+        .unwrap();
+    assert!(
+        !repo
+            .vendor_outdated(None, &VendorFetchOpts::default(), None)
+            .unwrap()[0]
+            .is_outdated()
+    );
+
+    // A new upstream commit is detected without fetching it.
+    let v2 = commit_files(&upstream, &[("a.txt", "v2\n")], "update");
+    let report = repo
+        .vendor_outdated(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+    assert!(report[0].is_outdated());
+    assert_eq!(report[0].upstream, v2);
+    assert_eq!(report[0].fetched, Some(v1));
+    assert_eq!(report[0].merged, Some(v1));
+    assert_eq!(repo.refname_to_id("refs/vendor/up").unwrap(), v1);
+}
+
+#[test]
+fn preview_reports_pending_upstream_changes() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (upstream, up_dir) = setup_upstream(&[("a.txt", "one\n"), ("b.txt", "b\n")]);
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+
+    let attrs = format!(
+        "*.txt vendored vendor-name=up vendor-url={}\n",
+        up_dir.path().display()
+    );
+    commit_files(&repo, &[(".gitattributes", &attrs)], "track");
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+    let dep = &repo.vendor_deps(None).unwrap()[0];
+
+    let preview = repo.vendor_preview(dep).unwrap();
+    assert_eq!(preview.behind, 1);
+    assert_eq!(preview.files_changed, 2);
+    assert_eq!(preview.insertions, 2);
+    assert!(preview.diffstat.contains("a.txt"));
+
+    repo.vendor_merge(None, &VendorMergeOpts::default(), None)
+        .unwrap();
+    assert!(repo.vendor_preview(dep).unwrap().is_up_to_date());
+
+    commit_files(&upstream, &[("a.txt", "two\n")], "second");
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+    let preview = repo.vendor_preview(dep).unwrap();
+    assert_eq!(preview.behind, 1);
+    assert_eq!(preview.files_changed, 1);
+    assert_eq!((preview.insertions, preview.deletions), (1, 1));
+    assert!(!preview.diffstat.contains("b.txt"));
+}
+
+#[test]
+fn state_distinguishes_clean_modified_and_behind() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (upstream, up_dir) = setup_upstream(&[("a.txt", "one\n"), ("b.txt", "b\n")]);
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+
+    let attrs = format!(
+        "*.txt vendored vendor-name=up vendor-url={}\n",
+        up_dir.path().display()
+    );
+    commit_files(&repo, &[(".gitattributes", &attrs)], "track");
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+    let dep = &repo.vendor_deps(None).unwrap()[0];
+    assert_eq!(
+        repo.vendor_state(dep).unwrap().summary(),
+        "update available (2 files differ)"
+    );
+
+    repo.vendor_merge(None, &VendorMergeOpts::default(), None)
+        .unwrap();
+    assert!(repo.vendor_state(dep).unwrap().is_clean());
+
+    // Unstaged, then committed, local edits.
+    fs::write(dir.path().join("b.txt"), "local\n").unwrap();
+    assert_eq!(
+        repo.vendor_state(dep).unwrap().summary(),
+        "locally modified"
+    );
+    commit_files(&repo, &[("b.txt", "local\n")], "edit");
+    assert!(repo.vendor_state(dep).unwrap().locally_modified);
+
+    commit_files(&upstream, &[("a.txt", "two\n")], "second");
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+    assert_eq!(
+        repo.vendor_state(dep).unwrap().summary(),
+        "locally modified, update available (1 files differ)"
+    );
+    assert!(repo.vendor_statuses(None).is_ok());
+}
+
+// ---------------------------------------------------------------------------
+// extract
+// ---------------------------------------------------------------------------
+
+#[test]
+fn extract_replays_local_changes_onto_upstream() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (upstream, up_dir) =
+        setup_upstream(&[("a.txt", "one\n"), ("b.txt", "b\n"), ("c.rs", "c\n")]);
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+
+    let attrs = format!(
+        "*.txt vendored vendor-name=up vendor-url={}\n",
+        up_dir.path().display()
+    );
+    commit_files(&repo, &[(".gitattributes", &attrs)], "track");
+    let dep = &repo.vendor_deps(None).unwrap()[0];
+
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+    let opts = VendorExtractOpts::default();
+    assert!(repo.vendor_extract(dep, &opts).is_err());
+
+    repo.vendor_merge(None, &VendorMergeOpts::default(), None)
+        .unwrap();
+    assert!(repo.vendor_extract(dep, &opts).is_err());
+
+    // Change a vendored file locally, while upstream moves on.
+    commit_files(&repo, &[("a.txt", "one\nlocal\n")], "local fix");
+    let upstream_tip = commit_files(&upstream, &[("b.txt", "b2\n")], "upstream change");
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+
+    let opts = VendorExtractOpts {
+        message: Some("Fix a".into()),
+        branch: Some("contrib".into()),
+    };
+    let commit = repo.vendor_extract(dep, &opts).unwrap();
+    let commit = repo.find_commit(commit).unwrap();
+    assert_eq!(commit.parent_id(0).unwrap(), upstream_tip);
+    assert_eq!(commit.message(), Some("Fix a"));
+
+    let read = |path: &str| {
+        let entry = commit.tree().unwrap().get_path(Path::new(path)).unwrap();
+        let blob = repo.find_blob(entry.id()).unwrap();
+        String::from_utf8(blob.content().to_vec()).unwrap()
+    };
+    assert_eq!(read("a.txt"), "one\nlocal\n");
+    assert_eq!(read("b.txt"), "b2\n");
+    assert_eq!(read("c.rs"), "c\n");
+    assert!(commit.tree().unwrap().get_name(".gitattributes").is_none());
+
+    let branch = repo
+        .find_branch("contrib", git2::BranchType::Local)
+        .unwrap();
+    assert_eq!(branch.get().target(), Some(commit.id()));
+}
+
+// ---------------------------------------------------------------------------
+// signature verification
+// ---------------------------------------------------------------------------
+
+#[test]
+fn merge_records_licenses_and_enforces_allow_list() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (_upstream, up_dir) = setup_upstream(&[
+        (
+            "LICENSE",
+            "MIT License\n\nPermission is hereby granted, free of charge, to any person\n",
+        ),
+        ("lib.c", "// SPDX-License-Identifier: Apache-2.0\nint x;\n"),
+    ]);
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+
+    let attrs = format!(
+        "LICENSE vendored vendor-name=up vendor-url={}\n\
+         *.c vendored vendor-name=up vendor-url={}\n",
+        up_dir.path().display(),
+        up_dir.path().display()
+    );
+    let track = commit_files(&repo, &[(".gitattributes", &attrs)], "track");
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+
+    let dep = repo.vendor_deps(Some("LICENSE")).unwrap().remove(0);
+    assert_eq!(repo.vendor_licenses(&dep).unwrap(), vec!["MIT"]);
+
+    let mut config = repo.config().unwrap();
+    config.set_str("vendor.allowedLicenses", "MIT").unwrap();
+    let err = repo
+        .vendor_merge(Some("*.c"), &VendorMergeOpts::default(), None)
+        .unwrap_err();
+    assert!(err.message().contains("License Apache-2.0 of up"));
+    assert_eq!(repo.head().unwrap().target().unwrap(), track);
+
+    config
+        .set_str("vendor.allowedLicenses", "MIT, Apache-2.0")
+        .unwrap();
+    repo.vendor_merge(None, &VendorMergeOpts::default(), None)
+        .unwrap();
+    let head = repo.head().unwrap().peel_to_commit().unwrap();
+    assert!(
+        head.message()
+            .unwrap()
+            .contains("\n\nVendor-License: Apache-2.0\n")
+    );
+}
+
+#[test]
+fn merge_enforces_file_size_and_binary_policy() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (_upstream, up_dir) = setup_upstream(&[
+        ("big.txt", &"x".repeat(2048)),
+        ("lib.bin", "\0\x01binary"),
+        ("small.txt", "ok\n"),
+    ]);
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+
+    let attrs = format!(
+        "* vendored vendor-name=up vendor-url={}\n",
+        up_dir.path().display()
+    );
+    let track = commit_files(&repo, &[(".gitattributes", &attrs)], "track");
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+
+    let mut config = repo.config().unwrap();
+    config.set_str("vendor.maxFileSize", "-1").unwrap();
+    let err = repo
+        .vendor_merge(None, &VendorMergeOpts::default(), None)
+        .unwrap_err();
+    assert!(
+        err.message().contains("Invalid vendor.maxFileSize"),
+        "{}",
+        err.message()
+    );
+
+    config.set_str("vendor.maxFileSize", "1k").unwrap();
+    let err = repo
+        .vendor_merge(None, &VendorMergeOpts::default(), None)
+        .unwrap_err();
+    assert!(err.message().contains("big.txt (2048 bytes"));
+    assert!(!err.message().contains("lib.bin"));
+    assert_eq!(repo.head().unwrap().target().unwrap(), track);
+
+    config.set_str("vendor.denyBinary", "*.bin").unwrap();
+    let err = repo
+        .vendor_merge(None, &VendorMergeOpts::default(), None)
+        .unwrap_err();
+    assert!(err.message().contains("lib.bin (binary"));
+
+    config.set_str("vendor.blobPolicy", "warn").unwrap();
+    repo.vendor_merge(None, &VendorMergeOpts::default(), None)
+        .unwrap();
+    assert_eq!(head_file(&repo, "lib.bin").as_deref(), Some("\0\x01binary"));
+}
+
+#[test]
+fn merge_keeps_configured_empty_dirs() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (_upstream, up_dir) = setup_upstream(&[("a.txt", "a\n"), ("out/build.o", "obj\n")]);
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+
+    let attrs = format!(
+        "*.txt vendored vendor-name=up vendor-url={} vendor-keep-dir=out\n",
+        up_dir.path().display()
+    );
+    commit_files(&repo, &[(".gitattributes", &attrs)], "track");
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+    repo.vendor_merge(None, &VendorMergeOpts::default(), None)
+        .unwrap();
+
+    assert_eq!(head_file(&repo, "out/.gitkeep").as_deref(), Some(""));
+    assert_eq!(head_file(&repo, "out/build.o"), None);
+    assert!(dir.path().join("out/.gitkeep").exists());
+    let statuses = repo.vendor_statuses(None).unwrap();
+    assert!(statuses[0].is_up_to_date());
+}
+
+#[test]
+fn excluded_lists_upstream_files_no_pattern_vendors() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (_upstream, up_dir) = setup_upstream(&[
+        ("a.txt", "a\n"),
+        ("README.md", "readme\n"),
+        ("build.sh", "make\n"),
+        ("lib/x.rs", "x\n"),
+        ("lib/y.bin", "y\n"),
+    ]);
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+
+    let url = up_dir.path().display();
+    let attrs = format!(
+        "*.txt vendored vendor-name=up vendor-url={url}\n\
+         README.md vendored vendor-name=up vendor-url={url}\n\
+         *.rs vendored vendor-name=lib vendor-url={url} vendor-src=lib\n"
+    );
+    commit_files(&repo, &[(".gitattributes", &attrs)], "track");
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+
+    let up = repo.vendor_deps(Some("*.txt")).unwrap().remove(0);
+    assert_eq!(
+        repo.vendor_excluded(&up).unwrap(),
+        ["build.sh", "lib/x.rs", "lib/y.bin"]
+    );
+    let lib = repo.vendor_deps(Some("*.rs")).unwrap().remove(0);
+    assert_eq!(repo.vendor_excluded(&lib).unwrap(), ["lib/y.bin"]);
+}
+
+#[test]
+fn doctor_reports_and_fixes_inconsistencies() {
+    use git_vendor::VendorFindingKind::*;
+
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (_upstream, up_dir) = setup_upstream(&[("a.txt", "upstream\n")]);
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+
+    let attrs = format!(
+        "*.txt vendored vendor-name=up vendor-url={url}\n\
+         *.md vendor-name=docs vendor-url={url}\n\
+         *.rs vendored vendor-name=up vendor-url={url} vendor-branch=dev\n",
+        url = up_dir.path().display()
+    );
+    commit_files(&repo, &[(".gitattributes", &attrs)], "track");
+    repo.vendor_fetch(Some("*.txt"), &VendorFetchOpts::default(), None)
+        .unwrap();
+    repo.vendor_merge(Some("*.txt"), &VendorMergeOpts::default(), None)
+        .unwrap();
+    let head = repo.head().unwrap().target().unwrap();
+    repo.reference("refs/vendor/old", head, true, "").unwrap();
+
+    let kinds = |findings: &[git_vendor::VendorFinding]| {
+        let mut kinds: Vec<_> = findings.iter().map(|f| f.kind).collect();
+        kinds.sort_by_key(|k| format!("{k:?}"));
+        kinds
+    };
+    let findings = repo.vendor_doctor(false).unwrap();
+    assert_eq!(
+        kinds(&findings),
+        vec![
+            ConflictingName,
+            MissingVendoredFlag,
+            OrphanedRef,
+            UnmatchedPattern
+        ]
+    );
+    assert!(findings.iter().all(|f| !f.fixed));
+
+    let findings = repo.vendor_doctor(true).unwrap();
+    assert!(
+        findings
+            .iter()
+            .any(|f| f.kind == MissingVendoredFlag && f.fixed)
+    );
+    assert!(findings.iter().any(|f| f.kind == OrphanedRef && f.fixed));
+    assert!(repo.find_reference("refs/vendor/old").is_err());
+    assert_eq!(repo.vendor_deps(None).unwrap().len(), 3);
+
+    fs::write(
+        repo.path().join("refs/vendor/docs"),
+        "0123456789012345678901234567890123456789\n",
+    )
+    .unwrap();
+    let findings = repo.vendor_doctor(true).unwrap();
+    assert!(findings.iter().any(|f| f.kind == MissingCommit && f.fixed));
+    let findings = repo.vendor_doctor(false).unwrap();
+    assert_eq!(
+        kinds(&findings),
+        vec![ConflictingName, UnmatchedPattern, UnmatchedPattern]
+    );
+}
+
+// ---------------------------------------------------------------------------
+// Hooks
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Default)]
+struct RecordingHooks {
+    events: Mutex<Vec<String>>,
+}
+
+impl VendorHooks for RecordingHooks {
+    fn pre_fetch(&self, _repo: &Repository, dep: &VendorDep) -> Result<(), git2::Error> {
+        self.events
+            .lock()
+            .unwrap()
+            .push(format!("pre-fetch {}", dep.name));
+        Ok(())
+    }
+
+    fn pre_merge(&self, _repo: &Repository, dep: &VendorDep, tree: Oid) -> Result<(), git2::Error> {
+        self.events
+            .lock()
+            .unwrap()
+            .push(format!("pre-merge {} {tree}", dep.name));
+        Ok(())
+    }
+
+    fn post_commit(
+        &self,
+        _repo: &Repository,
+        dep: &VendorDep,
+        commit: Oid,
+    ) -> Result<(), git2::Error> {
+        self.events
+            .lock()
+            .unwrap()
+            .push(format!("post-commit {} {commit}", dep.name));
+        Ok(())
+    }
+}
+
+#[test]
+fn hooks_run_around_fetch_and_merge() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (_upstream, up_dir) = setup_upstream(&[("a.txt", "upstream\n")]);
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+
+    let attrs = format!(
+        "*.txt vendored vendor-name=up vendor-url={}\n",
+        up_dir.path().display()
+    );
+    commit_files(&repo, &[(".gitattributes", &attrs)], "track");
+
+    let hooks = Arc::new(RecordingHooks::default());
+    let fetch_opts = VendorFetchOpts {
+        hooks: Some(hooks.clone()),
+        ..Default::default()
+    };
+    repo.vendor_fetch(None, &fetch_opts, None).unwrap();
+    let merge_opts = VendorMergeOpts {
+        hooks: Some(hooks.clone()),
+        ..Default::default()
+    };
+    repo.vendor_merge(None, &merge_opts, None).unwrap();
+
+    let dep = repo.vendor_deps(None).unwrap().remove(0);
+    let tree = repo.vendor_filtered_tree(&dep).unwrap();
+    let head = repo.head().unwrap().target().unwrap();
+    assert_eq!(
+        *hooks.events.lock().unwrap(),
+        vec![
+            "pre-fetch up".to_string(),
+            format!("pre-merge up {tree}"),
+            format!("post-commit up {head}"),
+        ]
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn post_commit_hooks_run_once_per_dependency() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (_upstream, up_dir) = setup_upstream(&[("a.txt", "a\n"), ("b.md", "b\n")]);
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+
+    let attrs = format!(
+        "*.txt vendored vendor-name=up vendor-url={0}\n\
+         *.md vendored vendor-name=up vendor-url={0}\n",
+        up_dir.path().display()
+    );
+    commit_files(&repo, &[(".gitattributes", &attrs)], "track");
+
+    let hooks_dir = repo.path().join("hooks");
+    fs::create_dir_all(&hooks_dir).unwrap();
+    let hook = hooks_dir.join("vendor-post-commit");
+    fs::write(&hook, "#!/bin/sh\necho \"$@\" >> hook-args\n").unwrap();
+    fs::set_permissions(&hook, fs::Permissions::from_mode(0o755)).unwrap();
+
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+    let hooks = Arc::new(RecordingHooks::default());
+    let merge_opts = VendorMergeOpts {
+        hooks: Some(hooks.clone()),
+        ..Default::default()
+    };
+    repo.vendor_merge(None, &merge_opts, None).unwrap();
+
+    let head = repo.head().unwrap().target().unwrap();
+    let post_commits: Vec<String> = hooks
+        .events
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|event| event.starts_with("post-commit"))
+        .cloned()
+        .collect();
+    assert_eq!(post_commits, vec![format!("post-commit up {head}")]);
+    assert_eq!(
+        fs::read_to_string(dir.path().join("hook-args")).unwrap(),
+        format!("up {head}\n")
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn failing_pre_merge_hook_script_aborts_merge() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (_upstream, up_dir) = setup_upstream(&[("a.txt", "upstream\n")]);
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+
+    let attrs = format!(
+        "*.txt vendored vendor-name=up vendor-url={}\n",
+        up_dir.path().display()
+    );
+    let track = commit_files(&repo, &[(".gitattributes", &attrs)], "track");
+
+    let hooks_dir = repo.path().join("hooks");
+    fs::create_dir_all(&hooks_dir).unwrap();
+    let hook = hooks_dir.join("vendor-pre-merge");
+    fs::write(&hook, "#!/bin/sh\necho \"$@\" > hook-args\nexit 1\n").unwrap();
+    fs::set_permissions(&hook, fs::Permissions::from_mode(0o755)).unwrap();
+
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+    let err = repo
+        .vendor_merge(None, &VendorMergeOpts::default(), None)
+        .unwrap_err();
+    assert!(err.message().contains("vendor-pre-merge hook rejected"));
+    assert_eq!(repo.head().unwrap().target().unwrap(), track);
+
+    let dep = repo.vendor_deps(None).unwrap().remove(0);
+    let tree = repo.vendor_filtered_tree(&dep).unwrap();
+    let args = fs::read_to_string(dir.path().join("hook-args")).unwrap();
+    assert_eq!(args.trim(), format!("up {tree}"));
+}
+
+#[test]
+fn merge_in_linked_worktree_uses_its_head_and_shared_hooks() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (_upstream, up_dir) = setup_upstream(&[("a.txt", "upstream\n")]);
+    let (repo, _dir) = setup_repo();
+    let attrs = format!(
+        "*.txt vendored vendor-name=up vendor-url={}\n",
+        up_dir.path().display()
+    );
+    let track = commit_files(&repo, &[(".gitattributes", &attrs)], "track");
+
+    let hooks_dir = repo.path().join("hooks");
+    fs::create_dir_all(&hooks_dir).unwrap();
+    let hook = hooks_dir.join("vendor-pre-merge");
+    fs::write(&hook, "#!/bin/sh\necho \"$1\" > hook-ran\n").unwrap();
+    fs::set_permissions(&hook, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let wt_dir = TempDir::new().unwrap();
+    let wt_path = wt_dir.path().join("wt");
+    repo.worktree("wt", &wt_path, None).unwrap();
+    let wt = Repository::open(&wt_path).unwrap();
+    std::env::set_current_dir(&wt_path).unwrap();
+
+    wt.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+    let no_commit = VendorMergeOpts {
+        no_commit: true,
+        ..Default::default()
+    };
+    wt.vendor_merge(None, &no_commit, None).unwrap();
+    assert!(wt.path().join("MERGE_HEAD").exists());
+    assert!(!repo.path().join("MERGE_HEAD").exists());
+    assert!(
+        wt.index()
+            .unwrap()
+            .get_path(Path::new("a.txt"), 0)
+            .is_some()
+    );
+    assert!(
+        repo.index()
+            .unwrap()
+            .get_path(Path::new("a.txt"), 0)
+            .is_none()
+    );
+    assert_eq!(
+        fs::read_to_string(wt_path.join("hook-ran")).unwrap().trim(),
+        "up"
+    );
+
+    wt.cleanup_state().unwrap();
+    wt.checkout_head(Some(git2::build::CheckoutBuilder::new().force()))
+        .unwrap();
+    wt.vendor_merge(None, &VendorMergeOpts::default(), None)
+        .unwrap();
+    assert_eq!(head_file(&wt, "a.txt").as_deref(), Some("upstream\n"));
+    assert_eq!(repo.head().unwrap().target().unwrap(), track);
+}
+
+/// Rewrite the `HEAD` commit of `repo` as an SSH-signed commit using `key`.
+fn sign_head_with_ssh_key(repo: &Repository, key: &Path) {
+    let head = repo.head().unwrap().peel_to_commit().unwrap();
+    let parents: Vec<git2::Commit> = head.parents().collect();
+    let parent_refs: Vec<&git2::Commit> = parents.iter().collect();
+    let buffer = repo
+        .commit_create_buffer(
+            &head.author(),
+            &head.committer(),
+            head.message().unwrap(),
+            &head.tree().unwrap(),
+            &parent_refs,
+        )
+        .unwrap();
+    let buffer = buffer.as_str().unwrap().to_string();
+
+    let payload = key.with_extension("payload");
+    fs::write(&payload, &buffer).unwrap();
+    let status = Command::new("ssh-keygen")
+        .args(["-Y", "sign", "-n", "git", "-f"])
+        .arg(key)
+        .arg(&payload)
+        .output()
+        .unwrap();
+    assert!(status.status.success());
+    let signature = fs::read_to_string(payload.with_extension("payload.sig")).unwrap();
+
+    let signed = repo.commit_signed(&buffer, &signature, None).unwrap();
+    let head_ref = repo.head().unwrap().name().unwrap().to_string();
+    repo.reference(&head_ref, signed, true, "sign").unwrap();
+}
+
+#[test]
+fn merge_rejects_unsigned_upstream_when_verify_is_set() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (_upstream, up_dir) = setup_upstream(&[("a.txt", "upstream\n")]);
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+
+    let attrs = format!(
+        "*.txt vendored vendor-name=up vendor-url={} vendor-verify=allowed_signers\n",
+        up_dir.path().display()
+    );
+    let head = commit_files(
+        &repo,
+        &[(".gitattributes", &attrs), ("allowed_signers", "")],
+        "track",
+    );
+
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+    let err = repo
+        .vendor_merge(None, &VendorMergeOpts::default(), None)
+        .unwrap_err();
+    assert!(err.message().contains("not signed"), "{}", err.message());
+    assert_eq!(repo.head().unwrap().target().unwrap(), head);
+
+    let dep = &repo.vendor_deps(None).unwrap()[0];
+    assert!(repo.vendor_merged_tree(dep, None).is_err());
+}
+
+#[test]
+fn merge_accepts_upstream_signed_by_allowed_ssh_key() {
+    if Command::new("ssh-keygen").arg("-?").output().is_err() {
+        return;
+    }
+
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (upstream, up_dir) = setup_upstream(&[("a.txt", "upstream\n")]);
+    let keys = TempDir::new().unwrap();
+    let key = keys.path().join("id_ed25519");
+    let status = Command::new("ssh-keygen")
+        .args(["-q", "-t", "ed25519", "-N", "", "-C", "upstream", "-f"])
+        .arg(&key)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    sign_head_with_ssh_key(&upstream, &key);
+
+    let public = fs::read_to_string(key.with_extension("pub")).unwrap();
+    let mut fields = public.split_whitespace();
+    let allowed = format!(
+        "upstream@test {} {}\n",
+        fields.next().unwrap(),
+        fields.next().unwrap()
+    );
+
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+    let attrs = format!(
+        "*.txt vendored vendor-name=up vendor-url={}\n",
+        up_dir.path().display()
+    );
+    commit_files(&repo, &[(".gitattributes", &attrs)], "track");
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+
+    let dep = &repo.vendor_deps(None).unwrap()[0];
+    let other = keys.path().join("other_signers");
+    fs::write(&other, "# nobody is allowed\n").unwrap();
+    assert!(repo.vendor_verify(dep, Some(&other)).is_err());
+
+    let allowed_path = keys.path().join("allowed_signers");
+    fs::write(&allowed_path, allowed).unwrap();
+    assert_eq!(
+        repo.vendor_verify(dep, Some(&allowed_path)).unwrap(),
+        "upstream@test"
+    );
+
+    let opts = VendorMergeOpts {
+        allowed_signers: Some(allowed_path),
+        ..Default::default()
+    };
+    repo.vendor_merge(None, &opts, None).unwrap();
+    assert_eq!(head_file(&repo, "a.txt").as_deref(), Some("upstream\n"));
+}
+
+#[test]
+fn attestation_is_signed_and_checked_against_vendored_content() {
+    if Command::new("ssh-keygen").arg("-?").output().is_err() {
+        return;
+    }
+
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (upstream, up_dir) = setup_upstream(&[("a.txt", "upstream\n")]);
+    let keys = TempDir::new().unwrap();
+    let key = keys.path().join("id_ed25519");
+    let status = Command::new("ssh-keygen")
+        .args(["-q", "-t", "ed25519", "-N", "", "-C", "reviewer", "-f"])
+        .arg(&key)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let public = fs::read_to_string(key.with_extension("pub")).unwrap();
+    let allowed_path = keys.path().join("allowed_signers");
+    fs::write(&allowed_path, format!("reviewer@test {public}")).unwrap();
+
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+    let attrs = format!(
+        "*.txt vendored vendor-name=up vendor-url={}\n",
+        up_dir.path().display()
+    );
+    commit_files(&repo, &[(".gitattributes", &attrs)], "track");
+    let mut config = repo.config().unwrap();
+    config.set_str("gpg.format", "ssh").unwrap();
+    config
+        .set_str("user.signingkey", key.to_str().unwrap())
+        .unwrap();
+
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+    repo.vendor_merge(None, &VendorMergeOpts::default(), None)
+        .unwrap();
+    repo.vendor_attest(None).unwrap();
+
+    let head = repo.head().unwrap().target().unwrap();
+    let attested = repo
+        .vendor_verify_attestation(head, Some(&allowed_path))
+        .unwrap();
+    assert_eq!(attested.len(), 1);
+    assert_eq!(attested[0].name, "up");
+    assert_eq!(
+        attested[0].upstream,
+        upstream.head().unwrap().target().unwrap()
+    );
+    let dep = repo.vendor_deps(None).unwrap().remove(0);
+    assert_eq!(attested[0].tree, repo.vendor_filtered_tree(&dep).unwrap());
+
+    // Failing to sign leaves the notes ref as it was.
+    let notes = repo.refname_to_id("refs/notes/vendor").unwrap();
+    commit_files(&repo, &[("README.md", "app\n")], "readme");
+    let missing = keys.path().join("missing");
+    config
+        .set_str("user.signingkey", missing.to_str().unwrap())
+        .unwrap();
+    assert!(repo.vendor_attest(None).is_err());
+    assert_eq!(repo.refname_to_id("refs/notes/vendor").unwrap(), notes);
+    assert!(repo.find_reference("refs/notes/vendor-unsigned").is_err());
+    config
+        .set_str("user.signingkey", key.to_str().unwrap())
+        .unwrap();
+    let readme = repo.head().unwrap().target().unwrap();
+    repo.vendor_attest(None).unwrap();
+    assert_eq!(
+        repo.vendor_verify_attestation(readme, Some(&allowed_path))
+            .unwrap()
+            .len(),
+        1
+    );
+
+    let other = keys.path().join("other_signers");
+    fs::write(&other, "# nobody is allowed\n").unwrap();
+    assert!(repo.vendor_verify_attestation(head, Some(&other)).is_err());
+
+    // Locally modified vendored content can be neither attested nor verified
+    let modified = commit_files(&repo, &[("a.txt", "patched\n")], "patch");
+    assert!(repo.vendor_attest(None).is_err());
+    let err = repo
+        .vendor_verify_attestation(modified, Some(&allowed_path))
+        .unwrap_err();
+    assert!(err.message().contains("No vendor attestation"));
+}
+
+// ---------------------------------------------------------------------------
+// bare repository
+// ---------------------------------------------------------------------------
+
+#[test]
+fn bare_repo_rejects_all_operations() {
+    let dir = TempDir::new().unwrap();
+    let repo = Repository::init_bare(dir.path()).unwrap();
+
+    assert!(
+        repo.track_pattern(
+            "*.txt",
+            "https://github.com/o/r.git",
+            None,
+            None,
+            &VendorTrackOpts::default()
+        )
+        .is_err()
+    );
+    assert!(repo.untrack_pattern("*.txt", None).is_err());
+    assert!(repo.vendor_statuses(None).is_err());
+    assert!(
+        repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+            .is_err()
+    );
+    assert!(Vendor::vendor_merge(&repo, None, &VendorMergeOpts::default(), None).is_err());
+}
+
+// ---------------------------------------------------------------------------
+// manifest
+// ---------------------------------------------------------------------------
+
+#[test]
+fn migrate_moves_deps_between_gitattributes_and_manifest() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+
+    repo.track_pattern(
+        "*.txt",
+        "https://github.com/owner/repo.git",
+        Some("main"),
+        None,
+        &VendorTrackOpts::default(),
+    )
+    .unwrap();
+    repo.track_pattern(
+        "*.md",
+        "https://github.com/owner/repo.git",
+        Some("main"),
+        None,
+        &VendorTrackOpts::default(),
+    )
+    .unwrap();
+    let before = repo.vendor_deps(None).unwrap();
+
+    repo.vendor_migrate(DepsFormat::Manifest).unwrap();
+    let manifest = fs::read_to_string(dir.path().join(".gitvendor.toml")).unwrap();
+    assert!(manifest.contains("[dependencies.\"owner/repo\"]"));
+    assert!(manifest.contains("patterns = [\"*.txt\", \"*.md\"]"));
+    let attributes = fs::read_to_string(dir.path().join(".gitattributes")).unwrap();
+    assert!(!attributes.contains("vendored"));
+    assert_eq!(repo.vendor_deps(None).unwrap(), before);
+
+    // Tracking and untracking edit the manifest once it exists.
+    repo.track_pattern(
+        "*.rs",
+        "https://github.com/owner/repo.git",
+        Some("main"),
+        None,
+        &VendorTrackOpts::default(),
+    )
+    .unwrap();
+    repo.untrack_pattern("*.md", None).unwrap();
+    let manifest = fs::read_to_string(dir.path().join(".gitvendor.toml")).unwrap();
+    assert!(manifest.contains("patterns = [\"*.txt\", \"*.rs\"]"));
+    assert!(
+        !fs::read_to_string(dir.path().join(".gitattributes"))
+            .unwrap()
+            .contains("vendored")
+    );
+
+    repo.vendor_migrate(DepsFormat::Gitattributes).unwrap();
+    assert!(!dir.path().join(".gitvendor.toml").exists());
+    let attributes = fs::read_to_string(dir.path().join(".gitattributes")).unwrap();
+    assert!(attributes.contains("*.txt vendored"));
+    assert!(attributes.contains("*.rs vendored"));
+    assert_eq!(repo.vendor_deps(None).unwrap().len(), 2);
+}
+
+#[test]
+fn merge_applies_manifest_excludes_prefix_and_patches() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (_upstream, up_dir) = setup_upstream(&[
+        ("src/a.txt", "upstream\n"),
+        ("src/tests/t.txt", "test\n"),
+        ("README.md", "readme\n"),
+    ]);
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+
+    let manifest = format!(
+        "[dependencies.up]\n\
+         url = \"{}\"\n\
+         patterns = [\"src/**\"]\n\
+         excludes = [\"src/tests\"]\n\
+         prefix = \"third_party/up\"\n\
+         patches = [\"patches/up.patch\"]\n",
+        up_dir.path().display()
+    );
+    let patch = "diff --git a/src/a.txt b/src/a.txt\n\
+                 --- a/src/a.txt\n\
+                 +++ b/src/a.txt\n\
+                 @@ -1 +1 @@\n\
+                 -upstream\n\
+                 +patched\n";
+    commit_files(
+        &repo,
+        &[(".gitvendor.toml", &manifest), ("patches/up.patch", patch)],
+        "track",
+    );
+
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+    repo.vendor_merge(None, &VendorMergeOpts::default(), None)
+        .unwrap();
+
+    assert_eq!(
+        head_file(&repo, "third_party/up/src/a.txt").as_deref(),
+        Some("patched\n")
+    );
+    assert!(head_file(&repo, "third_party/up/src/tests/t.txt").is_none());
+    assert!(head_file(&repo, "src/a.txt").is_none());
+    assert!(head_file(&repo, "third_party/up/README.md").is_none());
+
+    // The patch is part of the vendored baseline, so migrating the dependency
+    // back to .gitattributes is refused rather than silently dropping it.
+    let err = repo.vendor_migrate(DepsFormat::Gitattributes).unwrap_err();
+    assert!(err.message().contains("cannot express"));
+}
+
+#[test]
+fn merge_leaves_out_default_excludes_unless_a_dependency_opts_out() {
+    let _guard = CWD_LOCK.lock().unwrap();
+    let (_upstream, up_dir) = setup_upstream(&[
+        ("src/a.rs", "a\n"),
+        ("src/README.md", "readme\n"),
+        (".github/ci.yml", "up\n"),
+    ]);
+    let (_ci, ci_dir) = setup_upstream(&[(".github/ci.yml", "ci\n")]);
+    let (repo, dir) = setup_repo();
+    std::env::set_current_dir(dir.path()).unwrap();
+    repo.config()
+        .unwrap()
+        .set_str("vendor.defaultExcludes", "**/.github/**, **/*.md")
+        .unwrap();
+
+    let attrs = format!(
+        "src/** vendored vendor-name=up vendor-url={}\n\
+         .github/** vendored vendor-name=ci vendor-url={} -vendor-default-excludes\n",
+        up_dir.path().display(),
+        ci_dir.path().display()
+    );
+    commit_files(&repo, &[(".gitattributes", &attrs)], "track");
+    repo.vendor_fetch(None, &VendorFetchOpts::default(), None)
+        .unwrap();
+    repo.vendor_merge(None, &VendorMergeOpts::default(), None)
+        .unwrap();
+
+    assert_eq!(head_file(&repo, "src/a.rs").as_deref(), Some("a\n"));
+    assert!(head_file(&repo, "src/README.md").is_none());
+    assert_eq!(head_file(&repo, ".github/ci.yml").as_deref(), Some("ci\n"));
+
+    let up = repo.vendor_deps(Some("src/**")).unwrap().remove(0);
+    assert!(
+        repo.vendor_excluded(&up)
+            .unwrap()
+            .contains(&"src/README.md".to_string())
+    );
 }
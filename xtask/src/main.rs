@@ -41,6 +41,7 @@ fn generate_man_pages(output_dir: &Path) -> std::io::Result<()> {
 
     println!("Generating man pages to: {}", man1_dir.display());
 
+    generate_git_vendor_man(&man1_dir)?;
     generate_git_filter_tree_man(&man1_dir)?;
     generate_git_set_attr_man(&man1_dir)?;
 
@@ -49,6 +50,19 @@ fn generate_man_pages(output_dir: &Path) -> std::io::Result<()> {
     Ok(())
 }
 
+fn generate_git_vendor_man(output_dir: &Path) -> std::io::Result<()> {
+    let cmd = git_vendor::cli::Cli::command();
+    let man = clap_mangen::Man::new(cmd);
+    let mut buffer = Vec::new();
+    man.render(&mut buffer)?;
+
+    let man_path = output_dir.join("git-vendor.1");
+    fs::write(&man_path, buffer)?;
+
+    println!("  → git-vendor.1");
+    Ok(())
+}
+
 fn generate_git_filter_tree_man(output_dir: &Path) -> std::io::Result<()> {
     let cmd = git_filter_tree::cli::Cli::command();
     let man = clap_mangen::Man::new(cmd);